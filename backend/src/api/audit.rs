@@ -0,0 +1,258 @@
+use axum::{
+    extract::{Query, State},
+    http::header,
+    middleware::from_fn_with_state,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::entities::audit_log,
+    error::{AppError, AppResult},
+    middleware::permission::require_permission,
+    services::{
+        audit::{self as audit_service, AuditFilter, AuditService, ChainVerification},
+        rbac::Permission,
+    },
+    AppState,
+};
+
+const DEFAULT_PER_PAGE: u64 = 50;
+const MAX_PER_PAGE: u64 = 500;
+
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub user_id: Option<i32>,
+    pub username: Option<String>,
+    pub ip: Option<String>,
+    pub action: String,
+    pub target: String,
+    pub params: serde_json::Value,
+    pub result: String,
+    pub error: Option<String>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl From<audit_log::Model> for AuditEntry {
+    fn from(entry: audit_log::Model) -> Self {
+        Self {
+            params: serde_json::from_str(&entry.params).unwrap_or(serde_json::Value::Null),
+            id: entry.id,
+            created_at: entry.created_at,
+            user_id: entry.user_id,
+            username: entry.username,
+            ip: entry.ip,
+            action: entry.action,
+            target: entry.target,
+            result: entry.result,
+            error: entry.error,
+            prev_hash: entry.prev_hash,
+            hash: entry.hash,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    #[serde(flatten)]
+    pub filter: AuditFilter,
+    pub page: Option<u64>,
+    pub per_page: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub total: u64,
+    pub page: u64,
+    pub per_page: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(flatten)]
+    pub filter: AuditFilter,
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_entries))
+        .route("/export", get(export_entries))
+        .route("/verify", get(verify_chain))
+        .route_layer(from_fn_with_state(Permission::AuditRead, require_permission))
+}
+
+async fn list_entries(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> AppResult<Json<AuditPage>> {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+
+    let (entries, total) = AuditService::query(&state.db, &query.filter, page, per_page).await?;
+
+    Ok(Json(AuditPage {
+        entries: entries.into_iter().map(AuditEntry::from).collect(),
+        total,
+        page,
+        per_page,
+    }))
+}
+
+/// Download the matching entries, oldest first, as CSV or JSON lines
+async fn export_entries(
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> AppResult<Response> {
+    let entries = AuditService::export(&state.db, &query.filter).await?;
+
+    let (body, content_type, extension) = match query.format {
+        ExportFormat::Csv => (audit_service::to_csv(&entries), "text/csv; charset=utf-8", "csv"),
+        ExportFormat::Jsonl => {
+            let mut body = String::new();
+            for entry in entries {
+                let line = serde_json::to_string(&AuditEntry::from(entry))
+                    .map_err(|e| AppError::Internal(e.into()))?;
+                body.push_str(&line);
+                body.push('\n');
+            }
+            (body, "application/x-ndjson", "jsonl")
+        }
+    };
+
+    let disposition = format!(
+        "attachment; filename=\"audit-{}.{}\"",
+        chrono::Utc::now().format("%Y%m%d-%H%M%S"),
+        extension
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+async fn verify_chain(State(state): State<AppState>) -> AppResult<Json<ChainVerification>> {
+    Ok(Json(AuditService::verify(&state.db).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use crate::services::rbac::{ROLE_OPERATOR, ROLE_VIEWER};
+    use crate::test_util::{admin_token, body_json, test_app_with_state, user_token};
+
+    async fn get(app: &axum::Router, uri: &str, token: &str) -> axum::response::Response {
+        let req = Request::get(uri).header("Authorization", token).body(Body::empty()).unwrap();
+        app.clone().oneshot(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mutations_are_recorded() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let operator = user_token(&state, ROLE_OPERATOR).await;
+
+        let name = format!("mana-audit-{},test.txt", uuid::Uuid::new_v4());
+        let path = std::env::temp_dir().join(name);
+        let req = Request::put("/api/files/content")
+            .header("Authorization", &admin)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json!({ "path": path, "content": "secret" }).to_string()))
+            .unwrap();
+        assert_eq!(app.clone().oneshot(req).await.unwrap().status(), StatusCode::OK);
+
+        // Docker isn't available in tests, so this is recorded as a failure
+        let req = Request::post("/api/docker/containers/web/restart")
+            .header("Authorization", &operator)
+            .body(Body::empty())
+            .unwrap();
+        assert_ne!(app.clone().oneshot(req).await.unwrap().status(), StatusCode::OK);
+
+        let res = get(&app, "/api/audit?action=file.write", &admin).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let page = body_json(res).await;
+        assert_eq!(page["total"], 1);
+        let entry = &page["entries"][0];
+        assert_eq!(entry["username"], "admin");
+        assert_eq!(entry["target"], path.to_str().unwrap());
+        assert_eq!(entry["params"], json!({ "size": 6 }));
+        assert_eq!(entry["result"], "success");
+
+        let uri = "/api/audit?action=docker.&result=failure";
+        let page = body_json(get(&app, uri, &admin).await).await;
+        assert_eq!(page["total"], 1);
+        assert_eq!(page["entries"][0]["action"], "docker.container.restart");
+        assert_eq!(page["entries"][0]["target"], "web");
+
+        let res = get(&app, "/api/audit/export?format=csv&action=file.", &admin).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers()["content-disposition"].to_str().unwrap().starts_with("attachment"));
+        let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let csv = String::from_utf8(bytes.to_vec()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains(&format!("\"{}\"", path.display())));
+
+        let verification = body_json(get(&app, "/api/audit/verify", &admin).await).await;
+        assert_eq!(verification["valid"], true);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_login_is_recorded_without_password() {
+        let (app, state) = test_app_with_state().await;
+
+        let req = Request::post("/api/auth/login")
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"username":"admin","password":"hunter2"}"#))
+            .unwrap();
+        assert_eq!(app.clone().oneshot(req).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+        let admin = admin_token(&state).await;
+        let page = body_json(get(&app, "/api/audit?action=auth.login", &admin).await).await;
+        assert_eq!(page["total"], 1);
+        let entry = &page["entries"][0];
+        assert_eq!(entry["target"], "admin");
+        assert_eq!(entry["result"], "failure");
+        assert!(!entry.to_string().contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_requires_permission() {
+        let (app, state) = test_app_with_state().await;
+
+        for role in [ROLE_VIEWER, ROLE_OPERATOR] {
+            let token = user_token(&state, role).await;
+            for uri in ["/api/audit", "/api/audit/export", "/api/audit/verify"] {
+                assert_eq!(get(&app, uri, &token).await.status(), StatusCode::FORBIDDEN, "{}", uri);
+            }
+        }
+    }
+}
@@ -1,11 +1,45 @@
 use axum::{
-    extract::State,
-    routing::{get, post},
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderName, Method},
+    response::{AppendHeaders, IntoResponse, Response},
+    Extension,
+    routing::{delete, get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
-use crate::{error::{AppError, AppResult}, services::user::UserService, AppState};
+use crate::{
+    db::entities::{api_token, user, webauthn_credential},
+    error::{AppError, AppResult},
+    middleware::{
+        audit::Audit,
+        auth::Claims,
+        client::ClientInfo,
+        cookie::{self, CSRF_COOKIE, REFRESH_COOKIE, SESSION_COOKIE},
+        permission::GrantedPermissions,
+    },
+    services::{
+        api_token::{self as api_tokens, ApiTokenService, MAX_TOKEN_DAYS},
+        login_throttle::LoginThrottle,
+        oidc::OidcService,
+        password::PasswordPolicy,
+        rbac::Permission,
+        session::SessionService,
+        totp::TotpService,
+        user::UserService,
+        webauthn::{
+            AssertionCredential, CreationOptions, RegistrationCredential, RequestOptions,
+            WebauthnService,
+        },
+        ws_ticket::TICKET_TTL,
+    },
+    AppState,
+};
+
+/// How long the password step of a two-factor login stays valid
+const CHALLENGE_MINUTES: i64 = 5;
+const CHALLENGE_AUDIENCE: &str = "mana-panel:second-factor-challenge";
 
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
@@ -13,16 +47,96 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+/// Result of the password step: either a token, or a challenge that has to be
+/// exchanged at `/auth/login/totp` or `/auth/webauthn/login/finish` when
+/// two-factor authentication is enabled
 #[derive(Debug, Serialize)]
-pub struct LoginResponse {
+#[serde(untagged)]
+pub enum LoginResponse {
+    Token(TokenResponse),
+    SecondFactorRequired(SecondFactorChallengeResponse),
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
     pub token: String,
     pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub refresh_token: String,
+    pub refresh_expires_at: chrono::DateTime<chrono::Utc>,
+    /// Only the password change is allowed until the password is changed
+    pub must_change_password: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OidcStatusResponse {
+    pub enabled: bool,
+    pub provider_name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OidcAuthorizeResponse {
+    pub authorization_url: String,
+}
+
+/// What the provider appended to the redirect URL
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackRequest {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    /// Browsers leave this out and send the refresh cookie instead
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_seen_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    /// Whether this is the session making the request
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SecondFactorChallengeResponse {
+    pub second_factor_required: bool,
+    /// A TOTP or recovery code is accepted
+    pub totp: bool,
+    /// A registered security key or passkey is accepted
+    pub webauthn: bool,
+    pub challenge_token: String,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpLoginRequest {
+    pub challenge_token: String,
+    /// A TOTP code or an unused recovery code
+    pub code: String,
+}
+
+/// Claims of the short-lived token returned by the password step
+#[derive(Debug, Serialize, Deserialize)]
+struct SecondFactorChallengeClaims {
+    sub: String,
+    aud: String,
+    exp: usize,
 }
 
 #[derive(Debug, Serialize)]
 pub struct UserInfo {
     pub id: i32,
     pub username: String,
+    pub role: String,
+    pub permissions: Vec<Permission>,
+    pub totp_enabled: bool,
+    pub must_change_password: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -31,83 +145,1558 @@ pub struct ChangePasswordRequest {
     pub new_password: String,
 }
 
+#[derive(Debug, Serialize)]
+pub struct PasswordPolicyResponse {
+    pub min_length: usize,
+    pub min_char_classes: usize,
+    pub history: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WsTicketResponse {
+    pub ticket: String,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct MessageResponse {
     pub success: bool,
     pub message: String,
 }
 
+#[derive(Debug, Serialize)]
+pub struct TotpStatusResponse {
+    pub enabled: bool,
+    pub recovery_codes_remaining: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpDisableRequest {
+    pub password: String,
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiTokenInfo {
+    pub id: i32,
+    pub name: String,
+    pub token_prefix: String,
+    pub scopes: Vec<Permission>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_used_ip: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<api_token::Model> for ApiTokenInfo {
+    fn from(token: api_token::Model) -> Self {
+        let mut scopes: Vec<Permission> = api_tokens::token_scopes(&token).into_iter().collect();
+        scopes.sort_by_key(|p| p.as_str());
+
+        Self {
+            id: token.id,
+            name: token.name,
+            token_prefix: token.token_prefix,
+            scopes,
+            expires_at: token.expires_at,
+            last_used_at: token.last_used_at,
+            last_used_ip: token.last_used_ip,
+            created_at: token.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiTokenRequest {
+    pub name: String,
+    pub scopes: Vec<Permission>,
+    pub expires_in_days: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateApiTokenResponse {
+    /// Only returned once; the server keeps a hash
+    pub token: String,
+    #[serde(flatten)]
+    pub info: ApiTokenInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebauthnCredentialInfo {
+    pub id: i32,
+    pub name: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<webauthn_credential::Model> for WebauthnCredentialInfo {
+    fn from(credential: webauthn_credential::Model) -> Self {
+        Self {
+            id: credential.id,
+            name: credential.name,
+            created_at: credential.created_at,
+            last_used_at: credential.last_used_at,
+        }
+    }
+}
+
+/// Wrapper matching the argument of `navigator.credentials.create()`
+#[derive(Debug, Serialize)]
+pub struct WebauthnRegisterStartResponse {
+    #[serde(rename = "publicKey")]
+    pub public_key: CreationOptions,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebauthnRegisterFinishRequest {
+    pub name: String,
+    pub credential: RegistrationCredential,
+}
+
+/// Without a challenge token this starts a passwordless login
+#[derive(Debug, Default, Deserialize)]
+pub struct WebauthnLoginStartRequest {
+    pub challenge_token: Option<String>,
+}
+
+/// Wrapper matching the argument of `navigator.credentials.get()`
+#[derive(Debug, Serialize)]
+pub struct WebauthnLoginStartResponse {
+    #[serde(rename = "publicKey")]
+    pub public_key: RequestOptions,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebauthnLoginFinishRequest {
+    /// The password step's challenge when the key is used as a second factor
+    pub challenge_token: Option<String>,
+    pub credential: AssertionCredential,
+}
+
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/login", post(login))
+        .route("/login/totp", post(login_totp))
+        .route("/refresh", post(refresh))
+        .route("/oidc", get(oidc_status))
+        .route("/oidc/authorize", post(oidc_authorize))
+        .route("/oidc/callback", post(oidc_callback))
+        .route("/logout", post(logout))
+        .route("/ws-ticket", post(ws_ticket))
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/{id}", delete(revoke_session))
         .route("/me", get(current_user))
         .route("/password", post(change_password))
+        .route("/password/policy", get(password_policy))
+        .route("/totp", get(totp_status))
+        .route("/totp/enroll", post(totp_enroll))
+        .route("/totp/confirm", post(totp_confirm))
+        .route("/totp/disable", post(totp_disable))
+        .route("/totp/recovery-codes", post(totp_recovery_codes))
+        .route("/tokens", get(list_api_tokens).post(create_api_token))
+        .route("/tokens/{id}", delete(revoke_api_token))
+        .route("/webauthn/credentials", get(list_webauthn_credentials))
+        .route("/webauthn/credentials/{id}", delete(remove_webauthn_credential))
+        .route("/webauthn/register/start", post(webauthn_register_start))
+        .route("/webauthn/register/finish", post(webauthn_register_finish))
+        .route("/webauthn/login/start", post(webauthn_login_start))
+        .route("/webauthn/login/finish", post(webauthn_login_finish))
+}
+
+/// Sign a short-lived access token bound to a session
+fn issue_access_token(
+    state: &AppState,
+    user: &user::Model,
+    session_id: &str,
+) -> AppResult<(String, chrono::DateTime<chrono::Utc>)> {
+    let expiry = chrono::Utc::now() + chrono::Duration::minutes(state.config.access_token_minutes);
+
+    let claims = Claims {
+        sub: user.id.to_string(),
+        username: user.username.clone(),
+        role: user.role.clone(),
+        sid: session_id.to_string(),
+        exp: expiry.timestamp() as usize,
+        api_token_id: None,
+    };
+
+    let token = state.signing_keys.sign(&claims)?;
+
+    Ok((token, expiry))
+}
+
+type SessionCookies = AppendHeaders<[(HeaderName, String); 3]>;
+
+/// The session as HttpOnly cookies, for browsers that can't send the bearer
+/// token, e.g. with `EventSource`. The CSRF cookie has to be echoed in
+/// [`cookie::CSRF_HEADER`] on state-changing requests.
+fn session_cookies(state: &AppState, tokens: &TokenResponse) -> SessionCookies {
+    let now = chrono::Utc::now();
+    let secure = state.config.cookie_secure;
+    let access_secs = (tokens.expires_at - now).num_seconds();
+    let refresh_secs = (tokens.refresh_expires_at - now).num_seconds();
+
+    AppendHeaders([
+        (header::SET_COOKIE, cookie::set(SESSION_COOKIE, &tokens.token, access_secs, true, secure)),
+        (
+            header::SET_COOKIE,
+            cookie::set(REFRESH_COOKIE, &tokens.refresh_token, refresh_secs, true, secure),
+        ),
+        (
+            header::SET_COOKIE,
+            cookie::set(CSRF_COOKIE, &cookie::generate_csrf_token(), refresh_secs, false, secure),
+        ),
+    ])
+}
+
+/// Open a session for a fully authenticated user
+async fn start_session(
+    state: &AppState,
+    user: &user::Model,
+    client: ClientInfo,
+) -> AppResult<TokenResponse> {
+    SessionService::purge_stale(&state.db).await?;
+
+    let ttl = chrono::Duration::hours(state.config.refresh_token_hours);
+    let (session, refresh_token) =
+        SessionService::create(&state.db, user.id, client.into(), ttl).await?;
+
+    UserService::record_login(&state.db, user.id).await?;
+
+    let (token, expires_at) = issue_access_token(state, user, &session.id)?;
+
+    Ok(TokenResponse {
+        token,
+        expires_at,
+        refresh_token,
+        refresh_expires_at: session.expires_at,
+        must_change_password: user.must_change_password,
+    })
+}
+
+fn issue_second_factor_challenge(
+    state: &AppState,
+    user: &user::Model,
+    webauthn: bool,
+) -> AppResult<SecondFactorChallengeResponse> {
+    let expiry = chrono::Utc::now() + chrono::Duration::minutes(CHALLENGE_MINUTES);
+
+    let claims = SecondFactorChallengeClaims {
+        sub: user.id.to_string(),
+        aud: CHALLENGE_AUDIENCE.to_string(),
+        exp: expiry.timestamp() as usize,
+    };
+
+    let challenge_token = state.signing_keys.sign(&claims)?;
+
+    Ok(SecondFactorChallengeResponse {
+        second_factor_required: true,
+        totp: user.totp_enabled,
+        webauthn,
+        challenge_token,
+        expires_at: expiry,
+    })
+}
+
+fn verify_second_factor_challenge(state: &AppState, token: &str) -> AppResult<i32> {
+    let mut validation = jsonwebtoken::Validation::default();
+    validation.set_audience(&[CHALLENGE_AUDIENCE]);
+
+    let claims = state
+        .signing_keys
+        .verify::<SecondFactorChallengeClaims>(token, &validation)
+        .ok_or_else(|| AppError::Auth("Invalid or expired challenge".to_string()))?;
+
+    claims
+        .sub
+        .parse()
+        .map_err(|_| AppError::Auth("Invalid or expired challenge".to_string()))
+}
+
+/// Load the account behind the authenticated claims
+async fn current_account(state: &AppState, claims: &Claims) -> AppResult<user::Model> {
+    let user_id: i32 = claims.sub.parse()
+        .map_err(|_| AppError::Auth("Invalid user ID".to_string()))?;
+
+    UserService::find_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))
+}
+
+/// Record a login attempt. Successful ones are attributed to the account that
+/// signed in, failed ones to the account they were made for, when known.
+async fn audit_login<T>(
+    audit: Audit,
+    action: &str,
+    claimed: Option<&str>,
+    result: AppResult<(user::Model, T)>,
+) -> AppResult<Json<T>> {
+    let (audit, target) = match (&result, claimed) {
+        (Ok((user, _)), _) => {
+            (audit.for_user(Some(user.id), &user.username), user.username.clone())
+        }
+        (Err(_), Some(username)) => (audit.for_user(None, username), username.to_string()),
+        (Err(_), None) => (audit, String::new()),
+    };
+
+    audit.record(action, &target, json!({}), &result).await;
+    result.map(|(_, response)| Json(response))
+}
+
+/// The account a second factor challenge was issued for, to attribute failed
+/// attempts to
+async fn challenge_account(state: &AppState, token: Option<&str>) -> Option<user::Model> {
+    let user_id = verify_second_factor_challenge(state, token?).ok()?;
+    UserService::find_by_id(&state.db, user_id).await.ok().flatten()
 }
 
 async fn login(
     State(state): State<AppState>,
+    audit: Audit,
+    client: ClientInfo,
     Json(payload): Json<LoginRequest>,
-) -> AppResult<Json<LoginResponse>> {
-    use jsonwebtoken::{encode, EncodingKey, Header};
-    use crate::middleware::auth::Claims;
+) -> AppResult<Response> {
+    let result = password_login(&state, client, &payload).await;
+    let Json(response) = audit_login(audit, "auth.login", Some(&payload.username), result).await?;
+
+    Ok(match response {
+        LoginResponse::Token(tokens) => {
+            (session_cookies(&state, &tokens), Json(LoginResponse::Token(tokens))).into_response()
+        }
+        challenge => Json(challenge).into_response(),
+    })
+}
+
+async fn password_login(
+    state: &AppState,
+    client: ClientInfo,
+    payload: &LoginRequest,
+) -> AppResult<(user::Model, LoginResponse)> {
+    let ip = client.ip.map(|ip| ip.to_string());
+    LoginThrottle::check(&state.db, &payload.username, ip.as_deref()).await?;
 
     // Validate against database
-    let user = UserService::verify_credentials(&state.db, &payload.username, &payload.password)
+    let Some(user) =
+        UserService::verify_credentials(&state.db, &payload.username, &payload.password).await?
+    else {
+        LoginThrottle::record_failure(
+            &state.db,
+            &state.config,
+            &payload.username,
+            ip.as_deref(),
+            "invalid_credentials",
+        )
+        .await?;
+        return Err(AppError::Auth("Invalid credentials".to_string()));
+    };
+
+    if user.disabled {
+        LoginThrottle::record_failure(
+            &state.db,
+            &state.config,
+            &payload.username,
+            ip.as_deref(),
+            "disabled",
+        )
+        .await?;
+        return Err(AppError::Auth("Account is disabled".to_string()));
+    }
+
+    let webauthn = WebauthnService::has_credentials(&state.db, user.id).await?;
+    if user.totp_enabled || webauthn {
+        let challenge = issue_second_factor_challenge(state, &user, webauthn)?;
+        return Ok((user, LoginResponse::SecondFactorRequired(challenge)));
+    }
+
+    LoginThrottle::record_success(&state.db, &user.username, ip.as_deref()).await?;
+
+    let tokens = start_session(state, &user, client).await?;
+    Ok((user, LoginResponse::Token(tokens)))
+}
+
+async fn login_totp(
+    State(state): State<AppState>,
+    audit: Audit,
+    client: ClientInfo,
+    Json(payload): Json<TotpLoginRequest>,
+) -> AppResult<(SessionCookies, Json<TokenResponse>)> {
+    let result = totp_login(&state, client, &payload).await;
+    let claimed = match &result {
+        Ok(_) => None,
+        Err(_) => challenge_account(&state, Some(&payload.challenge_token)).await,
+    };
+    let claimed = claimed.as_ref().map(|user| user.username.as_str());
+    let Json(tokens) = audit_login(audit, "auth.login.totp", claimed, result).await?;
+    Ok((session_cookies(&state, &tokens), Json(tokens)))
+}
+
+async fn totp_login(
+    state: &AppState,
+    client: ClientInfo,
+    payload: &TotpLoginRequest,
+) -> AppResult<(user::Model, TokenResponse)> {
+    let user_id = verify_second_factor_challenge(state, &payload.challenge_token)?;
+
+    let user = UserService::find_by_id(&state.db, user_id)
         .await?
-        .ok_or_else(|| AppError::Auth("Invalid credentials".to_string()))?;
+        .filter(|user| !user.disabled)
+        .ok_or_else(|| AppError::Auth("Invalid or expired challenge".to_string()))?;
 
-    let expiry = chrono::Utc::now() + chrono::Duration::hours(state.config.jwt_expiry_hours);
-    
-    let claims = Claims {
-        sub: user.id.to_string(),
-        username: user.username.clone(),
-        exp: expiry.timestamp() as usize,
+    // The second factor is throttled like the password step
+    let ip = client.ip.map(|ip| ip.to_string());
+    LoginThrottle::check(&state.db, &user.username, ip.as_deref()).await?;
+
+    if !TotpService::verify_second_factor(&state.db, user.clone(), &payload.code).await? {
+        LoginThrottle::record_failure(
+            &state.db,
+            &state.config,
+            &user.username,
+            ip.as_deref(),
+            "invalid_totp",
+        )
+        .await?;
+        return Err(AppError::Auth("Invalid verification code".to_string()));
+    }
+
+    LoginThrottle::record_success(&state.db, &user.username, ip.as_deref()).await?;
+
+    let tokens = start_session(state, &user, client).await?;
+    Ok((user, tokens))
+}
+
+async fn oidc_status(State(state): State<AppState>) -> Json<OidcStatusResponse> {
+    Json(OidcStatusResponse {
+        enabled: state.config.oidc.is_some(),
+        provider_name: state.config.oidc.as_ref().map(|oidc| oidc.provider_name.clone()),
+    })
+}
+
+fn oidc_config(state: &AppState) -> AppResult<&crate::config::OidcConfig> {
+    state
+        .config
+        .oidc
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("Single sign-on is not configured".to_string()))
+}
+
+async fn oidc_authorize(State(state): State<AppState>) -> AppResult<Json<OidcAuthorizeResponse>> {
+    let config = oidc_config(&state)?;
+    let authorization_url = OidcService::authorization_url(&state.db, config).await?;
+
+    Ok(Json(OidcAuthorizeResponse { authorization_url }))
+}
+
+/// Finish an SSO login. The provider takes care of second factors, so this
+/// skips the panel's own TOTP step.
+async fn oidc_callback(
+    State(state): State<AppState>,
+    audit: Audit,
+    client: ClientInfo,
+    Json(payload): Json<OidcCallbackRequest>,
+) -> AppResult<(SessionCookies, Json<TokenResponse>)> {
+    let result = async {
+        let config = oidc_config(&state)?;
+        let identity =
+            OidcService::complete(&state.db, config, &payload.state, &payload.code).await?;
+        let user = OidcService::resolve_user(&state.db, config, &identity).await?;
+
+        let tokens = start_session(&state, &user, client).await?;
+        Ok((user, tokens))
+    }
+    .await;
+    let Json(tokens) = audit_login(audit, "auth.login.oidc", None, result).await?;
+    Ok((session_cookies(&state, &tokens), Json(tokens)))
+}
+
+/// Renew the access token. API clients send the refresh token in the body,
+/// browsers may rely on the refresh cookie together with the CSRF token.
+async fn refresh(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    payload: Option<Json<RefreshRequest>>,
+) -> AppResult<(SessionCookies, Json<TokenResponse>)> {
+    let refresh_token = match payload.and_then(|Json(payload)| payload.refresh_token) {
+        Some(token) => token,
+        None => {
+            cookie::check_csrf(&Method::POST, &headers)?;
+            cookie::get(&headers, REFRESH_COOKIE)
+                .ok_or_else(|| AppError::Auth("Missing refresh token".to_string()))?
+                .to_string()
+        }
     };
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(state.config.jwt_secret.as_bytes()),
-    )
-    .map_err(|e| AppError::Internal(e.into()))?;
+    let ttl = chrono::Duration::hours(state.config.refresh_token_hours);
+    let (session, refresh_token) = SessionService::rotate(&state.db, &refresh_token, ttl).await?;
 
-    Ok(Json(LoginResponse {
+    let user = match UserService::find_by_id(&state.db, session.user_id).await? {
+        Some(user) if !user.disabled => user,
+        _ => {
+            SessionService::revoke(&state.db, &session.id).await?;
+            return Err(AppError::Auth("Invalid or expired refresh token".to_string()));
+        }
+    };
+
+    let (token, expires_at) = issue_access_token(&state, &user, &session.id)?;
+    let tokens = TokenResponse {
         token,
-        expires_at: expiry,
-    }))
+        expires_at,
+        refresh_token,
+        refresh_expires_at: session.expires_at,
+        must_change_password: user.must_change_password,
+    };
+
+    Ok((session_cookies(&state, &tokens), Json(tokens)))
+}
+
+async fn logout(
+    State(state): State<AppState>,
+    audit: Audit,
+    claims: Claims,
+) -> AppResult<(SessionCookies, Json<MessageResponse>)> {
+    audit.run("auth.logout", &claims.username, json!({}), async {
+        SessionService::revoke(&state.db, &claims.sid).await?;
+
+        Ok((
+            AppendHeaders(cookie::clear(state.config.cookie_secure)),
+            Json(MessageResponse {
+                success: true,
+                message: "Logged out".to_string(),
+            }),
+        ))
+    })
+    .await
+}
+
+/// A single-use ticket for opening a WebSocket on behalf of the caller's
+/// session, see [`crate::middleware::auth::TICKET_ROUTES`]
+async fn ws_ticket(State(state): State<AppState>, claims: Claims) -> Json<WsTicketResponse> {
+    let expires_at = chrono::Utc::now() + TICKET_TTL;
+
+    Json(WsTicketResponse {
+        ticket: state.ws_tickets.issue(claims),
+        expires_at,
+    })
+}
+
+async fn list_sessions(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> AppResult<Json<Vec<SessionInfo>>> {
+    let user = current_account(&state, &claims).await?;
+    let sessions = SessionService::list_active(&state.db, user.id).await?;
+
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|session| SessionInfo {
+                current: session.id == claims.sid,
+                id: session.id,
+                ip: session.ip,
+                user_agent: session.user_agent,
+                created_at: session.created_at,
+                last_seen_at: session.last_seen_at,
+                expires_at: session.expires_at,
+            })
+            .collect(),
+    ))
+}
+
+async fn revoke_session(
+    State(state): State<AppState>,
+    audit: Audit,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> AppResult<Json<MessageResponse>> {
+    audit.run("auth.session.revoke", &id, json!({}), async {
+        let user = current_account(&state, &claims).await?;
+        SessionService::revoke_for_user(&state.db, user.id, &id).await?;
+
+        Ok(Json(MessageResponse {
+            success: true,
+            message: "Session revoked".to_string(),
+        }))
+    })
+    .await
 }
 
 async fn current_user(
-    claims: crate::middleware::auth::Claims,
+    State(state): State<AppState>,
+    claims: Claims,
+    Extension(granted): Extension<GrantedPermissions>,
 ) -> AppResult<Json<UserInfo>> {
+    let user = current_account(&state, &claims).await?;
+
+    let mut permissions: Vec<Permission> = granted.0.into_iter().collect();
+    permissions.sort_by_key(|p| p.as_str());
+
     Ok(Json(UserInfo {
-        id: claims.sub.parse().unwrap_or(0),
-        username: claims.username,
+        id: user.id,
+        username: user.username,
+        role: user.role,
+        permissions,
+        totp_enabled: user.totp_enabled,
+        must_change_password: user.must_change_password,
     }))
 }
 
 async fn change_password(
     State(state): State<AppState>,
-    claims: crate::middleware::auth::Claims,
+    audit: Audit,
+    claims: Claims,
     Json(payload): Json<ChangePasswordRequest>,
 ) -> AppResult<Json<MessageResponse>> {
-    let user_id: i32 = claims.sub.parse()
-        .map_err(|_| AppError::Auth("Invalid user ID".to_string()))?;
-    
-    // Verify current password
-    let user = UserService::find_by_username(&state.db, &claims.username)
-        .await?
-        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
-    
-    if !crate::services::password::verify_password(&payload.current_password, &user.password_hash)? {
-        return Err(AppError::Auth("Current password is incorrect".to_string()));
-    }
-    
-    // Update password
-    UserService::update_password(&state.db, user_id, &payload.new_password).await?;
-    
-    Ok(Json(MessageResponse {
-        success: true,
-        message: "Password changed successfully".to_string(),
+    audit.run("auth.password.change", &claims.username, json!({}), async {
+        // Verify current password
+        let user = current_account(&state, &claims).await?;
+        let user_id = user.id;
+
+        if !crate::services::password::verify_password(&payload.current_password, &user.password_hash)? {
+            return Err(AppError::Auth("Current password is incorrect".to_string()));
+        }
+
+        // Update password and sign out everywhere else
+        let policy = PasswordPolicy::from_config(&state.config);
+        UserService::update_password(&state.db, &policy, user_id, &payload.new_password, false)
+            .await?;
+        SessionService::revoke_all_for_user(&state.db, user_id, Some(&claims.sid)).await?;
+
+        Ok(Json(MessageResponse {
+            success: true,
+            message: "Password changed successfully".to_string(),
+        }))
+    })
+    .await
+}
+
+async fn password_policy(State(state): State<AppState>) -> Json<PasswordPolicyResponse> {
+    let policy = PasswordPolicy::from_config(&state.config);
+
+    Json(PasswordPolicyResponse {
+        min_length: policy.min_length,
+        min_char_classes: policy.min_char_classes,
+        history: policy.history,
+    })
+}
+
+async fn totp_status(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> AppResult<Json<TotpStatusResponse>> {
+    let user = current_account(&state, &claims).await?;
+    let recovery_codes_remaining = if user.totp_enabled {
+        TotpService::remaining_recovery_codes(&state.db, user.id).await?
+    } else {
+        0
+    };
+
+    Ok(Json(TotpStatusResponse {
+        enabled: user.totp_enabled,
+        recovery_codes_remaining,
+    }))
+}
+
+async fn totp_enroll(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> AppResult<Json<TotpEnrollResponse>> {
+    let user = current_account(&state, &claims).await?;
+    let enrollment = TotpService::enroll(&state.db, user).await?;
+
+    Ok(Json(TotpEnrollResponse {
+        secret: enrollment.secret,
+        otpauth_uri: enrollment.otpauth_uri,
     }))
 }
+
+async fn totp_confirm(
+    State(state): State<AppState>,
+    audit: Audit,
+    claims: Claims,
+    Json(payload): Json<TotpCodeRequest>,
+) -> AppResult<Json<RecoveryCodesResponse>> {
+    audit.run("auth.totp.enable", &claims.username, json!({}), async {
+        let user = current_account(&state, &claims).await?;
+        let recovery_codes = TotpService::confirm(&state.db, user, &payload.code).await?;
+
+        Ok(Json(RecoveryCodesResponse { recovery_codes }))
+    })
+    .await
+}
+
+async fn totp_disable(
+    State(state): State<AppState>,
+    audit: Audit,
+    claims: Claims,
+    Json(payload): Json<TotpDisableRequest>,
+) -> AppResult<Json<MessageResponse>> {
+    audit.run("auth.totp.disable", &claims.username, json!({}), async {
+        let user = current_account(&state, &claims).await?;
+
+        if !crate::services::password::verify_password(&payload.password, &user.password_hash)? {
+            return Err(AppError::Auth("Current password is incorrect".to_string()));
+        }
+        if !TotpService::verify_second_factor(&state.db, user.clone(), &payload.code).await? {
+            return Err(AppError::Auth("Invalid verification code".to_string()));
+        }
+
+        TotpService::disable(&state.db, user).await?;
+
+        Ok(Json(MessageResponse {
+            success: true,
+            message: "Two-factor authentication disabled".to_string(),
+        }))
+    })
+    .await
+}
+
+async fn totp_recovery_codes(
+    State(state): State<AppState>,
+    audit: Audit,
+    claims: Claims,
+    Json(payload): Json<TotpCodeRequest>,
+) -> AppResult<Json<RecoveryCodesResponse>> {
+    audit.run("auth.totp.recovery_codes", &claims.username, json!({}), async {
+        let user = current_account(&state, &claims).await?;
+        let user_id = user.id;
+
+        if !TotpService::verify_second_factor(&state.db, user, &payload.code).await? {
+            return Err(AppError::Auth("Invalid verification code".to_string()));
+        }
+
+        let recovery_codes = TotpService::regenerate_recovery_codes(&state.db, user_id).await?;
+
+        Ok(Json(RecoveryCodesResponse { recovery_codes }))
+    })
+    .await
+}
+
+async fn list_api_tokens(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> AppResult<Json<Vec<ApiTokenInfo>>> {
+    let user = current_account(&state, &claims).await?;
+    let tokens = ApiTokenService::list(&state.db, user.id).await?;
+
+    Ok(Json(tokens.into_iter().map(ApiTokenInfo::from).collect()))
+}
+
+async fn create_api_token(
+    State(state): State<AppState>,
+    audit: Audit,
+    claims: Claims,
+    Extension(granted): Extension<GrantedPermissions>,
+    Json(payload): Json<CreateApiTokenRequest>,
+) -> AppResult<Json<CreateApiTokenResponse>> {
+    let params = json!({ "scopes": &payload.scopes, "expires_in_days": payload.expires_in_days });
+    audit.run("auth.token.create", &payload.name, params, async {
+        let user = current_account(&state, &claims).await?;
+
+        if !(1..=MAX_TOKEN_DAYS).contains(&payload.expires_in_days) {
+            return Err(AppError::Validation(format!(
+                "Token lifetime must be between 1 and {} days",
+                MAX_TOKEN_DAYS
+            )));
+        }
+        // A token can never do more than its owner
+        if let Some(scope) = payload.scopes.iter().find(|scope| !granted.has(**scope)) {
+            return Err(AppError::Validation(format!(
+                "Cannot grant scope you don't have: {}",
+                scope
+            )));
+        }
+
+        let (token, plaintext) = ApiTokenService::create(
+            &state.db,
+            user.id,
+            &payload.name,
+            &payload.scopes,
+            chrono::Duration::days(payload.expires_in_days),
+        )
+        .await?;
+
+        Ok(Json(CreateApiTokenResponse {
+            token: plaintext,
+            info: token.into(),
+        }))
+    })
+    .await
+}
+
+async fn revoke_api_token(
+    State(state): State<AppState>,
+    audit: Audit,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> AppResult<Json<MessageResponse>> {
+    audit.run("auth.token.revoke", &id.to_string(), json!({}), async {
+        let user = current_account(&state, &claims).await?;
+        ApiTokenService::revoke(&state.db, user.id, id).await?;
+
+        Ok(Json(MessageResponse {
+            success: true,
+            message: "API token revoked".to_string(),
+        }))
+    })
+    .await
+}
+
+async fn list_webauthn_credentials(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> AppResult<Json<Vec<WebauthnCredentialInfo>>> {
+    let user = current_account(&state, &claims).await?;
+    let credentials = WebauthnService::list(&state.db, user.id).await?;
+
+    Ok(Json(credentials.into_iter().map(WebauthnCredentialInfo::from).collect()))
+}
+
+async fn remove_webauthn_credential(
+    State(state): State<AppState>,
+    audit: Audit,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> AppResult<Json<MessageResponse>> {
+    audit.run("auth.webauthn.remove", &id.to_string(), json!({}), async {
+        let user = current_account(&state, &claims).await?;
+        WebauthnService::remove(&state.db, user.id, id).await?;
+
+        Ok(Json(MessageResponse {
+            success: true,
+            message: "Authenticator removed".to_string(),
+        }))
+    })
+    .await
+}
+
+async fn webauthn_register_start(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> AppResult<Json<WebauthnRegisterStartResponse>> {
+    let user = current_account(&state, &claims).await?;
+    let public_key =
+        WebauthnService::start_registration(&state.db, &state.config.webauthn, &user).await?;
+
+    Ok(Json(WebauthnRegisterStartResponse { public_key }))
+}
+
+async fn webauthn_register_finish(
+    State(state): State<AppState>,
+    audit: Audit,
+    claims: Claims,
+    Json(payload): Json<WebauthnRegisterFinishRequest>,
+) -> AppResult<Json<WebauthnCredentialInfo>> {
+    audit.run("auth.webauthn.register", &payload.name, json!({}), async {
+        let user = current_account(&state, &claims).await?;
+        let credential = WebauthnService::finish_registration(
+            &state.db,
+            &state.config.webauthn,
+            user.id,
+            &payload.name,
+            &payload.credential,
+        )
+        .await?;
+
+        Ok(Json(credential.into()))
+    })
+    .await
+}
+
+async fn webauthn_login_start(
+    State(state): State<AppState>,
+    payload: Option<Json<WebauthnLoginStartRequest>>,
+) -> AppResult<Json<WebauthnLoginStartResponse>> {
+    let payload = payload.map(|Json(payload)| payload).unwrap_or_default();
+    let user_id = payload
+        .challenge_token
+        .map(|token| verify_second_factor_challenge(&state, &token))
+        .transpose()?;
+
+    let public_key =
+        WebauthnService::start_login(&state.db, &state.config.webauthn, user_id).await?;
+
+    Ok(Json(WebauthnLoginStartResponse { public_key }))
+}
+
+/// Finish a security key login. As a second factor it completes the password
+/// login; on its own the authenticator has verified the user (PIN or
+/// biometrics), which counts as two factors and skips TOTP.
+async fn webauthn_login_finish(
+    State(state): State<AppState>,
+    audit: Audit,
+    client: ClientInfo,
+    Json(payload): Json<WebauthnLoginFinishRequest>,
+) -> AppResult<(SessionCookies, Json<TokenResponse>)> {
+    let result = webauthn_login(&state, client, &payload).await;
+    let claimed = match &result {
+        Ok(_) => None,
+        Err(_) => challenge_account(&state, payload.challenge_token.as_deref()).await,
+    };
+    let claimed = claimed.as_ref().map(|user| user.username.as_str());
+    let Json(tokens) = audit_login(audit, "auth.login.webauthn", claimed, result).await?;
+    Ok((session_cookies(&state, &tokens), Json(tokens)))
+}
+
+async fn webauthn_login(
+    state: &AppState,
+    client: ClientInfo,
+    payload: &WebauthnLoginFinishRequest,
+) -> AppResult<(user::Model, TokenResponse)> {
+    let ip = client.ip.map(|ip| ip.to_string());
+
+    let challenge_user = match &payload.challenge_token {
+        Some(token) => {
+            let user_id = verify_second_factor_challenge(state, token)?;
+            let user = UserService::find_by_id(&state.db, user_id)
+                .await?
+                .filter(|user| !user.disabled)
+                .ok_or_else(|| AppError::Auth("Invalid or expired challenge".to_string()))?;
+            LoginThrottle::check(&state.db, &user.username, ip.as_deref()).await?;
+            Some(user)
+        }
+        None => None,
+    };
+
+    let result = WebauthnService::finish_login(
+        &state.db,
+        &state.config.webauthn,
+        challenge_user.as_ref().map(|user| user.id),
+        &payload.credential,
+    )
+    .await;
+
+    let credential = match (result, &challenge_user) {
+        (Ok(credential), _) => credential,
+        (Err(e), Some(user)) => {
+            LoginThrottle::record_failure(
+                &state.db,
+                &state.config,
+                &user.username,
+                ip.as_deref(),
+                "invalid_webauthn",
+            )
+            .await?;
+            return Err(e);
+        }
+        (Err(e), None) => return Err(e),
+    };
+
+    let user = match challenge_user {
+        Some(user) => user,
+        None => {
+            let user = UserService::find_by_id(&state.db, credential.user_id)
+                .await?
+                .ok_or_else(|| AppError::Auth("Security key verification failed".to_string()))?;
+            if user.disabled {
+                return Err(AppError::Auth("Account is disabled".to_string()));
+            }
+            LoginThrottle::check(&state.db, &user.username, ip.as_deref()).await?;
+            user
+        }
+    };
+
+    LoginThrottle::record_success(&state.db, &user.username, ip.as_deref()).await?;
+
+    let tokens = start_session(state, &user, client).await?;
+    Ok((user, tokens))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use crate::services::totp;
+    use crate::services::oidc::mock_idp::MockIdp;
+    use crate::services::webauthn::soft_authenticator::SoftAuthenticator;
+    use crate::test_util::{
+        admin_token, app_for, body_json, test_app, test_app_with_state, test_state, user_token,
+    };
+
+    async fn post(
+        app: &axum::Router,
+        uri: &str,
+        token: Option<&str>,
+        body: serde_json::Value,
+    ) -> (StatusCode, serde_json::Value) {
+        let mut req = Request::post(uri).header("Content-Type", "application/json");
+        if let Some(token) = token {
+            req = req.header("Authorization", token);
+        }
+        let res = app
+            .clone()
+            .oneshot(req.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap();
+        let status = res.status();
+        (status, body_json(res).await)
+    }
+
+    #[tokio::test]
+    async fn test_totp_two_step_login() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let credentials = json!({ "username": "admin", "password": "admin" });
+
+        let (status, enrollment) = post(&app, "/api/auth/totp/enroll", Some(&admin), json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        let secret = enrollment["secret"].as_str().unwrap().to_string();
+        assert!(enrollment["otpauth_uri"].as_str().unwrap().starts_with("otpauth://totp/"));
+
+        // Still a single-step login until the secret is confirmed
+        let (_, login) = post(&app, "/api/auth/login", None, credentials.clone()).await;
+        assert!(login["token"].is_string());
+
+        let now = chrono::Utc::now().timestamp();
+        let code = totp::current_code(&secret, now).unwrap();
+        let (status, confirmed) =
+            post(&app, "/api/auth/totp/confirm", Some(&admin), json!({ "code": code })).await;
+        assert_eq!(status, StatusCode::OK);
+        let recovery_codes = confirmed["recovery_codes"].as_array().unwrap().clone();
+        assert_eq!(recovery_codes.len(), 10);
+
+        let (status, login) = post(&app, "/api/auth/login", None, credentials.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(login.get("token").is_none());
+        assert_eq!(login["second_factor_required"], true);
+        assert_eq!(login["totp"], true);
+        assert_eq!(login["webauthn"], false);
+        let challenge = login["challenge_token"].as_str().unwrap().to_string();
+
+        // The challenge is not an access token
+        let req = Request::get("/api/auth/me")
+            .header("Authorization", format!("Bearer {}", challenge))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(app.clone().oneshot(req).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+        // The confirmation code was already used, so it cannot be replayed
+        let body = json!({ "challenge_token": challenge, "code": code });
+        let (status, _) = post(&app, "/api/auth/login/totp", None, body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let next_code = totp::current_code(&secret, now + 30).unwrap();
+        let body = json!({ "challenge_token": challenge, "code": next_code });
+        let (status, token) = post(&app, "/api/auth/login/totp", None, body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(token["token"].is_string());
+
+        // Recovery codes work exactly once
+        let body = json!({ "challenge_token": challenge, "code": recovery_codes[0] });
+        let (status, _) = post(&app, "/api/auth/login/totp", None, body.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = post(&app, "/api/auth/login/totp", None, body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_totp_login_rejects_bad_challenge() {
+        let (app, state) = test_app_with_state().await;
+
+        let body = json!({ "challenge_token": "garbage", "code": "123456" });
+        let (status, _) = post(&app, "/api/auth/login/totp", None, body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // An access token cannot stand in for a challenge
+        let access = admin_token(&state).await;
+        let access = access.trim_start_matches("Bearer ");
+        let body = json!({ "challenge_token": access, "code": "123456" });
+        let (status, _) = post(&app, "/api/auth/login/totp", None, body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    async fn get(app: &axum::Router, uri: &str, token: &str) -> (StatusCode, serde_json::Value) {
+        let req = Request::get(uri)
+            .header("Authorization", token)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        (status, body_json(res).await)
+    }
+
+    async fn login(app: &axum::Router, password: &str) -> serde_json::Value {
+        let credentials = json!({ "username": "admin", "password": password });
+        let (status, body) = post(app, "/api/auth/login", None, credentials).await;
+        assert_eq!(status, StatusCode::OK);
+        body
+    }
+
+    fn bearer(body: &serde_json::Value) -> String {
+        format!("Bearer {}", body["token"].as_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rotation_and_logout() {
+        let app = test_app().await;
+        let first = login(&app, "admin").await;
+        let refresh_token = first["refresh_token"].as_str().unwrap();
+
+        let body = json!({ "refresh_token": refresh_token });
+        let (status, second) = post(&app, "/api/auth/refresh", None, body.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_ne!(second["refresh_token"], first["refresh_token"]);
+
+        // The old refresh token is single-use
+        let (status, _) = post(&app, "/api/auth/refresh", None, body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        // ...and replaying it revoked the session
+        let (status, _) = get(&app, "/api/auth/me", &bearer(&second)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let third = login(&app, "admin").await;
+        let (status, _) = post(&app, "/api/auth/logout", Some(&bearer(&third)), json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = get(&app, "/api/auth/me", &bearer(&third)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let body = json!({ "refresh_token": third["refresh_token"] });
+        let (status, _) = post(&app, "/api/auth/refresh", None, body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_list_and_revoke_sessions() {
+        let app = test_app().await;
+        let laptop = bearer(&login(&app, "admin").await);
+        let phone = bearer(&login(&app, "admin").await);
+
+        let (status, sessions) = get(&app, "/api/auth/sessions", &laptop).await;
+        assert_eq!(status, StatusCode::OK);
+        let sessions = sessions.as_array().unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions.iter().filter(|s| s["current"] == true).count(), 1);
+
+        let phone_id = sessions.iter().find(|s| s["current"] == false).unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let req = Request::delete(format!("/api/auth/sessions/{}", phone_id))
+            .header("Authorization", &laptop)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(app.clone().oneshot(req).await.unwrap().status(), StatusCode::OK);
+
+        let (status, _) = get(&app, "/api/auth/me", &phone).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = get(&app, "/api/auth/me", &laptop).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_password_change_revokes_other_sessions() {
+        let app = test_app().await;
+        let current = bearer(&login(&app, "admin").await);
+        let other = bearer(&login(&app, "admin").await);
+
+        let body = json!({ "current_password": "admin", "new_password": "n3w-password" });
+        let (status, _) = post(&app, "/api/auth/password", Some(&current), body).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = get(&app, "/api/auth/me", &current).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = get(&app, "/api/auth/me", &other).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_api_tokens() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+
+        let body =
+            json!({ "name": "monitoring", "scopes": ["system:read"], "expires_in_days": 30 });
+        let (status, created) = post(&app, "/api/auth/tokens", Some(&admin), body).await;
+        assert_eq!(status, StatusCode::OK);
+        let token = format!("Bearer {}", created["token"].as_str().unwrap());
+        assert_eq!(created["scopes"], json!(["system:read"]));
+
+        let (status, _) = get(&app, "/api/system/info", &token).await;
+        assert_eq!(status, StatusCode::OK);
+        // Outside the token's scopes, even though the owner is an admin
+        let (status, _) = get(&app, "/api/processes", &token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = get(&app, "/api/auth/tokens", &token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (_, tokens) = get(&app, "/api/auth/tokens", &admin).await;
+        let tokens = tokens.as_array().unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert!(tokens[0]["last_used_at"].is_string());
+        assert!(tokens[0].get("token").is_none());
+
+        let req = Request::delete(format!("/api/auth/tokens/{}", created["id"]))
+            .header("Authorization", &admin)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(app.clone().oneshot(req).await.unwrap().status(), StatusCode::OK);
+        let (status, _) = get(&app, "/api/system/info", &token).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_api_token_scopes_limited_to_owner() {
+        let (app, state) = test_app_with_state().await;
+        let viewer = user_token(&state, "viewer").await;
+
+        let body = json!({ "name": "deploy", "scopes": ["docker:write"], "expires_in_days": 30 });
+        let (status, _) = post(&app, "/api/auth/tokens", Some(&viewer), body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let body = json!({ "name": "forever", "scopes": ["docker:read"], "expires_in_days": 0 });
+        let (status, _) = post(&app, "/api/auth/tokens", Some(&viewer), body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_forced_password_change() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+
+        let body = json!({ "username": "newbie", "password": "Initial-pass1", "role": "operator" });
+        let (status, _) = post(&app, "/api/users", Some(&admin), body).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let credentials = json!({ "username": "newbie", "password": "Initial-pass1" });
+        let (_, login) = post(&app, "/api/auth/login", None, credentials).await;
+        assert_eq!(login["must_change_password"], true);
+        let token = bearer(&login);
+
+        let (status, body) = get(&app, "/api/system/info", &token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"]["code"], "PASSWORD_CHANGE_REQUIRED");
+        let (status, me) = get(&app, "/api/auth/me", &token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(me["must_change_password"], true);
+
+        for weak in ["short", "Initial-pass1", "newbie-Pass-1"] {
+            let body = json!({ "current_password": "Initial-pass1", "new_password": weak });
+            let (status, _) = post(&app, "/api/auth/password", Some(&token), body).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{} was accepted", weak);
+        }
+
+        let body = json!({ "current_password": "Initial-pass1", "new_password": "My-own-pass2" });
+        let (status, _) = post(&app, "/api/auth/password", Some(&token), body).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = get(&app, "/api/system/info", &token).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_oidc_login() {
+        let idp = MockIdp::start().await;
+        let mut state = test_state().await;
+        state.config.oidc = Some(idp.config());
+        let app = app_for(&state);
+
+        let (status, authorize) = post(&app, "/api/auth/oidc/authorize", None, json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        let url = authorize["authorization_url"].as_str().unwrap();
+        assert!(url.starts_with(&format!("{}/authorize?", idp.config().issuer)));
+
+        // Mapped onto the existing local admin by username
+        let claims =
+            json!({ "sub": "idp-admin", "preferred_username": "admin", "groups": ["admins"] });
+        let (code, login_state) = idp.sign_in(url, claims);
+        let body = json!({ "code": code, "state": login_state });
+        let (status, tokens) = post(&app, "/api/auth/oidc/callback", None, body.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, me) = get(&app, "/api/auth/me", &bearer(&tokens)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(me["username"], "admin");
+
+        let (status, _) = post(&app, "/api/auth/oidc/callback", None, body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Unmapped groups get no account
+        let (_, authorize) = post(&app, "/api/auth/oidc/authorize", None, json!({})).await;
+        let url = authorize["authorization_url"].as_str().unwrap();
+        let claims =
+            json!({ "sub": "idp-guest", "preferred_username": "guest", "groups": ["staff"] });
+        let (code, login_state) = idp.sign_in(url, claims);
+        let body = json!({ "code": code, "state": login_state });
+        let (status, _) = post(&app, "/api/auth/oidc/callback", None, body).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Password login stays available as a break-glass option
+        login(&app, "admin").await;
+    }
+
+    #[tokio::test]
+    async fn test_oidc_disabled_by_default() {
+        let app = test_app().await;
+
+        let req = Request::get("/api/auth/oidc").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_json(res).await["enabled"], false);
+
+        let (status, _) = post(&app, "/api/auth/oidc/authorize", None, json!({})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_webauthn_second_factor_and_passwordless_login() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let origin = &state.config.webauthn.origins[0];
+        let mut key = SoftAuthenticator::new();
+
+        let (status, start) =
+            post(&app, "/api/auth/webauthn/register/start", Some(&admin), json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        let credential = key.register(&start["publicKey"], origin);
+        let body = json!({ "name": "YubiKey", "credential": credential });
+        let (status, registered) =
+            post(&app, "/api/auth/webauthn/register/finish", Some(&admin), body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(registered["name"], "YubiKey");
+
+        // The password alone is no longer enough
+        let (_, challenge) = post(&app, "/api/auth/login", None, json!({
+            "username": "admin", "password": "admin"
+        }))
+        .await;
+        assert_eq!(challenge["second_factor_required"], true);
+        assert_eq!(challenge["webauthn"], true);
+        let challenge_token = challenge["challenge_token"].clone();
+
+        let body = json!({ "challenge_token": challenge_token });
+        let (status, start) = post(&app, "/api/auth/webauthn/login/start", None, body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(start["publicKey"]["allowCredentials"][0]["id"], key.id());
+        let assertion = key.authenticate(&start["publicKey"], origin);
+        let body = json!({ "challenge_token": challenge_token, "credential": assertion });
+        let (status, tokens) = post(&app, "/api/auth/webauthn/login/finish", None, body).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = get(&app, "/api/auth/me", &bearer(&tokens)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Passwordless with a discoverable credential
+        let (status, start) = post(&app, "/api/auth/webauthn/login/start", None, json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(start["publicKey"]["userVerification"], "required");
+        let assertion = key.authenticate(&start["publicKey"], origin);
+        let body = json!({ "credential": assertion });
+        let (status, tokens) = post(&app, "/api/auth/webauthn/login/finish", None, body).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, me) = get(&app, "/api/auth/me", &bearer(&tokens)).await;
+        assert_eq!(me["username"], "admin");
+
+        let (status, credentials) = get(&app, "/api/auth/webauthn/credentials", &admin).await;
+        assert_eq!(status, StatusCode::OK);
+        let credentials = credentials.as_array().unwrap();
+        assert_eq!(credentials.len(), 1);
+        assert!(credentials[0]["last_used_at"].is_string());
+
+        let req = Request::delete(format!("/api/auth/webauthn/credentials/{}", registered["id"]))
+            .header("Authorization", &admin)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(app.clone().oneshot(req).await.unwrap().status(), StatusCode::OK);
+
+        // Back to a single-step password login, and the removed key is useless
+        let login = login(&app, "admin").await;
+        assert!(login["token"].is_string());
+        let (_, start) = post(&app, "/api/auth/webauthn/login/start", None, json!({})).await;
+        let body = json!({ "credential": key.authenticate(&start["publicKey"], origin) });
+        let (status, _) = post(&app, "/api/auth/webauthn/login/finish", None, body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_webauthn_credentials_are_per_user() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let viewer = user_token(&state, "viewer").await;
+        let origin = &state.config.webauthn.origins[0];
+        let mut key = SoftAuthenticator::new();
+
+        let (_, start) =
+            post(&app, "/api/auth/webauthn/register/start", Some(&admin), json!({})).await;
+        let credential = key.register(&start["publicKey"], origin);
+        // The challenge was issued to the admin
+        let body = json!({ "name": "stolen", "credential": credential });
+        let (status, _) =
+            post(&app, "/api/auth/webauthn/register/finish", Some(&viewer), body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (_, start) =
+            post(&app, "/api/auth/webauthn/register/start", Some(&admin), json!({})).await;
+        let body = json!({ "name": "key", "credential": key.register(&start["publicKey"], origin) });
+        let (_, registered) =
+            post(&app, "/api/auth/webauthn/register/finish", Some(&admin), body).await;
+
+        let (_, credentials) = get(&app, "/api/auth/webauthn/credentials", &viewer).await;
+        assert_eq!(credentials, json!([]));
+        let req = Request::delete(format!("/api/auth/webauthn/credentials/{}", registered["id"]))
+            .header("Authorization", &viewer)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(app.clone().oneshot(req).await.unwrap().status(), StatusCode::NOT_FOUND);
+    }
+
+    /// The `name=value` pairs of the `Set-Cookie` headers, plus the attributes
+    /// of the session cookie
+    fn cookies(res: &axum::response::Response) -> (String, String, String) {
+        let set_cookies: Vec<&str> = res
+            .headers()
+            .get_all("set-cookie")
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect();
+        let pair = |name: &str| {
+            let cookie = set_cookies.iter().find(|c| c.starts_with(name)).unwrap();
+            cookie.split(';').next().unwrap().to_string()
+        };
+        let session = set_cookies.iter().find(|c| c.starts_with("mana_session=")).unwrap();
+        (
+            format!("{}; {}", pair("mana_session="), pair("mana_csrf=")),
+            pair("mana_csrf=").trim_start_matches("mana_csrf=").to_string(),
+            session.to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_cookie_session_requires_csrf_token() {
+        let app = test_app().await;
+
+        let req = Request::post("/api/auth/login")
+            .header("Content-Type", "application/json")
+            .body(Body::from(json!({ "username": "admin", "password": "admin" }).to_string()))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let (cookie, csrf, session) = cookies(&res);
+        assert!(session.contains("HttpOnly"));
+        assert!(session.contains("SameSite=Strict"));
+        assert!(session.contains("Secure"));
+
+        // Reads and event streams work with the cookie alone
+        for uri in ["/api/auth/me", "/api/system/stats/stream"] {
+            let req = Request::get(uri).header("Cookie", &cookie).body(Body::empty()).unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK, "{}", uri);
+        }
+
+        let path = std::env::temp_dir().join(format!("mana-csrf-{}", uuid::Uuid::new_v4()));
+        let mkdir = |csrf: Option<&str>| {
+            let mut req = Request::post("/api/files/mkdir")
+                .header("Cookie", &cookie)
+                .header("Content-Type", "application/json");
+            if let Some(csrf) = csrf {
+                req = req.header("X-CSRF-Token", csrf);
+            }
+            req.body(Body::from(json!({ "path": path }).to_string())).unwrap()
+        };
+        let res = app.clone().oneshot(mkdir(None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app.clone().oneshot(mkdir(Some("forged"))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app.clone().oneshot(mkdir(Some(&csrf))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        std::fs::remove_dir(&path).unwrap();
+
+        // Browsers renew through the refresh cookie
+        let req = Request::post("/api/auth/refresh")
+            .header("Cookie", format!("{}; {}", cookie, "mana_refresh=bogus"))
+            .header("X-CSRF-Token", &csrf)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(app.clone().oneshot(req).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+        let req = Request::post("/api/auth/logout")
+            .header("Cookie", &cookie)
+            .header("X-CSRF-Token", &csrf)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(cookies(&res).2.contains("Max-Age=0"));
+
+        let req = Request::get("/api/auth/me").header("Cookie", &cookie).body(Body::empty());
+        assert_eq!(app.oneshot(req.unwrap()).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_with_cookie() {
+        let app = test_app().await;
+
+        let req = Request::post("/api/auth/login")
+            .header("Content-Type", "application/json")
+            .body(Body::from(json!({ "username": "admin", "password": "admin" }).to_string()))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let (_, csrf, _) = cookies(&res);
+        let login = body_json(res).await;
+        let cookie = format!(
+            "mana_refresh={}; mana_csrf={}",
+            login["refresh_token"].as_str().unwrap(),
+            csrf
+        );
+
+        let refresh = |csrf: Option<&str>| {
+            let mut req = Request::post("/api/auth/refresh").header("Cookie", &cookie);
+            if let Some(csrf) = csrf {
+                req = req.header("X-CSRF-Token", csrf);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+        let res = app.clone().oneshot(refresh(None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app.clone().oneshot(refresh(Some(&csrf))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(body_json(res).await["token"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_websocket_ticket_is_single_use() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+
+        let (status, ticket) = post(&app, "/api/auth/ws-ticket", Some(&admin), json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        let uri = format!("/api/terminal/ws?ticket={}", ticket["ticket"].as_str().unwrap());
+
+        // Not a real handshake, so getting past authentication means a 4xx
+        // from the upgrade extractor rather than a 401
+        let ws = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+        let status = app.clone().oneshot(ws(&uri)).await.unwrap().status();
+        assert_ne!(status, StatusCode::UNAUTHORIZED);
+        assert_ne!(status, StatusCode::FORBIDDEN);
+
+        let status = app.clone().oneshot(ws(&uri)).await.unwrap().status();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let status = app.clone().oneshot(ws("/api/terminal/ws")).await.unwrap().status();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // The session cookie doesn't open WebSockets
+        let req = Request::post("/api/auth/login")
+            .header("Content-Type", "application/json")
+            .body(Body::from(json!({ "username": "admin", "password": "admin" }).to_string()))
+            .unwrap();
+        let (cookie, _, _) = cookies(&app.clone().oneshot(req).await.unwrap());
+        let req = Request::get("/api/terminal/ws").header("Cookie", cookie).body(Body::empty());
+        let status = app.oneshot(req.unwrap()).await.unwrap().status();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -1,16 +1,20 @@
 use axum::{
     extract::{Path, Query, State},
+    middleware::from_fn_with_state,
     routing::{delete, get, post},
     Json, Router,
 };
 use serde::Deserialize;
+use serde_json::json;
 
 use crate::{
     error::AppResult,
+    middleware::{audit::Audit, permission::require_permission},
     services::docker::{
         ContainerDetail, ContainerInfo, ContainerStats, CreateContainerRequest,
         DockerActionResponse, ImageInfo, PullProgress,
     },
+    services::rbac::Permission,
     AppState,
 };
 
@@ -43,21 +47,29 @@ pub struct PullImageRequest {
 // ---------------------------------------------------------------------------
 
 pub fn router() -> Router<AppState> {
-    Router::new()
+    let read = Router::new()
         // Containers
         .route("/containers", get(list_containers))
-        .route("/containers", post(create_container))
         .route("/containers/{id}", get(inspect_container))
+        .route("/containers/{id}/logs", get(container_logs))
+        .route("/containers/{id}/stats", get(container_stats))
+        // Images
+        .route("/images", get(list_images))
+        .route_layer(from_fn_with_state(Permission::DockerRead, require_permission));
+
+    let write = Router::new()
+        // Containers
+        .route("/containers", post(create_container))
         .route("/containers/{id}", delete(remove_container))
         .route("/containers/{id}/start", post(start_container))
         .route("/containers/{id}/stop", post(stop_container))
         .route("/containers/{id}/restart", post(restart_container))
-        .route("/containers/{id}/logs", get(container_logs))
-        .route("/containers/{id}/stats", get(container_stats))
         // Images
-        .route("/images", get(list_images))
         .route("/images/pull", post(pull_image))
         .route("/images/{id}", delete(remove_image))
+        .route_layer(from_fn_with_state(Permission::DockerWrite, require_permission));
+
+    read.merge(write)
 }
 
 // ---------------------------------------------------------------------------
@@ -89,48 +101,64 @@ async fn inspect_container(
 
 async fn start_container(
     State(state): State<AppState>,
+    audit: Audit,
     Path(id): Path<String>,
 ) -> AppResult<Json<DockerActionResponse>> {
-    let docker = state.docker.as_ref().ok_or_else(|| {
-        crate::error::AppError::System("Docker is not available".into())
-    })?;
-    let resp = docker.start_container(&id).await?;
-    Ok(Json(resp))
+    audit.run("docker.container.start", &id, json!({}), async {
+        let docker = state.docker.as_ref().ok_or_else(|| {
+            crate::error::AppError::System("Docker is not available".into())
+        })?;
+        let resp = docker.start_container(&id).await?;
+        Ok(Json(resp))
+    })
+    .await
 }
 
 async fn stop_container(
     State(state): State<AppState>,
+    audit: Audit,
     Path(id): Path<String>,
 ) -> AppResult<Json<DockerActionResponse>> {
-    let docker = state.docker.as_ref().ok_or_else(|| {
-        crate::error::AppError::System("Docker is not available".into())
-    })?;
-    let resp = docker.stop_container(&id).await?;
-    Ok(Json(resp))
+    audit.run("docker.container.stop", &id, json!({}), async {
+        let docker = state.docker.as_ref().ok_or_else(|| {
+            crate::error::AppError::System("Docker is not available".into())
+        })?;
+        let resp = docker.stop_container(&id).await?;
+        Ok(Json(resp))
+    })
+    .await
 }
 
 async fn restart_container(
     State(state): State<AppState>,
+    audit: Audit,
     Path(id): Path<String>,
 ) -> AppResult<Json<DockerActionResponse>> {
-    let docker = state.docker.as_ref().ok_or_else(|| {
-        crate::error::AppError::System("Docker is not available".into())
-    })?;
-    let resp = docker.restart_container(&id).await?;
-    Ok(Json(resp))
+    audit.run("docker.container.restart", &id, json!({}), async {
+        let docker = state.docker.as_ref().ok_or_else(|| {
+            crate::error::AppError::System("Docker is not available".into())
+        })?;
+        let resp = docker.restart_container(&id).await?;
+        Ok(Json(resp))
+    })
+    .await
 }
 
 async fn remove_container(
     State(state): State<AppState>,
+    audit: Audit,
     Path(id): Path<String>,
     Query(query): Query<ForceQuery>,
 ) -> AppResult<Json<DockerActionResponse>> {
-    let docker = state.docker.as_ref().ok_or_else(|| {
-        crate::error::AppError::System("Docker is not available".into())
-    })?;
     let force = query.force.unwrap_or(false);
-    let resp = docker.remove_container(&id, force).await?;
-    Ok(Json(resp))
+    audit.run("docker.container.remove", &id, json!({ "force": force }), async {
+        let docker = state.docker.as_ref().ok_or_else(|| {
+            crate::error::AppError::System("Docker is not available".into())
+        })?;
+        let resp = docker.remove_container(&id, force).await?;
+        Ok(Json(resp))
+    })
+    .await
 }
 
 async fn container_logs(
@@ -163,13 +191,34 @@ async fn container_stats(
 
 async fn create_container(
     State(state): State<AppState>,
+    audit: Audit,
     Json(payload): Json<CreateContainerRequest>,
 ) -> AppResult<Json<DockerActionResponse>> {
-    let docker = state.docker.as_ref().ok_or_else(|| {
-        crate::error::AppError::System("Docker is not available".into())
-    })?;
-    let resp = docker.create_container(payload).await?;
-    Ok(Json(resp))
+    let target = payload.name.clone().unwrap_or_default();
+    // Environment values often carry secrets, so only their names are kept
+    let env: Vec<&str> = payload
+        .env
+        .iter()
+        .flatten()
+        .map(|var| var.split('=').next().unwrap_or_default())
+        .collect();
+    let params = json!({
+        "image": payload.image,
+        "cmd": payload.cmd,
+        "env": env,
+        "ports": payload.ports,
+        "volumes": payload.volumes,
+        "restart_policy": payload.restart_policy,
+    });
+
+    audit.run("docker.container.create", &target, params, async {
+        let docker = state.docker.as_ref().ok_or_else(|| {
+            crate::error::AppError::System("Docker is not available".into())
+        })?;
+        let resp = docker.create_container(payload).await?;
+        Ok(Json(resp))
+    })
+    .await
 }
 
 // ---------------------------------------------------------------------------
@@ -190,24 +239,32 @@ async fn list_images(
 
 async fn pull_image(
     State(state): State<AppState>,
+    audit: Audit,
     Json(payload): Json<PullImageRequest>,
 ) -> AppResult<Json<Vec<PullProgress>>> {
-    let docker = state.docker.as_ref().ok_or_else(|| {
-        crate::error::AppError::System("Docker is not available".into())
-    })?;
-    let progress = docker.pull_image(&payload.image).await?;
-    Ok(Json(progress))
+    audit.run("docker.image.pull", &payload.image, json!({}), async {
+        let docker = state.docker.as_ref().ok_or_else(|| {
+            crate::error::AppError::System("Docker is not available".into())
+        })?;
+        let progress = docker.pull_image(&payload.image).await?;
+        Ok(Json(progress))
+    })
+    .await
 }
 
 async fn remove_image(
     State(state): State<AppState>,
+    audit: Audit,
     Path(id): Path<String>,
     Query(query): Query<ForceQuery>,
 ) -> AppResult<Json<DockerActionResponse>> {
-    let docker = state.docker.as_ref().ok_or_else(|| {
-        crate::error::AppError::System("Docker is not available".into())
-    })?;
     let force = query.force.unwrap_or(false);
-    let resp = docker.remove_image(&id, force).await?;
-    Ok(Json(resp))
+    audit.run("docker.image.remove", &id, json!({ "force": force }), async {
+        let docker = state.docker.as_ref().ok_or_else(|| {
+            crate::error::AppError::System("Docker is not available".into())
+        })?;
+        let resp = docker.remove_image(&id, force).await?;
+        Ok(Json(resp))
+    })
+    .await
 }
@@ -0,0 +1,136 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::PathBuf;
+
+use crate::{
+    error::{AppError, AppResult},
+    middleware::{audit::Audit, auth::Claims},
+    services::{
+        archive::{self, ArchiveFormat},
+        files::ConflictPolicy,
+        jail::Jail,
+        jobs::JobInfo,
+    },
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct CompressRequest {
+    pub paths: Vec<String>,
+    /// The archive to create
+    pub destination: String,
+    /// Detected from the destination's extension if not given
+    pub format: Option<ArchiveFormat>,
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExtractRequest {
+    pub path: String,
+    /// Directory to extract into, created if missing; defaults to the one the
+    /// archive is in
+    pub destination: Option<String>,
+    /// Detected from the archive's extension if not given
+    pub format: Option<ArchiveFormat>,
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+}
+
+fn format_for(format: Option<ArchiveFormat>, path: &std::path::Path) -> AppResult<ArchiveFormat> {
+    format.or_else(|| ArchiveFormat::detect(path)).ok_or_else(|| {
+        AppError::Validation(
+            "Unknown archive format, use .zip, .tar.gz, .tar.xz or .tar.zst or name a format"
+                .to_string(),
+        )
+    })
+}
+
+/// Start a job that packs the given paths into a new archive
+pub(super) async fn compress(
+    State(state): State<AppState>,
+    jail: Jail,
+    audit: Audit,
+    claims: Claims,
+    Json(payload): Json<CompressRequest>,
+) -> AppResult<(StatusCode, Json<JobInfo>)> {
+    let params = json!({
+        "paths": &payload.paths,
+        "format": payload.format,
+        "conflict": payload.conflict,
+    });
+    audit.run("file.compress", &payload.destination, params, async {
+        if payload.paths.is_empty() {
+            return Err(AppError::Validation("Nothing to compress".to_string()));
+        }
+        let sources = payload
+            .paths
+            .iter()
+            .map(|path| {
+                let source = jail.resolve(path)?;
+                jail.check_tree(&source)?;
+                if source.file_name().is_none() {
+                    return Err(AppError::Validation("Can't compress /".to_string()));
+                }
+                if !source.exists() {
+                    return Err(AppError::NotFound(format!("Path not found: {}", path)));
+                }
+                Ok(source.into_path())
+            })
+            .collect::<AppResult<Vec<PathBuf>>>()?;
+
+        let destination = jail.resolve(&payload.destination)?;
+        let format = format_for(payload.format, &destination)?;
+        if !destination.parent().is_some_and(|dir| dir.is_dir()) {
+            return Err(AppError::NotFound(format!(
+                "Directory not found for {}",
+                payload.destination
+            )));
+        }
+
+        let conflict = payload.conflict;
+        let description = format!("Compress into {}", destination.display());
+        let user_id = claims.user_id()?;
+        let job = state.jobs.spawn_blocking(user_id, "compress", description, move |handle| {
+            archive::compress(&sources, &destination, format, conflict, handle)
+        });
+        Ok((StatusCode::ACCEPTED, Json(job)))
+    })
+    .await
+}
+
+/// Start a job that unpacks an archive
+pub(super) async fn extract(
+    State(state): State<AppState>,
+    jail: Jail,
+    audit: Audit,
+    claims: Claims,
+    Json(payload): Json<ExtractRequest>,
+) -> AppResult<(StatusCode, Json<JobInfo>)> {
+    let params = json!({
+        "destination": &payload.destination,
+        "format": payload.format,
+        "conflict": payload.conflict,
+    });
+    audit.run("file.extract", &payload.path, params, async {
+        let path = jail.resolve(&payload.path)?;
+        if !path.is_file() {
+            return Err(AppError::NotFound(format!("Archive not found: {}", payload.path)));
+        }
+        let format = format_for(payload.format, &path)?;
+        let destination = match &payload.destination {
+            Some(destination) => jail.resolve(destination)?.into_path(),
+            None => path.parent().map(PathBuf::from).unwrap_or_default(),
+        };
+
+        let (conflict, jail) = (payload.conflict, jail.clone());
+        let description = format!("Extract {}", path.display());
+        let user_id = claims.user_id()?;
+        let job = state.jobs.spawn_blocking(user_id, "extract", description, move |handle| {
+            archive::extract(&path, &destination, format, conflict, &jail, handle)
+        });
+        Ok((StatusCode::ACCEPTED, Json(job)))
+    })
+    .await
+}
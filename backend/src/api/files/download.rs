@@ -0,0 +1,313 @@
+use axum::{
+    body::Body,
+    extract::Query,
+    http::{header, HeaderMap, StatusCode},
+    response::Response,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::io::SeekFrom;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use super::PathQuery;
+use crate::{
+    error::{AppError, AppResult},
+    services::{files, jail::Jail},
+};
+
+/// Characters RFC 5987 allows unencoded in an extended parameter value
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// The part of the file a request asked for
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Full,
+    /// First and last byte, inclusive
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Stream a file from disk, honouring conditional and range requests so that
+/// browsers and download managers can resume large downloads
+pub(super) async fn download_file(
+    jail: Jail,
+    headers: HeaderMap,
+    Query(query): Query<PathQuery>,
+) -> AppResult<Response> {
+    let path = jail.resolve(&query.path)?;
+
+    // Everything below looks at the opened file, not the path, so it can't
+    // change between the checks and the read
+    let file = path.open().map(fs::File::from_std);
+    let mut file = file.map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => {
+            AppError::NotFound(format!("File not found: {}", query.path))
+        }
+        _ => e.into(),
+    })?;
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+        return Err(AppError::Validation("Path is not a file".to_string()));
+    }
+
+    let size = metadata.len();
+    let modified = metadata.modified().ok();
+    let etag = files::etag(&metadata);
+
+    let mut response = Response::builder()
+        .header(header::ETAG, &etag)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CACHE_CONTROL, "private, no-cache");
+    if let Some(modified) = modified {
+        response = response.header(header::LAST_MODIFIED, httpdate::fmt_http_date(modified));
+    }
+
+    if not_modified(&headers, &etag, modified) {
+        return Ok(response.status(StatusCode::NOT_MODIFIED).body(Body::empty()).unwrap());
+    }
+
+    let range = match header_str(&headers, header::RANGE) {
+        Some(range) if if_range_matches(&headers, &etag, modified) => {
+            parse_range(range, size).unwrap_or(ByteRange::Full)
+        }
+        _ => ByteRange::Full,
+    };
+
+    let (start, end) = match range {
+        ByteRange::Full => (0, size.saturating_sub(1)),
+        ByteRange::Partial(start, end) => {
+            response = response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size));
+            (start, end)
+        }
+        ByteRange::Unsatisfiable => {
+            return Ok(response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+                .body(Body::empty())
+                .unwrap());
+        }
+    };
+    let length = if size == 0 { 0 } else { end - start + 1 };
+
+    file.seek(SeekFrom::Start(start)).await?;
+    let body = Body::from_stream(ReaderStream::new(file.take(length)));
+
+    Ok(response
+        .header(header::CONTENT_TYPE, content_type(&path))
+        .header(header::CONTENT_LENGTH, length)
+        .header(header::CONTENT_DISPOSITION, content_disposition(&path))
+        // The browser must not render a downloaded file as a page of the panel
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .body(body)
+        .unwrap())
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Whether the client's cached copy is still current. `If-None-Match` takes
+/// precedence over `If-Modified-Since` (RFC 9110, section 13.2.2).
+fn not_modified(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(tags) = header_str(headers, header::IF_NONE_MATCH) {
+        return tags.split(',').map(str::trim).any(|tag| {
+            // Weak comparison: a `W/` prefix doesn't matter here
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        });
+    }
+
+    let since = header_str(headers, header::IF_MODIFIED_SINCE)
+        .and_then(|since| httpdate::parse_http_date(since).ok());
+    match (since, modified) {
+        (Some(since), Some(modified)) => unix_secs(modified) <= unix_secs(since),
+        _ => false,
+    }
+}
+
+/// Whether a range request may be served partially: only if the part the
+/// client already has comes from the same version of the file
+fn if_range_matches(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    let Some(condition) = header_str(headers, header::IF_RANGE) else {
+        return true;
+    };
+
+    if condition.starts_with('"') {
+        // Strong comparison, weak tags never match
+        return condition == etag;
+    }
+    match (httpdate::parse_http_date(condition), modified) {
+        (Ok(date), Some(modified)) => unix_secs(date) == unix_secs(modified),
+        _ => false,
+    }
+}
+
+/// Parse a `Range` header for a file of `size` bytes. Returns `None` for
+/// headers that should be ignored, which includes requests for several
+/// ranges: those get the whole file, as RFC 9110 allows.
+fn parse_range(header: &str, size: u64) -> Option<ByteRange> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.trim().split_once('-')?;
+
+    if start.is_empty() {
+        // The last `end` bytes
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || size == 0 {
+            return Some(ByteRange::Unsatisfiable);
+        }
+        return Some(ByteRange::Partial(size - suffix.min(size), size - 1));
+    }
+
+    let start: u64 = start.parse().ok()?;
+    let end = match end {
+        "" => u64::MAX,
+        end => end.parse().ok()?,
+    };
+    if end < start {
+        return None;
+    }
+    if start >= size {
+        return Some(ByteRange::Unsatisfiable);
+    }
+    Some(ByteRange::Partial(start, end.min(size - 1)))
+}
+
+fn content_type(path: &Path) -> String {
+    mime_guess::from_path(path).first_or_octet_stream().to_string()
+}
+
+/// An `attachment` disposition with the file name both as plain ASCII, for
+/// old clients, and UTF-8 encoded per RFC 5987, so that any name survives
+fn content_disposition(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "download".to_string());
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        utf8_percent_encode(&name, ATTR_CHAR)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    use crate::test_util::{admin_token, test_app_with_state};
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-9", 100), Some(ByteRange::Partial(0, 9)));
+        assert_eq!(parse_range("bytes=90-", 100), Some(ByteRange::Partial(90, 99)));
+        assert_eq!(parse_range("bytes=90-200", 100), Some(ByteRange::Partial(90, 99)));
+        assert_eq!(parse_range("bytes=-10", 100), Some(ByteRange::Partial(90, 99)));
+        assert_eq!(parse_range("bytes=-500", 100), Some(ByteRange::Partial(0, 99)));
+        assert_eq!(parse_range("bytes=100-", 100), Some(ByteRange::Unsatisfiable));
+        assert_eq!(parse_range("bytes=-0", 100), Some(ByteRange::Unsatisfiable));
+        assert_eq!(parse_range("bytes=5-1", 100), None);
+        assert_eq!(parse_range("bytes=0-1,5-6", 100), None);
+        assert_eq!(parse_range("items=0-1", 100), None);
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(
+            content_disposition(Path::new("/tmp/résumé \"final\".pdf")),
+            "attachment; filename=\"r_sum_ _final_.pdf\"; \
+             filename*=UTF-8''r%C3%A9sum%C3%A9%20%22final%22.pdf"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_ranges_and_conditionals() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let dir = std::env::temp_dir().join(format!("mana-download-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.txt");
+        std::fs::write(&path, "0123456789").unwrap();
+
+        let get = |headers: &[(header::HeaderName, &str)]| {
+            let mut req = Request::get(format!("/api/files/download?path={}", path.display()))
+                .header("Authorization", &admin);
+            for (name, value) in headers {
+                req = req.header(name, *value);
+            }
+            app.clone().oneshot(req.body(Body::empty()).unwrap())
+        };
+        let body = |res: Response| async {
+            let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+
+        let res = get(&[]).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(res.headers()[header::CONTENT_LENGTH], "10");
+        let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+        let modified = res.headers()[header::LAST_MODIFIED].to_str().unwrap().to_string();
+        assert_eq!(body(res).await, "0123456789");
+
+        let res = get(&[(header::RANGE, "bytes=2-4")]).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[header::CONTENT_RANGE], "bytes 2-4/10");
+        assert_eq!(body(res).await, "234");
+
+        let res = get(&[(header::RANGE, "bytes=-3"), (header::IF_RANGE, &etag)]).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body(res).await, "789");
+
+        // The client's part is from another version, so it gets the whole file
+        let res = get(&[(header::RANGE, "bytes=-3"), (header::IF_RANGE, "\"stale\"")]);
+        let res = res.await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body(res).await, "0123456789");
+
+        let res = get(&[(header::RANGE, "bytes=10-")]).await.unwrap();
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers()[header::CONTENT_RANGE], "bytes */10");
+
+        let res = get(&[(header::IF_NONE_MATCH, &etag)]).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        let res = get(&[(header::IF_MODIFIED_SINCE, &modified)]).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        let res = get(&[(header::IF_NONE_MATCH, "\"other\"")]).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
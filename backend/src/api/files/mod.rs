@@ -0,0 +1,500 @@
+use axum::{
+    extract::{multipart::Field, Multipart, Query, State},
+    http::{header, HeaderMap},
+    middleware::from_fn_with_state,
+    routing::{delete, get, patch, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::{
+    error::{AppError, AppResult},
+    middleware::{audit::Audit, auth::Claims, permission::require_permission},
+    services::{
+        attributes::{self, Accounts, AttributeChange},
+        files,
+        jail::{Jail, RenameMode, Resolved},
+        rbac::Permission,
+        text::{self, LineEnding, Page, PageRequest},
+        trash::TrashService,
+        versions::Author,
+    },
+    AppState,
+};
+
+mod archive;
+mod download;
+mod search;
+mod transfer;
+mod trash;
+mod upload;
+mod usage;
+mod versions;
+
+pub use usage::invalidate_disk_usage;
+
+#[derive(Debug, Serialize)]
+pub struct FileEntry {
+    pub name: String,
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: i64,
+    /// Octal mode including the setuid, setgid and sticky bits, e.g. `2775`
+    pub permissions: String,
+    /// The mode as `ls -l` shows it, e.g. `drwxrwsr-x`
+    pub symbolic: String,
+    pub owner: String,
+    pub group: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PathQuery {
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteQuery {
+    pub path: String,
+    /// Delete for good instead of moving to the trash
+    #[serde(default)]
+    pub permanent: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FileContentRequest {
+    pub path: String,
+    pub content: String,
+    /// Encoding to save in, as read; UTF-8 if not given
+    pub encoding: Option<String>,
+    /// Start the file with a byte order mark, as read
+    #[serde(default)]
+    pub bom: bool,
+    /// Line endings to save with, as read; left alone if not given
+    pub line_ending: Option<LineEnding>,
+    /// The ETag the file had when it was read; the write is refused if the
+    /// file changed since. An `If-Match` header does the same. Required
+    /// unless the file is new.
+    pub etag: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PermissionRequest {
+    pub path: String,
+    /// Octal mode, e.g. `755`
+    pub mode: Option<String>,
+    /// User name or uid
+    pub owner: Option<String>,
+    /// Group name or gid
+    pub group: Option<String>,
+    /// Also change everything below a directory
+    #[serde(default)]
+    pub recursive: bool,
+    /// Mode for files instead of `mode`, for recursive changes
+    pub file_mode: Option<String>,
+    /// Mode for directories instead of `mode`, for recursive changes
+    pub dir_mode: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MkdirRequest {
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContentQuery {
+    pub path: String,
+    /// Byte offset of the page to read
+    pub offset: Option<u64>,
+    pub length: Option<u64>,
+    /// First line of the page to read, counting from 1
+    pub start_line: Option<u64>,
+    pub lines: Option<u64>,
+    /// Decode with this encoding instead of the detected one
+    pub encoding: Option<String>,
+    /// A hex dump even of a text file
+    #[serde(default)]
+    pub hex: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileContentResponse {
+    pub path: String,
+    /// To send back when saving, also given as the `ETag` header
+    pub etag: String,
+    #[serde(flatten)]
+    pub page: Page,
+}
+
+pub fn router() -> Router<AppState> {
+    let read = Router::new()
+        .route("/", get(list_files))
+        .route("/download", get(download::download_file))
+        .route("/content", get(read_file))
+        .route("/search", get(search::search))
+        .route("/trash", get(trash::list_trash))
+        .route("/usage", get(usage::disk_usage))
+        .route("/versions", get(versions::list_versions))
+        .route("/versions/{id}/diff", get(versions::diff_version))
+        .route_layer(from_fn_with_state(Permission::FilesRead, require_permission));
+
+    let write = Router::new()
+        .route("/", delete(delete_path))
+        .route("/upload", post(upload_file))
+        .route("/uploads", post(upload::create_upload))
+        .route(
+            "/uploads/{id}",
+            get(upload::upload_status).patch(upload::write_chunk).delete(upload::cancel_upload),
+        )
+        .route("/uploads/{id}/complete", post(upload::complete_upload))
+        .route("/content", put(write_file))
+        .route("/permissions", patch(set_permissions))
+        .route("/mkdir", post(create_directory))
+        .route("/compress", post(archive::compress))
+        .route("/extract", post(archive::extract))
+        .route("/copy", post(transfer::copy))
+        .route("/move", post(transfer::move_paths))
+        .route("/rename", post(transfer::rename))
+        .route("/replace", post(search::replace))
+        .route("/trash", delete(trash::empty_trash))
+        .route("/trash/{id}", delete(trash::purge_item))
+        .route("/trash/{id}/restore", post(trash::restore_item))
+        .route("/versions/{id}/restore", post(versions::restore_version))
+        .route_layer(from_fn_with_state(Permission::FilesWrite, require_permission));
+
+    read.merge(write)
+}
+
+async fn list_files(
+    jail: Jail,
+    Query(query): Query<PathQuery>,
+) -> AppResult<Json<Vec<FileEntry>>> {
+    let path = jail.resolve(&query.path)?;
+    
+    if !path.exists() {
+        return Err(AppError::NotFound(format!("Path not found: {}", query.path)));
+    }
+    
+    if !path.is_dir() {
+        return Err(AppError::Validation("Path is not a directory".to_string()));
+    }
+    
+    let mut entries = Vec::new();
+    let mut accounts = Accounts::default();
+    let mut dir = fs::read_dir(&path).await?;
+    
+    while let Some(entry) = dir.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        let file_type = metadata.file_type();
+        let (owner, group) = accounts.owner(&metadata);
+        
+        entries.push(FileEntry {
+            name: entry.file_name().to_string_lossy().to_string(),
+            path: entry.path().to_string_lossy().to_string(),
+            is_dir: file_type.is_dir(),
+            size: metadata.len(),
+            modified: metadata
+                .modified()
+                .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
+                .unwrap_or(0),
+            permissions: format!("{:o}", metadata.permissions().mode() & 0o7777),
+            symbolic: attributes::symbolic_mode(&metadata),
+            owner,
+            group,
+        });
+    }
+    
+    // Sort: directories first, then alphabetically
+    entries.sort_by(|a, b| {
+        match (a.is_dir, b.is_dir) {
+            (true, false) => std::cmp::Ordering::Less,
+            (false, true) => std::cmp::Ordering::Greater,
+            _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+        }
+    });
+    
+    Ok(Json(entries))
+}
+
+/// Upload a small file in a single multipart request. Large files go through
+/// the resumable `/uploads` instead.
+async fn upload_file(
+    State(state): State<AppState>,
+    jail: Jail,
+    audit: Audit,
+    mut multipart: Multipart,
+) -> AppResult<Json<serde_json::Value>> {
+    let mut target_path: Option<String> = None;
+    
+    while let Some(mut field) = multipart.next_field().await.map_err(|e| AppError::Validation(e.to_string()))? {
+        let name = field.name().unwrap_or_default().to_string();
+        
+        if name == "path" {
+            target_path = Some(field.text().await.map_err(|e| AppError::Validation(e.to_string()))?);
+        } else if name == "file" {
+            let path = target_path.as_ref()
+                .ok_or_else(|| AppError::Validation("Path must be provided before file".to_string()))?;
+            
+            let file_path = jail.resolve(path)?;
+            // Streamed next to the destination and renamed into place, so
+            // nobody sees a half-written file
+            let temp = file_path.sibling(format!(".mana-upload-{}.part", uuid::Uuid::new_v4()))?;
+            let size = match receive(&mut field, &temp, state.config.upload_max_bytes).await {
+                Ok(size) => size,
+                Err(e) => {
+                    temp.remove().ok();
+                    return Err(e);
+                }
+            };
+            
+            let params = json!({ "size": size });
+            return audit.run("file.upload", path, params, async {
+                if let Err(e) = temp.rename(&file_path, RenameMode::Replace) {
+                    temp.remove().ok();
+                    return Err(e.into());
+                }
+                
+                Ok(Json(serde_json::json!({
+                    "success": true,
+                    "path": file_path.to_string_lossy()
+                })))
+            }).await;
+        }
+    }
+    
+    Err(AppError::Validation("No file provided".to_string()))
+}
+
+/// Write a multipart field to a new file at `temp`, refusing anything over
+/// `limit` bytes. Returns the number of bytes written.
+async fn receive(field: &mut Field<'_>, temp: &Resolved, limit: u64) -> AppResult<u64> {
+    let mut file = fs::File::from_std(temp.create_new()?);
+    let mut size = 0;
+    while let Some(chunk) = field.chunk().await.map_err(|e| AppError::Validation(e.to_string()))? {
+        size += chunk.len() as u64;
+        if size > limit {
+            return Err(AppError::Validation(format!("Uploads are limited to {} bytes", limit)));
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+    Ok(size)
+}
+
+async fn read_file(
+    State(state): State<AppState>,
+    jail: Jail,
+    Query(query): Query<ContentQuery>,
+) -> AppResult<([(header::HeaderName, String); 1], Json<FileContentResponse>)> {
+    let path = jail.resolve(&query.path)?;
+    
+    if !path.exists() {
+        return Err(AppError::NotFound(format!("File not found: {}", query.path)));
+    }
+    
+    if !path.is_file() {
+        return Err(AppError::Validation("Path is not a file".to_string()));
+    }
+    
+    let request = PageRequest {
+        offset: query.offset,
+        length: query.length,
+        start_line: query.start_line,
+        lines: query.lines,
+        encoding: query.encoding,
+        hex: query.hex,
+    };
+    let edit_max_bytes = state.config.edit_max_bytes;
+    let (etag, page) = tokio::task::spawn_blocking(move || {
+        let mut file = path.open()?;
+        let etag = files::etag(&file.metadata()?);
+        Ok::<_, AppError>((etag, text::read_page(&mut file, &request, edit_max_bytes)?))
+    })
+    .await
+    .map_err(|e| AppError::Internal(e.into()))??;
+    
+    Ok((
+        [(header::ETAG, etag.clone())],
+        Json(FileContentResponse { path: query.path, etag, page }),
+    ))
+}
+
+async fn write_file(
+    State(state): State<AppState>,
+    jail: Jail,
+    audit: Audit,
+    claims: Claims,
+    headers: HeaderMap,
+    Json(payload): Json<FileContentRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    let if_match = headers.get(header::IF_MATCH).and_then(|value| value.to_str().ok());
+    let etag = payload.etag.as_deref().or(if_match);
+    let params = json!({ "size": payload.content.len() });
+    audit.run("file.write", &payload.path, params, async {
+        let path = jail.resolve(&payload.path)?;
+        let encoding = payload.encoding.as_deref();
+        let content = text::encode(&payload.content, encoding, payload.bom, payload.line_ending)?;
+        if content.len() as u64 > state.config.edit_max_bytes {
+            return Err(AppError::Validation(format!(
+                "Files larger than {} bytes can't be saved from the editor",
+                state.config.edit_max_bytes
+            )));
+        }
+        
+        let author = Author::from(&claims);
+        let etag =
+            state.versions.write(&state.db, &state.config, &path, content, etag, author).await?;
+        
+        Ok(Json(serde_json::json!({
+            "success": true,
+            "path": &payload.path,
+            "etag": etag
+        })))
+    }).await
+}
+
+async fn set_permissions(
+    jail: Jail,
+    audit: Audit,
+    Json(payload): Json<PermissionRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    let params = json!({
+        "mode": payload.mode,
+        "owner": payload.owner,
+        "group": payload.group,
+        "recursive": payload.recursive,
+        "file_mode": payload.file_mode,
+        "dir_mode": payload.dir_mode,
+    });
+    audit.run("file.chmod", &payload.path, params, apply_permissions(&jail, &payload)).await
+}
+
+async fn apply_permissions(
+    jail: &Jail,
+    payload: &PermissionRequest,
+) -> AppResult<Json<serde_json::Value>> {
+    let path = jail.resolve(&payload.path)?;
+    
+    if !path.exists() {
+        return Err(AppError::NotFound(format!("Path not found: {}", payload.path)));
+    }
+    
+    let mode = |mode: &Option<String>| mode.as_deref().map(attributes::parse_mode).transpose();
+    let change = AttributeChange {
+        mode: mode(&payload.mode)?,
+        file_mode: mode(&payload.file_mode)?,
+        dir_mode: mode(&payload.dir_mode)?,
+        uid: payload.owner.as_deref().map(attributes::lookup_user).transpose()?,
+        gid: payload.group.as_deref().map(attributes::lookup_group).transpose()?,
+    };
+    
+    let (jail, recursive) = (jail.clone(), payload.recursive);
+    let summary = tokio::task::spawn_blocking(move || {
+        attributes::apply(&path, &change, recursive, &jail)
+    })
+    .await
+    .map_err(|e| AppError::Internal(e.into()))??;
+    
+    Ok(Json(serde_json::json!({
+        "success": summary.failed == 0,
+        "path": &payload.path,
+        "changed": summary.changed,
+        "failed": summary.failed,
+        "errors": summary.errors
+    })))
+}
+
+async fn create_directory(
+    jail: Jail,
+    audit: Audit,
+    Json(payload): Json<MkdirRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    audit.run("file.mkdir", &payload.path, json!({}), async {
+        let path = jail.resolve(&payload.path)?;
+        
+        path.create_dir_all()?;
+        
+        Ok(Json(serde_json::json!({
+            "success": true,
+            "path": &payload.path
+        })))
+    }).await
+}
+
+/// Move a path to the trash, or with `permanent` delete it right away
+async fn delete_path(
+    State(state): State<AppState>,
+    jail: Jail,
+    audit: Audit,
+    claims: Claims,
+    Query(query): Query<DeleteQuery>,
+) -> AppResult<Json<serde_json::Value>> {
+    let params = json!({ "permanent": query.permanent });
+    let run = remove_path(&state, &jail, &claims, &query);
+    audit.run("file.delete", &query.path, params, run).await
+}
+
+async fn remove_path(
+    state: &AppState,
+    jail: &Jail,
+    claims: &Claims,
+    query: &DeleteQuery,
+) -> AppResult<Json<serde_json::Value>> {
+    // A symlink is removed itself, not whatever it points to
+    let path = jail.resolve_link(&query.path)?;
+    jail.check_tree(&path)?;
+    
+    path.symlink_metadata().map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => {
+            AppError::NotFound(format!("Path not found: {}", query.path))
+        }
+        _ => e.into(),
+    })?;
+    
+    if !query.permanent {
+        let user_id = claims.user_id().ok();
+        let username = Some(claims.username.clone());
+        let item = TrashService::trash(&state.db, &state.config, &path, user_id, username).await?;
+        return Ok(Json(serde_json::json!({
+            "success": true,
+            "path": &query.path,
+            "trash_id": item.id
+        })));
+    }
+    
+    tokio::task::spawn_blocking(move || path.remove())
+        .await
+        .map_err(|e| AppError::Internal(e.into()))??;
+    
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "path": &query.path
+    })))
+}
+
+// Trait extension for file permissions (Unix-only)
+#[cfg(unix)]
+trait PermissionsExt {
+    fn mode(&self) -> u32;
+}
+
+#[cfg(unix)]
+impl PermissionsExt for std::fs::Permissions {
+    fn mode(&self) -> u32 {
+        std::os::unix::fs::PermissionsExt::mode(self)
+    }
+}
+
+#[cfg(not(unix))]
+trait PermissionsExt {
+    fn mode(&self) -> u32;
+}
+
+#[cfg(not(unix))]
+impl PermissionsExt for std::fs::Permissions {
+    fn mode(&self) -> u32 {
+        0o755 // Default for non-Unix
+    }
+}
@@ -0,0 +1,249 @@
+use axum::{
+    extract::{Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+
+use crate::{
+    error::{AppError, AppResult},
+    middleware::{audit::Audit, auth::Claims},
+    services::{
+        jail::Jail,
+        search::{self, Matcher, Search, SearchRequest},
+        versions::Author,
+    },
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ReplaceFile {
+    pub path: String,
+    /// The hash the preview reported for the file
+    pub sha256: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReplaceRequest {
+    pub content: String,
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub ignore_case: bool,
+    pub replace: String,
+    /// Files picked from the preview
+    pub files: Vec<ReplaceFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplaceResult {
+    pub path: String,
+    pub replacements: usize,
+    pub error: Option<String>,
+}
+
+/// Search from a root down, streaming every match as a `match` event and a
+/// closing `done` event with the summary. With `replace` set this is the
+/// preview of a find-and-replace: matching lines come with their
+/// replacement and files with the hash to apply it with.
+pub(super) async fn search(
+    jail: Jail,
+    Query(request): Query<SearchRequest>,
+) -> AppResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    let search = Search::new(&request)?;
+    let root = jail.resolve(&request.root)?;
+    if !root.is_dir() {
+        return Err(AppError::NotFound(format!("Directory not found: {}", request.root)));
+    }
+
+    let (sender, receiver) = tokio::sync::mpsc::channel(64);
+    tokio::task::spawn_blocking(move || {
+        // Sending fails once the client has gone, which ends the search
+        let summary = search.run(&root, &jail, |found| {
+            let json = serde_json::to_string(&found).unwrap_or_default();
+            sender.blocking_send(Event::default().event("match").data(json)).is_ok()
+        });
+        let json = serde_json::to_string(&summary).unwrap_or_default();
+        sender.blocking_send(Event::default().event("done").data(json)).ok();
+    });
+
+    let stream = ReceiverStream::new(receiver).map(Ok);
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Apply a find-and-replace to the files of a preview. A file changed since
+/// the preview is left alone and reported as failed. Files are saved the way
+/// the editor saves them, keeping what they held before as a version.
+pub(super) async fn replace(
+    State(state): State<AppState>,
+    jail: Jail,
+    audit: Audit,
+    claims: Claims,
+    Json(payload): Json<ReplaceRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    let paths: Vec<&str> = payload.files.iter().map(|file| file.path.as_str()).collect();
+    let params = json!({
+        "content": &payload.content,
+        "regex": payload.regex,
+        "replace": &payload.replace,
+    });
+    audit.run("file.replace", &paths.join(", "), params, async {
+        if payload.files.len() > search::MAX_RESULTS {
+            return Err(AppError::Validation(format!(
+                "At most {} files can be changed at once",
+                search::MAX_RESULTS
+            )));
+        }
+        let matcher = Matcher::new(&payload.content, payload.regex, payload.ignore_case)?;
+        let matcher = Arc::new(matcher);
+
+        let mut results = Vec::with_capacity(payload.files.len());
+        for file in &payload.files {
+            let author = Author::from(&claims);
+            let outcome = replace_file(&state, &jail, &matcher, &payload.replace, file, author).await;
+            results.push(ReplaceResult {
+                path: file.path.clone(),
+                replacements: *outcome.as_ref().unwrap_or(&0),
+                error: outcome.err().map(|e| e.to_string()),
+            });
+        }
+
+        let replacements: usize = results.iter().map(|result| result.replacements).sum();
+        let failed = results.iter().filter(|result| result.error.is_some()).count();
+        Ok(Json(json!({
+            "files": results,
+            "replacements": replacements,
+            "failed": failed,
+        })))
+    })
+    .await
+}
+
+/// Replace the matches in one file and save it through the versions, with
+/// the ETag it had when it was read. Returns how many there were.
+async fn replace_file(
+    state: &AppState,
+    jail: &Jail,
+    matcher: &Arc<Matcher>,
+    replacement: &str,
+    file: &ReplaceFile,
+    author: Author,
+) -> AppResult<usize> {
+    let path = jail.resolve(&file.path)?;
+    let (source, matcher) = (path.try_clone()?, matcher.clone());
+    let (replacement, sha256) = (replacement.to_string(), file.sha256.clone());
+    let replaced = tokio::task::spawn_blocking(move || {
+        search::replace_in_file(&source, &matcher, &replacement, &sha256)
+    })
+    .await
+    .map_err(|e| AppError::Internal(e.into()))??;
+    if replaced.count == 0 {
+        return Ok(0);
+    }
+
+    let (content, etag) = (replaced.content.into_bytes(), Some(replaced.etag.as_str()));
+    state.versions.write(&state.db, &state.config, &path, content, etag, author).await?;
+    Ok(replaced.count)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+    use tower::ServiceExt;
+
+    use crate::services::jail::JailSettings;
+    use crate::services::versions::VersionService;
+    use crate::test_util::{admin_token, body_json, test_app_with_state};
+
+    #[tokio::test]
+    async fn test_search_streams_matches_inside_the_jail() {
+        let (app, state) = test_app_with_state().await;
+        let token = admin_token(&state).await;
+        let dir = std::env::temp_dir().join(format!("mana-search-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("secret")).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "needle\n").unwrap();
+        std::fs::write(dir.join("secret/b.txt"), "needle\n").unwrap();
+        let settings = JailSettings {
+            roots: vec![dir.to_string_lossy().to_string()],
+            denied: vec![dir.join("secret").to_string_lossy().to_string()],
+            ..Default::default()
+        };
+        state.jail.update(&state.db, settings).await.unwrap();
+
+        let get = |query: String| {
+            let req = Request::get(format!("/api/files/search?{}", query))
+                .header("Authorization", &token)
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let res = get("root=/&name=*.txt".to_string()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = get(format!("root={}&content=(", dir.display())).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = get(format!("root={}&content=(&regex=true", dir.display())).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = get(format!("root={}&content=needle", dir.display())).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-type"], "text/event-stream");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let events: Vec<&str> = body.lines().filter(|line| line.starts_with("event:")).collect();
+        assert_eq!(events, ["event: match", "event: done"]);
+        assert!(body.contains("a.txt") && !body.contains("b.txt"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replace_saves_versions() {
+        let (app, state) = test_app_with_state().await;
+        let token = admin_token(&state).await;
+        let dir = std::env::temp_dir().join(format!("mana-replace-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        let (kept, changed) = (dir.join("a.conf"), dir.join("b.conf"));
+        std::fs::write(&kept, "host = old\n").unwrap();
+        std::fs::write(&changed, "host = old\n").unwrap();
+        let sha256 = data_encoding::HEXLOWER.encode(&Sha256::digest(b"host = old\n"));
+        // Changed after the preview
+        std::fs::write(&changed, "host = old\nport = 80\n").unwrap();
+
+        let body = json!({
+            "content": "old",
+            "replace": "new",
+            "files": [
+                { "path": &kept, "sha256": &sha256 },
+                { "path": &changed, "sha256": &sha256 },
+            ],
+        });
+        let req = Request::post("/api/files/replace")
+            .header("Authorization", &token)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = body_json(res).await;
+        assert_eq!((body["replacements"].as_u64(), body["failed"].as_u64()), (Some(1), Some(1)));
+
+        assert_eq!(std::fs::read_to_string(&kept).unwrap(), "host = new\n");
+        assert_eq!(std::fs::read_to_string(&changed).unwrap(), "host = old\nport = 80\n");
+        let versions = VersionService::list(&state.db, &kept).await.unwrap();
+        assert_eq!(versions.len(), 1);
+        let content = VersionService::content(&state.config, &versions[0]).await.unwrap();
+        assert_eq!(content, b"host = old\n");
+        assert!(VersionService::list(&state.db, &changed).await.unwrap().is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    error::{AppError, AppResult},
+    middleware::{audit::Audit, auth::Claims},
+    services::{
+        files::ConflictPolicy,
+        jail::{Jail, Resolved},
+        jobs::JobInfo,
+        transfer::{self, ItemResult, TransferKind},
+        trash::TrashService,
+    },
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct TransferRequest {
+    pub paths: Vec<String>,
+    /// Directory the paths go into, under their own names
+    pub destination: String,
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameItem {
+    pub path: String,
+    /// New name in the same directory
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameRequest {
+    pub items: Vec<RenameItem>,
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+}
+
+/// Start a job that copies paths into a directory
+pub(super) async fn copy(
+    state: State<AppState>,
+    jail: Jail,
+    audit: Audit,
+    claims: Claims,
+    payload: Json<TransferRequest>,
+) -> AppResult<(StatusCode, Json<JobInfo>)> {
+    start(TransferKind::Copy, state, jail, audit, claims, payload).await
+}
+
+/// Start a job that moves paths into a directory
+pub(super) async fn move_paths(
+    state: State<AppState>,
+    jail: Jail,
+    audit: Audit,
+    claims: Claims,
+    payload: Json<TransferRequest>,
+) -> AppResult<(StatusCode, Json<JobInfo>)> {
+    start(TransferKind::Move, state, jail, audit, claims, payload).await
+}
+
+async fn start(
+    kind: TransferKind,
+    State(state): State<AppState>,
+    jail: Jail,
+    audit: Audit,
+    claims: Claims,
+    Json(payload): Json<TransferRequest>,
+) -> AppResult<(StatusCode, Json<JobInfo>)> {
+    let (action, job_kind, verb) = match kind {
+        TransferKind::Copy => ("file.copy", "copy", "Copy"),
+        TransferKind::Move => ("file.move", "move", "Move"),
+    };
+    let params = json!({ "paths": &payload.paths, "conflict": payload.conflict });
+    audit.run(action, &payload.destination, params, async {
+        if payload.paths.is_empty() {
+            return Err(AppError::Validation(format!("Nothing to {}", job_kind)));
+        }
+        let destination = jail.resolve(&payload.destination)?;
+        if !destination.is_dir() {
+            return Err(AppError::NotFound(format!(
+                "Directory not found: {}",
+                payload.destination
+            )));
+        }
+
+        let description = match payload.paths.as_slice() {
+            [path] => format!("{} {} into {}", verb, path, destination.display()),
+            paths => format!("{} {} items into {}", verb, paths.len(), destination.display()),
+        };
+        let (paths, conflict, jail) = (payload.paths.clone(), payload.conflict, jail.clone());
+        let user_id = claims.user_id()?;
+        let username = claims.username.clone();
+        let runtime = tokio::runtime::Handle::current();
+        let job = state.jobs.spawn_blocking(user_id, job_kind, description, move |handle| {
+            // Directories that get overwritten go to the trash
+            let displace = |target: &Resolved| {
+                let (db, config, username) = (&state.db, &state.config, Some(username.clone()));
+                let trash = TrashService::trash(db, config, target, Some(user_id), username);
+                runtime.block_on(trash).map(|_| ())
+            };
+            transfer::transfer(kind, &paths, &destination, conflict, &jail, &displace, handle)
+        });
+        Ok((StatusCode::ACCEPTED, Json(job)))
+    })
+    .await
+}
+
+/// Rename paths within their directories. Renames are quick, so unlike copy
+/// and move this answers with the result of every item right away.
+pub(super) async fn rename(
+    jail: Jail,
+    audit: Audit,
+    Json(payload): Json<RenameRequest>,
+) -> AppResult<Json<Value>> {
+    let paths: Vec<&str> = payload.items.iter().map(|item| item.path.as_str()).collect();
+    let names: Vec<&str> = payload.items.iter().map(|item| item.name.as_str()).collect();
+    let params = json!({ "names": names, "conflict": payload.conflict });
+    audit.run("file.rename", &paths.join(", "), params, async {
+        if payload.items.is_empty() {
+            return Err(AppError::Validation("Nothing to rename".to_string()));
+        }
+        let conflict = payload.conflict;
+        let items: Vec<(String, String)> =
+            payload.items.iter().map(|item| (item.path.clone(), item.name.clone())).collect();
+        let results = tokio::task::spawn_blocking(move || {
+            items
+                .iter()
+                .map(|(path, name)| {
+                    ItemResult::new(path, transfer::rename(&jail, path, name, conflict))
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+        Ok(Json(transfer::summary(&results)))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::services::jobs::JobStatus;
+    use crate::test_util::{admin_token, body_json, test_app_with_state};
+
+    #[tokio::test]
+    async fn test_rename_and_move_batches() {
+        let (app, state) = test_app_with_state().await;
+        let token = admin_token(&state).await;
+        let dir = std::env::temp_dir().join(format!("mana-transfer-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("archive")).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+
+        let post = |uri: &str, body: Value| {
+            let req = Request::post(uri)
+                .header("Authorization", &token)
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let body = json!({
+            "items": [
+                { "path": dir.join("a.txt"), "name": "b.txt" },
+                { "path": dir.join("c.txt"), "name": "../c.txt" },
+                { "path": dir.join("c.txt"), "name": "d.txt" },
+            ],
+            "conflict": "fail",
+        });
+        let res = post("/api/files/rename", body).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let result = body_json(res).await;
+        assert_eq!(result["failed"], 2);
+        assert_eq!(result["items"][0]["status"], "failed");
+        assert_eq!(result["items"][2]["status"], "done");
+        assert!(dir.join("d.txt").exists() && dir.join("a.txt").exists());
+
+        let body = json!({
+            "paths": [dir.join("a.txt"), dir.join("b.txt")],
+            "destination": dir.join("missing"),
+        });
+        let res = post("/api/files/move", body).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let body = json!({
+            "paths": [dir.join("a.txt"), dir.join("b.txt")],
+            "destination": dir.join("archive"),
+        });
+        let res = post("/api/files/move", body).await.unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let job = body_json(res).await;
+        let info = state.jobs.wait(job["id"].as_str().unwrap(), 1).await;
+        assert_eq!(info.status, JobStatus::Completed);
+        assert_eq!(info.result.unwrap()["done"], 2);
+        assert_eq!(std::fs::read_to_string(dir.join("archive/b.txt")).unwrap(), "b.txt");
+        assert!(!dir.join("a.txt").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    db::entities::trash_item,
+    error::AppResult,
+    middleware::{audit::Audit, auth::Claims},
+    services::{files::ConflictPolicy, jail::Jail, trash::TrashService},
+    AppState,
+};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RestoreRequest {
+    /// What to do when something new is where the item was; fails by default
+    pub conflict: Option<ConflictPolicy>,
+}
+
+/// Trash items deleted from inside the caller's jail
+pub(super) async fn list_trash(
+    State(state): State<AppState>,
+    jail: Jail,
+) -> AppResult<Json<Vec<trash_item::Model>>> {
+    let items = TrashService::list(&state.db, &state.config).await?;
+    Ok(Json(
+        items
+            .into_iter()
+            .filter(|item| jail.resolve_link(&item.original_path).is_ok())
+            .collect(),
+    ))
+}
+
+/// Move an item back to where it was deleted from. Overwriting a directory
+/// there puts that directory in the trash in turn.
+pub(super) async fn restore_item(
+    State(state): State<AppState>,
+    jail: Jail,
+    audit: Audit,
+    claims: Claims,
+    Path(id): Path<i32>,
+    payload: Option<Json<RestoreRequest>>,
+) -> AppResult<Json<serde_json::Value>> {
+    let Json(payload) = payload.unwrap_or_default();
+    let conflict = payload.conflict.unwrap_or(ConflictPolicy::Fail);
+    let item = TrashService::get(&state.db, id).await?;
+
+    let params = json!({ "id": id, "conflict": conflict });
+    audit.run("file.trash.restore", &item.original_path, params, async {
+        let target = jail.resolve_link(&item.original_path)?;
+        if conflict == ConflictPolicy::Overwrite && item.is_dir && target.is_dir() {
+            jail.check_tree(&target)?;
+            let (user_id, username) = (claims.user_id().ok(), Some(claims.username.clone()));
+            TrashService::trash(&state.db, &state.config, &target, user_id, username).await?;
+        }
+        let placed = TrashService::restore(&state.db, &state.config, &item, &target, conflict)
+            .await?;
+        Ok(Json(json!({
+            "success": true,
+            "skipped": placed.is_none(),
+            "path": placed,
+        })))
+    })
+    .await
+}
+
+/// Delete an item for good
+pub(super) async fn purge_item(
+    State(state): State<AppState>,
+    jail: Jail,
+    audit: Audit,
+    Path(id): Path<i32>,
+) -> AppResult<Json<serde_json::Value>> {
+    let item = TrashService::get(&state.db, id).await?;
+
+    audit.run("file.trash.purge", &item.original_path, json!({ "id": id }), async {
+        jail.resolve_link(&item.original_path)?;
+        TrashService::purge(&state.db, &state.config, &item).await?;
+        Ok(Json(json!({ "success": true })))
+    })
+    .await
+}
+
+/// Purge every item the caller can see
+pub(super) async fn empty_trash(
+    State(state): State<AppState>,
+    jail: Jail,
+    audit: Audit,
+) -> AppResult<Json<serde_json::Value>> {
+    audit.run("file.trash.empty", "", json!({}), async {
+        let mut purged = 0;
+        for item in TrashService::list(&state.db, &state.config).await? {
+            if jail.resolve_link(&item.original_path).is_ok() {
+                TrashService::purge(&state.db, &state.config, &item).await?;
+                purged += 1;
+            }
+        }
+        Ok(Json(json!({ "success": true, "purged": purged })))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use crate::test_util::{admin_token, body_json, test_app_with_state};
+
+    #[tokio::test]
+    async fn test_delete_goes_to_trash() {
+        let (app, state) = test_app_with_state().await;
+        let token = admin_token(&state).await;
+        let dir = std::env::temp_dir().join(format!("mana-delete-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("site")).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        std::fs::write(dir.join("site/index.html"), "<h1>hi</h1>").unwrap();
+        std::fs::write(dir.join("notes.txt"), "notes").unwrap();
+
+        let send = |method: Method, uri: String| {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", &token)
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let site = dir.join("site");
+        let res = send(Method::DELETE, format!("/api/files?path={}", site.display())).await;
+        let res = res.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let id = body_json(res).await["trash_id"].as_i64().unwrap();
+        assert!(!site.exists());
+
+        let notes = dir.join("notes.txt");
+        let uri = format!("/api/files?path={}&permanent=true", notes.display());
+        assert_eq!(send(Method::DELETE, uri).await.unwrap().status(), StatusCode::OK);
+        assert!(!notes.exists());
+
+        let res = send(Method::GET, "/api/files/trash".to_string()).await.unwrap();
+        let items = body_json(res).await;
+        assert_eq!(items.as_array().unwrap().len(), 1);
+        assert_eq!(items[0]["original_path"], site.to_string_lossy().as_ref());
+        assert_eq!(items[0]["deleted_by_username"], "admin");
+        assert!(items[0].get("stored_name").is_none());
+
+        let res = send(Method::POST, format!("/api/files/trash/{}/restore", id)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(std::fs::read_to_string(site.join("index.html")).unwrap(), "<h1>hi</h1>");
+        let res = send(Method::DELETE, format!("/api/files/trash/{}", id)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(&state.config.trash_dir).unwrap();
+    }
+}
@@ -0,0 +1,364 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{HeaderMap, HeaderName, StatusCode},
+    response::{AppendHeaders, IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    error::{AppError, AppResult},
+    middleware::{audit::Audit, auth::Claims},
+    services::{files::ConflictPolicy, jail::Jail, upload::Upload},
+    AppState,
+};
+
+/// Bytes received so far, as in the tus protocol
+pub const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+/// Final size of the upload
+pub const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUploadRequest {
+    pub path: String,
+    pub size: u64,
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CompleteUploadRequest {
+    /// Hex SHA-256 of the whole file, checked before it is moved into place
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadInfo {
+    pub id: String,
+    pub path: String,
+    pub size: u64,
+    pub offset: u64,
+    pub conflict: ConflictPolicy,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadResult {
+    /// Where the file ended up, which differs from the requested path after
+    /// a rename; `None` if it was skipped
+    pub path: Option<String>,
+    pub skipped: bool,
+}
+
+impl From<&Upload> for UploadInfo {
+    fn from(upload: &Upload) -> Self {
+        Self {
+            id: upload.id.clone(),
+            path: upload.path.clone(),
+            size: upload.size,
+            offset: upload.offset(),
+            conflict: upload.conflict,
+            created_at: upload.created_at,
+        }
+    }
+}
+
+/// The upload as JSON, with its progress also in the tus headers
+fn upload_response(upload: &Upload) -> Response {
+    let headers = AppendHeaders([
+        (UPLOAD_OFFSET, upload.offset().to_string()),
+        (UPLOAD_LENGTH, upload.size.to_string()),
+    ]);
+    (headers, Json(UploadInfo::from(upload))).into_response()
+}
+
+pub(super) async fn create_upload(
+    State(state): State<AppState>,
+    jail: Jail,
+    claims: Claims,
+    Json(payload): Json<CreateUploadRequest>,
+) -> AppResult<Response> {
+    if payload.size > state.config.upload_max_bytes {
+        return Err(AppError::Validation(format!(
+            "Uploads are limited to {} bytes",
+            state.config.upload_max_bytes
+        )));
+    }
+    let target = jail.resolve(&payload.path)?;
+
+    let upload = state
+        .uploads
+        .create(claims.user_id()?, &payload.path, &target, payload.size, payload.conflict)
+        .await?;
+
+    Ok((StatusCode::CREATED, upload_response(&upload)).into_response())
+}
+
+/// Where the upload stands; a `HEAD` request gets the same headers, which is
+/// all a client resuming an upload needs
+pub(super) async fn upload_status(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> AppResult<Response> {
+    let upload = state.uploads.get(&id, claims.user_id()?)?;
+    Ok(upload_response(&upload))
+}
+
+/// Receive the request body as the chunk starting at the `Upload-Offset`
+/// header
+pub(super) async fn write_chunk(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> AppResult<Response> {
+    let upload = state.uploads.get(&id, claims.user_id()?)?;
+    let offset = headers
+        .get(UPLOAD_OFFSET)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| AppError::Validation("Upload-Offset header required".to_string()))?;
+
+    state.uploads.write_chunk(&upload, offset, body).await?;
+    Ok(upload_response(&upload))
+}
+
+pub(super) async fn complete_upload(
+    State(state): State<AppState>,
+    jail: Jail,
+    audit: Audit,
+    claims: Claims,
+    Path(id): Path<String>,
+    payload: Option<Json<CompleteUploadRequest>>,
+) -> AppResult<Json<UploadResult>> {
+    let upload = state.uploads.get(&id, claims.user_id()?)?;
+    let Json(payload) = payload.unwrap_or_default();
+
+    let params = json!({
+        "size": upload.size,
+        "conflict": upload.conflict,
+        "sha256": &payload.sha256,
+    });
+    audit.run("file.upload", &upload.path, params, async {
+        let target = jail.resolve(&upload.path)?;
+        let placed = state.uploads.complete(&upload, &target, payload.sha256.as_deref()).await?;
+
+        Ok(Json(UploadResult {
+            skipped: placed.is_none(),
+            path: placed.map(|path| path.to_string_lossy().to_string()),
+        }))
+    })
+    .await
+}
+
+pub(super) async fn cancel_upload(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> AppResult<StatusCode> {
+    let upload = state.uploads.get(&id, claims.user_id()?)?;
+    state.uploads.cancel(&upload).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, Request};
+    use sha2::{Digest, Sha256};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::services::{rbac::ROLE_ADMIN, upload::Uploads, user::UserService};
+    use crate::test_util::{admin_token, bearer_token, body_json, test_app_with_state};
+
+    #[tokio::test]
+    async fn test_resumable_upload() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let other = UserService::create_user(&state.db, "other", "test-password", ROLE_ADMIN, false)
+            .await
+            .unwrap();
+        let other = bearer_token(&state, other.id, &other.username, ROLE_ADMIN).await;
+        let dir = std::env::temp_dir().join(format!("mana-upload-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("a.txt");
+        std::fs::write(&target, "existing").unwrap();
+
+        let send = |method: Method, uri: String, token: &str, body: Body, offset: Option<u64>| {
+            let mut req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", token)
+                .header("Content-Type", "application/json");
+            if let Some(offset) = offset {
+                req = req.header(UPLOAD_OFFSET, offset);
+            }
+            app.clone().oneshot(req.body(body).unwrap())
+        };
+        let create = |size: u64| {
+            let body = json!({ "path": target, "size": size, "conflict": "rename" });
+            let body = Body::from(body.to_string());
+            send(Method::POST, "/api/files/uploads".to_string(), &admin, body, None)
+        };
+
+        let res = create(2 * 1024 * 1024).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = create(11).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let id = body_json(res).await["id"].as_str().unwrap().to_string();
+        let uri = format!("/api/files/uploads/{}", id);
+
+        let res = send(Method::PATCH, uri.clone(), &admin, Body::from("hello "), Some(0));
+        let res = res.await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[UPLOAD_OFFSET], "6");
+
+        // A retried chunk that already arrived is refused, the client resumes
+        // from the offset the upload reports
+        let res = send(Method::PATCH, uri.clone(), &admin, Body::from("hello "), Some(0));
+        assert_eq!(res.await.unwrap().status(), StatusCode::CONFLICT);
+        let res = send(Method::HEAD, uri.clone(), &admin, Body::empty(), None).await.unwrap();
+        assert_eq!(res.headers()[UPLOAD_OFFSET], "6");
+        assert_eq!(res.headers()[UPLOAD_LENGTH], "11");
+
+        // Other users can't see it
+        let res = send(Method::GET, uri.clone(), &other, Body::empty(), None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let complete = format!("{}/complete", uri);
+        let res = send(Method::POST, complete.clone(), &admin, Body::empty(), None);
+        assert_eq!(res.await.unwrap().status(), StatusCode::BAD_REQUEST);
+
+        let res = send(Method::PATCH, uri.clone(), &admin, Body::from("world!"), Some(6));
+        assert_eq!(res.await.unwrap().status(), StatusCode::BAD_REQUEST);
+        let res = send(Method::PATCH, uri.clone(), &admin, Body::from("world"), Some(6));
+        assert_eq!(res.await.unwrap().status(), StatusCode::OK);
+
+        let sha256 = data_encoding::HEXLOWER.encode(&Sha256::digest(b"hello world"));
+        let body = Body::from(json!({ "sha256": sha256 }).to_string());
+        let res = send(Method::POST, complete.clone(), &admin, body, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let result = body_json(res).await;
+        assert_eq!(result["path"], dir.join("a (1).txt").to_string_lossy().as_ref());
+        assert_eq!(std::fs::read_to_string(dir.join("a (1).txt")).unwrap(), "hello world");
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "existing");
+
+        // A corrupted upload is discarded
+        let res = create(3).await.unwrap();
+        let id = body_json(res).await["id"].as_str().unwrap().to_string();
+        let uri = format!("/api/files/uploads/{}", id);
+        let res = send(Method::PATCH, uri.clone(), &admin, Body::from("abc"), Some(0));
+        assert_eq!(res.await.unwrap().status(), StatusCode::OK);
+        let body = Body::from(json!({ "sha256": sha256 }).to_string());
+        let res = send(Method::POST, format!("{}/complete", uri), &admin, body, None);
+        assert_eq!(res.await.unwrap().status(), StatusCode::BAD_REQUEST);
+        let res = send(Method::GET, uri, &admin, Body::empty(), None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_upload_never_overwrites_a_directory() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let dir = std::env::temp_dir().join(format!("mana-upload-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("site")).unwrap();
+        std::fs::write(dir.join("site/index.html"), "<h1>hi</h1>").unwrap();
+
+        let send = |method: Method, uri: String, body: Body, offset: Option<u64>| {
+            let mut req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", &admin)
+                .header("Content-Type", "application/json");
+            if let Some(offset) = offset {
+                req = req.header(UPLOAD_OFFSET, offset);
+            }
+            app.clone().oneshot(req.body(body).unwrap())
+        };
+        let body = json!({ "path": dir.join("site"), "size": 2, "conflict": "overwrite" });
+        let body = Body::from(body.to_string());
+        let res = send(Method::POST, "/api/files/uploads".to_string(), body, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let id = body_json(res).await["id"].as_str().unwrap().to_string();
+        let uri = format!("/api/files/uploads/{}", id);
+        let res = send(Method::PATCH, uri.clone(), Body::from("hi"), Some(0));
+        assert_eq!(res.await.unwrap().status(), StatusCode::OK);
+
+        let res = send(Method::POST, format!("{}/complete", uri), Body::from("{}"), None);
+        assert_eq!(res.await.unwrap().status(), StatusCode::CONFLICT);
+        let index = std::fs::read_to_string(dir.join("site/index.html")).unwrap();
+        assert_eq!(index, "<h1>hi</h1>");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload_limit() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let dir = std::env::temp_dir().join(format!("mana-upload-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("a.bin");
+
+        let upload = |size: usize| {
+            let mut body = format!(
+                "--b\r\nContent-Disposition: form-data; name=\"path\"\r\n\r\n{}\r\n\
+                 --b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\r\n",
+                target.display()
+            )
+            .into_bytes();
+            body.extend(vec![b'x'; size]);
+            body.extend(b"\r\n--b--\r\n");
+            let req = Request::post("/api/files/upload")
+                .header("Authorization", &admin)
+                .header("Content-Type", "multipart/form-data; boundary=b")
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let res = upload(state.config.upload_max_bytes as usize + 1).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(!target.exists());
+        // The partial file is gone too
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        let res = upload(1000).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(std::fs::metadata(&target).unwrap().len(), 1000);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restart_removes_interrupted_uploads() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let dir = std::env::temp_dir().join(format!("mana-upload-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let body = json!({ "path": dir.join("a.txt"), "size": 100 });
+        let req = Request::post("/api/files/uploads")
+            .header("Authorization", &admin)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        assert_eq!(app.clone().oneshot(req).await.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // What the next start finds
+        Uploads::load(&state.config).await.unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(std::fs::read_dir(&state.config.uploads_dir).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
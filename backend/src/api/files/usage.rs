@@ -0,0 +1,129 @@
+use axum::{
+    extract::{Query, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use serde::Deserialize;
+
+use crate::{
+    error::{AppError, AppResult},
+    middleware::auth::Claims,
+    services::{
+        disk_usage::{DEFAULT_DEPTH, DEFAULT_TOP, MAX_DEPTH, MAX_TOP},
+        jail::Jail,
+        jobs::JobInfo,
+    },
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    pub path: String,
+    /// Levels below the path that list their largest entries
+    pub depth: Option<usize>,
+    /// Entries listed per directory, and largest files listed overall
+    pub top: Option<usize>,
+    /// Walk the tree again even if a recent report is cached
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+/// Start a job that adds up the disk usage below a path. Its result is a
+/// tree of the largest directories and files, from the cache when the same
+/// path was analyzed recently.
+pub(super) async fn disk_usage(
+    State(state): State<AppState>,
+    jail: Jail,
+    claims: Claims,
+    Query(query): Query<UsageQuery>,
+) -> AppResult<(StatusCode, Json<JobInfo>)> {
+    let path = jail.resolve(&query.path)?;
+    if !path.exists() {
+        return Err(AppError::NotFound(format!("Path not found: {}", query.path)));
+    }
+    let depth = query.depth.unwrap_or(DEFAULT_DEPTH).min(MAX_DEPTH);
+    let top = query.top.unwrap_or(DEFAULT_TOP).clamp(1, MAX_TOP);
+
+    let usage = state.disk_usage.clone();
+    let description = format!("Disk usage of {}", path.display());
+    let job = state.jobs.spawn_blocking(claims.user_id()?, "usage", description, move |handle| {
+        let report = usage.report(&path, depth, top, query.refresh, &jail, handle)?;
+        serde_json::to_value(report).map_err(|e| AppError::Internal(e.into()))
+    });
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Drop cached disk usage reports once a request changed files, so the next
+/// analysis sees the change. Wraps the files router.
+pub async fn invalidate_disk_usage(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let changes = !req.method().is_safe();
+    let res = next.run(req).await;
+    if changes && res.status().is_success() {
+        state.disk_usage.invalidate();
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use crate::test_util::{admin_token, body_json, test_app_with_state};
+
+    #[tokio::test]
+    async fn test_disk_usage_job() {
+        let (app, state) = test_app_with_state().await;
+        let token = admin_token(&state).await;
+        let dir = std::env::temp_dir().join(format!("mana-du-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("big")).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        std::fs::write(dir.join("big/data"), vec![7u8; 64 * 1024]).unwrap();
+        std::fs::write(dir.join("small"), "x").unwrap();
+
+        let usage = |refresh: bool| {
+            let uri =
+                format!("/api/files/usage?path={}&depth=1&refresh={}", dir.display(), refresh);
+            let req = Request::get(uri)
+                .header("Authorization", &token)
+                .body(Body::empty())
+                .unwrap();
+            let (app, state) = (app.clone(), state.clone());
+            async move {
+                let res = app.oneshot(req).await.unwrap();
+                assert_eq!(res.status(), StatusCode::ACCEPTED);
+                let job = body_json(res).await;
+                state.jobs.wait(job["id"].as_str().unwrap(), 1).await.result.unwrap()
+            }
+        };
+
+        let report = usage(false).await;
+        assert_eq!(report["cached"], false);
+        assert_eq!(report["root"]["files"], 2);
+        assert_eq!(report["root"]["children"][0]["name"], "big");
+        assert_eq!(report["largest_files"][0]["path"], dir.join("big/data").to_str().unwrap());
+        assert_eq!(usage(false).await["cached"], true);
+
+        // A change through the panel drops the cached report
+        let req = Request::post("/api/files/mkdir")
+            .header("Authorization", &token)
+            .header("Content-Type", "application/json")
+            .body(Body::from(format!(r#"{{"path":"{}/new"}}"#, dir.display())))
+            .unwrap();
+        assert_eq!(app.clone().oneshot(req).await.unwrap().status(), StatusCode::OK);
+        let report = usage(false).await;
+        assert_eq!(report["cached"], false);
+        assert_eq!(report["root"]["dirs"], 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
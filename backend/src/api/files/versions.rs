@@ -0,0 +1,222 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::PathQuery;
+use crate::{
+    db::entities::file_version,
+    error::{AppError, AppResult},
+    middleware::{audit::Audit, auth::Claims},
+    services::{
+        jail::Jail,
+        versions::{self, Author, VersionService},
+    },
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    /// Another version to compare with instead of the file as it is now
+    pub against: Option<i32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RestoreVersionRequest {
+    /// The file's ETag as the client last read it; only a file that is
+    /// gone may be restored without one
+    pub etag: Option<String>,
+}
+
+/// Earlier versions of a file, newest first
+pub(super) async fn list_versions(
+    State(state): State<AppState>,
+    jail: Jail,
+    Query(query): Query<PathQuery>,
+) -> AppResult<Json<Vec<file_version::Model>>> {
+    let path = jail.resolve(&query.path)?;
+    Ok(Json(VersionService::list(&state.db, &path).await?))
+}
+
+/// What changed from a version to the file as it is now, or to another
+/// version of it, as a unified diff
+pub(super) async fn diff_version(
+    State(state): State<AppState>,
+    jail: Jail,
+    Path(id): Path<i32>,
+    Query(query): Query<DiffQuery>,
+) -> AppResult<Json<serde_json::Value>> {
+    let version = VersionService::get(&state.db, id).await?;
+    let path = jail.resolve(&version.path)?;
+    let old = VersionService::content(&state.config, &version).await?;
+    let old_name = format!("{} ({})", version.path, version.created_at.to_rfc3339());
+
+    let (new, new_name) = match query.against {
+        Some(against) => {
+            let other = VersionService::get(&state.db, against).await?;
+            if other.path != version.path {
+                return Err(AppError::Validation(
+                    "Versions of different files can't be compared".to_string(),
+                ));
+            }
+            let content = VersionService::content(&state.config, &other).await?;
+            (content, format!("{} ({})", other.path, other.created_at.to_rfc3339()))
+        }
+        None => (tokio::fs::read(&path).await?, format!("{} (current)", version.path)),
+    };
+
+    let diff = versions::unified_diff(&old, &new, &old_name, &new_name)?;
+    Ok(Json(json!({ "diff": diff })))
+}
+
+/// Put a version back into its file
+pub(super) async fn restore_version(
+    State(state): State<AppState>,
+    jail: Jail,
+    audit: Audit,
+    claims: Claims,
+    Path(id): Path<i32>,
+    payload: Option<Json<RestoreVersionRequest>>,
+) -> AppResult<Json<serde_json::Value>> {
+    let Json(payload) = payload.unwrap_or_default();
+    let version = VersionService::get(&state.db, id).await?;
+
+    let params = json!({ "version": id });
+    audit.run("file.version.restore", &version.path, params, async {
+        let path = jail.resolve(&version.path)?;
+        let (etag, author) = (payload.etag.as_deref(), Author::from(&claims));
+        let (db, config) = (&state.db, &state.config);
+        let etag = state.versions.restore(db, config, &version, &path, etag, author).await?;
+        Ok(Json(json!({
+            "success": true,
+            "path": &version.path,
+            "etag": etag,
+        })))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+    };
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use crate::test_util::{admin_token, body_json, test_app_with_state};
+
+    #[tokio::test]
+    async fn test_edit_conflicts_and_versions() {
+        let (app, state) = test_app_with_state().await;
+        let token = admin_token(&state).await;
+        let dir = std::env::temp_dir().join(format!("mana-edit-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = std::fs::canonicalize(&dir).unwrap().join("app.conf");
+        std::fs::write(&path, "port = 80\n").unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let send = |method: &str, uri: String, body: Option<Value>| {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", &token)
+                .header("Content-Type", "application/json")
+                .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let res = send("GET", format!("/api/files/content?path={}", path), None).await.unwrap();
+        let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert_eq!(body_json(res).await["etag"], etag.as_str());
+
+        let save = |content: &str, etag: &str| {
+            let body = json!({ "path": &path, "content": content, "etag": etag });
+            send("PUT", "/api/files/content".to_string(), Some(body))
+        };
+        let res = save("port = 8080\n", &etag).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let new_etag = body_json(res).await["etag"].as_str().unwrap().to_string();
+
+        // Someone else saving from the old copy is turned away, and so is a
+        // save that doesn't say which copy it comes from
+        let res = save("port = 81\n", &etag).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        let body = json!({ "path": &path, "content": "port = 82\n" });
+        let res = send("PUT", "/api/files/content".to_string(), Some(body)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_REQUIRED);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "port = 8080\n");
+
+        // A new file needs none
+        let new = dir.join("new.conf").to_string_lossy().to_string();
+        let body = json!({ "path": &new, "content": "port = 90\n" });
+        let res = send("PUT", "/api/files/content".to_string(), Some(body)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = send("GET", format!("/api/files/versions?path={}", path), None).await.unwrap();
+        let versions = body_json(res).await;
+        assert_eq!(versions.as_array().unwrap().len(), 1);
+        let id = versions[0]["id"].as_i64().unwrap();
+
+        let res = send("GET", format!("/api/files/versions/{}/diff", id), None).await.unwrap();
+        let diff = body_json(res).await["diff"].as_str().unwrap().to_string();
+        assert!(diff.contains("-port = 80\n+port = 8080\n"), "{}", diff);
+
+        let uri = format!("/api/files/versions/{}/restore", id);
+        let res = send("POST", uri, Some(json!({ "etag": new_etag }))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "port = 80\n");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_saves_with_one_etag() {
+        let (app, state) = test_app_with_state().await;
+        let token = admin_token(&state).await;
+        let dir = std::env::temp_dir().join(format!("mana-edit-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = std::fs::canonicalize(&dir).unwrap().join("app.conf");
+        std::fs::write(&path, "port = 80\n").unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let req = Request::get(format!("/api/files/content?path={}", path))
+            .header("Authorization", &token)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+
+        let save = |content: &str| {
+            let body = json!({ "path": &path, "content": content, "etag": &etag });
+            let req = Request::put("/api/files/content")
+                .header("Authorization", &token)
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            tokio::spawn(app.clone().oneshot(req))
+        };
+        let (first, second) = (save("port = 81\n"), save("port = 82\n"));
+        let mut statuses = [first.await.unwrap().unwrap(), second.await.unwrap().unwrap()]
+            .map(|res| res.status());
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::PRECONDITION_FAILED]);
+
+        // The save that got through is the one on disk, with the file before
+        // it as its only version
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content == "port = 81\n" || content == "port = 82\n");
+        let req = Request::get(format!("/api/files/versions?path={}", path))
+            .header("Authorization", &token)
+            .body(Body::empty())
+            .unwrap();
+        let versions = body_json(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(versions.as_array().unwrap().len(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
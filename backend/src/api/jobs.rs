@@ -0,0 +1,41 @@
+use axum::{
+    extract::{Path, State},
+    routing::{get, post},
+    Json, Router,
+};
+
+use crate::{
+    error::AppResult,
+    middleware::auth::Claims,
+    services::jobs::JobInfo,
+    AppState,
+};
+
+/// Background jobs of the calling user. Starting one takes the permission of
+/// the endpoint that starts it; following and cancelling it only ownership.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_jobs))
+        .route("/{id}", get(get_job))
+        .route("/{id}/cancel", post(cancel_job))
+}
+
+async fn list_jobs(State(state): State<AppState>, claims: Claims) -> AppResult<Json<Vec<JobInfo>>> {
+    Ok(Json(state.jobs.list(claims.user_id()?)))
+}
+
+async fn get_job(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> AppResult<Json<JobInfo>> {
+    Ok(Json(state.jobs.get(&id, claims.user_id()?)?))
+}
+
+async fn cancel_job(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> AppResult<Json<JobInfo>> {
+    Ok(Json(state.jobs.cancel(&id, claims.user_id()?)?))
+}
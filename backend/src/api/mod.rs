@@ -1,20 +1,253 @@
+pub mod audit;
 pub mod auth;
 pub mod docker;
 pub mod files;
+pub mod jobs;
 pub mod process;
+pub mod security;
 pub mod services;
 pub mod system;
 pub mod terminal;
+pub mod users;
 
-use axum::Router;
+use axum::{http::HeaderValue, middleware::from_fn_with_state, routing::get, Json, Router};
+use serde_json::json;
+use tower_http::cors::{AllowHeaders, AllowMethods, CorsLayer};
+
+use crate::config::Config;
+
+/// The whole application: the `/api` router behind the access restrictions.
+/// Those wrap the router instead of being one of its layers, so that they run
+/// before routing and can strip the security entrance from the path.
+pub fn app(state: crate::AppState) -> Router {
+    let routes = Router::new()
+        .nest("/api", create_router(state.clone()))
+        .with_state(state.clone());
 
-pub fn create_router() -> Router<crate::AppState> {
     Router::new()
+        .fallback_service(routes)
+        .layer(from_fn_with_state(state, crate::middleware::access::enforce_access))
+}
+
+/// Cross-origin requests are only answered for the origins the panel is
+/// served from, the same ones passkeys are accepted for. Browsers send the
+/// session cookie along, so any other origin is left without CORS headers.
+pub fn cors(config: &Config) -> CorsLayer {
+    let origins: Vec<HeaderValue> = config
+        .webauthn
+        .origins
+        .iter()
+        .filter_map(|origin| origin.parse().ok())
+        .collect();
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+        .allow_credentials(true)
+}
+
+/// Build the `/api` router. Every route requires a valid token unless it is
+/// listed in [`crate::middleware::auth::PUBLIC_ROUTES`]; individual routes
+/// additionally require a permission of the caller's role.
+pub fn create_router(state: crate::AppState) -> Router<crate::AppState> {
+    Router::new()
+        .route("/health", get(health))
         .nest("/auth", auth::router())
         .nest("/system", system::router())
         .nest("/processes", process::router())
-        .nest("/files", files::router())
+        .nest(
+            "/files",
+            files::router().layer(from_fn_with_state(state.clone(), files::invalidate_disk_usage)),
+        )
         .nest("/services", services::router())
         .nest("/terminal", terminal::router())
         .nest("/docker", docker::router())
+        .nest("/users", users::router())
+        .nest("/security", security::router())
+        .nest("/audit", audit::router())
+        .nest("/jobs", jobs::router())
+        .layer(from_fn_with_state(state, crate::middleware::auth::require_auth))
+}
+
+async fn health() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use crate::services::rbac::{ROLE_OPERATOR, ROLE_VIEWER};
+    use crate::test_util::{admin_token, test_app, test_app_with_state, user_token};
+
+    /// One representative route per nested router, including the ones that
+    /// mutate the host.
+    const PROTECTED: &[(Method, &str)] = &[
+        (Method::GET, "/api/auth/me"),
+        (Method::POST, "/api/auth/password"),
+        (Method::GET, "/api/system/info"),
+        (Method::GET, "/api/system/stats/stream"),
+        (Method::GET, "/api/processes"),
+        (Method::POST, "/api/processes/1/kill"),
+        (Method::GET, "/api/files?path=/"),
+        (Method::DELETE, "/api/files?path=/tmp/x"),
+        (Method::PUT, "/api/files/content"),
+        (Method::GET, "/api/services"),
+        (Method::POST, "/api/services/nginx/restart"),
+        (Method::GET, "/api/terminal/ws"),
+        (Method::GET, "/api/docker/containers"),
+        (Method::DELETE, "/api/docker/images/abc"),
+        (Method::GET, "/api/users"),
+        (Method::DELETE, "/api/users/2"),
+        (Method::POST, "/api/security/signing-keys/rotate"),
+        (Method::PUT, "/api/security/access/allowlist"),
+        (Method::GET, "/api/audit"),
+        (Method::GET, "/api/jobs"),
+        (Method::POST, "/api/files/extract"),
+        (Method::POST, "/api/files/move"),
+    ];
+
+    #[tokio::test]
+    async fn test_protected_routes_reject_missing_token() {
+        let app = test_app().await;
+
+        for (method, uri) in PROTECTED {
+            let req = Request::builder()
+                .method(method)
+                .uri(*uri)
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_protected_routes_reject_invalid_token() {
+        let app = test_app().await;
+
+        for (method, uri) in PROTECTED {
+            let req = Request::builder()
+                .method(method)
+                .uri(*uri)
+                .header("Authorization", "Bearer not-a-jwt")
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_public_routes_skip_auth() {
+        let app = test_app().await;
+
+        let req = Request::get("/api/health").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = Request::post("/api/auth/login")
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"username":"admin","password":"admin"}"#))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_valid_token_is_accepted() {
+        let (app, state) = test_app_with_state().await;
+
+        let req = Request::get("/api/auth/me")
+            .header("Authorization", admin_token(&state).await)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    async fn status_as(app: &axum::Router, token: &str, method: Method, uri: &str) -> StatusCode {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Authorization", token)
+            .header("Content-Type", "application/json")
+            .body(Body::from("{}"))
+            .unwrap();
+        app.clone().oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_viewer_is_read_only() {
+        let (app, state) = test_app_with_state().await;
+        let viewer = user_token(&state, ROLE_VIEWER).await;
+
+        for uri in ["/api/system/stats", "/api/files?path=/", "/api/auth/me"] {
+            assert_eq!(status_as(&app, &viewer, Method::GET, uri).await, StatusCode::OK, "{}", uri);
+        }
+
+        for (method, uri) in [
+            (Method::PUT, "/api/files/content"),
+            (Method::DELETE, "/api/files?path=/tmp/x"),
+            (Method::POST, "/api/services/nginx/restart"),
+            (Method::POST, "/api/docker/containers/abc/restart"),
+            (Method::POST, "/api/processes/1/kill"),
+            (Method::GET, "/api/terminal/ws"),
+        ] {
+            let status = status_as(&app, &viewer, method.clone(), uri).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", method, uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_operator_can_restart_but_not_open_terminal() {
+        let (app, state) = test_app_with_state().await;
+        let operator = user_token(&state, ROLE_OPERATOR).await;
+
+        // Docker is unavailable in tests, so getting past the guard means a 500
+        let uri = "/api/docker/containers/abc/restart";
+        assert_ne!(status_as(&app, &operator, Method::POST, uri).await, StatusCode::FORBIDDEN);
+
+        let status = status_as(&app, &operator, Method::GET, "/api/terminal/ws").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = status_as(&app, &operator, Method::PUT, "/api/files/content").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_admin_passes_terminal_guard() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+
+        // Not a real WebSocket handshake, so the upgrade extractor rejects it
+        let status = status_as(&app, &admin, Method::GET, "/api/terminal/ws").await;
+        assert_ne!(status, StatusCode::FORBIDDEN);
+        assert_ne!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_cors_only_for_panel_origins() {
+        let (app, state) = test_app_with_state().await;
+        let app = app.layer(super::cors(&state.config));
+
+        let preflight = |origin: &str| {
+            let req = Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/api/files")
+                .header("Origin", origin)
+                .header("Access-Control-Request-Method", "DELETE")
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+        let res = preflight("http://localhost:5173").await.unwrap();
+        assert_eq!(res.headers()["access-control-allow-origin"], "http://localhost:5173");
+        assert_eq!(res.headers()["access-control-allow-credentials"], "true");
+        let res = preflight("https://evil.example").await.unwrap();
+        assert!(res.headers().get("access-control-allow-origin").is_none());
+    }
 }
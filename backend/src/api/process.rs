@@ -1,15 +1,22 @@
 use axum::{
     extract::{Path, Query, State},
+    middleware::from_fn_with_state,
     response::sse::{Event, KeepAlive, Sse},
     routing::{get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::convert::Infallible;
 use std::time::Duration;
 use tokio_stream::{wrappers::IntervalStream, StreamExt};
 
-use crate::{error::AppResult, AppState};
+use crate::{
+    error::AppResult,
+    middleware::{audit::Audit, permission::require_permission},
+    services::rbac::Permission,
+    AppState,
+};
 
 #[derive(Debug, Serialize)]
 pub struct ProcessInfo {
@@ -37,12 +44,18 @@ pub struct ActionResponse {
 }
 
 pub fn router() -> Router<AppState> {
-    Router::new()
+    let read = Router::new()
         .route("/", get(list_processes))
         .route("/stream", get(processes_stream))
+        .route_layer(from_fn_with_state(Permission::ProcessesRead, require_permission));
+
+    let write = Router::new()
         .route("/{pid}/kill", post(kill_process))
         .route("/{pid}/stop", post(stop_process))
         .route("/{pid}/resume", post(resume_process))
+        .route_layer(from_fn_with_state(Permission::ProcessesWrite, require_permission));
+
+    read.merge(write)
 }
 
 async fn list_processes(
@@ -103,9 +116,12 @@ fn query_processes(state: &AppState, query: &ProcessQuery) -> Vec<ProcessInfo> {
 
 async fn kill_process(
     State(state): State<AppState>,
+    audit: Audit,
     Path(pid): Path<u32>,
 ) -> AppResult<Json<ActionResponse>> {
-    state.monitor.kill_process(pid)?;
+    let result = state.monitor.kill_process(pid);
+    audit.record("process.kill", &pid.to_string(), json!({}), &result).await;
+    result?;
     Ok(Json(ActionResponse {
         success: true,
         message: format!("Process {} killed", pid),
@@ -114,9 +130,12 @@ async fn kill_process(
 
 async fn stop_process(
     State(state): State<AppState>,
+    audit: Audit,
     Path(pid): Path<u32>,
 ) -> AppResult<Json<ActionResponse>> {
-    state.monitor.stop_process(pid)?;
+    let result = state.monitor.stop_process(pid);
+    audit.record("process.stop", &pid.to_string(), json!({}), &result).await;
+    result?;
     Ok(Json(ActionResponse {
         success: true,
         message: format!("Process {} stopped", pid),
@@ -125,9 +144,12 @@ async fn stop_process(
 
 async fn resume_process(
     State(state): State<AppState>,
+    audit: Audit,
     Path(pid): Path<u32>,
 ) -> AppResult<Json<ActionResponse>> {
-    state.monitor.resume_process(pid)?;
+    let result = state.monitor.resume_process(pid);
+    audit.record("process.resume", &pid.to_string(), json!({}), &result).await;
+    result?;
     Ok(Json(ActionResponse {
         success: true,
         message: format!("Process {} resumed", pid),
@@ -0,0 +1,479 @@
+use axum::{
+    extract::State,
+    middleware::from_fn_with_state,
+    routing::{get, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    db::entities::signing_key,
+    error::{AppError, AppResult},
+    middleware::{audit::Audit, client::ClientInfo, permission::require_permission},
+    services::{access::parse_network, jail::JailSettings, rbac::Permission},
+    AppState,
+};
+
+#[derive(Debug, Serialize)]
+pub struct SigningKeyInfo {
+    pub kid: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub retired_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Whether new tokens are signed with this key
+    pub active: bool,
+}
+
+impl From<signing_key::Model> for SigningKeyInfo {
+    fn from(key: signing_key::Model) -> Self {
+        Self {
+            active: key.retired_at.is_none(),
+            kid: key.kid,
+            created_at: key.created_at,
+            retired_at: key.retired_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccessSettings {
+    pub allowlist: Vec<String>,
+    /// Path prefix the API is reached through, e.g. `/<entrance>/api`
+    pub entrance: Option<String>,
+    pub trusted_proxies: Vec<String>,
+    /// The caller's address as the panel sees it
+    pub client_ip: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateAllowlistRequest {
+    /// IPv4/IPv6 addresses or CIDR networks; empty allows everyone
+    pub allowlist: Vec<String>,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/signing-keys", get(list_signing_keys))
+        .route("/signing-keys/rotate", post(rotate_signing_key))
+        .route("/access", get(access_settings))
+        .route("/access/allowlist", put(update_allowlist))
+        .route("/access/entrance", post(generate_entrance).delete(remove_entrance))
+        .route("/filesystem", get(filesystem_settings).put(update_filesystem))
+        .route_layer(from_fn_with_state(Permission::SecurityManage, require_permission))
+}
+
+async fn list_signing_keys(State(state): State<AppState>) -> Json<Vec<SigningKeyInfo>> {
+    Json(state.signing_keys.list().into_iter().map(SigningKeyInfo::from).collect())
+}
+
+/// Sign new tokens with a fresh key. Tokens signed with the previous key stay
+/// valid until they expire, and sessions renew through their refresh tokens.
+async fn rotate_signing_key(
+    State(state): State<AppState>,
+    audit: Audit,
+) -> AppResult<Json<SigningKeyInfo>> {
+    audit.run("security.signing_key.rotate", "", json!({}), async {
+        let key = state.signing_keys.rotate(&state.db).await?;
+        tracing::info!("Rotated JWT signing key, new kid {}", key.kid);
+        Ok(Json(key.into()))
+    })
+    .await
+}
+
+fn current_access(state: &AppState, client: &ClientInfo) -> AccessSettings {
+    let policy = state.access.policy();
+
+    AccessSettings {
+        allowlist: policy.allowlist.iter().map(|net| net.to_string()).collect(),
+        entrance: policy.entrance,
+        trusted_proxies: state.config.trusted_proxies.iter().map(|net| net.to_string()).collect(),
+        client_ip: client.ip.map(|ip| ip.to_string()),
+    }
+}
+
+async fn access_settings(
+    State(state): State<AppState>,
+    client: ClientInfo,
+) -> Json<AccessSettings> {
+    Json(current_access(&state, &client))
+}
+
+/// Replace the IP allowlist. It has to include the caller, so that an admin
+/// can't cut off the connection they are making the change from.
+async fn update_allowlist(
+    State(state): State<AppState>,
+    audit: Audit,
+    client: ClientInfo,
+    Json(payload): Json<UpdateAllowlistRequest>,
+) -> AppResult<Json<AccessSettings>> {
+    let params = json!({ "allowlist": &payload.allowlist });
+    audit.run("security.allowlist.update", "", params, async {
+        let allowlist = payload
+            .allowlist
+            .iter()
+            .map(|network| {
+                parse_network(network).ok_or_else(|| {
+                    AppError::Validation(format!("Not an IP address or CIDR network: {}", network))
+                })
+            })
+            .collect::<AppResult<Vec<_>>>()?;
+
+        let locked_out = !allowlist.is_empty()
+            && !client
+                .ip
+                .is_some_and(|ip| allowlist.iter().any(|network| network.contains(&ip)));
+        if locked_out {
+            return Err(AppError::Validation(format!(
+                "The allowlist must include your own address ({}) so you don't lock yourself out",
+                client.ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string())
+            )));
+        }
+
+        state.access.set_allowlist(&state.db, allowlist).await?;
+        tracing::info!("IP allowlist changed to {:?}", payload.allowlist);
+
+        Ok(Json(current_access(&state, &client)))
+    })
+    .await
+}
+
+/// Move the API behind a new random entrance. The old entrance stops working
+/// right away, so the response is the only place the new one is shown.
+async fn generate_entrance(
+    State(state): State<AppState>,
+    audit: Audit,
+    client: ClientInfo,
+) -> AppResult<Json<AccessSettings>> {
+    audit.run("security.entrance.generate", "", json!({}), async {
+        state.access.generate_entrance(&state.db).await?;
+        tracing::info!("Security entrance changed");
+
+        Ok(Json(current_access(&state, &client)))
+    })
+    .await
+}
+
+async fn remove_entrance(
+    State(state): State<AppState>,
+    audit: Audit,
+    client: ClientInfo,
+) -> AppResult<Json<AccessSettings>> {
+    audit.run("security.entrance.remove", "", json!({}), async {
+        state.access.clear_entrance(&state.db).await?;
+        tracing::info!("Security entrance removed");
+
+        Ok(Json(current_access(&state, &client)))
+    })
+    .await
+}
+
+async fn filesystem_settings(State(state): State<AppState>) -> Json<JailSettings> {
+    Json(state.jail.settings())
+}
+
+/// Replace where file operations may go. Paths are stored canonicalized, so
+/// the response shows what they actually resolve to.
+async fn update_filesystem(
+    State(state): State<AppState>,
+    audit: Audit,
+    Json(payload): Json<JailSettings>,
+) -> AppResult<Json<JailSettings>> {
+    let params = serde_json::to_value(&payload).unwrap_or_default();
+    audit.run("security.filesystem.update", "", params, async {
+        let settings = state.jail.update(&state.db, payload).await?;
+        tracing::info!("Filesystem jail changed to {:?}", settings);
+
+        Ok(Json(settings))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{Request, StatusCode},
+    };
+    use serde_json::json;
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    use crate::services::{
+        jail::JailPolicy,
+        rbac::{ROLE_OPERATOR, ROLE_VIEWER},
+        user::UserService,
+    };
+    use crate::test_util::{
+        admin_token, app_for, body_json, test_app_with_state, test_state, user_token,
+    };
+
+    async fn send(app: &axum::Router, req: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        (status, body_json(res).await)
+    }
+
+    #[tokio::test]
+    async fn test_rotation_keeps_existing_tokens_valid() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+
+        let req = Request::post("/api/security/signing-keys/rotate")
+            .header("Authorization", &admin)
+            .body(Body::empty())
+            .unwrap();
+        let (status, key) = send(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(key["active"], true);
+
+        // Signed before the rotation, still accepted
+        let req = Request::get("/api/security/signing-keys")
+            .header("Authorization", &admin)
+            .body(Body::empty())
+            .unwrap();
+        let (status, keys) = send(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        let keys = keys.as_array().unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0]["kid"], key["kid"]);
+        assert!(keys[0].get("secret").is_none());
+        assert!(keys[1]["retired_at"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_rotation_requires_admin() {
+        let (app, state) = test_app_with_state().await;
+        let operator = user_token(&state, ROLE_OPERATOR).await;
+
+        let req = Request::post("/api/security/signing-keys/rotate")
+            .header("Authorization", &operator)
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(&app, req).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    fn from_ip(mut req: Request<Body>, ip: &str) -> Request<Body> {
+        let addr: SocketAddr = format!("{}:40000", ip).parse().unwrap();
+        req.extensions_mut().insert(ConnectInfo(addr));
+        req
+    }
+
+    fn put_allowlist(token: &str, ip: &str, allowlist: serde_json::Value) -> Request<Body> {
+        let req = Request::put("/api/security/access/allowlist")
+            .header("Authorization", token)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json!({ "allowlist": allowlist }).to_string()))
+            .unwrap();
+        from_ip(req, ip)
+    }
+
+    fn health(path: &str, ip: &str) -> Request<Body> {
+        from_ip(Request::get(path).body(Body::empty()).unwrap(), ip)
+    }
+
+    #[tokio::test]
+    async fn test_ip_allowlist() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+
+        // Leaving out the caller's own address is refused
+        let req = put_allowlist(&admin, "198.51.100.7", json!(["10.0.0.0/8"]));
+        let (status, _) = send(&app, req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let req = put_allowlist(&admin, "198.51.100.7", json!(["not-an-ip"]));
+        let (status, _) = send(&app, req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let allowlist = json!(["198.51.100.0/24", "2001:db8::/32"]);
+        let req = put_allowlist(&admin, "198.51.100.7", allowlist.clone());
+        let (status, settings) = send(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(settings["allowlist"], allowlist);
+        assert_eq!(settings["client_ip"], "198.51.100.7");
+
+        let (status, _) = send(&app, health("/api/health", "198.51.100.200")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, health("/api/health", "[2001:db8::5]")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, health("/api/health", "203.0.113.1")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Only trusted proxies can vouch for another client address
+        let req = Request::get("/api/health")
+            .header("X-Forwarded-For", "198.51.100.1")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(&app, from_ip(req, "203.0.113.1")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let req = put_allowlist(&admin, "198.51.100.7", json!([]));
+        let (status, _) = send(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, health("/api/health", "203.0.113.1")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_security_entrance() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+
+        let req = Request::post("/api/security/access/entrance")
+            .header("Authorization", &admin)
+            .body(Body::empty())
+            .unwrap();
+        let (status, settings) = send(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        let entrance = settings["entrance"].as_str().unwrap().to_string();
+        assert_eq!(entrance.len(), 16);
+
+        let (status, _) = send(&app, health("/api/health", "127.0.0.1")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&app, health("/wrong/api/health", "127.0.0.1")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let path = format!("/{}/api/health", entrance);
+        let (status, _) = send(&app, health(&path, "127.0.0.1")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Query strings survive the rewrite
+        let req = Request::get(format!("/{}/api/files?path=/", entrance))
+            .header("Authorization", &admin)
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let req = Request::delete(format!("/{}/api/security/access/entrance", entrance))
+            .header("Authorization", &admin)
+            .body(Body::empty())
+            .unwrap();
+        let (status, settings) = send(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(settings["entrance"].is_null());
+        let (status, _) = send(&app, health("/api/health", "127.0.0.1")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Restrictions are persisted and can be reset for recovery
+        state.access.generate_entrance(&state.db).await.unwrap();
+        let reloaded = crate::services::access::AccessControl::load(&state.db).await.unwrap();
+        assert_eq!(reloaded.policy(), state.access.policy());
+        reloaded.reset(&state.db).await.unwrap();
+        assert!(reloaded.policy().entrance.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_filesystem_jail() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let operator = user_token(&state, ROLE_OPERATOR).await;
+        let dir = std::env::temp_dir().join(format!("mana-jail-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+
+        let put = |token: &str, settings: serde_json::Value| {
+            Request::put("/api/security/filesystem")
+                .header("Authorization", token)
+                .header("Content-Type", "application/json")
+                .body(Body::from(settings.to_string()))
+                .unwrap()
+        };
+        let list = |path: &str| {
+            Request::get(format!("/api/files?path={}", path))
+                .header("Authorization", &admin)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let settings = json!({ "roots": [dir], "denied": ["/etc/shadow"] });
+        let (status, _) = send(&app, put(&operator, settings.clone())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, stored) = send(&app, put(&admin, settings)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(stored["roots"], json!([dir]));
+
+        let (status, _) = send(&app, list(&dir.to_string_lossy())).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, list("/")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&app, list(&format!("{}/..", dir.display()))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = send(&app, put(&admin, json!({ "roots": ["relative"] }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_panel_data_is_always_denied() {
+        let mut state = test_state().await;
+        let dir = std::env::temp_dir().join(format!("mana-data-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        let database = dir.join("mana-panel.db");
+        std::fs::write(&database, "signing keys").unwrap();
+        state.config.database_url = format!("sqlite://{}?mode=rwc", database.display());
+        state.jail = JailPolicy::load(&state.db, &state.config).await.unwrap();
+        let app = app_for(&state);
+        let admin = admin_token(&state).await;
+        let viewer = user_token(&state, ROLE_VIEWER).await;
+
+        let download = |token: &str, path: &str| {
+            Request::get(format!("/api/files/download?path={}", path))
+                .header("Authorization", token)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let (status, _) = send(&app, download(&viewer, &database.to_string_lossy())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Clearing the deny list doesn't expose it either
+        let req = Request::put("/api/security/filesystem")
+            .header("Authorization", &admin)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json!({ "denied": [] }).to_string()))
+            .unwrap();
+        let (status, _) = send(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, download(&admin, &database.to_string_lossy())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let trash = format!("{}/x", state.config.trash_dir);
+        let (status, _) = send(&app, download(&admin, &trash)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_jail_follows_role_changes() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let operator = user_token(&state, ROLE_OPERATOR).await;
+        let dir = std::env::temp_dir().join(format!("mana-jail-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+
+        let req = Request::put("/api/security/filesystem")
+            .header("Authorization", &admin)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json!({ "role_roots": { "viewer": [dir] } }).to_string()))
+            .unwrap();
+        assert_eq!(send(&app, req).await.0, StatusCode::OK);
+        let list = || {
+            Request::get("/api/files?path=/")
+                .header("Authorization", &operator)
+                .body(Body::empty())
+                .unwrap()
+        };
+        assert_eq!(send(&app, list()).await.0, StatusCode::OK);
+
+        // The token still says operator, but the account is jailed as a viewer
+        let user = UserService::find_by_username(&state.db, ROLE_OPERATOR).await.unwrap().unwrap();
+        UserService::update_profile(&state.db, user.id, None, Some(ROLE_VIEWER)).await.unwrap();
+        assert_eq!(send(&app, list()).await.0, StatusCode::FORBIDDEN);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
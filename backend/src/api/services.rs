@@ -1,11 +1,16 @@
 use axum::{
     extract::Path,
+    middleware::from_fn_with_state,
     routing::{get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::AppState;
+use crate::{
+    middleware::{audit::Audit, permission::require_permission},
+    services::rbac::Permission,
+    AppState,
+};
 
 #[cfg(target_os = "linux")]
 use std::process::Command;
@@ -37,12 +42,18 @@ pub struct LogsQuery {
 }
 
 pub fn router() -> Router<AppState> {
-    Router::new()
+    let read = Router::new()
         .route("/", get(list_services))
+        .route("/{name}/logs", get(get_logs))
+        .route_layer(from_fn_with_state(Permission::ServicesRead, require_permission));
+
+    let write = Router::new()
         .route("/{name}/start", post(start_service))
         .route("/{name}/stop", post(stop_service))
         .route("/{name}/restart", post(restart_service))
-        .route("/{name}/logs", get(get_logs))
+        .route_layer(from_fn_with_state(Permission::ServicesWrite, require_permission));
+
+    read.merge(write)
 }
 
 fn validate_service_name(name: &str) -> Result<(), crate::error::AppError> {
@@ -105,19 +116,34 @@ async fn list_services() -> crate::error::AppResult<Json<Vec<ServiceInfo>>> {
     }
 }
 
-async fn start_service(Path(name): Path<String>) -> crate::error::AppResult<Json<ServiceActionResponse>> {
+async fn start_service(
+    audit: Audit,
+    Path(name): Path<String>,
+) -> crate::error::AppResult<Json<ServiceActionResponse>> {
     validate_service_name(&name)?;
-    run_systemctl_action(&name, "start")
+    let result = run_systemctl_action(&name, "start");
+    audit.record("service.start", &name, serde_json::json!({}), &result).await;
+    result
 }
 
-async fn stop_service(Path(name): Path<String>) -> crate::error::AppResult<Json<ServiceActionResponse>> {
+async fn stop_service(
+    audit: Audit,
+    Path(name): Path<String>,
+) -> crate::error::AppResult<Json<ServiceActionResponse>> {
     validate_service_name(&name)?;
-    run_systemctl_action(&name, "stop")
+    let result = run_systemctl_action(&name, "stop");
+    audit.record("service.stop", &name, serde_json::json!({}), &result).await;
+    result
 }
 
-async fn restart_service(Path(name): Path<String>) -> crate::error::AppResult<Json<ServiceActionResponse>> {
+async fn restart_service(
+    audit: Audit,
+    Path(name): Path<String>,
+) -> crate::error::AppResult<Json<ServiceActionResponse>> {
     validate_service_name(&name)?;
-    run_systemctl_action(&name, "restart")
+    let result = run_systemctl_action(&name, "restart");
+    audit.record("service.restart", &name, serde_json::json!({}), &result).await;
+    result
 }
 
 fn run_systemctl_action(name: &str, action: &str) -> crate::error::AppResult<Json<ServiceActionResponse>> {
@@ -1,5 +1,6 @@
 use axum::{
     extract::State,
+    middleware::from_fn_with_state,
     response::sse::{Event, KeepAlive, Sse},
     routing::get,
     Json, Router,
@@ -9,7 +10,9 @@ use std::convert::Infallible;
 use std::time::Duration;
 use tokio_stream::{wrappers::IntervalStream, StreamExt};
 
-use crate::AppState;
+use crate::{
+    middleware::permission::require_permission, services::rbac::Permission, AppState,
+};
 
 #[derive(Debug, Serialize)]
 pub struct SystemInfo {
@@ -60,6 +63,7 @@ pub fn router() -> Router<AppState> {
         .route("/info", get(system_info))
         .route("/stats", get(system_stats))
         .route("/stats/stream", get(stats_stream))
+        .route_layer(from_fn_with_state(Permission::SystemRead, require_permission))
 }
 
 async fn system_info(State(state): State<AppState>) -> Json<SystemInfo> {
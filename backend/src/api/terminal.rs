@@ -1,19 +1,38 @@
 use axum::{
     extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    middleware::from_fn_with_state,
     response::Response,
     routing::get,
     Router,
 };
 use futures::{SinkExt, StreamExt};
+use serde_json::json;
 
-use crate::AppState;
+use crate::{
+    error::AppResult,
+    middleware::{audit::Audit, permission::require_permission},
+    services::rbac::Permission,
+    AppState,
+};
 
 pub fn router() -> Router<AppState> {
-    Router::new().route("/ws", get(ws_handler))
+    Router::new()
+        .route("/ws", get(ws_handler))
+        .route_layer(from_fn_with_state(Permission::TerminalOpen, require_permission))
 }
 
-async fn ws_handler(ws: WebSocketUpgrade) -> Response {
-    ws.on_upgrade(handle_socket)
+async fn ws_handler(audit: Audit, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| async move {
+        // Pairs the open and close entries of one session
+        let session = uuid::Uuid::new_v4().to_string();
+        let opened = std::time::Instant::now();
+        let ok: AppResult<()> = Ok(());
+
+        audit.record("terminal.open", "bash", json!({ "session": session }), &ok).await;
+        handle_socket(socket).await;
+        let params = json!({ "session": session, "duration_secs": opened.elapsed().as_secs() });
+        audit.record("terminal.close", "bash", params, &ok).await;
+    })
 }
 
 async fn handle_socket(socket: WebSocket) {
@@ -74,16 +93,8 @@ async fn handle_socket(socket: WebSocket) {
             use std::io::Write;
             while let Some(msg) = receiver.next().await {
                 match msg {
-                    Ok(Message::Text(text)) => {
-                        if writer.write_all(text.as_bytes()).is_err() {
-                            break;
-                        }
-                    }
-                    Ok(Message::Binary(data)) => {
-                        if writer.write_all(&data).is_err() {
-                            break;
-                        }
-                    }
+                    Ok(Message::Text(text)) if writer.write_all(text.as_bytes()).is_err() => break,
+                    Ok(Message::Binary(data)) if writer.write_all(&data).is_err() => break,
                     Ok(Message::Close(_)) => break,
                     _ => {}
                 }
@@ -0,0 +1,469 @@
+use axum::{
+    extract::{Path, Query, State},
+    middleware::from_fn_with_state,
+    routing::{delete, get, patch, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    api::auth::MessageResponse,
+    db::entities::{login_attempt, login_lockout, role, user},
+    error::{AppError, AppResult},
+    middleware::{audit::Audit, auth::Claims, permission::require_permission},
+    services::{
+        api_token::ApiTokenService,
+        login_throttle::LoginThrottle,
+        password::PasswordPolicy,
+        rbac::{Permission, RbacService},
+        session::SessionService,
+        user::UserService,
+    },
+    AppState,
+};
+
+#[derive(Debug, Serialize)]
+pub struct UserSummary {
+    pub id: i32,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub role: String,
+    pub disabled: bool,
+    pub must_change_password: bool,
+    pub last_login_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<user::Model> for UserSummary {
+    fn from(user: user::Model) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            display_name: user.display_name,
+            role: user.role,
+            disabled: user.disabled,
+            must_change_password: user.must_change_password,
+            last_login_at: user.last_login_at,
+            created_at: user.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUserRequest {
+    pub username: String,
+    pub password: String,
+    pub role: String,
+    pub display_name: Option<String>,
+    /// Whether the user has to pick their own password on first login,
+    /// defaults to true
+    pub must_change_password: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateUserRequest {
+    /// An empty string clears the display name
+    pub display_name: Option<String>,
+    pub role: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginAttemptsQuery {
+    pub username: Option<String>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub new_password: String,
+    /// Defaults to true, see [`CreateUserRequest::must_change_password`]
+    pub must_change_password: Option<bool>,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_users).post(create_user))
+        .route("/roles", get(list_roles))
+        .route("/lockouts", get(list_lockouts))
+        .route("/lockouts/{id}", delete(clear_lockout))
+        .route("/login-attempts", get(list_login_attempts))
+        .route("/{id}", patch(update_user).delete(delete_user))
+        .route("/{id}/disable", post(disable_user))
+        .route("/{id}/enable", post(enable_user))
+        .route("/{id}/password", post(reset_password))
+        .route_layer(from_fn_with_state(Permission::UsersManage, require_permission))
+}
+
+fn validate_username(username: &str) -> AppResult<()> {
+    if username.is_empty() || username.len() > 64 {
+        return Err(AppError::Validation("Invalid username length".to_string()));
+    }
+
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') {
+        return Err(AppError::Validation("Invalid username characters".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Admins must not lock themselves out through the management API
+fn ensure_not_self(claims: &Claims, user_id: i32, action: &str) -> AppResult<()> {
+    if claims.sub == user_id.to_string() {
+        return Err(AppError::Validation(format!("You cannot {} your own account", action)));
+    }
+    Ok(())
+}
+
+async fn list_users(State(state): State<AppState>) -> AppResult<Json<Vec<UserSummary>>> {
+    let users = UserService::list_users(&state.db).await?;
+    Ok(Json(users.into_iter().map(UserSummary::from).collect()))
+}
+
+async fn list_roles(State(state): State<AppState>) -> AppResult<Json<Vec<role::Model>>> {
+    let roles = RbacService::list_roles(&state.db).await?;
+    Ok(Json(roles))
+}
+
+async fn list_lockouts(
+    State(state): State<AppState>,
+) -> AppResult<Json<Vec<login_lockout::Model>>> {
+    let lockouts = LoginThrottle::list_lockouts(&state.db).await?;
+    Ok(Json(lockouts))
+}
+
+async fn clear_lockout(
+    State(state): State<AppState>,
+    audit: Audit,
+    Path(id): Path<i32>,
+) -> AppResult<Json<MessageResponse>> {
+    audit.run("user.lockout.clear", &id.to_string(), json!({}), async {
+        LoginThrottle::clear(&state.db, id).await?;
+
+        Ok(Json(MessageResponse {
+            success: true,
+            message: "Lockout cleared".to_string(),
+        }))
+    })
+    .await
+}
+
+async fn list_login_attempts(
+    State(state): State<AppState>,
+    Query(query): Query<LoginAttemptsQuery>,
+) -> AppResult<Json<Vec<login_attempt::Model>>> {
+    let limit = query.limit.unwrap_or(100).min(1000);
+    let attempts =
+        LoginThrottle::recent_attempts(&state.db, query.username.as_deref(), limit).await?;
+    Ok(Json(attempts))
+}
+
+async fn create_user(
+    State(state): State<AppState>,
+    audit: Audit,
+    Json(payload): Json<CreateUserRequest>,
+) -> AppResult<Json<UserSummary>> {
+    let params = json!({ "role": &payload.role, "display_name": &payload.display_name });
+    audit.run("user.create", &payload.username, params, async {
+        validate_username(&payload.username)?;
+        PasswordPolicy::from_config(&state.config).validate(&payload.password, &payload.username)?;
+
+        let user = UserService::create_user(
+            &state.db,
+            &payload.username,
+            &payload.password,
+            &payload.role,
+            payload.must_change_password.unwrap_or(true),
+        )
+        .await?;
+
+        let user = match payload.display_name.filter(|name| !name.is_empty()) {
+            Some(name) => UserService::update_profile(&state.db, user.id, Some(Some(name)), None).await?,
+            None => user,
+        };
+
+        Ok(Json(user.into()))
+    })
+    .await
+}
+
+async fn update_user(
+    State(state): State<AppState>,
+    audit: Audit,
+    claims: Claims,
+    Path(id): Path<i32>,
+    Json(payload): Json<UpdateUserRequest>,
+) -> AppResult<Json<UserSummary>> {
+    let params = json!({ "display_name": &payload.display_name, "role": &payload.role });
+    audit.run("user.update", &id.to_string(), params, async {
+        if payload.role.is_some() {
+            ensure_not_self(&claims, id, "change the role of")?;
+        }
+
+        let display_name = payload
+            .display_name
+            .map(|name| Some(name).filter(|name| !name.is_empty()));
+
+        let user =
+            UserService::update_profile(&state.db, id, display_name, payload.role.as_deref()).await?;
+
+        Ok(Json(user.into()))
+    })
+    .await
+}
+
+async fn disable_user(
+    State(state): State<AppState>,
+    audit: Audit,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> AppResult<Json<UserSummary>> {
+    audit.run("user.disable", &id.to_string(), json!({}), async {
+        ensure_not_self(&claims, id, "disable")?;
+        let user = UserService::set_disabled(&state.db, id, true).await?;
+        SessionService::revoke_all_for_user(&state.db, id, None).await?;
+        ApiTokenService::revoke_all_for_user(&state.db, id).await?;
+        Ok(Json(user.into()))
+    })
+    .await
+}
+
+async fn enable_user(
+    State(state): State<AppState>,
+    audit: Audit,
+    Path(id): Path<i32>,
+) -> AppResult<Json<UserSummary>> {
+    audit.run("user.enable", &id.to_string(), json!({}), async {
+        let user = UserService::set_disabled(&state.db, id, false).await?;
+        Ok(Json(user.into()))
+    })
+    .await
+}
+
+async fn delete_user(
+    State(state): State<AppState>,
+    audit: Audit,
+    claims: Claims,
+    Path(id): Path<i32>,
+) -> AppResult<Json<MessageResponse>> {
+    audit.run("user.delete", &id.to_string(), json!({}), async {
+        ensure_not_self(&claims, id, "delete")?;
+        UserService::delete_user(&state.db, id).await?;
+
+        Ok(Json(MessageResponse {
+            success: true,
+            message: format!("User {} deleted", id),
+        }))
+    })
+    .await
+}
+
+async fn reset_password(
+    State(state): State<AppState>,
+    audit: Audit,
+    Path(id): Path<i32>,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> AppResult<Json<MessageResponse>> {
+    let params = json!({ "must_change_password": payload.must_change_password });
+    audit.run("user.password.reset", &id.to_string(), params, async {
+        UserService::find_by_id(&state.db, id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("User {} not found", id)))?;
+
+        let policy = PasswordPolicy::from_config(&state.config);
+        let must_change_password = payload.must_change_password.unwrap_or(true);
+        UserService::update_password(&state.db, &policy, id, &payload.new_password, must_change_password)
+            .await?;
+        SessionService::revoke_all_for_user(&state.db, id, None).await?;
+        ApiTokenService::revoke_all_for_user(&state.db, id).await?;
+
+        Ok(Json(MessageResponse {
+            success: true,
+            message: "Password reset successfully".to_string(),
+        }))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use crate::services::{
+        api_token::ApiTokenService,
+        rbac::{Permission, ROLE_VIEWER},
+        user::UserService,
+    };
+    use crate::test_util::{admin_token, body_json, test_app_with_state, user_token};
+
+    async fn send(
+        app: &axum::Router,
+        token: &str,
+        method: Method,
+        uri: &str,
+        body: serde_json::Value,
+    ) -> (StatusCode, serde_json::Value) {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Authorization", token)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        (status, body_json(res).await)
+    }
+
+    async fn login(app: &axum::Router, username: &str, password: &str) -> StatusCode {
+        let req = Request::post("/api/auth/login")
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::json!({ "username": username, "password": password }).to_string(),
+            ))
+            .unwrap();
+        app.clone().oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_user_lifecycle() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let null = serde_json::Value::Null;
+
+        let (status, user) = send(
+            &app,
+            &admin,
+            Method::POST,
+            "/api/users",
+            serde_json::json!({
+                "username": "junior",
+                "password": "s3cret-pass",
+                "role": ROLE_VIEWER,
+                "display_name": "On-call junior"
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(user["display_name"], "On-call junior");
+        assert!(user.get("password_hash").is_none());
+        let id = user["id"].as_i64().unwrap();
+
+        let (_, users) = send(&app, &admin, Method::GET, "/api/users", null.clone()).await;
+        assert_eq!(users.as_array().unwrap().len(), 2);
+
+        assert_eq!(login(&app, "junior", "s3cret-pass").await, StatusCode::OK);
+        let (_, users) = send(&app, &admin, Method::GET, "/api/users", null.clone()).await;
+        assert!(!users[1]["last_login_at"].is_null());
+
+        let uri = format!("/api/users/{}/disable", id);
+        let (status, _) = send(&app, &admin, Method::POST, &uri, null.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(login(&app, "junior", "s3cret-pass").await, StatusCode::UNAUTHORIZED);
+
+        let uri = format!("/api/users/{}/enable", id);
+        let (status, _) = send(&app, &admin, Method::POST, &uri, null.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let uri = format!("/api/users/{}/password", id);
+        let body = serde_json::json!({ "new_password": "an0ther-pass" });
+        let (status, _) = send(&app, &admin, Method::POST, &uri, body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(login(&app, "junior", "an0ther-pass").await, StatusCode::OK);
+
+        let uri = format!("/api/users/{}", id);
+        let (status, _) = send(&app, &admin, Method::DELETE, &uri, null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(login(&app, "junior", "an0ther-pass").await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_disable_and_password_reset_revoke_api_tokens() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let null = serde_json::Value::Null;
+        let user = UserService::create_user(&state.db, "ci", "s3cret-pass", ROLE_VIEWER, false)
+            .await
+            .unwrap();
+        let new_token = || async {
+            let ttl = chrono::Duration::days(1);
+            let scopes = [Permission::FilesRead];
+            let (_, secret) =
+                ApiTokenService::create(&state.db, user.id, "deploy", &scopes, ttl).await.unwrap();
+            format!("Bearer {}", secret)
+        };
+        let list = "/api/files?path=/";
+
+        let token = new_token().await;
+        assert_eq!(send(&app, &token, Method::GET, list, null.clone()).await.0, StatusCode::OK);
+        let uri = format!("/api/users/{}/disable", user.id);
+        send(&app, &admin, Method::POST, &uri, null.clone()).await;
+        let uri = format!("/api/users/{}/enable", user.id);
+        send(&app, &admin, Method::POST, &uri, null.clone()).await;
+        let (status, _) = send(&app, &token, Method::GET, list, null.clone()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let token = new_token().await;
+        let uri = format!("/api/users/{}/password", user.id);
+        let body = serde_json::json!({ "new_password": "an0ther-pass" });
+        assert_eq!(send(&app, &admin, Method::POST, &uri, body).await.0, StatusCode::OK);
+        assert_eq!(send(&app, &token, Method::GET, list, null).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_cannot_remove_own_account() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let null = serde_json::Value::Null;
+
+        let (status, _) = send(&app, &admin, Method::DELETE, "/api/users/1", null.clone()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&app, &admin, Method::POST, "/api/users/1/disable", null).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_users_api_requires_admin() {
+        let (app, state) = test_app_with_state().await;
+
+        let req = Request::get("/api/users")
+            .header("Authorization", user_token(&state, ROLE_VIEWER).await)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_admin_can_clear_login_lockout() {
+        let (app, state) = test_app_with_state().await;
+        let admin = admin_token(&state).await;
+        let null = serde_json::Value::Null;
+
+        for _ in 0..=state.config.login_max_failures_per_user {
+            assert_eq!(login(&app, "admin", "wrong").await, StatusCode::UNAUTHORIZED);
+        }
+        // Locked, even with the right password
+        assert_eq!(login(&app, "admin", "admin").await, StatusCode::TOO_MANY_REQUESTS);
+
+        let uri = "/api/users/lockouts";
+        let (status, lockouts) = send(&app, &admin, Method::GET, uri, null.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        let lockout = &lockouts.as_array().unwrap()[0];
+        assert_eq!(lockout["key"], "admin");
+        assert!(!lockout["locked_until"].is_null());
+
+        let uri = "/api/users/login-attempts?username=admin";
+        let (_, attempts) = send(&app, &admin, Method::GET, uri, null.clone()).await;
+        assert_eq!(attempts[0]["reason"], "locked_out");
+
+        let uri = format!("/api/users/lockouts/{}", lockout["id"]);
+        let (status, _) = send(&app, &admin, Method::DELETE, &uri, null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(login(&app, "admin", "admin").await, StatusCode::OK);
+    }
+}
@@ -1,13 +1,144 @@
+use ipnet::IpNet;
 use serde::Deserialize;
 use std::env;
 
+use crate::services::access::parse_network;
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub host: String,
     pub port: u16,
     pub database_url: String,
-    pub jwt_secret: String,
-    pub jwt_expiry_hours: i64,
+    /// Lifetime of access tokens; keep it short, clients renew them with a refresh token
+    pub access_token_minutes: i64,
+    /// Lifetime of a session's refresh token, extended on every refresh
+    pub refresh_token_hours: i64,
+    /// Failed logins allowed per username before backoff kicks in
+    pub login_max_failures_per_user: i32,
+    /// Failed logins allowed per client IP before backoff kicks in
+    pub login_max_failures_per_ip: i32,
+    /// First backoff delay, doubled on every further failure
+    pub login_backoff_base_seconds: i64,
+    /// Upper bound for a single lockout
+    pub login_lockout_max_minutes: i64,
+    /// Failure counters reset after this long without failures
+    pub login_failure_window_minutes: i64,
+    /// Minimum number of characters in a new password
+    pub password_min_length: usize,
+    /// How many of lowercase, uppercase, digits and symbols a password must mix
+    pub password_min_char_classes: usize,
+    /// Number of previous passwords that can't be reused
+    pub password_history: u64,
+    /// Extra newline-separated list of breached passwords, on top of the built-in one
+    pub password_blocklist_file: Option<String>,
+    /// Single sign-on through an OpenID Connect provider, if configured
+    pub oidc: Option<OidcConfig>,
+    /// Relying party settings for passkeys and security keys
+    pub webauthn: WebauthnConfig,
+    /// Reverse proxies whose `X-Forwarded-For` header is believed
+    pub trusted_proxies: Vec<IpNet>,
+    /// Clear the IP allowlist and security entrance on startup
+    pub access_reset: bool,
+    /// Mark session cookies `Secure`; only turn off when serving plain HTTP
+    /// on something other than localhost
+    pub cookie_secure: bool,
+    /// Largest file a single upload may create
+    pub upload_max_bytes: u64,
+    /// Where unfinished uploads are recorded, so that a restart can remove
+    /// their data
+    pub uploads_dir: String,
+    /// Where deleted files are kept until they are restored or purged
+    pub trash_dir: String,
+    /// Oldest trash items are purged once the trash holds more than this; 0 for no limit
+    pub trash_max_bytes: u64,
+    /// Trash items are purged after this many days; 0 keeps them until purged by hand
+    pub trash_retention_days: i64,
+    /// Where earlier contents of edited files are kept
+    pub versions_dir: String,
+    /// Versions kept per file, the oldest are dropped; 0 keeps none
+    pub versions_keep: u64,
+    /// Larger files can only be read in pages and not edited
+    pub edit_max_bytes: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcConfig {
+    /// Issuer URL; discovery is fetched from `<issuer>/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    /// Empty for public clients, which rely on PKCE alone
+    pub client_secret: Option<String>,
+    /// Where the provider sends the browser back to, i.e. the panel's login page
+    pub redirect_url: String,
+    pub scopes: String,
+    /// Label for the login button
+    pub provider_name: String,
+    /// ID token claim matched against local usernames
+    pub username_claim: String,
+    /// ID token claim listing the user's groups
+    pub groups_claim: String,
+    /// Group to role mapping, first match wins
+    pub role_mapping: Vec<(String, String)>,
+    /// Role for users in none of the mapped groups; without one they are refused
+    pub default_role: Option<String>,
+    /// Create accounts for unknown users on their first login
+    pub auto_provision: bool,
+}
+
+impl OidcConfig {
+    fn from_env() -> Option<Self> {
+        let issuer = env::var("OIDC_ISSUER").ok().filter(|issuer| !issuer.is_empty())?;
+
+        Some(Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id: env::var("OIDC_CLIENT_ID").expect("OIDC_CLIENT_ID must be set"),
+            client_secret: env::var("OIDC_CLIENT_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty()),
+            redirect_url: env::var("OIDC_REDIRECT_URL").expect("OIDC_REDIRECT_URL must be set"),
+            scopes: env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid profile email".to_string()),
+            provider_name: env::var("OIDC_PROVIDER_NAME").unwrap_or_else(|_| "SSO".to_string()),
+            username_claim: env::var("OIDC_USERNAME_CLAIM")
+                .unwrap_or_else(|_| "preferred_username".to_string()),
+            groups_claim: env::var("OIDC_GROUPS_CLAIM").unwrap_or_else(|_| "groups".to_string()),
+            role_mapping: env::var("OIDC_ROLE_MAPPING")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(group, role)| (group.trim().to_string(), role.trim().to_string()))
+                .collect(),
+            default_role: env::var("OIDC_DEFAULT_ROLE").ok().filter(|role| !role.is_empty()),
+            auto_provision: env::var("OIDC_AUTO_PROVISION")
+                .unwrap_or_else(|_| "true".to_string())
+                .parse()
+                .expect("OIDC_AUTO_PROVISION must be true or false"),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebauthnConfig {
+    /// Domain the credentials are scoped to; the panel's host name or a parent domain
+    pub rp_id: String,
+    /// Name shown by the browser when creating a passkey
+    pub rp_name: String,
+    /// Origins the panel is served from, as `scheme://host[:port]`
+    pub origins: Vec<String>,
+}
+
+impl WebauthnConfig {
+    fn from_env() -> Self {
+        Self {
+            rp_id: env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| "localhost".to_string()),
+            rp_name: env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| "Mana Panel".to_string()),
+            origins: env::var("WEBAUTHN_ORIGINS")
+                .unwrap_or_else(|_| "http://localhost:3000,http://localhost:5173".to_string())
+                .split(',')
+                .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect(),
+        }
+    }
 }
 
 impl Config {
@@ -22,12 +153,90 @@ impl Config {
                 .expect("PORT must be a number"),
             database_url: env::var("DATABASE_URL")
                 .unwrap_or_else(|_| "sqlite:./mana-panel.db?mode=rwc".to_string()),
-            jwt_secret: env::var("JWT_SECRET")
-                .unwrap_or_else(|_| "your-super-secret-key-change-in-production".to_string()),
-            jwt_expiry_hours: env::var("JWT_EXPIRY_HOURS")
-                .unwrap_or_else(|_| "24".to_string())
+            access_token_minutes: env::var("ACCESS_TOKEN_MINUTES")
+                .unwrap_or_else(|_| "15".to_string())
+                .parse()
+                .expect("ACCESS_TOKEN_MINUTES must be a number"),
+            refresh_token_hours: env::var("REFRESH_TOKEN_HOURS")
+                .unwrap_or_else(|_| "168".to_string())
+                .parse()
+                .expect("REFRESH_TOKEN_HOURS must be a number"),
+            login_max_failures_per_user: env::var("LOGIN_MAX_FAILURES_PER_USER")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()
+                .expect("LOGIN_MAX_FAILURES_PER_USER must be a number"),
+            login_max_failures_per_ip: env::var("LOGIN_MAX_FAILURES_PER_IP")
+                .unwrap_or_else(|_| "20".to_string())
+                .parse()
+                .expect("LOGIN_MAX_FAILURES_PER_IP must be a number"),
+            login_backoff_base_seconds: env::var("LOGIN_BACKOFF_BASE_SECONDS")
+                .unwrap_or_else(|_| "2".to_string())
+                .parse()
+                .expect("LOGIN_BACKOFF_BASE_SECONDS must be a number"),
+            login_lockout_max_minutes: env::var("LOGIN_LOCKOUT_MAX_MINUTES")
+                .unwrap_or_else(|_| "15".to_string())
+                .parse()
+                .expect("LOGIN_LOCKOUT_MAX_MINUTES must be a number"),
+            login_failure_window_minutes: env::var("LOGIN_FAILURE_WINDOW_MINUTES")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .expect("LOGIN_FAILURE_WINDOW_MINUTES must be a number"),
+            password_min_length: env::var("PASSWORD_MIN_LENGTH")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()
+                .expect("PASSWORD_MIN_LENGTH must be a number"),
+            password_min_char_classes: env::var("PASSWORD_MIN_CHAR_CLASSES")
+                .unwrap_or_else(|_| "3".to_string())
+                .parse()
+                .expect("PASSWORD_MIN_CHAR_CLASSES must be a number"),
+            password_history: env::var("PASSWORD_HISTORY")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()
+                .expect("PASSWORD_HISTORY must be a number"),
+            password_blocklist_file: env::var("PASSWORD_BLOCKLIST_FILE")
+                .ok()
+                .filter(|path| !path.is_empty()),
+            oidc: OidcConfig::from_env(),
+            webauthn: WebauthnConfig::from_env(),
+            trusted_proxies: env::var("TRUSTED_PROXIES")
+                .unwrap_or_default()
+                .split(',')
+                .filter(|network| !network.trim().is_empty())
+                .map(|network| {
+                    parse_network(network).expect("TRUSTED_PROXIES must list IPs or CIDR networks")
+                })
+                .collect(),
+            access_reset: env::var("ACCESS_RESET")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .expect("ACCESS_RESET must be true or false"),
+            cookie_secure: env::var("COOKIE_SECURE")
+                .unwrap_or_else(|_| "true".to_string())
+                .parse()
+                .expect("COOKIE_SECURE must be true or false"),
+            upload_max_bytes: env::var("UPLOAD_MAX_BYTES")
+                .unwrap_or_else(|_| "10737418240".to_string())
+                .parse()
+                .expect("UPLOAD_MAX_BYTES must be a number"),
+            uploads_dir: env::var("UPLOADS_DIR").unwrap_or_else(|_| "./uploads".to_string()),
+            trash_dir: env::var("TRASH_DIR").unwrap_or_else(|_| "./trash".to_string()),
+            trash_max_bytes: env::var("TRASH_MAX_BYTES")
+                .unwrap_or_else(|_| "10737418240".to_string())
+                .parse()
+                .expect("TRASH_MAX_BYTES must be a number"),
+            trash_retention_days: env::var("TRASH_RETENTION_DAYS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .expect("TRASH_RETENTION_DAYS must be a number"),
+            versions_dir: env::var("VERSIONS_DIR").unwrap_or_else(|_| "./versions".to_string()),
+            versions_keep: env::var("VERSIONS_KEEP")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()
+                .expect("VERSIONS_KEEP must be a number"),
+            edit_max_bytes: env::var("EDIT_MAX_BYTES")
+                .unwrap_or_else(|_| "5242880".to_string())
                 .parse()
-                .expect("JWT_EXPIRY_HOURS must be a number"),
+                .expect("EDIT_MAX_BYTES must be a number"),
         }
     }
 }
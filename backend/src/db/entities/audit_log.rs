@@ -0,0 +1,33 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// One state-changing action, chained to the previous entry by its hash
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "audit_log")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub created_at: DateTimeUtc,
+    /// Acting account; empty for anonymous requests such as failed logins
+    pub user_id: Option<i32>,
+    pub username: Option<String>,
+    pub ip: Option<String>,
+    /// Dotted action name, e.g. `file.delete` or `docker.container.stop`
+    pub action: String,
+    /// What was acted on: a path, PID, service, container or username
+    pub target: String,
+    /// Request parameters as JSON, without secrets
+    pub params: String,
+    /// `success` or `failure`
+    pub result: String,
+    pub error: Option<String>,
+    /// Hash of the previous entry, or zeros for the first one
+    pub prev_hash: String,
+    /// SHA-256 over `prev_hash` and this entry's fields
+    pub hash: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,26 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The content a file had before it was overwritten from the editor
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "file_versions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    /// The file this is a version of
+    pub path: String,
+    /// Its name inside the versions directory
+    #[serde(skip)]
+    #[sea_orm(unique)]
+    pub stored_name: String,
+    pub size: i64,
+    /// Who made the change that replaced this content
+    pub created_by: Option<i32>,
+    pub created_by_username: Option<String>,
+    pub created_at: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
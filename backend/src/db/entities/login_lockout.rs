@@ -0,0 +1,21 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Failure counter for one username or client IP
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "login_lockouts")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    /// `username` or `ip`
+    pub kind: String,
+    pub key: String,
+    pub failures: i32,
+    pub locked_until: Option<DateTimeUtc>,
+    pub last_failure_at: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,4 +1,20 @@
 // Entity definitions will go here
 // For now, we'll use in-memory/default credentials
 
+pub mod api_token;
+pub mod audit_log;
+pub mod file_version;
+pub mod login_attempt;
+pub mod login_lockout;
+pub mod oidc_login;
+pub mod password_history;
+pub mod recovery_code;
+pub mod role;
+pub mod role_permission;
+pub mod session;
+pub mod setting;
+pub mod signing_key;
+pub mod trash_item;
 pub mod user;
+pub mod webauthn_challenge;
+pub mod webauthn_credential;
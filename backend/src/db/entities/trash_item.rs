@@ -0,0 +1,28 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A deleted file or directory kept in the trash until it is restored or
+/// purged
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "trash_items")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    /// Where it was deleted from, and is restored to
+    pub original_path: String,
+    /// Its name inside the trash directory
+    #[serde(skip)]
+    #[sea_orm(unique)]
+    pub stored_name: String,
+    pub is_dir: bool,
+    /// Bytes in the files it contains
+    pub size: i64,
+    pub deleted_by: Option<i32>,
+    pub deleted_by_username: Option<String>,
+    pub deleted_at: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
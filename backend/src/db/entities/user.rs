@@ -9,6 +9,17 @@ pub struct Model {
     #[sea_orm(unique)]
     pub username: String,
     pub password_hash: String,
+    pub role: String,
+    pub disabled: bool,
+    pub last_login_at: Option<DateTimeUtc>,
+    pub display_name: Option<String>,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub totp_last_step: Option<i64>,
+    /// Every endpoint but the password change is blocked until this is cleared
+    pub must_change_password: bool,
+    /// `sub` claim of the linked account at the OIDC provider
+    pub oidc_subject: Option<String>,
     pub created_at: DateTimeUtc,
     pub updated_at: DateTimeUtc,
 }
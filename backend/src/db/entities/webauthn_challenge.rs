@@ -0,0 +1,21 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A registration or login ceremony waiting for the authenticator's response
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "webauthn_challenges")]
+pub struct Model {
+    /// Base64url challenge, echoed back in the client data
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub challenge: String,
+    /// Set for registrations and second-factor logins, empty for passwordless logins
+    pub user_id: Option<i32>,
+    /// `register` or `login`
+    pub purpose: String,
+    pub created_at: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,28 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A passkey or security key registered by a user
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "webauthn_credentials")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    /// Base64url credential id chosen by the authenticator
+    #[sea_orm(unique)]
+    pub credential_id: String,
+    /// Base64url COSE public key
+    pub public_key: String,
+    /// COSE algorithm identifier of the key
+    pub algorithm: i32,
+    /// Last signature counter seen, used to detect cloned authenticators
+    pub sign_count: i64,
+    pub created_at: DateTimeUtc,
+    pub last_used_at: Option<DateTimeUtc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
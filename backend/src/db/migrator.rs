@@ -5,7 +5,23 @@ pub struct Migrator;
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20240101_000001_create_users_table::Migration)]
+        vec![
+            Box::new(m20240101_000001_create_users_table::Migration),
+            Box::new(m20240102_000002_create_roles_tables::Migration),
+            Box::new(m20240103_000003_add_user_profile_columns::Migration),
+            Box::new(m20240104_000004_add_totp::Migration),
+            Box::new(m20240105_000005_create_sessions_table::Migration),
+            Box::new(m20240106_000006_create_login_throttle_tables::Migration),
+            Box::new(m20240107_000007_create_api_tokens_table::Migration),
+            Box::new(m20240108_000008_add_password_policy::Migration),
+            Box::new(m20240109_000009_create_signing_keys_table::Migration),
+            Box::new(m20240110_000010_add_oidc::Migration),
+            Box::new(m20240111_000011_create_webauthn_tables::Migration),
+            Box::new(m20240112_000012_create_settings_table::Migration),
+            Box::new(m20240113_000013_create_audit_log_table::Migration),
+            Box::new(m20240114_000014_create_trash_items_table::Migration),
+            Box::new(m20240115_000015_create_file_versions_table::Migration),
+        ]
     }
 }
 
@@ -69,3 +85,1395 @@ mod m20240101_000001_create_users_table {
         UpdatedAt,
     }
 }
+
+mod m20240102_000002_create_roles_tables {
+    use sea_orm_migration::prelude::*;
+
+    /// Built-in roles and the permissions they grant
+    const ROLES: &[(&str, &str, &[&str])] = &[
+        (
+            "viewer",
+            "Read-only access to dashboards, processes, files, services and containers",
+            &[
+                "system:read",
+                "processes:read",
+                "files:read",
+                "services:read",
+                "docker:read",
+            ],
+        ),
+        (
+            "operator",
+            "Viewer plus restarting services, containers and processes",
+            &[
+                "system:read",
+                "processes:read",
+                "processes:write",
+                "files:read",
+                "services:read",
+                "services:write",
+                "docker:read",
+                "docker:write",
+            ],
+        ),
+        (
+            "admin",
+            "Full access including the terminal, file writes and user management",
+            &[
+                "system:read",
+                "processes:read",
+                "processes:write",
+                "files:read",
+                "files:write",
+                "services:read",
+                "services:write",
+                "docker:read",
+                "docker:write",
+                "terminal:open",
+                "users:manage",
+            ],
+        ),
+    ];
+
+    pub struct Migration;
+
+    // Migrations share this file, so the name derived from `file!()` would collide
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240102_000002_create_roles_tables"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .create_table(
+                    Table::create()
+                        .table(Roles::Table)
+                        .if_not_exists()
+                        .col(ColumnDef::new(Roles::Name).string().not_null().primary_key())
+                        .col(ColumnDef::new(Roles::Description).string().not_null())
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .create_table(
+                    Table::create()
+                        .table(RolePermissions::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(RolePermissions::Id)
+                                .integer()
+                                .not_null()
+                                .auto_increment()
+                                .primary_key(),
+                        )
+                        .col(ColumnDef::new(RolePermissions::Role).string().not_null())
+                        .col(ColumnDef::new(RolePermissions::Permission).string().not_null())
+                        .foreign_key(
+                            ForeignKey::create()
+                                .from(RolePermissions::Table, RolePermissions::Role)
+                                .to(Roles::Table, Roles::Name)
+                                .on_delete(ForeignKeyAction::Cascade),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .create_index(
+                    Index::create()
+                        .name("idx_role_permissions_role_permission")
+                        .table(RolePermissions::Table)
+                        .col(RolePermissions::Role)
+                        .col(RolePermissions::Permission)
+                        .unique()
+                        .to_owned(),
+                )
+                .await?;
+
+            for (name, description, permissions) in ROLES {
+                manager
+                    .exec_stmt(
+                        Query::insert()
+                            .into_table(Roles::Table)
+                            .columns([Roles::Name, Roles::Description])
+                            .values_panic([(*name).into(), (*description).into()])
+                            .to_owned(),
+                    )
+                    .await?;
+
+                for permission in *permissions {
+                    manager
+                        .exec_stmt(
+                            Query::insert()
+                                .into_table(RolePermissions::Table)
+                                .columns([RolePermissions::Role, RolePermissions::Permission])
+                                .values_panic([(*name).into(), (*permission).into()])
+                                .to_owned(),
+                        )
+                        .await?;
+                }
+            }
+
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(
+                            ColumnDef::new(Users::Role)
+                                .string()
+                                .not_null()
+                                .default("viewer"),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+
+            // Accounts created before roles existed could already do everything
+            manager
+                .exec_stmt(
+                    Query::update()
+                        .table(Users::Table)
+                        .value(Users::Role, "admin")
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .drop_column(Users::Role)
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .drop_table(Table::drop().table(RolePermissions::Table).to_owned())
+                .await?;
+            manager
+                .drop_table(Table::drop().table(Roles::Table).to_owned())
+                .await
+        }
+    }
+
+    #[derive(Iden)]
+    enum Users {
+        Table,
+        Role,
+    }
+
+    #[derive(Iden)]
+    enum Roles {
+        Table,
+        Name,
+        Description,
+    }
+
+    #[derive(Iden)]
+    enum RolePermissions {
+        Table,
+        Id,
+        Role,
+        Permission,
+    }
+}
+
+mod m20240103_000003_add_user_profile_columns {
+    use sea_orm_migration::prelude::*;
+
+    pub struct Migration;
+
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240103_000003_add_user_profile_columns"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            // SQLite only supports one column per ALTER TABLE
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(
+                            ColumnDef::new(Users::Disabled)
+                                .boolean()
+                                .not_null()
+                                .default(false),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(ColumnDef::new(Users::LastLoginAt).timestamp_with_time_zone().null())
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(ColumnDef::new(Users::DisplayName).string().null())
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            for column in [Users::DisplayName, Users::LastLoginAt, Users::Disabled] {
+                manager
+                    .alter_table(
+                        Table::alter()
+                            .table(Users::Table)
+                            .drop_column(column)
+                            .to_owned(),
+                    )
+                    .await?;
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Iden)]
+    enum Users {
+        Table,
+        Disabled,
+        LastLoginAt,
+        DisplayName,
+    }
+}
+
+mod m20240104_000004_add_totp {
+    use sea_orm_migration::prelude::*;
+
+    pub struct Migration;
+
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240104_000004_add_totp"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(ColumnDef::new(Users::TotpSecret).string().null())
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(
+                            ColumnDef::new(Users::TotpEnabled)
+                                .boolean()
+                                .not_null()
+                                .default(false),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(ColumnDef::new(Users::TotpLastStep).big_integer().null())
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .create_table(
+                    Table::create()
+                        .table(RecoveryCodes::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(RecoveryCodes::Id)
+                                .integer()
+                                .not_null()
+                                .auto_increment()
+                                .primary_key(),
+                        )
+                        .col(ColumnDef::new(RecoveryCodes::UserId).integer().not_null())
+                        .col(ColumnDef::new(RecoveryCodes::CodeHash).string().not_null())
+                        .col(ColumnDef::new(RecoveryCodes::UsedAt).timestamp_with_time_zone().null())
+                        .col(
+                            ColumnDef::new(RecoveryCodes::CreatedAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .foreign_key(
+                            ForeignKey::create()
+                                .from(RecoveryCodes::Table, RecoveryCodes::UserId)
+                                .to(Users::Table, Users::Id)
+                                .on_delete(ForeignKeyAction::Cascade),
+                        )
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .drop_table(Table::drop().table(RecoveryCodes::Table).to_owned())
+                .await?;
+            for column in [Users::TotpLastStep, Users::TotpEnabled, Users::TotpSecret] {
+                manager
+                    .alter_table(
+                        Table::alter()
+                            .table(Users::Table)
+                            .drop_column(column)
+                            .to_owned(),
+                    )
+                    .await?;
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Iden)]
+    enum Users {
+        Table,
+        Id,
+        TotpSecret,
+        TotpEnabled,
+        TotpLastStep,
+    }
+
+    #[derive(Iden)]
+    enum RecoveryCodes {
+        Table,
+        Id,
+        UserId,
+        CodeHash,
+        UsedAt,
+        CreatedAt,
+    }
+}
+
+mod m20240105_000005_create_sessions_table {
+    use sea_orm_migration::prelude::*;
+
+    pub struct Migration;
+
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240105_000005_create_sessions_table"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .create_table(
+                    Table::create()
+                        .table(Sessions::Table)
+                        .if_not_exists()
+                        .col(ColumnDef::new(Sessions::Id).string().not_null().primary_key())
+                        .col(ColumnDef::new(Sessions::UserId).integer().not_null())
+                        .col(ColumnDef::new(Sessions::RefreshTokenHash).string().not_null())
+                        .col(ColumnDef::new(Sessions::Ip).string().null())
+                        .col(ColumnDef::new(Sessions::UserAgent).string().null())
+                        .col(
+                            ColumnDef::new(Sessions::CreatedAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .col(
+                            ColumnDef::new(Sessions::LastSeenAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .col(
+                            ColumnDef::new(Sessions::ExpiresAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .col(ColumnDef::new(Sessions::RevokedAt).timestamp_with_time_zone().null())
+                        .foreign_key(
+                            ForeignKey::create()
+                                .from(Sessions::Table, Sessions::UserId)
+                                .to(Users::Table, Users::Id)
+                                .on_delete(ForeignKeyAction::Cascade),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .create_index(
+                    Index::create()
+                        .name("idx_sessions_user_id")
+                        .table(Sessions::Table)
+                        .col(Sessions::UserId)
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .drop_table(Table::drop().table(Sessions::Table).to_owned())
+                .await
+        }
+    }
+
+    #[derive(Iden)]
+    enum Users {
+        Table,
+        Id,
+    }
+
+    #[derive(Iden)]
+    enum Sessions {
+        Table,
+        Id,
+        UserId,
+        RefreshTokenHash,
+        Ip,
+        UserAgent,
+        CreatedAt,
+        LastSeenAt,
+        ExpiresAt,
+        RevokedAt,
+    }
+}
+
+mod m20240106_000006_create_login_throttle_tables {
+    use sea_orm_migration::prelude::*;
+
+    pub struct Migration;
+
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240106_000006_create_login_throttle_tables"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .create_table(
+                    Table::create()
+                        .table(LoginAttempts::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(LoginAttempts::Id)
+                                .integer()
+                                .not_null()
+                                .auto_increment()
+                                .primary_key(),
+                        )
+                        .col(ColumnDef::new(LoginAttempts::Username).string().not_null())
+                        .col(ColumnDef::new(LoginAttempts::Ip).string().null())
+                        .col(ColumnDef::new(LoginAttempts::Success).boolean().not_null())
+                        .col(ColumnDef::new(LoginAttempts::Reason).string().not_null())
+                        .col(
+                            ColumnDef::new(LoginAttempts::CreatedAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .create_table(
+                    Table::create()
+                        .table(LoginLockouts::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(LoginLockouts::Id)
+                                .integer()
+                                .not_null()
+                                .auto_increment()
+                                .primary_key(),
+                        )
+                        .col(ColumnDef::new(LoginLockouts::Kind).string().not_null())
+                        .col(ColumnDef::new(LoginLockouts::Key).string().not_null())
+                        .col(ColumnDef::new(LoginLockouts::Failures).integer().not_null())
+                        .col(
+                            ColumnDef::new(LoginLockouts::LockedUntil)
+                                .timestamp_with_time_zone()
+                                .null(),
+                        )
+                        .col(
+                            ColumnDef::new(LoginLockouts::LastFailureAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .create_index(
+                    Index::create()
+                        .name("idx_login_lockouts_kind_key")
+                        .table(LoginLockouts::Table)
+                        .col(LoginLockouts::Kind)
+                        .col(LoginLockouts::Key)
+                        .unique()
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .drop_table(Table::drop().table(LoginLockouts::Table).to_owned())
+                .await?;
+            manager
+                .drop_table(Table::drop().table(LoginAttempts::Table).to_owned())
+                .await
+        }
+    }
+
+    #[derive(Iden)]
+    enum LoginAttempts {
+        Table,
+        Id,
+        Username,
+        Ip,
+        Success,
+        Reason,
+        CreatedAt,
+    }
+
+    #[derive(Iden)]
+    enum LoginLockouts {
+        Table,
+        Id,
+        Kind,
+        Key,
+        Failures,
+        LockedUntil,
+        LastFailureAt,
+    }
+}
+
+mod m20240107_000007_create_api_tokens_table {
+    use sea_orm_migration::prelude::*;
+
+    pub struct Migration;
+
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240107_000007_create_api_tokens_table"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .create_table(
+                    Table::create()
+                        .table(ApiTokens::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(ApiTokens::Id)
+                                .integer()
+                                .not_null()
+                                .auto_increment()
+                                .primary_key(),
+                        )
+                        .col(ColumnDef::new(ApiTokens::UserId).integer().not_null())
+                        .col(ColumnDef::new(ApiTokens::Name).string().not_null())
+                        .col(ColumnDef::new(ApiTokens::TokenPrefix).string().not_null())
+                        .col(
+                            ColumnDef::new(ApiTokens::TokenHash)
+                                .string()
+                                .not_null()
+                                .unique_key(),
+                        )
+                        .col(ColumnDef::new(ApiTokens::Scopes).string().not_null())
+                        .col(
+                            ColumnDef::new(ApiTokens::ExpiresAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .col(
+                            ColumnDef::new(ApiTokens::LastUsedAt)
+                                .timestamp_with_time_zone()
+                                .null(),
+                        )
+                        .col(ColumnDef::new(ApiTokens::LastUsedIp).string().null())
+                        .col(
+                            ColumnDef::new(ApiTokens::CreatedAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .col(
+                            ColumnDef::new(ApiTokens::RevokedAt)
+                                .timestamp_with_time_zone()
+                                .null(),
+                        )
+                        .foreign_key(
+                            ForeignKey::create()
+                                .from(ApiTokens::Table, ApiTokens::UserId)
+                                .to(Users::Table, Users::Id)
+                                .on_delete(ForeignKeyAction::Cascade),
+                        )
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .drop_table(Table::drop().table(ApiTokens::Table).to_owned())
+                .await
+        }
+    }
+
+    #[derive(Iden)]
+    enum Users {
+        Table,
+        Id,
+    }
+
+    #[derive(Iden)]
+    enum ApiTokens {
+        Table,
+        Id,
+        UserId,
+        Name,
+        TokenPrefix,
+        TokenHash,
+        Scopes,
+        ExpiresAt,
+        LastUsedAt,
+        LastUsedIp,
+        CreatedAt,
+        RevokedAt,
+    }
+}
+
+mod m20240108_000008_add_password_policy {
+    use sea_orm_migration::prelude::*;
+
+    pub struct Migration;
+
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240108_000008_add_password_policy"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(
+                            ColumnDef::new(Users::MustChangePassword)
+                                .boolean()
+                                .not_null()
+                                .default(false),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .create_table(
+                    Table::create()
+                        .table(PasswordHistory::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(PasswordHistory::Id)
+                                .integer()
+                                .not_null()
+                                .auto_increment()
+                                .primary_key(),
+                        )
+                        .col(ColumnDef::new(PasswordHistory::UserId).integer().not_null())
+                        .col(ColumnDef::new(PasswordHistory::PasswordHash).string().not_null())
+                        .col(
+                            ColumnDef::new(PasswordHistory::CreatedAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .foreign_key(
+                            ForeignKey::create()
+                                .from(PasswordHistory::Table, PasswordHistory::UserId)
+                                .to(Users::Table, Users::Id)
+                                .on_delete(ForeignKeyAction::Cascade),
+                        )
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .drop_table(Table::drop().table(PasswordHistory::Table).to_owned())
+                .await?;
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .drop_column(Users::MustChangePassword)
+                        .to_owned(),
+                )
+                .await
+        }
+    }
+
+    #[derive(Iden)]
+    enum Users {
+        Table,
+        Id,
+        MustChangePassword,
+    }
+
+    #[derive(Iden)]
+    enum PasswordHistory {
+        Table,
+        Id,
+        UserId,
+        PasswordHash,
+        CreatedAt,
+    }
+}
+
+mod m20240109_000009_create_signing_keys_table {
+    use sea_orm_migration::prelude::*;
+
+    pub struct Migration;
+
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240109_000009_create_signing_keys_table"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .create_table(
+                    Table::create()
+                        .table(SigningKeys::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(SigningKeys::Kid)
+                                .string()
+                                .not_null()
+                                .primary_key(),
+                        )
+                        .col(ColumnDef::new(SigningKeys::Secret).string().not_null())
+                        .col(
+                            ColumnDef::new(SigningKeys::CreatedAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .col(
+                            ColumnDef::new(SigningKeys::RetiredAt)
+                                .timestamp_with_time_zone()
+                                .null(),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+
+            // Key rotation and later security settings are admin-only
+            manager
+                .exec_stmt(
+                    Query::insert()
+                        .into_table(RolePermissions::Table)
+                        .columns([RolePermissions::Role, RolePermissions::Permission])
+                        .values_panic(["admin".into(), "security:manage".into()])
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .exec_stmt(
+                    Query::delete()
+                        .from_table(RolePermissions::Table)
+                        .and_where(Expr::col(RolePermissions::Permission).eq("security:manage"))
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .drop_table(Table::drop().table(SigningKeys::Table).to_owned())
+                .await
+        }
+    }
+
+    #[derive(Iden)]
+    enum SigningKeys {
+        Table,
+        Kid,
+        Secret,
+        CreatedAt,
+        RetiredAt,
+    }
+
+    #[derive(Iden)]
+    enum RolePermissions {
+        Table,
+        Role,
+        Permission,
+    }
+}
+
+mod m20240110_000010_add_oidc {
+    use sea_orm_migration::prelude::*;
+
+    pub struct Migration;
+
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240110_000010_add_oidc"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(ColumnDef::new(Users::OidcSubject).string().null())
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .create_index(
+                    Index::create()
+                        .name("idx_users_oidc_subject")
+                        .table(Users::Table)
+                        .col(Users::OidcSubject)
+                        .unique()
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .create_table(
+                    Table::create()
+                        .table(OidcLogins::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(OidcLogins::State)
+                                .string()
+                                .not_null()
+                                .primary_key(),
+                        )
+                        .col(ColumnDef::new(OidcLogins::Nonce).string().not_null())
+                        .col(ColumnDef::new(OidcLogins::CodeVerifier).string().not_null())
+                        .col(
+                            ColumnDef::new(OidcLogins::CreatedAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .drop_table(Table::drop().table(OidcLogins::Table).to_owned())
+                .await?;
+            manager
+                .drop_index(
+                    Index::drop()
+                        .name("idx_users_oidc_subject")
+                        .table(Users::Table)
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .drop_column(Users::OidcSubject)
+                        .to_owned(),
+                )
+                .await
+        }
+    }
+
+    #[derive(Iden)]
+    enum Users {
+        Table,
+        OidcSubject,
+    }
+
+    #[derive(Iden)]
+    enum OidcLogins {
+        Table,
+        State,
+        Nonce,
+        CodeVerifier,
+        CreatedAt,
+    }
+}
+
+mod m20240111_000011_create_webauthn_tables {
+    use sea_orm_migration::prelude::*;
+
+    pub struct Migration;
+
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240111_000011_create_webauthn_tables"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .create_table(
+                    Table::create()
+                        .table(WebauthnCredentials::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(WebauthnCredentials::Id)
+                                .integer()
+                                .not_null()
+                                .auto_increment()
+                                .primary_key(),
+                        )
+                        .col(ColumnDef::new(WebauthnCredentials::UserId).integer().not_null())
+                        .col(ColumnDef::new(WebauthnCredentials::Name).string().not_null())
+                        .col(
+                            ColumnDef::new(WebauthnCredentials::CredentialId)
+                                .string()
+                                .not_null()
+                                .unique_key(),
+                        )
+                        .col(ColumnDef::new(WebauthnCredentials::PublicKey).string().not_null())
+                        .col(ColumnDef::new(WebauthnCredentials::Algorithm).integer().not_null())
+                        .col(
+                            ColumnDef::new(WebauthnCredentials::SignCount)
+                                .big_integer()
+                                .not_null()
+                                .default(0),
+                        )
+                        .col(
+                            ColumnDef::new(WebauthnCredentials::CreatedAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .col(
+                            ColumnDef::new(WebauthnCredentials::LastUsedAt)
+                                .timestamp_with_time_zone()
+                                .null(),
+                        )
+                        .foreign_key(
+                            ForeignKey::create()
+                                .from(WebauthnCredentials::Table, WebauthnCredentials::UserId)
+                                .to(Users::Table, Users::Id)
+                                .on_delete(ForeignKeyAction::Cascade),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .create_table(
+                    Table::create()
+                        .table(WebauthnChallenges::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(WebauthnChallenges::Challenge)
+                                .string()
+                                .not_null()
+                                .primary_key(),
+                        )
+                        .col(ColumnDef::new(WebauthnChallenges::UserId).integer().null())
+                        .col(ColumnDef::new(WebauthnChallenges::Purpose).string().not_null())
+                        .col(
+                            ColumnDef::new(WebauthnChallenges::CreatedAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .foreign_key(
+                            ForeignKey::create()
+                                .from(WebauthnChallenges::Table, WebauthnChallenges::UserId)
+                                .to(Users::Table, Users::Id)
+                                .on_delete(ForeignKeyAction::Cascade),
+                        )
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .drop_table(Table::drop().table(WebauthnChallenges::Table).to_owned())
+                .await?;
+            manager
+                .drop_table(Table::drop().table(WebauthnCredentials::Table).to_owned())
+                .await
+        }
+    }
+
+    #[derive(Iden)]
+    enum Users {
+        Table,
+        Id,
+    }
+
+    #[derive(Iden)]
+    enum WebauthnCredentials {
+        Table,
+        Id,
+        UserId,
+        Name,
+        CredentialId,
+        PublicKey,
+        Algorithm,
+        SignCount,
+        CreatedAt,
+        LastUsedAt,
+    }
+
+    #[derive(Iden)]
+    enum WebauthnChallenges {
+        Table,
+        Challenge,
+        UserId,
+        Purpose,
+        CreatedAt,
+    }
+}
+
+mod m20240112_000012_create_settings_table {
+    use sea_orm_migration::prelude::*;
+
+    pub struct Migration;
+
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240112_000012_create_settings_table"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .create_table(
+                    Table::create()
+                        .table(Settings::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(Settings::Key)
+                                .string()
+                                .not_null()
+                                .primary_key(),
+                        )
+                        .col(ColumnDef::new(Settings::Value).text().not_null())
+                        .col(
+                            ColumnDef::new(Settings::UpdatedAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .drop_table(Table::drop().table(Settings::Table).to_owned())
+                .await
+        }
+    }
+
+    #[derive(Iden)]
+    enum Settings {
+        Table,
+        Key,
+        Value,
+        UpdatedAt,
+    }
+}
+
+mod m20240113_000013_create_audit_log_table {
+    use sea_orm_migration::prelude::*;
+
+    pub struct Migration;
+
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240113_000013_create_audit_log_table"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            // No foreign key on the user: entries outlive deleted accounts
+            manager
+                .create_table(
+                    Table::create()
+                        .table(AuditLog::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(AuditLog::Id)
+                                .integer()
+                                .not_null()
+                                .auto_increment()
+                                .primary_key(),
+                        )
+                        .col(
+                            ColumnDef::new(AuditLog::CreatedAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .col(ColumnDef::new(AuditLog::UserId).integer().null())
+                        .col(ColumnDef::new(AuditLog::Username).string().null())
+                        .col(ColumnDef::new(AuditLog::Ip).string().null())
+                        .col(ColumnDef::new(AuditLog::Action).string().not_null())
+                        .col(ColumnDef::new(AuditLog::Target).string().not_null())
+                        .col(ColumnDef::new(AuditLog::Params).text().not_null())
+                        .col(ColumnDef::new(AuditLog::Result).string().not_null())
+                        .col(ColumnDef::new(AuditLog::Error).text().null())
+                        .col(ColumnDef::new(AuditLog::PrevHash).string().not_null())
+                        .col(ColumnDef::new(AuditLog::Hash).string().not_null())
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .create_index(
+                    Index::create()
+                        .name("idx_audit_log_created_at")
+                        .table(AuditLog::Table)
+                        .col(AuditLog::CreatedAt)
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .exec_stmt(
+                    Query::insert()
+                        .into_table(RolePermissions::Table)
+                        .columns([RolePermissions::Role, RolePermissions::Permission])
+                        .values_panic(["admin".into(), "audit:read".into()])
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .exec_stmt(
+                    Query::delete()
+                        .from_table(RolePermissions::Table)
+                        .and_where(Expr::col(RolePermissions::Permission).eq("audit:read"))
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .drop_table(Table::drop().table(AuditLog::Table).to_owned())
+                .await
+        }
+    }
+
+    #[derive(Iden)]
+    enum AuditLog {
+        Table,
+        Id,
+        CreatedAt,
+        UserId,
+        Username,
+        Ip,
+        Action,
+        Target,
+        Params,
+        Result,
+        Error,
+        PrevHash,
+        Hash,
+    }
+
+    #[derive(Iden)]
+    enum RolePermissions {
+        Table,
+        Role,
+        Permission,
+    }
+}
+
+mod m20240114_000014_create_trash_items_table {
+    use sea_orm_migration::prelude::*;
+
+    pub struct Migration;
+
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240114_000014_create_trash_items_table"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            // No foreign key on the user, like the audit log
+            manager
+                .create_table(
+                    Table::create()
+                        .table(TrashItems::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(TrashItems::Id)
+                                .integer()
+                                .not_null()
+                                .auto_increment()
+                                .primary_key(),
+                        )
+                        .col(ColumnDef::new(TrashItems::OriginalPath).text().not_null())
+                        .col(
+                            ColumnDef::new(TrashItems::StoredName)
+                                .string()
+                                .not_null()
+                                .unique_key(),
+                        )
+                        .col(ColumnDef::new(TrashItems::IsDir).boolean().not_null())
+                        .col(ColumnDef::new(TrashItems::Size).big_integer().not_null())
+                        .col(ColumnDef::new(TrashItems::DeletedBy).integer().null())
+                        .col(ColumnDef::new(TrashItems::DeletedByUsername).string().null())
+                        .col(
+                            ColumnDef::new(TrashItems::DeletedAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .create_index(
+                    Index::create()
+                        .name("idx_trash_items_deleted_at")
+                        .table(TrashItems::Table)
+                        .col(TrashItems::DeletedAt)
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .drop_table(Table::drop().table(TrashItems::Table).to_owned())
+                .await
+        }
+    }
+
+    #[derive(Iden)]
+    enum TrashItems {
+        Table,
+        Id,
+        OriginalPath,
+        StoredName,
+        IsDir,
+        Size,
+        DeletedBy,
+        DeletedByUsername,
+        DeletedAt,
+    }
+}
+
+mod m20240115_000015_create_file_versions_table {
+    use sea_orm_migration::prelude::*;
+
+    pub struct Migration;
+
+    impl MigrationName for Migration {
+        fn name(&self) -> &str {
+            "m20240115_000015_create_file_versions_table"
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MigrationTrait for Migration {
+        async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .create_table(
+                    Table::create()
+                        .table(FileVersions::Table)
+                        .if_not_exists()
+                        .col(
+                            ColumnDef::new(FileVersions::Id)
+                                .integer()
+                                .not_null()
+                                .auto_increment()
+                                .primary_key(),
+                        )
+                        .col(ColumnDef::new(FileVersions::Path).text().not_null())
+                        .col(
+                            ColumnDef::new(FileVersions::StoredName)
+                                .string()
+                                .not_null()
+                                .unique_key(),
+                        )
+                        .col(ColumnDef::new(FileVersions::Size).big_integer().not_null())
+                        .col(ColumnDef::new(FileVersions::CreatedBy).integer().null())
+                        .col(ColumnDef::new(FileVersions::CreatedByUsername).string().null())
+                        .col(
+                            ColumnDef::new(FileVersions::CreatedAt)
+                                .timestamp_with_time_zone()
+                                .not_null(),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .create_index(
+                    Index::create()
+                        .name("idx_file_versions_path")
+                        .table(FileVersions::Table)
+                        .col(FileVersions::Path)
+                        .to_owned(),
+                )
+                .await
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+            manager
+                .drop_table(Table::drop().table(FileVersions::Table).to_owned())
+                .await
+        }
+    }
+
+    #[derive(Iden)]
+    enum FileVersions {
+        Table,
+        Id,
+        Path,
+        StoredName,
+        Size,
+        CreatedBy,
+        CreatedByUsername,
+        CreatedAt,
+    }
+}
@@ -1,6 +1,6 @@
 use thiserror::Error;
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -20,6 +20,21 @@ pub enum AppError {
     #[error("Permission denied: {0}")]
     Forbidden(String),
 
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Precondition failed: {0}")]
+    PreconditionFailed(String),
+
+    #[error("Precondition required: {0}")]
+    PreconditionRequired(String),
+
+    #[error("Password change required")]
+    PasswordChangeRequired,
+
+    #[error("Too many attempts, retry in {0} seconds")]
+    RateLimited(i64),
+
     #[error("System error: {0}")]
     System(String),
 
@@ -40,6 +55,11 @@ impl IntoResponse for AppError {
             AppError::Validation(msg) => (StatusCode::BAD_REQUEST, "VALIDATION_ERROR", msg.clone()),
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, "NOT_FOUND", msg.clone()),
             AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, "FORBIDDEN", msg.clone()),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, "CONFLICT", msg.clone()),
+            AppError::PreconditionFailed(msg) => (StatusCode::PRECONDITION_FAILED, "PRECONDITION_FAILED", msg.clone()),
+            AppError::PreconditionRequired(msg) => (StatusCode::PRECONDITION_REQUIRED, "PRECONDITION_REQUIRED", msg.clone()),
+            AppError::PasswordChangeRequired => (StatusCode::FORBIDDEN, "PASSWORD_CHANGE_REQUIRED", self.to_string()),
+            AppError::RateLimited(_) => (StatusCode::TOO_MANY_REQUESTS, "RATE_LIMITED", self.to_string()),
             AppError::System(msg) => (StatusCode::INTERNAL_SERVER_ERROR, "SYSTEM_ERROR", msg.clone()),
             AppError::Database(e) => (StatusCode::INTERNAL_SERVER_ERROR, "DATABASE_ERROR", e.to_string()),
             AppError::Io(e) => (StatusCode::INTERNAL_SERVER_ERROR, "IO_ERROR", e.to_string()),
@@ -54,7 +74,12 @@ impl IntoResponse for AppError {
             }
         }));
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if let AppError::RateLimited(seconds) = self {
+            response.headers_mut().insert(header::RETRY_AFTER, seconds.max(1).into());
+        }
+
+        response
     }
 }
 
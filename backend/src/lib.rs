@@ -5,12 +5,23 @@ pub mod error;
 pub mod middleware;
 pub mod services;
 
+#[cfg(test)]
+mod test_util;
+
 use sea_orm::DatabaseConnection;
 use std::sync::Arc;
 
 pub use config::Config;
+pub use services::access::AccessControl;
+pub use services::disk_usage::DiskUsage;
 pub use services::docker::DockerService;
+pub use services::jail::JailPolicy;
+pub use services::jobs::Jobs;
 pub use services::monitor::SystemMonitor;
+pub use services::signing_key::SigningKeys;
+pub use services::upload::Uploads;
+pub use services::versions::VersionService;
+pub use services::ws_ticket::WsTickets;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -18,4 +29,16 @@ pub struct AppState {
     pub monitor: SystemMonitor,
     pub db: Arc<DatabaseConnection>,
     pub docker: Option<DockerService>,
+    pub signing_keys: SigningKeys,
+    pub access: AccessControl,
+    pub ws_tickets: WsTickets,
+    pub jail: JailPolicy,
+    pub uploads: Uploads,
+    pub jobs: Jobs,
+    pub disk_usage: DiskUsage,
+    pub versions: VersionService,
+    /// Holds the directories of a test's config, removed with the last
+    /// clone of the state
+    #[cfg(test)]
+    pub scratch: Arc<tempfile::TempDir>,
 }
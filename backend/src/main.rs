@@ -1,7 +1,5 @@
-use axum::Router;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -9,7 +7,11 @@ use mana_panel_backend::{
     AppState, api,
     config::Config,
     db,
-    services::{docker::DockerService, monitor::SystemMonitor, user::UserService},
+    services::{
+        access::AccessControl, disk_usage::DiskUsage, docker::DockerService, jail::JailPolicy,
+        jobs::Jobs, monitor::SystemMonitor, signing_key::SigningKeys, upload::Uploads,
+        user::UserService, versions::VersionService, ws_ticket::WsTickets,
+    },
 };
 
 #[tokio::main]
@@ -34,6 +36,30 @@ async fn main() {
         .await
         .expect("Failed to initialize default admin user");
 
+    // Access tokens must outlive a key rotation
+    let grace = chrono::Duration::minutes(config.access_token_minutes);
+    let signing_keys = SigningKeys::load(&db, grace)
+        .await
+        .expect("Failed to load JWT signing keys");
+
+    let access = AccessControl::load(&db)
+        .await
+        .expect("Failed to load access restrictions");
+    if config.access_reset {
+        tracing::warn!("ACCESS_RESET is set, clearing the IP allowlist and security entrance");
+        access.reset(&db).await.expect("Failed to reset access restrictions");
+    } else if access.policy().entrance.is_some() {
+        tracing::info!("The API is only reachable through the security entrance");
+    }
+
+    let jail = JailPolicy::load(&db, &config)
+        .await
+        .expect("Failed to load filesystem jail settings");
+
+    let uploads = Uploads::load(&config)
+        .await
+        .expect("Failed to clean up interrupted uploads");
+
     // Initialize system monitor
     let monitor = SystemMonitor::new();
 
@@ -57,23 +83,29 @@ async fn main() {
         monitor,
         db,
         docker,
+        signing_keys,
+        access,
+        ws_tickets: WsTickets::default(),
+        jail,
+        uploads,
+        jobs: Jobs::default(),
+        disk_usage: DiskUsage::default(),
+        versions: VersionService::default(),
     };
 
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
-
-    let app = Router::new()
-        .nest("/api", api::create_router())
+    let app = api::app(state)
         .layer(TraceLayer::new_for_http())
-        .layer(cors)
-        .with_state(state);
+        .layer(api::cors(&config));
 
     let addr = SocketAddr::new(config.host.parse().expect("Invalid host"), config.port);
 
     tracing::info!("Mana Panel listening on http://{}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
@@ -0,0 +1,62 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::net::{IpAddr, SocketAddr};
+
+use crate::{error::AppError, services::access::resolve_client_ip, AppState};
+
+/// Client address after resolving trusted proxies, inserted by [`enforce_access`]
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+/// Outermost layer, running before routing: resolves the client address,
+/// applies the IP allowlist and, when a security entrance is set, only lets
+/// requests through that start with it, stripping it before routing.
+pub async fn enforce_access(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let client_ip = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| {
+            resolve_client_ip(addr.ip(), req.headers(), &state.config.trusted_proxies)
+        });
+    if let Some(ip) = client_ip {
+        req.extensions_mut().insert(ClientIp(ip));
+    }
+
+    let policy = state.access.policy();
+    if !policy.allows(client_ip) {
+        tracing::warn!("Rejected request from {:?}, not in the IP allowlist", client_ip);
+        return Err(AppError::Forbidden("Access from this address is not allowed".to_string()));
+    }
+
+    if let Some(entrance) = &policy.entrance {
+        // Anything outside the entrance looks like there is nothing here
+        let Some(uri) = strip_entrance(req.uri(), entrance) else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+        *req.uri_mut() = uri;
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// `/<entrance>/api/...` becomes `/api/...`; `None` for any other path
+fn strip_entrance(uri: &Uri, entrance: &str) -> Option<Uri> {
+    let rest = uri.path().strip_prefix('/')?.strip_prefix(entrance)?;
+    if !rest.starts_with('/') {
+        return None;
+    }
+
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", rest, query),
+        None => rest.to_string(),
+    };
+    path_and_query.parse().ok()
+}
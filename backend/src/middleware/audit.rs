@@ -0,0 +1,90 @@
+use axum::{extract::FromRequestParts, http::request::Parts};
+use sea_orm::DatabaseConnection;
+use serde_json::Value;
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::{
+    error::AppResult,
+    middleware::{auth::Claims, client::ClientInfo},
+    services::audit::{AuditService, NewAuditEntry},
+    AppState,
+};
+
+/// Records actions of the calling user in the audit log.
+///
+/// The user comes from the claims verified by
+/// [`require_auth`](crate::middleware::auth::require_auth); on public routes
+/// the handler names the user with [`Audit::for_user`] once it knows who they
+/// are. A failure to write an entry is logged but never fails the action,
+/// which has already happened by then.
+#[derive(Clone)]
+pub struct Audit {
+    db: Arc<DatabaseConnection>,
+    user_id: Option<i32>,
+    username: Option<String>,
+    ip: Option<String>,
+}
+
+impl FromRequestParts<AppState> for Audit {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let claims = parts.extensions.get::<Claims>().cloned();
+        let client = ClientInfo::from_request_parts(parts, state).await?;
+
+        Ok(Self {
+            db: state.db.clone(),
+            user_id: claims.as_ref().and_then(|claims| claims.sub.parse().ok()),
+            username: claims.map(|claims| claims.username),
+            ip: client.ip.map(|ip| ip.to_string()),
+        })
+    }
+}
+
+impl Audit {
+    pub fn for_user(mut self, user_id: Option<i32>, username: &str) -> Self {
+        self.user_id = user_id;
+        self.username = Some(username.to_string());
+        self
+    }
+
+    pub async fn record<T>(
+        &self,
+        action: &str,
+        target: &str,
+        params: Value,
+        result: &AppResult<T>,
+    ) {
+        let entry = NewAuditEntry {
+            user_id: self.user_id,
+            username: self.username.clone(),
+            ip: self.ip.clone(),
+            action: action.to_string(),
+            target: target.to_string(),
+            params,
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+
+        if let Err(e) = AuditService::append(&self.db, entry).await {
+            tracing::error!("Failed to write audit entry for {} {}: {}", action, target, e);
+        }
+    }
+
+    /// Run `action` and record its outcome
+    pub async fn run<T>(
+        &self,
+        action: &str,
+        target: &str,
+        params: Value,
+        run: impl Future<Output = AppResult<T>>,
+    ) -> AppResult<T> {
+        let result = run.await;
+        self.record(action, target, params, &result).await;
+        result
+    }
+}
@@ -1,27 +1,97 @@
 use axum::{
-    extract::FromRequestParts,
-    http::{request::Parts, StatusCode},
+    extract::{FromRequestParts, Query, Request, State},
+    http::{header, request::Parts, StatusCode},
+    middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
-use jsonwebtoken::{decode, DecodingKey, Validation};
+use jsonwebtoken::Validation;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::HashMap;
+
+use crate::{
+    error::{AppError, AppResult},
+    middleware::{
+        client::ClientInfo,
+        cookie::{self, SESSION_COOKIE},
+        permission::GrantedPermissions,
+    },
+    services::{
+        api_token::{self, ApiTokenService, API_TOKEN_PREFIX},
+        rbac::RbacService,
+        session::SessionService,
+        user::UserService,
+    },
+    AppState,
+};
+
+/// Routes that can be reached without a token, relative to the `/api` prefix.
+/// Everything else is authenticated by [`require_auth`].
+pub const PUBLIC_ROUTES: &[&str] = &[
+    "/health",
+    "/auth/login",
+    "/auth/login/totp",
+    "/auth/refresh",
+    "/auth/oidc",
+    "/auth/oidc/authorize",
+    "/auth/oidc/callback",
+    "/auth/webauthn/login/start",
+    "/auth/webauthn/login/finish",
+];
+
+/// Routes that stay reachable while the account has to change its password
+pub const PASSWORD_CHANGE_ROUTES: &[&str] =
+    &["/auth/me", "/auth/password", "/auth/password/policy", "/auth/logout"];
+
+/// WebSocket routes. Browsers reach them with a single-use ticket from
+/// `/auth/ws-ticket` in the `ticket` query parameter; the session cookie isn't
+/// accepted because a handshake can't carry a CSRF token.
+pub const TICKET_ROUTES: &[&str] = &["/terminal/ws"];
+
+/// Personal API tokens can't manage the account they belong to
+const API_TOKEN_DENIED_PREFIX: &str = "/auth/";
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub sub: String,
     pub username: String,
+    pub role: String,
+    /// Server-side session the token was issued for, empty for API tokens
+    pub sid: String,
     pub exp: usize,
+    /// Set when the request was authenticated with a personal API token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_token_id: Option<i32>,
+}
+
+/// The account a request was authenticated as, the way the database has it
+/// now. Stored by [`require_auth`] next to the [`Claims`], whose username and
+/// role date from when the token was issued.
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub username: String,
+    pub role: String,
+}
+
+impl Claims {
+    pub fn user_id(&self) -> AppResult<i32> {
+        self.sub.parse().map_err(|_| AppError::Auth("Invalid user ID".to_string()))
+    }
 }
 
-impl<S> FromRequestParts<S> for Claims
-where
-    S: Send + Sync,
-{
+impl FromRequestParts<AppState> for Claims {
     type Rejection = AuthError;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        // Already verified by the router-level auth layer
+        if let Some(claims) = parts.extensions.get::<Claims>() {
+            return Ok(claims.clone());
+        }
+
         let auth_header = parts
             .headers
             .get("Authorization")
@@ -32,19 +102,155 @@ where
             .strip_prefix("Bearer ")
             .ok_or(AuthError::InvalidToken)?;
 
-        // Get JWT secret from environment (in production, this should be more robust)
-        let secret = std::env::var("JWT_SECRET")
-            .unwrap_or_else(|_| "your-super-secret-key-change-in-production".to_string());
+        state
+            .signing_keys
+            .verify::<Claims>(token, &Validation::default())
+            .ok_or(AuthError::InvalidToken)
+    }
+}
+
+/// Router-level layer that rejects every request without a valid token,
+/// except for the paths listed in [`PUBLIC_ROUTES`].
+///
+/// The token's session must still be active and the account must still exist
+/// and be enabled. Permissions come from the
+/// account's current role, so a demotion takes effect without waiting for the
+/// token to expire. The verified claims, the [`Account`] and its permissions
+/// are stored in the request extensions, for the [`Claims`] extractor and the per-route
+/// [`require_permission`](crate::middleware::permission::require_permission) guard.
+///
+/// Personal API tokens (`mp_...`) are accepted as well; they are limited to
+/// the intersection of their scopes and the owner's current role. Browsers can
+/// also authenticate with the session cookie set at login, see
+/// [`session_claims`].
+///
+/// Accounts flagged with `must_change_password` only get through to
+/// [`PASSWORD_CHANGE_ROUTES`].
+pub async fn require_auth(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if PUBLIC_ROUTES.contains(&req.uri().path()) {
+        return Ok(next.run(req).await);
+    }
 
-        let token_data = decode::<Claims>(
-            token,
-            &DecodingKey::from_secret(secret.as_bytes()),
-            &Validation::default(),
-        )
-        .map_err(|_| AuthError::InvalidToken)?;
+    let (mut parts, body) = req.into_parts();
 
-        Ok(token_data.claims)
+    let api_token = parts
+        .headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .filter(|token| token.starts_with(API_TOKEN_PREFIX))
+        .map(|token| token.to_string());
+    if let Some(token) = api_token {
+        let must_change_password = authenticate_api_token(&state, &mut parts, &token).await?;
+        ensure_password_current(parts.uri.path(), must_change_password)?;
+        if parts.uri.path().starts_with(API_TOKEN_DENIED_PREFIX) {
+            return Err(AppError::Forbidden(
+                "API tokens cannot be used for account management".to_string(),
+            ));
+        }
+        return Ok(next.run(Request::from_parts(parts, body)).await);
     }
+
+    let claims = session_claims(&state, &mut parts).await?;
+
+    let user_id: i32 = claims.sub.parse().map_err(|_| AuthError::InvalidToken)?;
+
+    let session = SessionService::find_active(&state.db, &claims.sid)
+        .await?
+        .filter(|session| session.user_id == user_id)
+        .ok_or(AuthError::InvalidToken)?;
+    SessionService::touch(&state.db, &session).await?;
+
+    let user = UserService::find_by_id(&state.db, user_id)
+        .await?
+        .filter(|user| !user.disabled)
+        .ok_or(AuthError::InvalidToken)?;
+    ensure_password_current(parts.uri.path(), user.must_change_password)?;
+
+    let permissions = RbacService::permissions_for_role(&state.db, &user.role).await?;
+    parts.extensions.insert(claims);
+    parts.extensions.insert(GrantedPermissions(permissions));
+    parts.extensions.insert(Account { username: user.username, role: user.role });
+
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+/// Claims of the session the request belongs to, from the first credential
+/// present: the `Authorization` header, a WebSocket ticket on
+/// [`TICKET_ROUTES`], or the session cookie. With the cookie, state-changing
+/// requests also need the CSRF token.
+async fn session_claims(state: &AppState, parts: &mut Parts) -> Result<Claims, AppError> {
+    if parts.headers.contains_key(header::AUTHORIZATION) {
+        return Ok(Claims::from_request_parts(parts, state).await?);
+    }
+
+    if TICKET_ROUTES.contains(&parts.uri.path()) {
+        let query = Query::<HashMap<String, String>>::try_from_uri(&parts.uri)
+            .map(|Query(query)| query)
+            .unwrap_or_default();
+        let ticket = query.get("ticket").ok_or(AuthError::MissingToken)?;
+        return Ok(state.ws_tickets.redeem(ticket).ok_or(AuthError::InvalidToken)?);
+    }
+
+    let token = cookie::get(&parts.headers, SESSION_COOKIE).ok_or(AuthError::MissingToken)?;
+    let claims = state
+        .signing_keys
+        .verify::<Claims>(token, &Validation::default())
+        .ok_or(AuthError::InvalidToken)?;
+    cookie::check_csrf(&parts.method, &parts.headers)?;
+
+    Ok(claims)
+}
+
+fn ensure_password_current(path: &str, must_change_password: bool) -> Result<(), AppError> {
+    if must_change_password && !PASSWORD_CHANGE_ROUTES.contains(&path) {
+        return Err(AppError::PasswordChangeRequired);
+    }
+    Ok(())
+}
+
+/// Verify a personal API token and store its claims and permissions.
+/// Returns whether the owner still has to change their password.
+async fn authenticate_api_token(
+    state: &AppState,
+    parts: &mut Parts,
+    token: &str,
+) -> Result<bool, AppError> {
+    let token = ApiTokenService::authenticate(&state.db, token)
+        .await?
+        .ok_or(AuthError::InvalidToken)?;
+
+    let user = UserService::find_by_id(&state.db, token.user_id)
+        .await?
+        .filter(|user| !user.disabled)
+        .ok_or(AuthError::InvalidToken)?;
+
+    let client = ClientInfo::from_request_parts(parts, state).await.unwrap_or_default();
+    ApiTokenService::touch(&state.db, &token, client.ip.map(|ip| ip.to_string())).await?;
+
+    let scopes = api_token::token_scopes(&token);
+    let permissions = RbacService::permissions_for_role(&state.db, &user.role)
+        .await?
+        .into_iter()
+        .filter(|permission| scopes.contains(permission))
+        .collect();
+
+    parts.extensions.insert(Account { username: user.username.clone(), role: user.role.clone() });
+    parts.extensions.insert(Claims {
+        sub: user.id.to_string(),
+        username: user.username,
+        role: user.role,
+        sid: String::new(),
+        exp: token.expires_at.timestamp() as usize,
+        api_token_id: Some(token.id),
+    });
+    parts.extensions.insert(GrantedPermissions(permissions));
+
+    Ok(user.must_change_password)
 }
 
 #[derive(Debug)]
@@ -71,3 +277,12 @@ impl IntoResponse for AuthError {
         (status, body).into_response()
     }
 }
+
+impl From<AuthError> for AppError {
+    fn from(err: AuthError) -> Self {
+        match err {
+            AuthError::MissingToken => AppError::Auth("Missing authorization token".to_string()),
+            AuthError::InvalidToken => AppError::Auth("Invalid authorization token".to_string()),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts},
+};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::middleware::access::ClientIp;
+use crate::services::session::SessionOrigin;
+
+/// Address and user agent of the caller, when known. The address is the one
+/// resolved through trusted proxies by
+/// [`enforce_access`](crate::middleware::access::enforce_access), falling back
+/// to the peer address.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ip = parts
+            .extensions
+            .get::<ClientIp>()
+            .map(|ClientIp(ip)| *ip)
+            .or_else(|| {
+                parts
+                    .extensions
+                    .get::<ConnectInfo<SocketAddr>>()
+                    .map(|ConnectInfo(addr)| addr.ip())
+            });
+
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+
+        Ok(Self { ip, user_agent })
+    }
+}
+
+impl From<ClientInfo> for SessionOrigin {
+    fn from(client: ClientInfo) -> Self {
+        Self {
+            ip: client.ip.map(|ip| ip.to_string()),
+            user_agent: client.user_agent,
+        }
+    }
+}
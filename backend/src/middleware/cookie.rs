@@ -0,0 +1,99 @@
+use axum::http::{header, HeaderMap, HeaderName, Method};
+use data_encoding::BASE64URL_NOPAD;
+use rand::RngCore;
+
+use crate::error::AppError;
+
+/// Access token, sent with every request like a bearer token
+pub const SESSION_COOKIE: &str = "mana_session";
+/// Refresh token, only needed by `/auth/refresh`
+pub const REFRESH_COOKIE: &str = "mana_refresh";
+/// Readable by the page, which echoes it in [`CSRF_HEADER`]
+pub const CSRF_COOKIE: &str = "mana_csrf";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Value of a request cookie
+pub fn get<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+/// A `Set-Cookie` header for the whole site. Session cookies are always
+/// `SameSite=Strict`, so other sites can't make the browser send them.
+pub fn set(name: &str, value: &str, max_age_secs: i64, http_only: bool, secure: bool) -> String {
+    let mut cookie = format!(
+        "{}={}; Path=/; Max-Age={}; SameSite=Strict",
+        name,
+        value,
+        max_age_secs.max(0)
+    );
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    if secure {
+        cookie.push_str("; Secure");
+    }
+    cookie
+}
+
+/// `Set-Cookie` headers that remove the session cookies
+pub fn clear(secure: bool) -> [(HeaderName, String); 3] {
+    [
+        (header::SET_COOKIE, set(SESSION_COOKIE, "", 0, true, secure)),
+        (header::SET_COOKIE, set(REFRESH_COOKIE, "", 0, true, secure)),
+        (header::SET_COOKIE, set(CSRF_COOKIE, "", 0, false, secure)),
+    ]
+}
+
+pub fn generate_csrf_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE64URL_NOPAD.encode(&bytes)
+}
+
+/// Double-submit check for requests authenticated by cookie: a state-changing
+/// request has to repeat the CSRF cookie in [`CSRF_HEADER`]. Another site can
+/// make the browser send the cookie, but it can't read it to set the header.
+pub fn check_csrf(method: &Method, headers: &HeaderMap) -> Result<(), AppError> {
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(());
+    }
+
+    let cookie = get(headers, CSRF_COOKIE);
+    let header = headers.get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+    match (cookie, header) {
+        (Some(cookie), Some(header)) if constant_time_eq(cookie, header) => Ok(()),
+        _ => Err(AppError::Forbidden("Missing or invalid CSRF token".to_string())),
+    }
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_csrf_double_submit() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_static("a=1; mana_csrf=tok; b=2"));
+        assert_eq!(get(&headers, CSRF_COOKIE), Some("tok"));
+
+        assert!(check_csrf(&Method::GET, &headers).is_ok());
+        assert!(check_csrf(&Method::POST, &headers).is_err());
+        headers.insert(CSRF_HEADER, HeaderValue::from_static("other"));
+        assert!(check_csrf(&Method::POST, &headers).is_err());
+        headers.insert(CSRF_HEADER, HeaderValue::from_static("tok"));
+        assert!(check_csrf(&Method::DELETE, &headers).is_ok());
+    }
+}
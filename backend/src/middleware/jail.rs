@@ -0,0 +1,21 @@
+use axum::{extract::FromRequestParts, http::request::Parts};
+
+use crate::{error::AppError, middleware::auth::Account, services::jail::Jail, AppState};
+
+/// The filesystem jail of the calling user, by the account's current username
+/// and role as loaded by [`require_auth`](crate::middleware::auth::require_auth)
+impl FromRequestParts<AppState> for Jail {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let account = parts
+            .extensions
+            .get::<Account>()
+            .ok_or_else(|| AppError::Auth("Not authenticated".to_string()))?;
+
+        Ok(state.jail.for_user(&account.username, &account.role))
+    }
+}
@@ -1 +1,7 @@
+pub mod access;
+pub mod audit;
 pub mod auth;
+pub mod client;
+pub mod cookie;
+pub mod jail;
+pub mod permission;
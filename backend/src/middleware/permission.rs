@@ -0,0 +1,38 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::collections::HashSet;
+
+use crate::{error::AppError, services::rbac::Permission};
+
+/// Permissions of the authenticated caller, inserted by
+/// [`require_auth`](crate::middleware::auth::require_auth).
+#[derive(Debug, Clone, Default)]
+pub struct GrantedPermissions(pub HashSet<Permission>);
+
+impl GrantedPermissions {
+    pub fn has(&self, permission: Permission) -> bool {
+        self.0.contains(&permission)
+    }
+}
+
+/// Per-route guard, used as
+/// `.route_layer(from_fn_with_state(Permission::FilesWrite, require_permission))`.
+pub async fn require_permission(
+    State(permission): State<Permission>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let granted = req
+        .extensions()
+        .get::<GrantedPermissions>()
+        .is_some_and(|granted| granted.has(permission));
+
+    if !granted {
+        return Err(AppError::Forbidden(format!("Missing permission: {}", permission)));
+    }
+
+    Ok(next.run(req).await)
+}
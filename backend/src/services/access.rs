@@ -0,0 +1,202 @@
+use axum::http::HeaderMap;
+use ipnet::IpNet;
+use rand::{distributions::Alphanumeric, Rng};
+use sea_orm::DatabaseConnection;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use crate::error::AppResult;
+use crate::services::settings::SettingsService;
+
+const ALLOWLIST_KEY: &str = "access.allowlist";
+const ENTRANCE_KEY: &str = "access.entrance";
+const ENTRANCE_LENGTH: usize = 16;
+
+/// Who may reach the panel and under which path
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccessPolicy {
+    /// Networks clients must come from; empty allows everyone
+    pub allowlist: Vec<IpNet>,
+    /// Secret path segment the API has to be reached through, without slashes
+    pub entrance: Option<String>,
+}
+
+impl AccessPolicy {
+    pub fn allows(&self, ip: Option<IpAddr>) -> bool {
+        if self.allowlist.is_empty() {
+            return true;
+        }
+        ip.is_some_and(|ip| self.allowlist.iter().any(|net| net.contains(&ip)))
+    }
+}
+
+/// The access policy, persisted in the settings table and cached in memory
+/// because it is checked on every request.
+#[derive(Clone, Default)]
+pub struct AccessControl {
+    policy: Arc<RwLock<AccessPolicy>>,
+}
+
+impl AccessControl {
+    pub async fn load(db: &DatabaseConnection) -> AppResult<Self> {
+        let allowlist: Vec<String> =
+            SettingsService::get(db, ALLOWLIST_KEY).await?.unwrap_or_default();
+        let entrance: Option<String> = SettingsService::get(db, ENTRANCE_KEY).await?;
+
+        let allowlist = allowlist
+            .iter()
+            .filter_map(|network| {
+                let parsed = parse_network(network);
+                if parsed.is_none() {
+                    tracing::warn!("Ignoring invalid allowlist entry {}", network);
+                }
+                parsed
+            })
+            .collect();
+
+        Ok(Self {
+            policy: Arc::new(RwLock::new(AccessPolicy { allowlist, entrance })),
+        })
+    }
+
+    pub fn policy(&self) -> AccessPolicy {
+        self.policy.read().unwrap().clone()
+    }
+
+    pub async fn set_allowlist(
+        &self,
+        db: &DatabaseConnection,
+        allowlist: Vec<IpNet>,
+    ) -> AppResult<()> {
+        let stored: Vec<String> = allowlist.iter().map(|net| net.to_string()).collect();
+        SettingsService::set(db, ALLOWLIST_KEY, &stored).await?;
+
+        self.policy.write().unwrap().allowlist = allowlist;
+        Ok(())
+    }
+
+    /// Replace the security entrance with a new random one and return it
+    pub async fn generate_entrance(&self, db: &DatabaseConnection) -> AppResult<String> {
+        let entrance: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(ENTRANCE_LENGTH)
+            .map(char::from)
+            .collect();
+        SettingsService::set(db, ENTRANCE_KEY, &entrance).await?;
+
+        self.policy.write().unwrap().entrance = Some(entrance.clone());
+        Ok(entrance)
+    }
+
+    pub async fn clear_entrance(&self, db: &DatabaseConnection) -> AppResult<()> {
+        SettingsService::remove(db, ENTRANCE_KEY).await?;
+
+        self.policy.write().unwrap().entrance = None;
+        Ok(())
+    }
+
+    /// Lift all restrictions, the way back in after locking yourself out
+    pub async fn reset(&self, db: &DatabaseConnection) -> AppResult<()> {
+        self.set_allowlist(db, Vec::new()).await?;
+        self.clear_entrance(db).await
+    }
+}
+
+/// Parse a network in CIDR notation; a bare address is a single host
+pub fn parse_network(value: &str) -> Option<IpNet> {
+    let value = value.trim();
+    value
+        .parse::<IpNet>()
+        .ok()
+        .or_else(|| value.parse::<IpAddr>().ok().map(IpNet::from))
+        .map(|net| net.trunc())
+}
+
+/// The address of the client behind any trusted reverse proxies.
+///
+/// `X-Forwarded-For` is read from the right, where the nearest proxy appended
+/// its peer, and only as long as each hop is a trusted proxy; anything further
+/// left could have been sent by the client itself.
+pub fn resolve_client_ip(
+    peer: IpAddr,
+    headers: &HeaderMap,
+    trusted_proxies: &[IpNet],
+) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+
+    let mut client = peer.to_canonical();
+    let forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+
+    for hop in forwarded.into_iter().rev() {
+        if !is_trusted(&client) {
+            break;
+        }
+        match hop.parse::<IpAddr>() {
+            Ok(ip) => client = ip.to_canonical(),
+            Err(_) => break,
+        }
+    }
+
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn networks(values: &[&str]) -> Vec<IpNet> {
+        values.iter().map(|value| parse_network(value).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_allowlist_matches_v4_and_v6() {
+        let policy = AccessPolicy {
+            allowlist: networks(&["10.0.0.0/8", "2001:db8::/32", "192.0.2.7"]),
+            entrance: None,
+        };
+
+        for allowed in ["10.1.2.3", "2001:db8::1", "192.0.2.7"] {
+            assert!(policy.allows(allowed.parse().ok()), "{}", allowed);
+        }
+        for denied in ["11.0.0.1", "2001:db9::1", "192.0.2.8"] {
+            assert!(!policy.allows(denied.parse().ok()), "{}", denied);
+        }
+        assert!(!policy.allows(None));
+        assert!(AccessPolicy::default().allows(None));
+
+        assert_eq!(parse_network("10.1.2.3/8").unwrap().to_string(), "10.0.0.0/8");
+        assert!(parse_network("10.0.0.0/33").is_none());
+        assert!(parse_network("example.com").is_none());
+    }
+
+    #[test]
+    fn test_forwarded_for_only_trusted_from_proxies() {
+        let trusted = networks(&["172.16.0.0/12"]);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("6.6.6.6, 203.0.113.9, 172.18.0.5"),
+        );
+
+        // Through two trusted proxies; the spoofed leftmost entry is ignored
+        let peer = "172.18.0.2".parse().unwrap();
+        assert_eq!(resolve_client_ip(peer, &headers, &trusted).to_string(), "203.0.113.9");
+
+        // A direct client can't claim another address
+        let peer = "198.51.100.1".parse().unwrap();
+        assert_eq!(resolve_client_ip(peer, &headers, &trusted).to_string(), "198.51.100.1");
+        let peer = "172.18.0.2".parse().unwrap();
+        assert_eq!(resolve_client_ip(peer, &headers, &[]).to_string(), "172.18.0.2");
+
+        // IPv4 clients on a dual-stack socket
+        let peer = "::ffff:198.51.100.1".parse().unwrap();
+        assert_eq!(resolve_client_ip(peer, &HeaderMap::new(), &[]).to_string(), "198.51.100.1");
+    }
+}
@@ -0,0 +1,211 @@
+use rand::RngCore;
+use sea_orm::{entity::prelude::*, ActiveValue::Set, QueryOrder};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+use crate::db::entities::api_token;
+use crate::error::{AppError, AppResult};
+use crate::services::rbac::Permission;
+
+/// Personal API tokens start with this, which also tells them apart from JWTs
+pub const API_TOKEN_PREFIX: &str = "mp_";
+pub const MAX_TOKEN_DAYS: i64 = 365;
+
+/// Don't write `last_used_at` on every request
+const TOUCH_INTERVAL_SECONDS: i64 = 60;
+
+/// Long-lived, scoped tokens for automation. Only the SHA-256 of a token is
+/// stored; the plaintext is shown once at creation.
+pub struct ApiTokenService;
+
+impl ApiTokenService {
+    /// Create a token, returning it with its plaintext value
+    pub async fn create(
+        db: &DatabaseConnection,
+        user_id: i32,
+        name: &str,
+        scopes: &[Permission],
+        ttl: chrono::Duration,
+    ) -> AppResult<(api_token::Model, String)> {
+        let name = name.trim();
+        if name.is_empty() || name.len() > 64 {
+            return Err(AppError::Validation("Invalid token name length".to_string()));
+        }
+        if scopes.is_empty() {
+            return Err(AppError::Validation("At least one scope is required".to_string()));
+        }
+
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = format!(
+            "{}{}",
+            API_TOKEN_PREFIX,
+            data_encoding::BASE64URL_NOPAD.encode(&bytes)
+        );
+
+        let mut scopes: Vec<&str> = scopes.iter().map(|p| p.as_str()).collect();
+        scopes.sort_unstable();
+        scopes.dedup();
+
+        let now = chrono::Utc::now();
+        let model = api_token::ActiveModel {
+            id: Default::default(),
+            user_id: Set(user_id),
+            name: Set(name.to_string()),
+            token_prefix: Set(token.chars().take(API_TOKEN_PREFIX.len() + 6).collect()),
+            token_hash: Set(hash_token(&token)),
+            scopes: Set(scopes.join(" ")),
+            expires_at: Set(now + ttl),
+            last_used_at: Set(None),
+            last_used_ip: Set(None),
+            created_at: Set(now),
+            revoked_at: Set(None),
+        }
+        .insert(db)
+        .await?;
+
+        Ok((model, token))
+    }
+
+    /// Look up a presented token, if it is valid, not revoked and not expired
+    pub async fn authenticate(
+        db: &DatabaseConnection,
+        token: &str,
+    ) -> AppResult<Option<api_token::Model>> {
+        if !token.starts_with(API_TOKEN_PREFIX) {
+            return Ok(None);
+        }
+
+        let model = api_token::Entity::find()
+            .filter(api_token::Column::TokenHash.eq(hash_token(token)))
+            .filter(api_token::Column::RevokedAt.is_null())
+            .filter(api_token::Column::ExpiresAt.gt(chrono::Utc::now()))
+            .one(db)
+            .await?;
+
+        Ok(model)
+    }
+
+    /// Record use of a token, at most once per [`TOUCH_INTERVAL_SECONDS`]
+    pub async fn touch(
+        db: &DatabaseConnection,
+        token: &api_token::Model,
+        ip: Option<String>,
+    ) -> AppResult<()> {
+        let now = chrono::Utc::now();
+        let recent = token
+            .last_used_at
+            .is_some_and(|last| (now - last).num_seconds() < TOUCH_INTERVAL_SECONDS);
+        if recent && token.last_used_ip == ip {
+            return Ok(());
+        }
+
+        api_token::Entity::update_many()
+            .col_expr(api_token::Column::LastUsedAt, Expr::value(now))
+            .col_expr(api_token::Column::LastUsedIp, Expr::value(ip))
+            .filter(api_token::Column::Id.eq(token.id))
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Tokens of a user that haven't been revoked, newest first
+    pub async fn list(db: &DatabaseConnection, user_id: i32) -> AppResult<Vec<api_token::Model>> {
+        Ok(api_token::Entity::find()
+            .filter(api_token::Column::UserId.eq(user_id))
+            .filter(api_token::Column::RevokedAt.is_null())
+            .order_by_desc(api_token::Column::Id)
+            .all(db)
+            .await?)
+    }
+
+    /// Revoke one of a user's tokens
+    pub async fn revoke(db: &DatabaseConnection, user_id: i32, id: i32) -> AppResult<()> {
+        let result = api_token::Entity::update_many()
+            .col_expr(api_token::Column::RevokedAt, Expr::value(chrono::Utc::now()))
+            .filter(api_token::Column::Id.eq(id))
+            .filter(api_token::Column::UserId.eq(user_id))
+            .filter(api_token::Column::RevokedAt.is_null())
+            .exec(db)
+            .await?;
+
+        if result.rows_affected == 0 {
+            return Err(AppError::NotFound(format!("API token {} not found", id)));
+        }
+        Ok(())
+    }
+
+    /// Revoke every token of a user, when the account is disabled or its
+    /// password reset
+    pub async fn revoke_all_for_user(db: &DatabaseConnection, user_id: i32) -> AppResult<()> {
+        api_token::Entity::update_many()
+            .col_expr(api_token::Column::RevokedAt, Expr::value(chrono::Utc::now()))
+            .filter(api_token::Column::UserId.eq(user_id))
+            .filter(api_token::Column::RevokedAt.is_null())
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Scopes stored on a token. Unknown names are skipped.
+pub fn token_scopes(token: &api_token::Model) -> HashSet<Permission> {
+    token
+        .scopes
+        .split_whitespace()
+        .filter_map(|scope| scope.parse().ok())
+        .collect()
+}
+
+fn hash_token(token: &str) -> String {
+    data_encoding::HEXLOWER.encode(&Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_token_lifecycle() {
+        let state = crate::test_util::test_state().await;
+        let db = &state.db;
+        let scopes = [Permission::DockerWrite, Permission::DockerRead, Permission::DockerWrite];
+
+        let (model, token) = ApiTokenService::create(db, 1, "ci", &scopes, chrono::Duration::days(30))
+            .await
+            .unwrap();
+        assert!(token.starts_with(API_TOKEN_PREFIX));
+        assert!(token.starts_with(&model.token_prefix));
+        assert_ne!(model.token_hash, token);
+        assert_eq!(model.scopes, "docker:read docker:write");
+        assert_eq!(token_scopes(&model).len(), 2);
+
+        let found = ApiTokenService::authenticate(db, &token).await.unwrap().unwrap();
+        assert_eq!(found.id, model.id);
+        assert!(ApiTokenService::authenticate(db, "mp_nope").await.unwrap().is_none());
+
+        ApiTokenService::revoke(db, 1, model.id).await.unwrap();
+        assert!(ApiTokenService::authenticate(db, &token).await.unwrap().is_none());
+        assert!(ApiTokenService::revoke(db, 1, model.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expired_token_is_rejected() {
+        let state = crate::test_util::test_state().await;
+        let db = &state.db;
+
+        let (_, token) = ApiTokenService::create(
+            db,
+            1,
+            "old",
+            &[Permission::FilesRead],
+            chrono::Duration::seconds(-1),
+        )
+        .await
+        .unwrap();
+
+        assert!(ApiTokenService::authenticate(db, &token).await.unwrap().is_none());
+    }
+}
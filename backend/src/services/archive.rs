@@ -0,0 +1,649 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+use zip::{result::ZipError, write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::error::{AppError, AppResult};
+use crate::services::files::{self, ConflictPolicy};
+use crate::services::jail::{Jail, Resolved};
+use crate::services::jobs::{copy_with_progress, JobHandle, ProgressReader};
+
+/// Skipped entries listed in an extraction result; the count is always exact
+const MAX_LISTED_SKIPS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+    TarXz,
+    TarZst,
+}
+
+const EXTENSIONS: &[(&str, ArchiveFormat)] = &[
+    (".zip", ArchiveFormat::Zip),
+    (".tar.gz", ArchiveFormat::TarGz),
+    (".tgz", ArchiveFormat::TarGz),
+    (".tar.xz", ArchiveFormat::TarXz),
+    (".txz", ArchiveFormat::TarXz),
+    (".tar.zst", ArchiveFormat::TarZst),
+    (".tzst", ArchiveFormat::TarZst),
+];
+
+impl ArchiveFormat {
+    /// Tell the format from a file name
+    pub fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        EXTENSIONS.iter().find(|(ext, _)| name.ends_with(ext)).map(|(_, format)| *format)
+    }
+}
+
+fn zip_error(e: ZipError) -> AppError {
+    match e {
+        ZipError::Io(e) => e.into(),
+        e => AppError::Validation(format!("Zip archive: {}", e)),
+    }
+}
+
+/// Pack `sources` into a new archive at `destination`, each under its own
+/// name and directories with everything below them. Symlinks are stored as
+/// links and never followed, so nothing from outside the sources ends up in
+/// the archive.
+pub fn compress(
+    sources: &[PathBuf],
+    destination: &Resolved,
+    format: ArchiveFormat,
+    conflict: ConflictPolicy,
+    handle: &JobHandle,
+) -> AppResult<Value> {
+    // Written next to the destination, then moved into place
+    let temp = destination.sibling(format!(".mana-archive-{}.part", uuid::Uuid::new_v4()))?;
+    let entries = Entries { sources, exclude: &temp };
+
+    let mut total = 0;
+    entries.walk(&mut |_, path, metadata| {
+        handle.check_cancelled()?;
+        if metadata.is_file() && path != &*temp {
+            total += metadata.len();
+        }
+        Ok(())
+    })?;
+    handle.set_total(total);
+
+    let file = temp.create_new()?;
+    let result = write_archive(&entries, file, format, handle).and_then(|count| {
+        let size = temp.symlink_metadata()?.len();
+        let placed = files::place(&temp, destination, conflict).map_err(files::conflict_error)?;
+        Ok(json!({
+            "path": placed,
+            "skipped": placed.is_none(),
+            "entries": count,
+            "size": size,
+        }))
+    });
+    if result.is_err() {
+        temp.remove().ok();
+    }
+    result
+}
+
+/// The files to archive, walked without following symlinks
+struct Entries<'a> {
+    sources: &'a [PathBuf],
+    /// The archive being written, in case it lies inside a source
+    exclude: &'a Path,
+}
+
+impl Entries<'_> {
+    /// Call `visit` with the name in the archive, the path and the `lstat`
+    /// metadata of every entry
+    fn walk(
+        &self,
+        visit: &mut dyn FnMut(&Path, &Path, &fs::Metadata) -> AppResult<()>,
+    ) -> AppResult<()> {
+        for source in self.sources {
+            let base = source.parent().unwrap_or(source);
+            let walk = WalkDir::new(source).follow_links(false).sort_by_file_name();
+            for entry in walk {
+                let entry = entry.map_err(io::Error::from)?;
+                if entry.path() == self.exclude {
+                    continue;
+                }
+                let name = entry.path().strip_prefix(base).unwrap_or(entry.path());
+                visit(name, entry.path(), &entry.metadata().map_err(io::Error::from)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write the archive to `path` and return the number of entries
+fn write_archive(
+    entries: &Entries,
+    file: File,
+    format: ArchiveFormat,
+    handle: &JobHandle,
+) -> AppResult<u64> {
+    let file = BufWriter::new(file);
+    let (file, count) = match format {
+        ArchiveFormat::Zip => write_zip(file, entries, handle)?,
+        ArchiveFormat::TarGz => {
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            let (encoder, count) = write_tar(encoder, entries, handle)?;
+            (encoder.finish()?, count)
+        }
+        ArchiveFormat::TarXz => {
+            let encoder = xz2::write::XzEncoder::new(file, 6);
+            let (encoder, count) = write_tar(encoder, entries, handle)?;
+            (encoder.finish()?, count)
+        }
+        ArchiveFormat::TarZst => {
+            let encoder = zstd::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            let (encoder, count) = write_tar(encoder, entries, handle)?;
+            (encoder.finish()?, count)
+        }
+    };
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(count)
+}
+
+fn write_tar<W: Write>(writer: W, entries: &Entries, handle: &JobHandle) -> AppResult<(W, u64)> {
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+    let mut count = 0;
+
+    entries.walk(&mut |name, path, metadata| {
+        handle.check_cancelled()?;
+        handle.set_current(name.to_string_lossy());
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(metadata);
+
+        let file_type = metadata.file_type();
+        if file_type.is_file() {
+            let file = files::open_nofollow(path)?.take(metadata.len());
+            builder.append_data(&mut header, name, ProgressReader::new(file, handle))?;
+        } else if file_type.is_dir() {
+            builder.append_data(&mut header, name, io::empty())?;
+        } else if file_type.is_symlink() {
+            builder.append_link(&mut header, name, fs::read_link(path)?)?;
+        } else {
+            // Sockets, pipes and devices have no content worth archiving
+            return Ok(());
+        }
+        count += 1;
+        Ok(())
+    })?;
+
+    Ok((builder.into_inner()?, count))
+}
+
+fn write_zip<W: Write + io::Seek>(
+    writer: W,
+    entries: &Entries,
+    handle: &JobHandle,
+) -> AppResult<(W, u64)> {
+    let mut zip = ZipWriter::new(writer);
+    let mut count = 0;
+
+    entries.walk(&mut |name, path, metadata| {
+        handle.check_cancelled()?;
+        handle.set_current(name.to_string_lossy());
+        // Zip always separates with `/`, which is what Unix paths use too
+        let name = name.to_string_lossy();
+        let mut options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .large_file(metadata.len() >= u32::MAX as u64);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            options = options.unix_permissions(metadata.permissions().mode() & 0o7777);
+        }
+
+        let file_type = metadata.file_type();
+        if file_type.is_file() {
+            zip.start_file(name, options).map_err(zip_error)?;
+            let file = files::open_nofollow(path)?.take(metadata.len());
+            copy_with_progress(file, &mut zip, handle)?;
+        } else if file_type.is_dir() {
+            zip.add_directory(name, options).map_err(zip_error)?;
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(path)?;
+            zip.add_symlink(name, target.to_string_lossy(), options).map_err(zip_error)?;
+        } else {
+            return Ok(());
+        }
+        count += 1;
+        Ok(())
+    })?;
+
+    Ok((zip.finish().map_err(zip_error)?, count))
+}
+
+/// Unpack `archive` into the directory `destination`.
+///
+/// Entries can't land outside `destination`: names that are absolute or
+/// climb with `..` are skipped (zip slip), and so are symlinks pointing out
+/// of it. Each entry's directory is also resolved through the jail and has
+/// to lie inside `destination`, so that a link, from the archive or already
+/// on disk, can't redirect a later entry. Files are written to a temporary
+/// name and moved into place under `conflict`.
+pub fn extract(
+    archive: &Resolved,
+    destination: &Path,
+    format: ArchiveFormat,
+    conflict: ConflictPolicy,
+    jail: &Jail,
+    handle: &JobHandle,
+) -> AppResult<Value> {
+    let destination = jail.resolve(destination)?;
+    destination.create_dir_all()?;
+    let mut extractor = Extractor {
+        destination: destination.into_path(),
+        jail,
+        conflict,
+        extracted: 0,
+        skipped: Vec::new(),
+        skipped_count: 0,
+    };
+
+    let file = archive.open()?;
+    match format {
+        ArchiveFormat::Zip => extract_zip(file, &mut extractor, handle)?,
+        _ => {
+            // Progress counts the compressed bytes, the only size known upfront
+            handle.set_total(file.metadata()?.len());
+            let reader = ProgressReader::new(BufReader::new(file), handle);
+            match format {
+                ArchiveFormat::TarGz => {
+                    extract_tar(flate2::read::GzDecoder::new(reader), &mut extractor, handle)?
+                }
+                ArchiveFormat::TarXz => {
+                    extract_tar(xz2::read::XzDecoder::new(reader), &mut extractor, handle)?
+                }
+                _ => extract_tar(zstd::Decoder::new(reader)?, &mut extractor, handle)?,
+            }
+        }
+    }
+
+    Ok(json!({
+        "destination": extractor.destination,
+        "extracted": extractor.extracted,
+        "skipped": extractor.skipped,
+        "skipped_count": extractor.skipped_count,
+    }))
+}
+
+fn extract_tar(reader: impl Read, extractor: &mut Extractor, handle: &JobHandle) -> AppResult<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        handle.check_cancelled()?;
+        let mut entry = entry?;
+        let name = entry.path()?.into_owned();
+        handle.set_current(name.to_string_lossy());
+
+        match entry.header().entry_type() {
+            tar::EntryType::Directory => extractor.directory(&name)?,
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let mode = entry.header().mode().ok();
+                extractor.file(&name, &mut entry, mode)?;
+            }
+            tar::EntryType::Symlink => match entry.link_name()? {
+                Some(target) => extractor.symlink(&name, &target)?,
+                None => extractor.skip(&name),
+            },
+            // Hard links could point anywhere on disk, devices aren't for us
+            _ => extractor.skip(&name),
+        }
+    }
+    Ok(())
+}
+
+fn extract_zip(file: File, extractor: &mut Extractor, handle: &JobHandle) -> AppResult<()> {
+    let mut zip = ZipArchive::new(BufReader::new(file)).map_err(zip_error)?;
+    let mut total = 0;
+    for i in 0..zip.len() {
+        total += zip.by_index_raw(i).map_err(zip_error)?.size();
+    }
+    handle.set_total(total);
+
+    for i in 0..zip.len() {
+        handle.check_cancelled()?;
+        let mut entry = zip.by_index(i).map_err(zip_error)?;
+        let name = PathBuf::from(entry.name());
+        handle.set_current(entry.name());
+
+        if entry.is_dir() {
+            extractor.directory(&name)?;
+        } else if entry.is_symlink() {
+            let mut target = String::new();
+            entry.read_to_string(&mut target)?;
+            extractor.symlink(&name, Path::new(&target))?;
+        } else if entry.is_file() {
+            let mode = entry.unix_mode();
+            extractor.file(&name, &mut ProgressReader::new(&mut entry, handle), mode)?;
+        } else {
+            extractor.skip(&name);
+        }
+    }
+    Ok(())
+}
+
+struct Extractor<'a> {
+    /// Canonical, so that containment is a prefix check
+    destination: PathBuf,
+    jail: &'a Jail,
+    conflict: ConflictPolicy,
+    extracted: u64,
+    skipped: Vec<String>,
+    skipped_count: u64,
+}
+
+impl Extractor<'_> {
+    fn skip(&mut self, name: &Path) {
+        tracing::debug!("Skipping archive entry {}", name.display());
+        self.skipped_count += 1;
+        if self.skipped.len() < MAX_LISTED_SKIPS {
+            self.skipped.push(name.to_string_lossy().to_string());
+        }
+    }
+
+    /// The path of an entry below the destination, `None` for names that
+    /// would leave it
+    fn relative(name: &Path) -> Option<PathBuf> {
+        let mut relative = PathBuf::new();
+        for component in name.components() {
+            match component {
+                Component::Normal(part) => relative.push(part),
+                Component::CurDir => {}
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+            }
+        }
+        (!relative.as_os_str().is_empty()).then_some(relative)
+    }
+
+    /// Create a directory inside the destination and return it resolved
+    fn make_dir(&self, relative: &Path) -> AppResult<Resolved> {
+        let dir = self.jail.resolve(self.destination.join(relative))?;
+        if !dir.starts_with(&self.destination) {
+            return Err(AppError::Forbidden(format!(
+                "{} leads outside the destination",
+                relative.display()
+            )));
+        }
+        dir.create_dir_all()?;
+        Ok(dir)
+    }
+
+    /// Where a new entry goes, in its real directory, created if needed.
+    /// `None` if the entry has to be skipped.
+    fn prepare(&mut self, name: &Path) -> AppResult<Option<Resolved>> {
+        let Some(relative) = Self::relative(name) else {
+            self.skip(name);
+            return Ok(None);
+        };
+        let dir = match relative.parent() {
+            Some(parent) => self.make_dir(parent).map(Resolved::into_path),
+            None => Ok(self.destination.clone()),
+        };
+        let dir = match dir {
+            Ok(dir) => dir,
+            Err(AppError::Forbidden(_)) => {
+                self.skip(name);
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        let target = self.jail.resolve_link(dir.join(relative.file_name().unwrap_or_default()))?;
+        Ok(Some(target))
+    }
+
+    fn directory(&mut self, name: &Path) -> AppResult<()> {
+        let Some(relative) = Self::relative(name) else {
+            self.skip(name);
+            return Ok(());
+        };
+        match self.make_dir(&relative) {
+            Ok(_) => self.extracted += 1,
+            Err(AppError::Forbidden(_)) => self.skip(name),
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    fn file(&mut self, name: &Path, content: &mut dyn Read, mode: Option<u32>) -> AppResult<()> {
+        let Some(target) = self.prepare(name)? else {
+            return Ok(());
+        };
+
+        let temp = target.sibling(format!(".mana-extract-{}.part", uuid::Uuid::new_v4()))?;
+        let result = (|| {
+            let mut file = temp.create_new()?;
+            io::copy(content, &mut file)?;
+            #[cfg(unix)]
+            if let Some(mode) = mode {
+                use std::os::unix::fs::PermissionsExt;
+                // Never setuid or setgid files from an archive
+                file.set_permissions(fs::Permissions::from_mode(mode & 0o777))?;
+            }
+            #[cfg(not(unix))]
+            let _ = mode;
+            files::place(&temp, &target, self.conflict)
+        })();
+        match result {
+            Ok(Some(_)) => self.extracted += 1,
+            Ok(None) => self.skip(name),
+            Err(e) => {
+                temp.remove().ok();
+                return Err(files::conflict_error(e));
+            }
+        }
+        Ok(())
+    }
+
+    fn symlink(&mut self, name: &Path, link: &Path) -> AppResult<()> {
+        // The link has to resolve inside the destination from where it sits
+        let depth = Self::relative(name).map_or(0, |relative| relative.components().count());
+        let mut level = depth as isize - 1;
+        let inside = !link.is_absolute()
+            && depth > 0
+            && link.components().all(|component| {
+                match component {
+                    Component::ParentDir => level -= 1,
+                    Component::Normal(_) => level += 1,
+                    _ => {}
+                }
+                level >= 0
+            });
+        if !inside {
+            self.skip(name);
+            return Ok(());
+        }
+
+        #[cfg(unix)]
+        {
+            let Some(target) = self.prepare(name)? else {
+                return Ok(());
+            };
+            let temp = target.sibling(format!(".mana-extract-{}.part", uuid::Uuid::new_v4()))?;
+            temp.create_symlink(link)?;
+            match files::place(&temp, &target, self.conflict) {
+                Ok(Some(_)) => self.extracted += 1,
+                Ok(None) => self.skip(name),
+                Err(e) => {
+                    temp.remove().ok();
+                    return Err(files::conflict_error(e));
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        self.skip(name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::jobs::{JobStatus, Jobs};
+
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mana-archive-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::canonicalize(dir).unwrap()
+    }
+
+    async fn run(
+        jobs: &Jobs,
+        work: impl FnOnce(&JobHandle) -> AppResult<Value> + Send + 'static,
+    ) -> Value {
+        let job = jobs.spawn_blocking(1, "test", String::new(), work);
+        let info = jobs.wait(&job.id, 1).await;
+        assert_eq!(info.status, JobStatus::Completed, "{:?}", info.error);
+        info.result.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_every_format() {
+        let jobs = Jobs::default();
+        let dir = scratch();
+        let source = dir.join("site");
+        fs::create_dir_all(source.join("css")).unwrap();
+        fs::write(source.join("index.html"), "<h1>hi</h1>").unwrap();
+        fs::write(source.join("css/main.css"), "body {}").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("css/main.css", source.join("style.css")).unwrap();
+
+        for (format, name) in [
+            (ArchiveFormat::Zip, "site.zip"),
+            (ArchiveFormat::TarGz, "site.tar.gz"),
+            (ArchiveFormat::TarXz, "site.tar.xz"),
+            (ArchiveFormat::TarZst, "site.tar.zst"),
+        ] {
+            assert_eq!(ArchiveFormat::detect(Path::new(name)), Some(format));
+            let (sources, archive) = (vec![source.clone()], dir.join(name));
+            let result = run(&jobs, move |handle| {
+                let archive = Jail::default().resolve(&archive)?;
+                compress(&sources, &archive, format, ConflictPolicy::Overwrite, handle)
+            })
+            .await;
+            assert_eq!(result["skipped"], false);
+
+            let out = dir.join(format!("out-{}", name));
+            let (archive, target) = (dir.join(name), out.clone());
+            let result = run(&jobs, move |handle| {
+                let jail = Jail::default();
+                let archive = jail.resolve(&archive)?;
+                extract(&archive, &target, format, ConflictPolicy::Overwrite, &jail, handle)
+            })
+            .await;
+            assert_eq!(result["skipped_count"], 0, "{}", name);
+            let css = fs::read_to_string(out.join("site/css/main.css")).unwrap();
+            assert_eq!(css, "body {}");
+            #[cfg(unix)]
+            assert_eq!(
+                fs::read_link(out.join("site/style.css")).unwrap(),
+                Path::new("css/main.css")
+            );
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_extraction_never_overwrites_a_directory() {
+        let jobs = Jobs::default();
+        let dir = scratch();
+        let archive = dir.join("site.tar.gz");
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            File::create(&archive).unwrap(),
+            flate2::Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(2);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "site", &b"hi"[..]).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let target = dir.join("out");
+        fs::create_dir_all(target.join("site")).unwrap();
+        fs::write(target.join("site/index.html"), "<h1>hi</h1>").unwrap();
+
+        let destination = target.clone();
+        let job = jobs.spawn_blocking(1, "test", String::new(), move |handle| {
+            let (format, conflict) = (ArchiveFormat::TarGz, ConflictPolicy::Overwrite);
+            let archive = Jail::default().resolve(&archive)?;
+            extract(&archive, &destination, format, conflict, &Jail::default(), handle)
+        });
+        let info = jobs.wait(&job.id, 1).await;
+        assert_eq!(info.status, JobStatus::Failed);
+        let index = fs::read_to_string(target.join("site/index.html")).unwrap();
+        assert_eq!(index, "<h1>hi</h1>");
+        assert_eq!(fs::read_dir(&target).unwrap().count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_extraction_stays_inside_destination() {
+        let jobs = Jobs::default();
+        let dir = scratch();
+        let archive = dir.join("evil.tar.gz");
+
+        // Hand-built, since tar::Builder refuses to write such names
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            File::create(&archive).unwrap(),
+            flate2::Compression::default(),
+        ));
+        let mut add = |name: &str, kind: tar::EntryType, link: Option<&str>, data: &[u8]| {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(kind);
+            if let Some(link) = link {
+                header.set_link_name(link).unwrap();
+            }
+            header.set_size(data.len() as u64);
+            header.set_mode(0o4755);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        };
+        add("../escaped.txt", tar::EntryType::Regular, None, b"x");
+        add("/tmp/absolute.txt", tar::EntryType::Regular, None, b"x");
+        add("out", tar::EntryType::Symlink, Some(".."), b"");
+        add("out/through-link.txt", tar::EntryType::Regular, None, b"x");
+        add("etc", tar::EntryType::Symlink, Some("/etc"), b"");
+        add("ok/file.txt", tar::EntryType::Regular, None, b"fine");
+        builder.into_inner().unwrap().finish().unwrap();
+
+        // A link already on disk can't redirect entries either
+        let target = dir.join("out");
+        fs::create_dir_all(&target).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&dir, target.join("out")).unwrap();
+
+        let (source, destination) = (archive.clone(), target.clone());
+        let result = run(&jobs, move |handle| {
+            let (format, conflict) = (ArchiveFormat::TarGz, ConflictPolicy::Overwrite);
+            let source = Jail::default().resolve(&source)?;
+            extract(&source, &destination, format, conflict, &Jail::default(), handle)
+        })
+        .await;
+
+        assert_eq!(result["extracted"], 1);
+        assert_eq!(result["skipped_count"], 5);
+        assert!(!dir.join("escaped.txt").exists());
+        assert!(!dir.join("through-link.txt").exists());
+        assert!(!target.join("etc").exists());
+        assert_eq!(fs::read_to_string(target.join("ok/file.txt")).unwrap(), "fine");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(target.join("ok/file.txt")).unwrap().permissions().mode();
+            assert_eq!(mode & 0o7777, 0o755);
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
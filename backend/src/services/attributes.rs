@@ -0,0 +1,429 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::error::{AppError, AppResult};
+use crate::services::jail::{Jail, Resolved};
+
+/// Errors listed in a recursive change; the count is always exact
+const MAX_LISTED_ERRORS: usize = 100;
+
+/// Parse an octal mode like `755` or `2775`
+pub fn parse_mode(mode: &str) -> AppResult<u32> {
+    u32::from_str_radix(mode.trim(), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| AppError::Validation(format!("Invalid permission mode: {}", mode)))
+}
+
+/// The mode as `ls -l` shows it, e.g. `drwxr-sr-x` or `-rwsr-xr-x`
+pub fn symbolic_mode(metadata: &Metadata) -> String {
+    let mut symbolic = String::with_capacity(10);
+    symbolic.push(type_char(metadata));
+
+    let mode = mode(metadata);
+    // (read, write, execute, special bit, letter for special bit)
+    let triplets = [
+        (0o400, 0o200, 0o100, 0o4000, 's'),
+        (0o040, 0o020, 0o010, 0o2000, 's'),
+        (0o004, 0o002, 0o001, 0o1000, 't'),
+    ];
+    for (read, write, execute, special, letter) in triplets {
+        symbolic.push(if mode & read != 0 { 'r' } else { '-' });
+        symbolic.push(if mode & write != 0 { 'w' } else { '-' });
+        symbolic.push(match (mode & execute != 0, mode & special != 0) {
+            (true, true) => letter,
+            (false, true) => letter.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    symbolic
+}
+
+#[cfg(unix)]
+fn mode(metadata: &Metadata) -> u32 {
+    std::os::unix::fs::PermissionsExt::mode(&metadata.permissions())
+}
+
+#[cfg(not(unix))]
+fn mode(metadata: &Metadata) -> u32 {
+    if metadata.permissions().readonly() { 0o555 } else { 0o755 }
+}
+
+fn type_char(metadata: &Metadata) -> char {
+    let file_type = metadata.file_type();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_block_device() {
+            return 'b';
+        } else if file_type.is_char_device() {
+            return 'c';
+        } else if file_type.is_fifo() {
+            return 'p';
+        } else if file_type.is_socket() {
+            return 's';
+        }
+    }
+    if file_type.is_symlink() {
+        'l'
+    } else if file_type.is_dir() {
+        'd'
+    } else {
+        '-'
+    }
+}
+
+/// Names of users and groups from the system account databases, looked up
+/// once per id. Ids without an account show as the number.
+#[derive(Default)]
+pub struct Accounts {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl Accounts {
+    pub fn user_name(&mut self, uid: u32) -> String {
+        self.users
+            .entry(uid)
+            .or_insert_with(|| sys::user_name(uid).unwrap_or_else(|| uid.to_string()))
+            .clone()
+    }
+
+    pub fn group_name(&mut self, gid: u32) -> String {
+        self.groups
+            .entry(gid)
+            .or_insert_with(|| sys::group_name(gid).unwrap_or_else(|| gid.to_string()))
+            .clone()
+    }
+
+    /// Owner and group of a file, as names
+    pub fn owner(&mut self, metadata: &Metadata) -> (String, String) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            (self.user_name(metadata.uid()), self.group_name(metadata.gid()))
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = metadata;
+            (String::new(), String::new())
+        }
+    }
+}
+
+/// A user by name or numeric id
+pub fn lookup_user(user: &str) -> AppResult<u32> {
+    user.parse()
+        .ok()
+        .or_else(|| sys::user_id(user))
+        .ok_or_else(|| AppError::Validation(format!("Unknown user: {}", user)))
+}
+
+/// A group by name or numeric id
+pub fn lookup_group(group: &str) -> AppResult<u32> {
+    group
+        .parse()
+        .ok()
+        .or_else(|| sys::group_id(group))
+        .ok_or_else(|| AppError::Validation(format!("Unknown group: {}", group)))
+}
+
+/// What to change; every part is optional
+#[derive(Debug, Clone, Default)]
+pub struct AttributeChange {
+    pub mode: Option<u32>,
+    /// Mode for files, overriding `mode`
+    pub file_mode: Option<u32>,
+    /// Mode for directories, overriding `mode`
+    pub dir_mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl AttributeChange {
+    /// The mode an entry gets, `None` to leave it alone
+    fn mode_for(&self, metadata: &Metadata) -> Option<u32> {
+        if metadata.is_dir() {
+            self.dir_mode.or(self.mode)
+        } else if metadata.is_file() {
+            self.file_mode.or(self.mode)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ChangeSummary {
+    pub changed: u64,
+    pub failed: u64,
+    pub errors: Vec<String>,
+}
+
+/// Apply `change` to `root`, and with `recursive` to everything below it
+/// that the jail allows. Symlinks are never followed: a link gets the new
+/// owner itself and keeps its mode, which Linux doesn't have.
+///
+/// Without `recursive` an error is returned as such; a recursive change
+/// carries on and reports failures in the summary.
+pub fn apply(
+    root: &Resolved,
+    change: &AttributeChange,
+    recursive: bool,
+    jail: &Jail,
+) -> AppResult<ChangeSummary> {
+    let mut summary = ChangeSummary::default();
+    if !recursive {
+        // Owner first: chown clears the setuid and setgid bits
+        let metadata = root.symlink_metadata()?;
+        if change.uid.is_some() || change.gid.is_some() {
+            root.set_owner(change.uid, change.gid)?;
+        }
+        if let Some(mode) = change.mode_for(&metadata) {
+            root.set_mode(mode)?;
+        }
+        summary.changed = 1;
+        return Ok(summary);
+    }
+
+    jail.check_tree(root)?;
+    let walk = WalkDir::new(root)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|entry| jail.check(entry.path()).is_ok());
+    for entry in walk {
+        let (path, result) = match entry {
+            Ok(entry) => {
+                let result = entry
+                    .metadata()
+                    .map_err(|e| AppError::from(io::Error::from(e)))
+                    .and_then(|metadata| apply_one(entry.path(), &metadata, change));
+                (entry.into_path(), result)
+            }
+            Err(e) => {
+                let path = e.path().map(Path::to_path_buf).unwrap_or_default();
+                (path, Err(io::Error::from(e).into()))
+            }
+        };
+        match result {
+            Ok(()) => summary.changed += 1,
+            Err(e) => {
+                summary.failed += 1;
+                if summary.errors.len() < MAX_LISTED_ERRORS {
+                    summary.errors.push(format!("{}: {}", path.display(), e));
+                }
+            }
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(unix)]
+fn apply_one(path: &Path, metadata: &Metadata, change: &AttributeChange) -> AppResult<()> {
+    // Owner first: chown clears the setuid and setgid bits
+    if change.uid.is_some() || change.gid.is_some() {
+        std::os::unix::fs::lchown(path, change.uid, change.gid)?;
+    }
+
+    if let Some(mode) = change.mode_for(metadata) {
+        // Without following links, in case the entry was swapped for one
+        // since it was looked at
+        sys::chmod_nofollow(path, mode)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn apply_one(_path: &Path, _metadata: &Metadata, _change: &AttributeChange) -> AppResult<()> {
+    Err(AppError::Validation("Permissions can only be changed on Unix".to_string()))
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::{CStr, CString};
+
+    /// Call a reentrant `getpw*_r` or `getgr*_r` function, growing the
+    /// buffer while it reports `ERANGE`
+    fn with_buffer<T>(
+        mut call: impl FnMut(&mut [libc::c_char]) -> (libc::c_int, Option<T>),
+    ) -> Option<T> {
+        let mut buffer = vec![0; 1024];
+        loop {
+            match call(&mut buffer) {
+                (libc::ERANGE, _) if buffer.len() < 1 << 20 => buffer.resize(buffer.len() * 2, 0),
+                (0, found) => return found,
+                _ => return None,
+            }
+        }
+    }
+
+    fn passwd(
+        lookup: impl Fn(*mut libc::passwd, &mut [libc::c_char], *mut *mut libc::passwd) -> i32,
+    ) -> Option<(String, u32)> {
+        with_buffer(|buffer| {
+            // SAFETY: the lookup fills `entry` with pointers into `buffer`,
+            // which are read before either goes away
+            unsafe {
+                let mut entry: libc::passwd = std::mem::zeroed();
+                let mut result = std::ptr::null_mut();
+                let code = lookup(&mut entry, buffer, &mut result);
+                let found = (!result.is_null()).then(|| {
+                    let name = CStr::from_ptr(entry.pw_name).to_string_lossy().into_owned();
+                    (name, entry.pw_uid)
+                });
+                (code, found)
+            }
+        })
+    }
+
+    fn group(
+        lookup: impl Fn(*mut libc::group, &mut [libc::c_char], *mut *mut libc::group) -> i32,
+    ) -> Option<(String, u32)> {
+        with_buffer(|buffer| {
+            // SAFETY: as for `passwd`
+            unsafe {
+                let mut entry: libc::group = std::mem::zeroed();
+                let mut result = std::ptr::null_mut();
+                let code = lookup(&mut entry, buffer, &mut result);
+                let found = (!result.is_null()).then(|| {
+                    let name = CStr::from_ptr(entry.gr_name).to_string_lossy().into_owned();
+                    (name, entry.gr_gid)
+                });
+                (code, found)
+            }
+        })
+    }
+
+    pub fn user_name(uid: u32) -> Option<String> {
+        passwd(|entry, buffer, result| unsafe {
+            libc::getpwuid_r(uid, entry, buffer.as_mut_ptr(), buffer.len(), result)
+        })
+        .map(|(name, _)| name)
+    }
+
+    pub fn group_name(gid: u32) -> Option<String> {
+        group(|entry, buffer, result| unsafe {
+            libc::getgrgid_r(gid, entry, buffer.as_mut_ptr(), buffer.len(), result)
+        })
+        .map(|(name, _)| name)
+    }
+
+    pub fn user_id(name: &str) -> Option<u32> {
+        let name = CString::new(name).ok()?;
+        passwd(|entry, buffer, result| unsafe {
+            libc::getpwnam_r(name.as_ptr(), entry, buffer.as_mut_ptr(), buffer.len(), result)
+        })
+        .map(|(_, uid)| uid)
+    }
+
+    pub fn group_id(name: &str) -> Option<u32> {
+        let name = CString::new(name).ok()?;
+        group(|entry, buffer, result| unsafe {
+            libc::getgrnam_r(name.as_ptr(), entry, buffer.as_mut_ptr(), buffer.len(), result)
+        })
+        .map(|(_, gid)| gid)
+    }
+
+    /// Change the mode of `path` itself, never of what a symlink there
+    /// points to. Unlike going through an opened file, this works without
+    /// read access, e.g. on a file left at mode 000.
+    pub fn chmod_nofollow(path: &std::path::Path, mode: u32) -> std::io::Result<()> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: `path` is a valid C string
+        let result = unsafe {
+            libc::fchmodat(
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                mode as libc::mode_t,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    pub fn user_name(_uid: u32) -> Option<String> {
+        None
+    }
+
+    pub fn group_name(_gid: u32) -> Option<String> {
+        None
+    }
+
+    pub fn user_id(_name: &str) -> Option<u32> {
+        None
+    }
+
+    pub fn group_id(_name: &str) -> Option<u32> {
+        None
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    #[test]
+    fn test_modes_and_owners() {
+        let dir = std::env::temp_dir().join(format!("mana-attributes-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("site/css")).unwrap();
+        std::fs::write(dir.join("site/index.html"), "").unwrap();
+        std::fs::write(dir.join("site/css/main.css"), "").unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", dir.join("site/passwd")).unwrap();
+
+        assert_eq!(parse_mode("2775").unwrap(), 0o2775);
+        assert!(parse_mode("10000").is_err() && parse_mode("rwx").is_err());
+        assert_eq!(lookup_user("root").unwrap(), 0);
+        assert_eq!(lookup_group("0").unwrap(), 0);
+        assert!(lookup_user("no-such-user-here").is_err());
+        let mut accounts = Accounts::default();
+        assert_eq!(accounts.user_name(0), "root");
+        assert_eq!(accounts.user_name(4_000_000), "4000000");
+
+        let change = AttributeChange {
+            file_mode: Some(0o640),
+            dir_mode: Some(0o2750),
+            ..Default::default()
+        };
+        let at = |path: &str| Jail::default().resolve(dir.join(path)).unwrap();
+        let summary = apply(&at("site"), &change, true, &Jail::default()).unwrap();
+        assert_eq!((summary.changed, summary.failed), (5, 0));
+        let metadata = |path: &str| std::fs::symlink_metadata(dir.join(path)).unwrap();
+        assert_eq!(metadata("site/css").permissions().mode() & 0o7777, 0o2750);
+        assert_eq!(metadata("site/css/main.css").permissions().mode() & 0o7777, 0o640);
+        assert_eq!(symbolic_mode(&metadata("site/css")), "drwxr-s---");
+        assert_eq!(symbolic_mode(&metadata("site/index.html")), "-rw-r-----");
+        assert!(symbolic_mode(&metadata("site/passwd")).starts_with('l'));
+        // The link was left alone, not its target
+        assert_ne!(std::fs::metadata("/etc/passwd").unwrap().mode() & 0o777, 0o640);
+
+        let change = AttributeChange { mode: Some(0o1777), ..Default::default() };
+        apply(&at("site"), &change, false, &Jail::default()).unwrap();
+        assert_eq!(symbolic_mode(&metadata("site")), "drwxrwxrwt");
+        let change = AttributeChange { mode: Some(0o4644), ..Default::default() };
+        apply(&at("site/index.html"), &change, false, &Jail::default()).unwrap();
+        assert_eq!(symbolic_mode(&metadata("site/index.html")), "-rwSr--r--");
+
+        // Nothing needs to be readable to get its mode back
+        let locked = std::fs::Permissions::from_mode(0o000);
+        std::fs::set_permissions(dir.join("site/css"), locked).unwrap();
+        let change = AttributeChange { mode: Some(0o755), ..Default::default() };
+        apply(&at("site/css"), &change, false, &Jail::default()).unwrap();
+        assert_eq!(metadata("site/css").permissions().mode() & 0o7777, 0o755);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
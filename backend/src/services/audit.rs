@@ -0,0 +1,368 @@
+use sea_orm::{entity::prelude::*, ActiveValue::Set, QueryOrder, Select};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::db::entities::audit_log;
+use crate::error::{AppError, AppResult};
+
+pub const RESULT_SUCCESS: &str = "success";
+pub const RESULT_FAILURE: &str = "failure";
+
+/// `prev_hash` of the first entry
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// Rows loaded at a time when walking the whole log
+const BATCH_SIZE: u64 = 1000;
+
+/// Appends are serialized so that every entry chains onto the latest one
+static APPEND_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// An action to record; the result is a failure when `error` is set
+#[derive(Debug, Clone, Default)]
+pub struct NewAuditEntry {
+    pub user_id: Option<i32>,
+    pub username: Option<String>,
+    pub ip: Option<String>,
+    pub action: String,
+    pub target: String,
+    pub params: serde_json::Value,
+    pub error: Option<String>,
+}
+
+/// Entry filters; every field is optional and they combine with AND
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditFilter {
+    pub username: Option<String>,
+    /// Exact action or a prefix ending in a dot, e.g. `docker.`
+    pub action: Option<String>,
+    /// Substring of the target
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    /// `success` or `failure`
+    pub result: Option<String>,
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChainVerification {
+    pub valid: bool,
+    pub entries: u64,
+    /// First entry whose hash or link doesn't match
+    pub first_invalid_id: Option<i32>,
+}
+
+/// Append-only log of state-changing actions.
+///
+/// Each entry stores the hash of its predecessor and a hash over its own
+/// fields, so editing or deleting a row breaks the chain from that point on,
+/// which [`AuditService::verify`] detects.
+pub struct AuditService;
+
+impl AuditService {
+    pub async fn append(
+        db: &DatabaseConnection,
+        entry: NewAuditEntry,
+    ) -> AppResult<audit_log::Model> {
+        let _guard = APPEND_LOCK.lock().await;
+
+        let prev_hash = audit_log::Entity::find()
+            .order_by_desc(audit_log::Column::Id)
+            .one(db)
+            .await?
+            .map(|last| last.hash)
+            .unwrap_or_else(|| GENESIS_HASH.to_string());
+
+        let mut model = audit_log::Model {
+            id: 0,
+            created_at: chrono::Utc::now(),
+            user_id: entry.user_id,
+            username: entry.username,
+            ip: entry.ip,
+            action: entry.action,
+            target: entry.target,
+            params: entry.params.to_string(),
+            result: if entry.error.is_none() { RESULT_SUCCESS } else { RESULT_FAILURE }
+                .to_string(),
+            error: entry.error,
+            prev_hash,
+            hash: String::new(),
+        };
+        model.hash = entry_hash(&model);
+
+        let active = audit_log::ActiveModel {
+            id: Default::default(),
+            created_at: Set(model.created_at),
+            user_id: Set(model.user_id),
+            username: Set(model.username),
+            ip: Set(model.ip),
+            action: Set(model.action),
+            target: Set(model.target),
+            params: Set(model.params),
+            result: Set(model.result),
+            error: Set(model.error),
+            prev_hash: Set(model.prev_hash),
+            hash: Set(model.hash),
+        };
+
+        Ok(active.insert(db).await?)
+    }
+
+    /// One page of matching entries, newest first, with the total count
+    pub async fn query(
+        db: &DatabaseConnection,
+        filter: &AuditFilter,
+        page: u64,
+        per_page: u64,
+    ) -> AppResult<(Vec<audit_log::Model>, u64)> {
+        let paginator = filtered(filter)?
+            .order_by_desc(audit_log::Column::Id)
+            .paginate(db, per_page);
+
+        let total = paginator.num_items().await?;
+        let entries = paginator.fetch_page(page.saturating_sub(1)).await?;
+        Ok((entries, total))
+    }
+
+    /// All matching entries, oldest first
+    pub async fn export(
+        db: &DatabaseConnection,
+        filter: &AuditFilter,
+    ) -> AppResult<Vec<audit_log::Model>> {
+        let mut pages = filtered(filter)?
+            .order_by_asc(audit_log::Column::Id)
+            .paginate(db, BATCH_SIZE);
+
+        let mut entries = Vec::new();
+        while let Some(batch) = pages.fetch_and_next().await? {
+            entries.extend(batch);
+        }
+        Ok(entries)
+    }
+
+    /// Walk the whole chain and check every link and hash
+    pub async fn verify(db: &DatabaseConnection) -> AppResult<ChainVerification> {
+        let mut pages = audit_log::Entity::find()
+            .order_by_asc(audit_log::Column::Id)
+            .paginate(db, BATCH_SIZE);
+
+        let mut expected_prev = GENESIS_HASH.to_string();
+        let mut entries = 0;
+        while let Some(batch) = pages.fetch_and_next().await? {
+            for entry in batch {
+                entries += 1;
+                if entry.prev_hash != expected_prev || entry.hash != entry_hash(&entry) {
+                    return Ok(ChainVerification {
+                        valid: false,
+                        entries,
+                        first_invalid_id: Some(entry.id),
+                    });
+                }
+                expected_prev = entry.hash;
+            }
+        }
+
+        Ok(ChainVerification {
+            valid: true,
+            entries,
+            first_invalid_id: None,
+        })
+    }
+}
+
+fn filtered(filter: &AuditFilter) -> AppResult<Select<audit_log::Entity>> {
+    let mut query = audit_log::Entity::find();
+
+    if let Some(username) = &filter.username {
+        query = query.filter(audit_log::Column::Username.eq(username));
+    }
+    if let Some(action) = &filter.action {
+        query = if action.ends_with('.') {
+            query.filter(audit_log::Column::Action.starts_with(action))
+        } else {
+            query.filter(audit_log::Column::Action.eq(action))
+        };
+    }
+    if let Some(target) = &filter.target {
+        query = query.filter(audit_log::Column::Target.contains(target));
+    }
+    if let Some(ip) = &filter.ip {
+        query = query.filter(audit_log::Column::Ip.eq(ip));
+    }
+    if let Some(result) = &filter.result {
+        if result != RESULT_SUCCESS && result != RESULT_FAILURE {
+            return Err(AppError::Validation(format!("Unknown result: {}", result)));
+        }
+        query = query.filter(audit_log::Column::Result.eq(result));
+    }
+    if let Some(from) = filter.from {
+        query = query.filter(audit_log::Column::CreatedAt.gte(from));
+    }
+    if let Some(to) = filter.to {
+        query = query.filter(audit_log::Column::CreatedAt.lt(to));
+    }
+
+    Ok(query)
+}
+
+fn entry_hash(entry: &audit_log::Model) -> String {
+    // A JSON array keeps field boundaries unambiguous
+    let fields = serde_json::json!([
+        entry.prev_hash,
+        entry.created_at.timestamp_micros(),
+        entry.user_id,
+        entry.username,
+        entry.ip,
+        entry.action,
+        entry.target,
+        entry.params,
+        entry.result,
+        entry.error,
+    ]);
+    data_encoding::HEXLOWER.encode(&Sha256::digest(fields.to_string().as_bytes()))
+}
+
+/// CSV export with a header row, quoted per RFC 4180
+pub fn to_csv(entries: &[audit_log::Model]) -> String {
+    let mut csv = String::from(
+        "id,created_at,user_id,username,ip,action,target,params,result,error,prev_hash,hash\r\n",
+    );
+
+    for entry in entries {
+        let fields = [
+            entry.id.to_string(),
+            entry.created_at.to_rfc3339(),
+            entry.user_id.map(|id| id.to_string()).unwrap_or_default(),
+            entry.username.clone().unwrap_or_default(),
+            entry.ip.clone().unwrap_or_default(),
+            entry.action.clone(),
+            entry.target.clone(),
+            entry.params.clone(),
+            entry.result.clone(),
+            entry.error.clone().unwrap_or_default(),
+            entry.prev_hash.clone(),
+            entry.hash.clone(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    // Leading formula characters are neutralized for spreadsheet apps
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::{sea_query::Expr, ConnectionTrait};
+
+    fn entry(action: &str, target: &str, error: Option<&str>) -> NewAuditEntry {
+        NewAuditEntry {
+            user_id: Some(1),
+            username: Some("admin".to_string()),
+            ip: Some("192.0.2.1".to_string()),
+            action: action.to_string(),
+            target: target.to_string(),
+            params: serde_json::json!({ "force": true }),
+            error: error.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hash_chain_detects_tampering() {
+        let state = crate::test_util::test_state().await;
+        let db = &state.db;
+
+        let first = AuditService::append(db, entry("file.delete", "/tmp/a", None)).await.unwrap();
+        let second =
+            AuditService::append(db, entry("process.kill", "42", Some("denied"))).await.unwrap();
+        AuditService::append(db, entry("service.restart", "nginx", None)).await.unwrap();
+        assert_eq!(first.prev_hash, GENESIS_HASH);
+        assert_eq!(second.prev_hash, first.hash);
+        assert_eq!(second.result, RESULT_FAILURE);
+
+        let verification = AuditService::verify(db).await.unwrap();
+        assert!(verification.valid);
+        assert_eq!(verification.entries, 3);
+
+        // Rewriting history breaks the chain at the edited entry
+        audit_log::Entity::update_many()
+            .col_expr(audit_log::Column::Target, Expr::value("/tmp/b"))
+            .filter(audit_log::Column::Id.eq(first.id))
+            .exec(db.as_ref())
+            .await
+            .unwrap();
+        let verification = AuditService::verify(db).await.unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.first_invalid_id, Some(first.id));
+
+        // So does deleting an entry
+        let restore = format!("UPDATE audit_log SET target = '/tmp/a' WHERE id = {}", first.id);
+        db.execute_unprepared(&restore).await.unwrap();
+        audit_log::Entity::delete_by_id(second.id).exec(db.as_ref()).await.unwrap();
+        let verification = AuditService::verify(db).await.unwrap();
+        assert_eq!(verification.first_invalid_id, Some(second.id + 1));
+    }
+
+    #[tokio::test]
+    async fn test_filters_and_pagination() {
+        let state = crate::test_util::test_state().await;
+        let db = &state.db;
+
+        for i in 0..5 {
+            AuditService::append(db, entry("docker.container.stop", &format!("web-{}", i), None))
+                .await
+                .unwrap();
+        }
+        AuditService::append(db, entry("docker.image.remove", "nginx", Some("in use")))
+            .await
+            .unwrap();
+        AuditService::append(db, entry("file.delete", "/srv/web-9", None)).await.unwrap();
+
+        let filter = AuditFilter {
+            action: Some("docker.".to_string()),
+            ..Default::default()
+        };
+        let (page, total) = AuditService::query(db, &filter, 1, 4).await.unwrap();
+        assert_eq!(total, 6);
+        assert_eq!(page.len(), 4);
+        assert_eq!(page[0].action, "docker.image.remove");
+        let (page, _) = AuditService::query(db, &filter, 2, 4).await.unwrap();
+        assert_eq!(page.len(), 2);
+
+        let filter = AuditFilter {
+            target: Some("web".to_string()),
+            result: Some(RESULT_SUCCESS.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(AuditService::export(db, &filter).await.unwrap().len(), 6);
+
+        let filter = AuditFilter {
+            result: Some("maybe".to_string()),
+            ..Default::default()
+        };
+        assert!(AuditService::query(db, &filter, 1, 10).await.is_err());
+    }
+
+    #[test]
+    fn test_csv_quoting() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("=cmd()"), "'=cmd()");
+    }
+}
@@ -0,0 +1,328 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+use crate::error::AppResult;
+use crate::services::jail::Jail;
+use crate::services::jobs::JobHandle;
+
+/// Cached reports are walked again after this long, since files also change
+/// outside of the panel
+pub const USAGE_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+pub const DEFAULT_DEPTH: usize = 3;
+pub const MAX_DEPTH: usize = 10;
+pub const DEFAULT_TOP: usize = 20;
+pub const MAX_TOP: usize = 200;
+
+/// A file or directory with the space taken by everything below it
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageNode {
+    pub name: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// Space allocated on disk, in bytes
+    pub size: u64,
+    /// Sum of the file lengths, which sparse and tiny files make differ from
+    /// `size`
+    pub apparent_size: u64,
+    pub files: u64,
+    pub dirs: u64,
+    /// The largest entries, largest first; empty below the requested depth
+    pub children: Vec<UsageNode>,
+    /// Size and number of the entries left out of `children`
+    pub other_size: u64,
+    pub other_count: u64,
+}
+
+impl UsageNode {
+    fn new(path: &Path, metadata: &Metadata) -> Self {
+        let is_dir = metadata.is_dir();
+        Self {
+            name: path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned(),
+            path: path.to_path_buf(),
+            is_dir,
+            size: disk_size(metadata),
+            apparent_size: if is_dir { 0 } else { metadata.len() },
+            files: u64::from(!is_dir),
+            dirs: 0,
+            children: Vec::new(),
+            other_size: 0,
+            other_count: 0,
+        }
+    }
+
+    fn add(&mut self, child: UsageNode, keep: bool, top: usize) {
+        self.size += child.size;
+        self.apparent_size += child.apparent_size;
+        self.files += child.files;
+        self.dirs += child.dirs + u64::from(child.is_dir);
+        if !keep {
+            self.other_size += child.size;
+            self.other_count += 1;
+            return;
+        }
+        self.children.push(child);
+        // Children arrive complete, so the ones dropped here can never make
+        // it into the top
+        if self.children.len() >= top.saturating_mul(2).max(64) {
+            self.prune(top);
+        }
+    }
+
+    fn prune(&mut self, top: usize) {
+        self.children.sort_by_key(|child| Reverse(child.size));
+        for child in self.children.drain(top.min(self.children.len())..) {
+            self.other_size += child.size;
+            self.other_count += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LargeFile {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    pub root: UsageNode,
+    /// The largest files anywhere below the root, largest first
+    pub largest_files: Vec<LargeFile>,
+    /// Entries that couldn't be read
+    pub skipped: u64,
+    pub computed_at: DateTime<Utc>,
+    /// Whether the report came out of the cache instead of a new walk
+    pub cached: bool,
+}
+
+/// Space allocated for a file, which is what fills a disk
+fn disk_size(metadata: &Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.blocks() * 512
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.len()
+    }
+}
+
+/// Hard links to the same file are counted once
+#[cfg(unix)]
+fn first_link(seen: &mut HashSet<(u64, u64)>, metadata: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.is_dir() || metadata.nlink() < 2 || seen.insert((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn first_link(_seen: &mut HashSet<(u64, u64)>, _metadata: &Metadata) -> bool {
+    true
+}
+
+/// Walk `root` and add up the space taken below it. The walk stays on the
+/// filesystem of `root`, doesn't follow symlinks and leaves out what the jail
+/// denies. Directories down to `depth` levels below the root list their
+/// `top` largest entries.
+pub fn analyze(
+    root: &Path,
+    depth: usize,
+    top: usize,
+    jail: &Jail,
+    handle: &JobHandle,
+) -> AppResult<UsageReport> {
+    jail.check(root)?;
+    let walk = WalkDir::new(root)
+        .follow_links(false)
+        .follow_root_links(false)
+        .same_file_system(true)
+        .into_iter()
+        .filter_entry(|entry| jail.check(entry.path()).is_ok());
+
+    // The directories from the root down to the one being walked
+    let mut open: Vec<UsageNode> = Vec::new();
+    let mut finished = None;
+    let mut largest = BinaryHeap::new();
+    let mut links = HashSet::new();
+    let mut skipped = 0;
+
+    // Hand a complete node to its directory; only the root has none
+    let attach = |open: &mut Vec<UsageNode>, finished: &mut Option<UsageNode>, node| {
+        let keep = open.len() <= depth;
+        match open.last_mut() {
+            Some(parent) => parent.add(node, keep, top),
+            None => *finished = Some(node),
+        }
+    };
+    let close = |open: &mut Vec<UsageNode>, finished: &mut Option<UsageNode>| {
+        let mut node = open.pop().expect("an open directory");
+        node.prune(top);
+        attach(open, finished, node);
+    };
+
+    for entry in walk {
+        handle.check_cancelled()?;
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) if e.depth() > 0 => {
+                skipped += 1;
+                continue;
+            }
+            Err(e) => return Err(std::io::Error::from(e).into()),
+        };
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if entry.depth() == 0 => return Err(std::io::Error::from(e).into()),
+            Err(_) => {
+                skipped += 1;
+                continue;
+            }
+        };
+
+        while open.len() > entry.depth() {
+            close(&mut open, &mut finished);
+        }
+        if metadata.is_dir() {
+            handle.set_current(entry.path().to_string_lossy());
+            open.push(UsageNode::new(entry.path(), &metadata));
+            continue;
+        }
+
+        let node = UsageNode::new(entry.path(), &metadata);
+        if !first_link(&mut links, &metadata) {
+            continue;
+        }
+        handle.advance(node.size);
+        if metadata.is_file() {
+            largest.push(Reverse((node.size, entry.path().to_path_buf())));
+            if largest.len() > top {
+                largest.pop();
+            }
+        }
+        attach(&mut open, &mut finished, node);
+    }
+    while !open.is_empty() {
+        close(&mut open, &mut finished);
+    }
+
+    let largest_files = largest
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((size, path))| LargeFile { path, size })
+        .collect();
+    Ok(UsageReport {
+        root: finished.expect("the root is walked first"),
+        largest_files,
+        skipped,
+        computed_at: Utc::now(),
+        cached: false,
+    })
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    root: PathBuf,
+    depth: usize,
+    top: usize,
+    /// What a report contains depends on the jail it was walked in
+    jail: Jail,
+}
+
+/// Recent disk usage reports, so that looking at a big tree again doesn't
+/// walk it again. Reports expire after [`USAGE_CACHE_TTL`] and are dropped
+/// whenever files are changed through the panel.
+#[derive(Clone, Default)]
+pub struct DiskUsage {
+    reports: Arc<Mutex<HashMap<CacheKey, (Instant, UsageReport)>>>,
+}
+
+impl DiskUsage {
+    /// A report for `root`, from the cache unless `refresh` is set or there
+    /// is none yet
+    pub fn report(
+        &self,
+        root: &Path,
+        depth: usize,
+        top: usize,
+        refresh: bool,
+        jail: &Jail,
+        handle: &JobHandle,
+    ) -> AppResult<UsageReport> {
+        let key = CacheKey { root: root.to_path_buf(), depth, top, jail: jail.clone() };
+        if !refresh {
+            let mut reports = self.reports.lock().unwrap();
+            reports.retain(|_, (created, _)| created.elapsed() < USAGE_CACHE_TTL);
+            if let Some((_, report)) = reports.get(&key) {
+                return Ok(UsageReport { cached: true, ..report.clone() });
+            }
+        }
+
+        let report = analyze(root, depth, top, jail, handle)?;
+        self.reports.lock().unwrap().insert(key, (Instant::now(), report.clone()));
+        Ok(report)
+    }
+
+    pub fn invalidate(&self) {
+        self.reports.lock().unwrap().clear();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze_tree() {
+        let dir = std::env::temp_dir().join(format!("mana-usage-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("logs/old")).unwrap();
+        std::fs::create_dir_all(dir.join("www")).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        std::fs::write(dir.join("logs/old/1.log"), vec![1u8; 300 * 1024]).unwrap();
+        std::fs::write(dir.join("logs/old/2.log"), vec![1u8; 100 * 1024]).unwrap();
+        std::fs::write(dir.join("logs/app.log"), vec![1u8; 50 * 1024]).unwrap();
+        std::fs::write(dir.join("www/index.html"), "hi").unwrap();
+        std::os::unix::fs::symlink("/usr", dir.join("www/usr")).unwrap();
+        std::fs::hard_link(dir.join("logs/old/1.log"), dir.join("logs/old/3.log")).unwrap();
+
+        let handle = JobHandle::detached();
+        let report = analyze(&dir, 1, 1, &Jail::default(), &handle).unwrap();
+        let root = &report.root;
+        // The symlink counts as itself, the hard link not at all
+        assert_eq!((root.files, root.dirs), (5, 3));
+        assert_eq!(root.apparent_size, 450 * 1024 + "hi".len() as u64 + "/usr".len() as u64);
+        assert!(root.size >= 450 * 1024);
+
+        // Only the largest child is listed, and nothing below depth 1
+        assert_eq!(root.children.len(), 1);
+        assert_eq!(root.other_count, 1);
+        let logs = &root.children[0];
+        assert_eq!((logs.name.as_str(), logs.files, logs.dirs), ("logs", 3, 1));
+        assert!(logs.children.is_empty());
+        assert_eq!(logs.other_count, 2);
+        assert_eq!(report.largest_files.len(), 1);
+        assert!(report.largest_files[0].path.starts_with(dir.join("logs/old")));
+
+        // Cached until invalidated
+        let usage = DiskUsage::default();
+        let jail = Jail::default();
+        assert!(!usage.report(&dir, 2, 5, false, &jail, &handle).unwrap().cached);
+        assert!(usage.report(&dir, 2, 5, false, &jail, &handle).unwrap().cached);
+        assert!(!usage.report(&dir, 2, 5, true, &jail, &handle).unwrap().cached);
+        usage.invalidate();
+        let report = usage.report(&dir, 2, 5, false, &jail, &handle).unwrap();
+        assert!(!report.cached);
+        let names: Vec<_> = report.root.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["logs", "www"]);
+        assert_eq!(report.root.children[0].children[0].name, "old");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
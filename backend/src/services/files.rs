@@ -0,0 +1,261 @@
+use serde::{Deserialize, Serialize};
+use std::fs::Metadata;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::error::AppError;
+use crate::services::jail::{RenameMode, Resolved};
+
+/// What to do when the destination of a file operation already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Replace the existing file
+    #[default]
+    Overwrite,
+    /// Keep the existing file and drop the new one
+    Skip,
+    /// Keep both, giving the new one a free name like `report (1).pdf`
+    Rename,
+    /// Leave both alone and report the conflict as an error
+    Fail,
+}
+
+/// Move `source` to `target` on the same filesystem, resolving a conflict
+/// with an existing `target` by `policy`. Returns where the file ended up,
+/// or `None` if it was skipped, in which case `source` is removed.
+pub fn place(
+    source: &Resolved,
+    target: &Resolved,
+    policy: ConflictPolicy,
+) -> io::Result<Option<PathBuf>> {
+    let placed = rename(source, target, policy)?;
+    if placed.is_none() {
+        source.remove()?;
+    }
+    Ok(placed)
+}
+
+/// Like [`place`], but a skipped `source` stays where it is. Directories
+/// are moved as a whole.
+///
+/// Overwriting never replaces a directory, since everything below it would
+/// go with it: a directory at `target` is a conflict. Callers that mean to
+/// replace one move it out of the way first, see [`transfer`].
+///
+/// [`transfer`]: crate::services::transfer
+pub fn rename(
+    source: &Resolved,
+    target: &Resolved,
+    policy: ConflictPolicy,
+) -> io::Result<Option<PathBuf>> {
+    match policy {
+        ConflictPolicy::Overwrite => {
+            if target.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is a directory and can't be overwritten", target.display()),
+                ));
+            }
+            match source.rename(target, RenameMode::Replace) {
+                Ok(()) => {}
+                // `rename` doesn't replace a file with a directory
+                Err(_) if source.is_dir() && target.exists() => replace(source, target)?,
+                Err(e) => return Err(e),
+            }
+            Ok(Some(target.to_path_buf()))
+        }
+        ConflictPolicy::Skip => match source.rename(target, RenameMode::NoReplace) {
+            Ok(()) => Ok(Some(target.to_path_buf())),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(None),
+            Err(e) => Err(e),
+        },
+        ConflictPolicy::Rename => {
+            for candidate in candidates(target) {
+                let candidate = target.sibling(candidate.file_name().unwrap_or_default())?;
+                match source.rename(&candidate, RenameMode::NoReplace) {
+                    Ok(()) => return Ok(Some(candidate.into_path())),
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+            Err(io::Error::new(io::ErrorKind::AlreadyExists, "No free name left"))
+        }
+        ConflictPolicy::Fail => match source.rename(target, RenameMode::NoReplace) {
+            Ok(()) => Ok(Some(target.to_path_buf())),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", target.display()),
+            )),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+/// An existing target under [`ConflictPolicy::Fail`] is a conflict for the
+/// client to resolve, not a server error
+pub fn conflict_error(e: io::Error) -> AppError {
+    match e.kind() {
+        io::ErrorKind::AlreadyExists => AppError::Conflict(e.to_string()),
+        _ => e.into(),
+    }
+}
+
+/// Swap the directory `source` in for `target`, which isn't one, then remove
+/// the old `target`. The swap is atomic, so `target` never goes missing.
+#[cfg(target_os = "linux")]
+fn replace(source: &Resolved, target: &Resolved) -> io::Result<()> {
+    source.rename(target, RenameMode::Exchange)?;
+    source.remove()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn replace(source: &Resolved, target: &Resolved) -> io::Result<()> {
+    target.remove()?;
+    source.rename(target, RenameMode::Replace)
+}
+
+/// `target` itself, then `name (1).ext`, `name (2).ext` and so on
+fn candidates(target: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = target.extension().map(|ext| format!(".{}", ext.to_string_lossy()));
+
+    std::iter::once(target.to_path_buf()).chain((1..=1000).map(move |n| {
+        target.with_file_name(format!("{} ({}){}", stem, n, extension.as_deref().unwrap_or("")))
+    }))
+}
+
+/// Validator for a file's content, as sent in `ETag` headers. It changes
+/// whenever the file is written to, which is all a client needs to tell
+/// whether its copy is current.
+pub fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", modified.as_nanos(), metadata.len())
+}
+
+/// Replace the content of `path` so that it holds either the old or the new
+/// content, never a mix or a truncated file, even after a crash. The new
+/// content is written and synced next to the file, then renamed over it; an
+/// existing file keeps its permissions and, where allowed, its owner.
+pub fn write_atomic(path: &Resolved, content: &[u8]) -> io::Result<()> {
+    let existing = path.symlink_metadata().ok();
+    let temp = path.sibling(format!(".mana-write-{}.part", uuid::Uuid::new_v4()))?;
+    let result = (|| {
+        let mut file = temp.create_new()?;
+        file.write_all(content)?;
+        if let Some(metadata) = &existing {
+            // Owner first, since a change of owner clears setuid and setgid
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                // Only root may hand files to someone else; otherwise the
+                // file keeps the panel's user as owner
+                std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid())).ok();
+            }
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        temp.rename(path, RenameMode::Replace)
+    })();
+    if let Err(e) = result {
+        temp.remove().ok();
+        return Err(e);
+    }
+
+    // The rename only survives a crash once the directory is synced too
+    #[cfg(unix)]
+    path.sync_dir().ok();
+    Ok(())
+}
+
+/// A directory of the panel's own, created on first use so that only the
+/// panel's user may look inside. Returns its canonical path.
+pub fn private_dir(dir: &Path) -> io::Result<PathBuf> {
+    if std::fs::metadata(dir).is_err() {
+        std::fs::create_dir_all(dir)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+        }
+    }
+    std::fs::canonicalize(dir)
+}
+
+/// Open a file for reading, failing instead of following a symlink in the
+/// last component. For walks that decided from `lstat` that an entry is a
+/// plain file, in case it was swapped for a link since.
+pub fn open_nofollow(path: &Path) -> io::Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.read(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NOFOLLOW);
+    }
+    options.open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conflict_policies() {
+        let dir = std::env::temp_dir().join(format!("mana-files-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        let target = dir.join("report.txt");
+        std::fs::write(&target, "old").unwrap();
+        let new = |content: &str| {
+            let source = dir.join(".new");
+            std::fs::write(&source, content).unwrap();
+            Resolved::new(&source).unwrap()
+        };
+        let at = |path: &Path| Resolved::new(path).unwrap();
+
+        let placed = place(&new("skipped"), &at(&target), ConflictPolicy::Skip).unwrap();
+        assert_eq!(placed, None);
+        assert!(!dir.join(".new").exists());
+
+        let placed = place(&new("renamed"), &at(&target), ConflictPolicy::Rename).unwrap();
+        assert_eq!(placed, Some(dir.join("report (1).txt")));
+        let placed = place(&new("renamed"), &at(&target), ConflictPolicy::Rename).unwrap();
+        assert_eq!(placed, Some(dir.join("report (2).txt")));
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "old");
+
+        let placed = place(&new("new"), &at(&target), ConflictPolicy::Overwrite).unwrap();
+        assert_eq!(placed, Some(target.clone()));
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "new");
+
+        let error = place(&new("failed"), &at(&target), ConflictPolicy::Fail).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "new");
+
+        // A skipped rename leaves the source alone
+        let source = new("kept");
+        assert_eq!(rename(&source, &at(&target), ConflictPolicy::Skip).unwrap(), None);
+        assert!(source.exists());
+
+        // A directory replaces a file, but nothing replaces a directory
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(tree.join("sub")).unwrap();
+        std::fs::write(tree.join("sub/a"), "a").unwrap();
+        let placed = place(&at(&tree), &at(&target), ConflictPolicy::Overwrite).unwrap();
+        assert_eq!(placed, Some(target.clone()));
+        assert_eq!(std::fs::read_to_string(target.join("sub/a")).unwrap(), "a");
+        std::fs::create_dir(&tree).unwrap();
+        let error = rename(&at(&tree), &at(&target), ConflictPolicy::Overwrite).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        let error = place(&new("file"), &at(&target), ConflictPolicy::Overwrite).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(target.join("sub/a")).unwrap(), "a");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,1025 @@
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, Metadata};
+use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use crate::services::settings::SettingsService;
+
+const SETTINGS_KEY: &str = "files.jail";
+
+/// Symlinks followed while resolving one path, as in Linux's `ELOOP` limit
+const MAX_SYMLINKS: usize = 40;
+
+/// Denied out of the box: password hashes and kernel interfaces
+const DEFAULT_DENIED: &[&str] = &["/etc/shadow", "/etc/gshadow", "/proc", "/sys"];
+
+/// Where file operations may go, as configured by admins
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JailSettings {
+    /// Directories everyone is confined to; empty allows the whole filesystem
+    #[serde(default)]
+    pub roots: Vec<String>,
+    /// Roots for members of a role, replacing `roots`
+    #[serde(default)]
+    pub role_roots: BTreeMap<String, Vec<String>>,
+    /// Roots for single users by username, replacing the role's and `roots`
+    #[serde(default)]
+    pub user_roots: BTreeMap<String, Vec<String>>,
+    /// Paths that stay out of reach even inside a root, with everything below
+    #[serde(default)]
+    pub denied: Vec<String>,
+}
+
+impl Default for JailSettings {
+    fn default() -> Self {
+        Self {
+            roots: Vec::new(),
+            role_roots: BTreeMap::new(),
+            user_roots: BTreeMap::new(),
+            denied: DEFAULT_DENIED.iter().map(|path| path.to_string()).collect(),
+        }
+    }
+}
+
+impl JailSettings {
+    /// Check every path and store it in canonical form, so that containment
+    /// checks compare like with like. Roots have to exist; denied paths may
+    /// not exist yet.
+    fn normalized(self) -> AppResult<Self> {
+        let roots = |paths: Vec<String>| -> AppResult<Vec<String>> {
+            paths
+                .iter()
+                .map(|path| {
+                    let path = absolute(path)?;
+                    let canonical = std::fs::canonicalize(path).map_err(|e| {
+                        AppError::Validation(format!("Invalid root {}: {}", path.display(), e))
+                    })?;
+                    if !canonical.is_dir() {
+                        return Err(AppError::Validation(format!(
+                            "Root {} is not a directory",
+                            path.display()
+                        )));
+                    }
+                    Ok(canonical.to_string_lossy().to_string())
+                })
+                .collect()
+        };
+
+        Ok(Self {
+            roots: roots(self.roots)?,
+            role_roots: self
+                .role_roots
+                .into_iter()
+                .map(|(role, paths)| Ok((role, roots(paths)?)))
+                .collect::<AppResult<_>>()?,
+            user_roots: self
+                .user_roots
+                .into_iter()
+                .map(|(user, paths)| Ok((user, roots(paths)?)))
+                .collect::<AppResult<_>>()?,
+            denied: self
+                .denied
+                .iter()
+                .map(|path| Ok(resolve(absolute(path)?)?.to_string_lossy().to_string()))
+                .collect::<AppResult<_>>()?,
+        })
+    }
+}
+
+/// The jail settings, persisted in the settings table and cached in memory
+/// because every file request consults them.
+#[derive(Clone, Default)]
+pub struct JailPolicy {
+    settings: Arc<RwLock<JailSettings>>,
+    /// The panel's own data, denied on top of the settings so that no
+    /// change to them can expose it
+    protected: Arc<Vec<PathBuf>>,
+}
+
+impl JailPolicy {
+    pub async fn load(db: &DatabaseConnection, config: &Config) -> AppResult<Self> {
+        let settings = SettingsService::get(db, SETTINGS_KEY).await?.unwrap_or_default();
+        Ok(Self {
+            settings: Arc::new(RwLock::new(settings)),
+            protected: Arc::new(protected_paths(config)?),
+        })
+    }
+
+    pub fn settings(&self) -> JailSettings {
+        self.settings.read().unwrap().clone()
+    }
+
+    pub async fn update(
+        &self,
+        db: &DatabaseConnection,
+        settings: JailSettings,
+    ) -> AppResult<JailSettings> {
+        let settings = settings.normalized()?;
+        SettingsService::set(db, SETTINGS_KEY, &settings).await?;
+
+        *self.settings.write().unwrap() = settings.clone();
+        Ok(settings)
+    }
+
+    /// The jail of one user: their own roots, else their role's, else the
+    /// global ones
+    pub fn for_user(&self, username: &str, role: &str) -> Jail {
+        let settings = self.settings.read().unwrap();
+        let roots = settings
+            .user_roots
+            .get(username)
+            .or_else(|| settings.role_roots.get(role))
+            .unwrap_or(&settings.roots);
+
+        Jail {
+            roots: roots.iter().map(PathBuf::from).collect(),
+            denied: settings
+                .denied
+                .iter()
+                .map(PathBuf::from)
+                .chain(self.protected.iter().cloned())
+                .collect(),
+        }
+    }
+}
+
+/// The database file with its journals, the trash, the versions and the
+/// upload records. The database holds the signing keys, sessions and token hashes,
+/// so reading it would be enough to become admin.
+fn protected_paths(config: &Config) -> AppResult<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    if let Some(database) = sqlite_file(&config.database_url) {
+        for suffix in ["", "-journal", "-wal", "-shm"] {
+            let mut name = database.clone().into_os_string();
+            name.push(suffix);
+            paths.push(PathBuf::from(name));
+        }
+    }
+    paths.push(PathBuf::from(&config.trash_dir));
+    paths.push(PathBuf::from(&config.versions_dir));
+    paths.push(PathBuf::from(&config.uploads_dir));
+
+    paths
+        .into_iter()
+        .map(|path| resolve(&std::path::absolute(path)?))
+        .collect()
+}
+
+/// The file behind a SQLite database URL, `None` for an in-memory database
+fn sqlite_file(url: &str) -> Option<PathBuf> {
+    let rest = url.strip_prefix("sqlite:")?;
+    let rest = rest.strip_prefix("//").unwrap_or(rest);
+    let path = rest.split('?').next().unwrap_or_default();
+    if path.is_empty() || path == ":memory:" {
+        return None;
+    }
+    Some(PathBuf::from(path))
+}
+
+/// The part of the filesystem one request may touch. Every path coming from
+/// a client goes through [`Jail::resolve`], and the file operations on it
+/// through the [`Resolved`] that returns.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Jail {
+    roots: Vec<PathBuf>,
+    denied: Vec<PathBuf>,
+}
+
+impl Jail {
+    /// Resolve a client path to its canonical form and check that it lies
+    /// inside the jail. Symlinks are followed the way the kernel would, so a
+    /// link can't lead out of a root. The path doesn't have to exist yet,
+    /// for operations that create it.
+    pub fn resolve(&self, requested: impl AsRef<Path>) -> AppResult<Resolved> {
+        let resolved = walk(absolute(requested.as_ref())?)?.into_entry();
+        self.check(&resolved)?;
+        Ok(resolved)
+    }
+
+    /// Like [`Jail::resolve`], but a symlink in the last component is left
+    /// as is, for operations on the link itself such as deleting it
+    pub fn resolve_link(&self, requested: impl AsRef<Path>) -> AppResult<Resolved> {
+        let path = absolute(requested.as_ref())?;
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return self.resolve(path);
+        };
+        let resolved = walk(parent)?.into_child(name);
+        self.check(&resolved)?;
+        Ok(resolved)
+    }
+
+    /// Check an already resolved path, e.g. one found by walking a directory
+    /// that was resolved through the jail
+    pub fn check(&self, path: &Path) -> AppResult<()> {
+        if !self.roots.is_empty() && !self.roots.iter().any(|root| path.starts_with(root)) {
+            return Err(AppError::Forbidden(format!(
+                "{} is outside the allowed directories",
+                path.display()
+            )));
+        }
+        if let Some(denied) = self.denied.iter().find(|denied| path.starts_with(denied)) {
+            return Err(AppError::Forbidden(format!("Access to {} is denied", denied.display())));
+        }
+        Ok(())
+    }
+
+    /// For operations on a whole tree, like a recursive delete: refuse when
+    /// a denied path lies below `path`
+    pub fn check_tree(&self, path: &Path) -> AppResult<()> {
+        if let Some(denied) = self.denied.iter().find(|denied| denied.starts_with(path)) {
+            return Err(AppError::Forbidden(format!(
+                "{} contains {}, which is denied",
+                path.display(),
+                denied.display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn absolute<P: AsRef<Path> + ?Sized>(path: &P) -> AppResult<&Path> {
+    let path = path.as_ref();
+    if !path.is_absolute() {
+        return Err(AppError::Validation("Absolute path required".to_string()));
+    }
+    Ok(path)
+}
+
+/// How [`Resolved::rename`] treats an entry already at the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameMode {
+    Replace,
+    /// Fail with `AlreadyExists`, checked atomically by the kernel
+    NoReplace,
+    /// Swap the two entries, atomically; both have to exist
+    Exchange,
+}
+
+/// A path resolved through the jail, with the deepest directory of it that
+/// exists held open.
+///
+/// The operations on it name the entry relative to that directory (`openat`,
+/// `renameat2`, `fchmodat`, `unlinkat`, ...) and never follow a symlink in
+/// its last component, so they reach what the jail checked even if a
+/// directory on the way is swapped for a symlink afterwards. Its `exists`,
+/// `is_dir` and `is_file` look at the entry the same way. For everything
+/// else, such as walking a tree below it, it dereferences to the path.
+#[derive(Debug)]
+pub struct Resolved {
+    path: PathBuf,
+    #[cfg(target_os = "linux")]
+    dir: std::os::fd::OwnedFd,
+    /// The names from `dir` down to the entry, more than one when
+    /// directories on the way don't exist yet; empty for `/`
+    #[cfg(target_os = "linux")]
+    rest: Vec<OsString>,
+}
+
+impl Deref for Resolved {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for Resolved {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// The same path, whichever directory each of them holds
+impl PartialEq for Resolved {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Resolved {
+    /// Resolve a path of the panel's own, like one in the trash, which no
+    /// jail applies to. A symlink there is taken as itself.
+    pub fn new(path: &Path) -> AppResult<Self> {
+        Jail::default().resolve_link(path)
+    }
+
+    pub fn into_path(self) -> PathBuf {
+        self.path
+    }
+
+    pub fn symlink_metadata(&self) -> io::Result<Metadata> {
+        #[cfg(target_os = "linux")]
+        {
+            let (dir, name) = self.at()?;
+            File::from(sys::open_at(Some(dir), name)?).metadata()
+        }
+        #[cfg(not(target_os = "linux"))]
+        std::fs::symlink_metadata(&self.path)
+    }
+
+    pub fn exists(&self) -> bool {
+        self.symlink_metadata().is_ok()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.symlink_metadata().is_ok_and(|metadata| metadata.is_dir())
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.symlink_metadata().is_ok_and(|metadata| metadata.is_file())
+    }
+
+    /// Another entry in the directory this one is in
+    pub fn sibling(&self, name: impl AsRef<OsStr>) -> io::Result<Self> {
+        let name = name.as_ref();
+        #[cfg(target_os = "linux")]
+        {
+            let (dir, _) = self.at()?;
+            Ok(Self {
+                path: self.path.with_file_name(name),
+                dir: dir.try_clone()?,
+                rest: vec![name.to_os_string()],
+            })
+        }
+        #[cfg(not(target_os = "linux"))]
+        Ok(Self { path: self.path.with_file_name(name) })
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            let (path, rest) = (self.path.clone(), self.rest.clone());
+            Ok(Self { path, dir: self.dir.try_clone()?, rest })
+        }
+        #[cfg(not(target_os = "linux"))]
+        Ok(Self { path: self.path.clone() })
+    }
+
+    /// Open the file for reading
+    pub fn open(&self) -> io::Result<File> {
+        #[cfg(target_os = "linux")]
+        {
+            let (dir, name) = self.at()?;
+            Ok(File::from(sys::open_file(dir, name, libc::O_RDONLY, 0)?))
+        }
+        #[cfg(not(target_os = "linux"))]
+        File::open(&self.path)
+    }
+
+    /// Create the file for writing, failing if anything is there already
+    pub fn create_new(&self) -> io::Result<File> {
+        #[cfg(target_os = "linux")]
+        {
+            let (dir, name) = self.at()?;
+            let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL;
+            Ok(File::from(sys::open_file(dir, name, flags, 0o666)?))
+        }
+        #[cfg(not(target_os = "linux"))]
+        File::create_new(&self.path)
+    }
+
+    /// Create a symlink to `target` here
+    #[cfg(unix)]
+    pub fn create_symlink(&self, target: &Path) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            let (dir, name) = self.at()?;
+            sys::symlink_at(target.as_os_str(), dir, name)
+        }
+        #[cfg(not(target_os = "linux"))]
+        std::os::unix::fs::symlink(target, &self.path)
+    }
+
+    /// Create the directories on the way that don't exist yet, so that the
+    /// entry itself can be created
+    pub fn create_parents(self) -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            let Self { path, mut dir, mut rest } = self;
+            let Some(name) = rest.pop() else {
+                return Ok(Self { path, dir, rest });
+            };
+            for parent in rest {
+                match sys::mkdir_at(&dir, &parent) {
+                    Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+                    _ => {}
+                }
+                dir = sys::open_dir(&dir, &parent)?;
+            }
+            Ok(Self { path, dir, rest: vec![name] })
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            Ok(self)
+        }
+    }
+
+    /// Create the directory with those on the way; one already there is
+    /// fine
+    pub fn create_dir_all(&self) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            let entry = self.try_clone()?.create_parents()?;
+            let (dir, name) = entry.at()?;
+            match sys::mkdir_at(dir, name) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && entry.is_dir() => Ok(()),
+                result => result,
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        std::fs::create_dir_all(&self.path)
+    }
+
+    /// Change the mode of the entry itself. Unlike going through an opened
+    /// file, this works without read access, e.g. on a file left at 000.
+    pub fn set_mode(&self, mode: u32) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            let (dir, name) = self.at()?;
+            sys::chmod_at(dir, name, mode)
+        }
+        #[cfg(all(unix, not(target_os = "linux")))]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(mode))
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = mode;
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    pub fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            let (dir, name) = self.at()?;
+            sys::chown_at(dir, name, uid, gid)
+        }
+        #[cfg(all(unix, not(target_os = "linux")))]
+        {
+            std::os::unix::fs::lchown(&self.path, uid, gid)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (uid, gid);
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    /// Remove a file, a symlink or a directory with everything below it
+    pub fn remove(&self) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            let (dir, name) = self.at()?;
+            sys::remove_at(dir, name)
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.is_dir() {
+            std::fs::remove_dir_all(&self.path)
+        } else {
+            std::fs::remove_file(&self.path)
+        }
+    }
+
+    /// Move the entry to `to`, which has to be on the same filesystem
+    pub fn rename(&self, to: &Resolved, mode: RenameMode) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            let (from_dir, from) = self.at()?;
+            let (to_dir, to) = to.at()?;
+            let flags = match mode {
+                RenameMode::Replace => 0,
+                RenameMode::NoReplace => libc::RENAME_NOREPLACE,
+                RenameMode::Exchange => libc::RENAME_EXCHANGE,
+            };
+            sys::rename_at(from_dir, from, to_dir, to, flags)
+        }
+        #[cfg(not(target_os = "linux"))]
+        match mode {
+            RenameMode::Replace => std::fs::rename(&self.path, &to.path),
+            // A hard link gives the same guarantee for files
+            RenameMode::NoReplace => {
+                std::fs::hard_link(&self.path, &to.path)?;
+                std::fs::remove_file(&self.path)
+            }
+            RenameMode::Exchange => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    /// Flush the directory the entry is in, which a rename or a new entry
+    /// needs to survive a crash
+    pub fn sync_dir(&self) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            let (dir, _) = self.at()?;
+            let flags = libc::O_RDONLY | libc::O_DIRECTORY;
+            File::from(sys::open_file(dir, ".".as_ref(), flags, 0)?).sync_all()
+        }
+        #[cfg(not(target_os = "linux"))]
+        match self.path.parent() {
+            Some(dir) => File::open(dir)?.sync_all(),
+            None => Ok(()),
+        }
+    }
+
+    /// The open directory the entry is in and its name there. `/` is `.`
+    /// in itself.
+    #[cfg(target_os = "linux")]
+    fn at(&self) -> io::Result<(&std::os::fd::OwnedFd, &OsStr)> {
+        match self.rest.as_slice() {
+            [] => Ok((&self.dir, ".".as_ref())),
+            [name] => Ok((&self.dir, name)),
+            _ => {
+                let parent = self.path.parent().unwrap_or(&self.path);
+                let message = format!("Directory not found: {}", parent.display());
+                Err(io::Error::new(io::ErrorKind::NotFound, message))
+            }
+        }
+    }
+}
+
+fn resolve(path: &Path) -> AppResult<PathBuf> {
+    Ok(walk(path)?.into_entry().into_path())
+}
+
+/// A path walked to its canonical form: the directories in it that exist,
+/// each open, and the names below the deepest of them that don't
+#[cfg(target_os = "linux")]
+struct Walk {
+    root: std::os::fd::OwnedFd,
+    /// Each with the name it was opened by
+    stack: Vec<(OsString, std::os::fd::OwnedFd)>,
+    missing: Vec<OsString>,
+}
+
+#[cfg(target_os = "linux")]
+impl Walk {
+    fn path(&self) -> PathBuf {
+        std::iter::once(OsStr::new("/"))
+            .chain(self.stack.iter().map(|(name, _)| name.as_os_str()))
+            .chain(self.missing.iter().map(OsString::as_os_str))
+            .collect()
+    }
+
+    /// The entry the walked path names
+    fn into_entry(mut self) -> Resolved {
+        let path = self.path();
+        if self.missing.is_empty()
+            && let Some((name, _)) = self.stack.pop()
+        {
+            self.missing.push(name);
+        }
+        let dir = self.stack.pop().map_or(self.root, |(_, fd)| fd);
+        Resolved { path, dir, rest: self.missing }
+    }
+
+    /// The entry `name` in the directory the walked path names
+    fn into_child(mut self, name: &OsStr) -> Resolved {
+        let path = self.path().join(name);
+        self.missing.push(name.to_os_string());
+        let dir = self.stack.pop().map_or(self.root, |(_, fd)| fd);
+        Resolved { path, dir, rest: self.missing }
+    }
+}
+
+/// Canonicalize `path`, which may end in components that don't exist yet.
+///
+/// The walk opens one component at a time relative to the directory opened
+/// before it (`openat` with `O_NOFOLLOW`) and expands symlinks itself. The
+/// directories stay open, so that [`Resolved`] can work relative to them.
+#[cfg(target_os = "linux")]
+fn walk(path: &Path) -> AppResult<Walk> {
+    use std::collections::VecDeque;
+
+    let mut pending: VecDeque<OsString> =
+        path.components().skip(1).map(|c| c.as_os_str().to_os_string()).collect();
+    let mut walk = Walk {
+        root: sys::open_at(None, "/".as_ref())?,
+        stack: Vec::new(),
+        missing: Vec::new(),
+    };
+    let mut symlinks = 0;
+
+    while let Some(name) = pending.pop_front() {
+        if name == "." {
+            continue;
+        }
+        if name == ".." {
+            walk.stack.pop();
+            continue;
+        }
+
+        let dir = walk.stack.last().map_or(&walk.root, |(_, fd)| fd);
+        match sys::open_at(Some(dir), &name) {
+            Ok(fd) if sys::is_symlink(&fd)? => {
+                symlinks += 1;
+                if symlinks > MAX_SYMLINKS {
+                    return Err(AppError::Validation(
+                        "Too many levels of symbolic links".to_string(),
+                    ));
+                }
+                let target = sys::read_link_at(dir, &name)?;
+                if target.is_absolute() {
+                    walk.stack.clear();
+                }
+                for component in target.components().rev() {
+                    if !matches!(component, std::path::Component::RootDir) {
+                        pending.push_front(component.as_os_str().to_os_string());
+                    }
+                }
+            }
+            Ok(fd) => walk.stack.push((name, fd)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // Nothing below a missing component exists, so the rest only
+                // has to be named, as long as it doesn't climb back out
+                walk.missing.push(name);
+                for rest in pending {
+                    if rest == ".." {
+                        return Err(AppError::Validation(
+                            "Path climbs out of a directory that doesn't exist".to_string(),
+                        ));
+                    }
+                    if rest != "." {
+                        walk.missing.push(rest);
+                    }
+                }
+                return Ok(walk);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(walk)
+}
+
+#[cfg(not(target_os = "linux"))]
+struct Walk {
+    path: PathBuf,
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Walk {
+    fn into_entry(self) -> Resolved {
+        Resolved { path: self.path }
+    }
+
+    fn into_child(self, name: &OsStr) -> Resolved {
+        Resolved { path: self.path.join(name) }
+    }
+}
+
+/// Fallback for platforms without `O_PATH`: canonicalize the longest existing
+/// prefix and append the rest. Operations go by path, so a component swapped
+/// for a symlink after the walk is followed.
+#[cfg(not(target_os = "linux"))]
+fn walk(path: &Path) -> AppResult<Walk> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+    loop {
+        match std::fs::canonicalize(existing) {
+            Ok(mut resolved) => {
+                for name in missing.into_iter().rev() {
+                    if name == ".." {
+                        return Err(AppError::Validation(
+                            "Path climbs out of a directory that doesn't exist".to_string(),
+                        ));
+                    }
+                    resolved.push(name);
+                }
+                return Ok(Walk { path: resolved });
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                missing.push(existing.file_name().unwrap_or_default().to_os_string());
+                existing = existing.parent().ok_or(e)?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::{CStr, CString, OsStr, OsString};
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    use std::path::PathBuf;
+
+    fn c_name(name: &OsStr) -> io::Result<CString> {
+        CString::new(name.as_bytes()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+    }
+
+    fn check(result: libc::c_int) -> io::Result<()> {
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn open(
+        dirfd: libc::c_int,
+        name: &OsStr,
+        flags: libc::c_int,
+        mode: u32,
+    ) -> io::Result<OwnedFd> {
+        let name = c_name(name)?;
+        let flags = flags | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        // SAFETY: `name` is a valid C string and `dirfd` an open descriptor
+        let fd = unsafe { libc::openat(dirfd, name.as_ptr(), flags, mode as libc::c_uint) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just opened and is owned by nobody else
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Open a single path component without following it, for walking and
+    /// looking at it
+    pub fn open_at(dir: Option<&OwnedFd>, name: &OsStr) -> io::Result<OwnedFd> {
+        let dirfd = dir.map_or(libc::AT_FDCWD, |fd| fd.as_raw_fd());
+        open(dirfd, name, libc::O_PATH, 0)
+    }
+
+    /// Open a directory to walk on from, failing if a symlink took its place
+    pub fn open_dir(dir: &OwnedFd, name: &OsStr) -> io::Result<OwnedFd> {
+        open(dir.as_raw_fd(), name, libc::O_PATH | libc::O_DIRECTORY, 0)
+    }
+
+    /// Open a file for reading or writing, never through a symlink
+    pub fn open_file(
+        dir: &OwnedFd,
+        name: &OsStr,
+        flags: libc::c_int,
+        mode: u32,
+    ) -> io::Result<OwnedFd> {
+        open(dir.as_raw_fd(), name, flags, mode)
+    }
+
+    pub fn mkdir_at(dir: &OwnedFd, name: &OsStr) -> io::Result<()> {
+        let name = c_name(name)?;
+        // SAFETY: `name` is a valid C string and `dir` an open descriptor
+        check(unsafe { libc::mkdirat(dir.as_raw_fd(), name.as_ptr(), 0o777) })
+    }
+
+    pub fn symlink_at(target: &OsStr, dir: &OwnedFd, name: &OsStr) -> io::Result<()> {
+        let (target, name) = (c_name(target)?, c_name(name)?);
+        // SAFETY: both are valid C strings and `dir` an open descriptor
+        check(unsafe { libc::symlinkat(target.as_ptr(), dir.as_raw_fd(), name.as_ptr()) })
+    }
+
+    pub fn chmod_at(dir: &OwnedFd, name: &OsStr, mode: u32) -> io::Result<()> {
+        let name = c_name(name)?;
+        let flags = libc::AT_SYMLINK_NOFOLLOW;
+        // SAFETY: `name` is a valid C string and `dir` an open descriptor
+        check(unsafe { libc::fchmodat(dir.as_raw_fd(), name.as_ptr(), mode, flags) })
+    }
+
+    pub fn chown_at(
+        dir: &OwnedFd,
+        name: &OsStr,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> io::Result<()> {
+        let name = c_name(name)?;
+        // -1 leaves the id as it is
+        let (uid, gid) = (uid.unwrap_or(u32::MAX), gid.unwrap_or(u32::MAX));
+        let flags = libc::AT_SYMLINK_NOFOLLOW;
+        // SAFETY: `name` is a valid C string and `dir` an open descriptor
+        check(unsafe { libc::fchownat(dir.as_raw_fd(), name.as_ptr(), uid, gid, flags) })
+    }
+
+    pub fn rename_at(
+        from_dir: &OwnedFd,
+        from: &OsStr,
+        to_dir: &OwnedFd,
+        to: &OsStr,
+        flags: libc::c_uint,
+    ) -> io::Result<()> {
+        let (from, to) = (c_name(from)?, c_name(to)?);
+        let (from_dir, to_dir) = (from_dir.as_raw_fd(), to_dir.as_raw_fd());
+        // SAFETY: both names are valid C strings and both descriptors open
+        check(unsafe { libc::renameat2(from_dir, from.as_ptr(), to_dir, to.as_ptr(), flags) })
+    }
+
+    fn unlink_at(dir: &OwnedFd, name: &OsStr, flags: libc::c_int) -> io::Result<()> {
+        let name = c_name(name)?;
+        // SAFETY: `name` is a valid C string and `dir` an open descriptor
+        check(unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), flags) })
+    }
+
+    /// Remove an entry, a directory with everything below it. Each level is
+    /// opened without following symlinks, so the removal stays in the tree.
+    pub fn remove_at(dir: &OwnedFd, name: &OsStr) -> io::Result<()> {
+        match unlink_at(dir, name, 0) {
+            Err(e) if e.raw_os_error() == Some(libc::EISDIR) => {}
+            result => return result,
+        }
+        let sub = open(dir.as_raw_fd(), name, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+        for entry in read_dir(&sub)? {
+            remove_at(&sub, &entry)?;
+        }
+        unlink_at(dir, name, libc::AT_REMOVEDIR)
+    }
+
+    /// The names in an open directory, without `.` and `..`
+    fn read_dir(dir: &OwnedFd) -> io::Result<Vec<OsString>> {
+        // The stream takes over a descriptor of its own
+        let fd = dir.try_clone()?.into_raw_fd();
+        // SAFETY: `fd` is open and owned by nobody else
+        let stream = unsafe { libc::fdopendir(fd) };
+        if stream.is_null() {
+            let e = io::Error::last_os_error();
+            // SAFETY: `fdopendir` failed, so `fd` is still ours to close
+            unsafe { libc::close(fd) };
+            return Err(e);
+        }
+
+        let mut names = Vec::new();
+        let result = loop {
+            // `readdir` tells the end from an error only through `errno`
+            // SAFETY: `errno` is thread local
+            unsafe { *libc::__errno_location() = 0 };
+            // SAFETY: `stream` is open
+            let entry = unsafe { libc::readdir(stream) };
+            if entry.is_null() {
+                let e = io::Error::last_os_error();
+                break if e.raw_os_error() == Some(0) { Ok(()) } else { Err(e) };
+            }
+            // SAFETY: `d_name` is a C string inside the entry, valid until
+            // the next `readdir`
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) }.to_bytes();
+            if name != b"." && name != b".." {
+                names.push(OsStr::from_bytes(name).to_os_string());
+            }
+        };
+        // SAFETY: `stream` is open, and closing it closes `fd`
+        unsafe { libc::closedir(stream) };
+        result.map(|()| names)
+    }
+
+    pub fn is_symlink(fd: &OwnedFd) -> io::Result<bool> {
+        // SAFETY: `stat` is plain data that `fstat` fills in
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        // SAFETY: `fd` is open and `stat` is writable
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stat.st_mode & libc::S_IFMT == libc::S_IFLNK)
+    }
+
+    pub fn read_link_at(dir: &OwnedFd, name: &OsStr) -> io::Result<PathBuf> {
+        let name = c_name(name)?;
+        let mut buf = vec![0u8; libc::PATH_MAX as usize];
+        // SAFETY: `buf` is writable for its whole length
+        let len = unsafe {
+            libc::readlinkat(dir.as_raw_fd(), name.as_ptr(), buf.as_mut_ptr().cast(), buf.len())
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        buf.truncate(len as usize);
+        Ok(OsString::from_vec(buf).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory, canonical so that it compares with resolved paths
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mana-jail-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::canonicalize(dir).unwrap()
+    }
+
+    fn jail(roots: &[&Path], denied: &[&Path]) -> Jail {
+        Jail {
+            roots: roots.iter().map(|path| path.to_path_buf()).collect(),
+            denied: denied.iter().map(|path| path.to_path_buf()).collect(),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_cannot_escape_root() {
+        let dir = scratch();
+        let root = dir.join("root");
+        let outside = dir.join("outside");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+        std::os::unix::fs::symlink("../sub", root.join("sub/loop")).unwrap();
+        std::os::unix::fs::symlink("sub", root.join("inside")).unwrap();
+        let jail = jail(&[&root], &[]);
+        let path = |rest: &str| format!("{}/{}", root.display(), rest);
+
+        let resolved = |rest: &str| jail.resolve(path(rest)).unwrap().into_path();
+        assert_eq!(resolved("inside/new.txt"), root.join("sub/new.txt"));
+        assert_eq!(resolved("sub/loop/loop/a"), root.join("sub/a"));
+        assert!(matches!(jail.resolve(path("escape/x")), Err(AppError::Forbidden(_))));
+        assert!(matches!(jail.resolve(path("../outside")), Err(AppError::Forbidden(_))));
+        assert!(matches!(jail.resolve(path("sub/../../outside")), Err(AppError::Forbidden(_))));
+        assert!(jail.resolve(path("missing/../../outside")).is_err());
+        assert!(jail.resolve("relative/path").is_err());
+        assert_eq!(jail.resolve_link(path("escape")).unwrap().into_path(), root.join("escape"));
+        assert!(jail.resolve_link(path("escape/x")).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_resolved_paths_stay_in_their_directory() {
+        let dir = scratch();
+        let (root, outside) = (dir.join("root"), dir.join("outside"));
+        std::fs::create_dir_all(root.join("site")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(root.join("site/old.txt"), "old").unwrap();
+        let jail = jail(&[&root], &[]);
+
+        let new = jail.resolve(root.join("site/new.txt")).unwrap();
+        let old = jail.resolve(root.join("site/old.txt")).unwrap();
+        // Swapped for a symlink out of the jail after resolving
+        std::fs::rename(root.join("site"), root.join("moved")).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("site")).unwrap();
+
+        new.create_new().unwrap();
+        old.set_mode(0o600).unwrap();
+        old.rename(&new, RenameMode::Replace).unwrap();
+        assert_eq!(std::fs::read_to_string(root.join("moved/new.txt")).unwrap(), "old");
+        new.remove().unwrap();
+        assert_eq!(std::fs::read_dir(root.join("moved")).unwrap().count(), 0);
+        assert_eq!(std::fs::read_dir(&outside).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_denied_paths() {
+        let dir = scratch();
+        let secret = dir.join("secret");
+        std::fs::create_dir_all(&secret).unwrap();
+        let jail = jail(&[], &[&secret]);
+
+        assert!(jail.resolve(dir.join("public")).is_ok());
+        assert!(jail.resolve(secret.join("key")).is_err());
+        assert!(jail.resolve(format!("{}/./secret", dir.display())).is_err());
+        assert!(jail.check_tree(&secret).is_err());
+        assert!(jail.check_tree(&dir).is_err());
+        assert!(jail.check_tree(&dir.join("public")).is_ok());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_roots_per_user_and_role() {
+        let state = crate::test_util::test_state().await;
+        let dir = scratch();
+        std::fs::create_dir_all(dir.join("ops/alice")).unwrap();
+
+        let settings = JailSettings {
+            roots: vec![dir.to_string_lossy().to_string()],
+            role_roots: BTreeMap::from([(
+                "operator".to_string(),
+                vec![dir.join("ops").to_string_lossy().to_string()],
+            )]),
+            user_roots: BTreeMap::from([(
+                "alice".to_string(),
+                vec![format!("{}/ops/../ops/alice", dir.display())],
+            )]),
+            denied: Vec::new(),
+        };
+        let stored = state.jail.update(&state.db, settings).await.unwrap();
+        assert_eq!(stored.user_roots["alice"], vec![dir.join("ops/alice").to_string_lossy()]);
+
+        let file = |rest: &str| dir.join(rest).to_string_lossy().to_string();
+        let viewer = state.jail.for_user("bob", "viewer");
+        assert!(viewer.resolve(file("a.txt")).is_ok());
+        let operator = state.jail.for_user("carol", "operator");
+        assert!(operator.resolve(file("a.txt")).is_err());
+        assert!(operator.resolve(file("ops/a.txt")).is_ok());
+        let alice = state.jail.for_user("alice", "operator");
+        assert!(alice.resolve(file("ops/a.txt")).is_err());
+        assert!(alice.resolve(file("ops/alice/a.txt")).is_ok());
+
+        let missing = JailSettings {
+            roots: vec![file("missing")],
+            ..Default::default()
+        };
+        assert!(state.jail.update(&state.db, missing).await.is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::{AppError, AppResult};
+
+/// Finished jobs stay visible this long, so their outcome can be picked up
+pub const FINISHED_JOB_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// A job as reported to its owner
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: String,
+    /// What kind of work it is, e.g. `compress`
+    pub kind: String,
+    pub description: String,
+    pub status: JobStatus,
+    /// Units of work done so far and in total, usually bytes; `total` is 0
+    /// while unknown
+    pub done: u64,
+    pub total: u64,
+    /// What the job is working on right now, e.g. a file name
+    pub current: Option<String>,
+    /// Outcome of a completed job, specific to its kind
+    pub result: Option<Value>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+struct Job {
+    user_id: i32,
+    cancelled: AtomicBool,
+    done: AtomicU64,
+    total: AtomicU64,
+    info: Mutex<JobInfo>,
+}
+
+impl Job {
+    fn new(user_id: i32, kind: &str, description: String) -> Self {
+        Self {
+            user_id,
+            cancelled: AtomicBool::new(false),
+            done: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            info: Mutex::new(JobInfo {
+                id: uuid::Uuid::new_v4().to_string(),
+                kind: kind.to_string(),
+                description,
+                status: JobStatus::Running,
+                done: 0,
+                total: 0,
+                current: None,
+                result: None,
+                error: None,
+                created_at: Utc::now(),
+                finished_at: None,
+            }),
+        }
+    }
+
+    fn info(&self) -> JobInfo {
+        let mut info = self.info.lock().unwrap().clone();
+        info.done = self.done.load(Ordering::Relaxed);
+        info.total = self.total.load(Ordering::Relaxed);
+        info
+    }
+
+    fn finished_long_ago(&self) -> bool {
+        let finished_at = self.info.lock().unwrap().finished_at;
+        finished_at.is_some_and(|finished_at| {
+            (Utc::now() - finished_at).to_std().unwrap_or_default() > FINISHED_JOB_TTL
+        })
+    }
+}
+
+/// The side of a job its work sees: progress reporting and the cancel flag
+#[derive(Clone)]
+pub struct JobHandle {
+    job: Arc<Job>,
+}
+
+impl JobHandle {
+    /// A handle for running job work inline, outside of any job; nothing
+    /// watches its progress and it is never cancelled
+    pub fn detached() -> Self {
+        Self { job: Arc::new(Job::new(0, "inline", String::new())) }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.job.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Stop the work with an error once the job was cancelled. Work calls
+    /// this between steps; [`ProgressReader`] also does it while copying.
+    pub fn check_cancelled(&self) -> AppResult<()> {
+        if self.is_cancelled() {
+            return Err(AppError::Conflict("Job cancelled".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn set_total(&self, total: u64) {
+        self.job.total.store(total, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, amount: u64) {
+        self.job.done.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    pub fn set_current(&self, current: impl Into<String>) {
+        self.job.info.lock().unwrap().current = Some(current.into());
+    }
+}
+
+/// Counts the bytes read as progress of a job, and fails the read once the
+/// job is cancelled so that long copies stop promptly
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    handle: &'a JobHandle,
+}
+
+impl<'a, R> ProgressReader<'a, R> {
+    pub fn new(inner: R, handle: &'a JobHandle) -> Self {
+        Self { inner, handle }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.handle.is_cancelled() {
+            // Not `Interrupted`, which `io::copy` would simply retry
+            return Err(io::Error::other("Job cancelled"));
+        }
+        let read = self.inner.read(buf)?;
+        self.handle.advance(read as u64);
+        Ok(read)
+    }
+}
+
+/// Copy a reader into a writer, counting progress on `handle`
+pub fn copy_with_progress(
+    reader: impl Read,
+    writer: &mut impl Write,
+    handle: &JobHandle,
+) -> io::Result<u64> {
+    io::copy(&mut ProgressReader::new(reader, handle), writer)
+}
+
+/// Long-running work started by a request, such as compressing a directory.
+///
+/// The request returns right away with the job, which its owner then polls
+/// for progress and can cancel. Like uploads, jobs only live in memory; a
+/// restart stops them.
+#[derive(Clone, Default)]
+pub struct Jobs {
+    jobs: Arc<Mutex<HashMap<String, Arc<Job>>>>,
+}
+
+impl Jobs {
+    /// Run blocking `work` on its own thread. The value it returns becomes
+    /// the job's result.
+    pub fn spawn_blocking<F>(
+        &self,
+        user_id: i32,
+        kind: &str,
+        description: String,
+        work: F,
+    ) -> JobInfo
+    where
+        F: FnOnce(&JobHandle) -> AppResult<Value> + Send + 'static,
+    {
+        let job = Arc::new(Job::new(user_id, kind, description));
+        let info = job.info();
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.retain(|_, job| !job.finished_long_ago());
+            jobs.insert(info.id.clone(), job.clone());
+        }
+
+        let handle = JobHandle { job: job.clone() };
+        tokio::task::spawn_blocking(move || {
+            // A panic fails the job instead of leaving it running forever
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| work(&handle)))
+                .unwrap_or_else(|panic| {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown cause".to_string());
+                    Err(AppError::Internal(anyhow::anyhow!("Job panicked: {}", message)))
+                });
+
+            let mut info = job.info.lock().unwrap();
+            info.status = match &result {
+                _ if handle.is_cancelled() => JobStatus::Cancelled,
+                Ok(_) => JobStatus::Completed,
+                Err(_) => JobStatus::Failed,
+            };
+            match result {
+                Ok(value) => info.result = Some(value),
+                Err(e) if info.status == JobStatus::Failed => {
+                    tracing::warn!("{} job {} failed: {}", info.kind, info.id, e);
+                    info.error = Some(e.to_string());
+                }
+                Err(_) => {}
+            }
+            info.current = None;
+            info.finished_at = Some(Utc::now());
+        });
+
+        info
+    }
+
+    /// The jobs of a user, newest first
+    pub fn list(&self, user_id: i32) -> Vec<JobInfo> {
+        let mut jobs: Vec<JobInfo> = self
+            .jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|job| job.user_id == user_id)
+            .map(|job| job.info())
+            .collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.created_at));
+        jobs
+    }
+
+    fn find(&self, id: &str, user_id: i32) -> AppResult<Arc<Job>> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|job| job.user_id == user_id)
+            .cloned()
+            .ok_or_else(|| AppError::NotFound(format!("Job not found: {}", id)))
+    }
+
+    pub fn get(&self, id: &str, user_id: i32) -> AppResult<JobInfo> {
+        Ok(self.find(id, user_id)?.info())
+    }
+
+    /// Ask a running job to stop. It finishes as cancelled once its work
+    /// notices, which it does between steps.
+    pub fn cancel(&self, id: &str, user_id: i32) -> AppResult<JobInfo> {
+        let job = self.find(id, user_id)?;
+        if job.info.lock().unwrap().status != JobStatus::Running {
+            return Err(AppError::Conflict("The job has already finished".to_string()));
+        }
+        job.cancelled.store(true, Ordering::Relaxed);
+        Ok(job.info())
+    }
+
+    /// Wait for a job to finish, for tests
+    #[cfg(test)]
+    pub async fn wait(&self, id: &str, user_id: i32) -> JobInfo {
+        loop {
+            let info = self.get(id, user_id).unwrap();
+            if info.status != JobStatus::Running {
+                return info;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_job_progress_and_cancel() {
+        let jobs = Jobs::default();
+
+        let job = jobs.spawn_blocking(1, "count", "Counting".to_string(), |handle| {
+            handle.set_total(3);
+            handle.advance(3);
+            Ok(Value::from(3))
+        });
+        let info = jobs.wait(&job.id, 1).await;
+        assert_eq!(info.status, JobStatus::Completed);
+        assert_eq!((info.done, info.total), (3, 3));
+        assert_eq!(info.result, Some(Value::from(3)));
+        assert!(jobs.get(&job.id, 2).is_err());
+        assert!(jobs.cancel(&job.id, 1).is_err());
+
+        let job = jobs.spawn_blocking(1, "wait", "Waiting".to_string(), |handle| loop {
+            handle.check_cancelled()?;
+            std::thread::sleep(Duration::from_millis(5));
+        });
+        assert_eq!(jobs.cancel(&job.id, 1).unwrap().status, JobStatus::Running);
+        let info = jobs.wait(&job.id, 1).await;
+        assert_eq!(info.status, JobStatus::Cancelled);
+        assert!(info.error.is_none());
+        assert_eq!(jobs.list(1).len(), 2);
+
+        let job = jobs.spawn_blocking(1, "broken", "Panicking".to_string(), |_| {
+            panic!("out of bounds")
+        });
+        let info = jobs.wait(&job.id, 1).await;
+        assert_eq!(info.status, JobStatus::Failed);
+        assert!(info.error.unwrap().contains("out of bounds"));
+        assert!(info.finished_at.is_some());
+    }
+}
@@ -0,0 +1,282 @@
+use sea_orm::{entity::prelude::*, ActiveValue::Set, QueryOrder, QuerySelect};
+
+use crate::config::Config;
+use crate::db::entities::{login_attempt, login_lockout};
+use crate::error::{AppError, AppResult};
+
+pub const KIND_USERNAME: &str = "username";
+pub const KIND_IP: &str = "ip";
+
+/// Login attempts are kept for this long
+const ATTEMPT_RETENTION_DAYS: i64 = 30;
+
+/// Delay before the next attempt is allowed after `excess` failures beyond
+/// the free ones: `base`, `2 * base`, `4 * base`, ... capped at `max_seconds`
+pub fn backoff_seconds(excess: i32, base_seconds: i64, max_seconds: i64) -> i64 {
+    if excess <= 0 {
+        return 0;
+    }
+    let factor = 1i64.checked_shl((excess - 1).min(62) as u32).unwrap_or(i64::MAX);
+    base_seconds.saturating_mul(factor).min(max_seconds)
+}
+
+/// Brute-force protection for `/auth/login`.
+///
+/// Failures are counted per username and per client IP. Once a counter goes
+/// past its allowance, the key is locked for an exponentially growing delay.
+pub struct LoginThrottle;
+
+impl LoginThrottle {
+    /// Reject the attempt if the username or IP is currently locked
+    pub async fn check(db: &DatabaseConnection, username: &str, ip: Option<&str>) -> AppResult<()> {
+        let now = chrono::Utc::now();
+        let mut keys = vec![(KIND_USERNAME, normalize_username(username))];
+        if let Some(ip) = ip {
+            keys.push((KIND_IP, ip.to_string()));
+        }
+
+        for (kind, key) in keys {
+            let locked_until = Self::find(db, kind, &key)
+                .await?
+                .and_then(|lockout| lockout.locked_until)
+                .filter(|until| *until > now);
+
+            if let Some(until) = locked_until {
+                Self::record_attempt(db, username, ip, false, "locked_out").await?;
+                return Err(AppError::RateLimited((until - now).num_seconds() + 1));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Count a failed attempt against the username and IP
+    pub async fn record_failure(
+        db: &DatabaseConnection,
+        config: &Config,
+        username: &str,
+        ip: Option<&str>,
+        reason: &str,
+    ) -> AppResult<()> {
+        Self::record_attempt(db, username, ip, false, reason).await?;
+
+        let username_key = normalize_username(username);
+        Self::bump(db, config, KIND_USERNAME, &username_key, config.login_max_failures_per_user)
+            .await?;
+        if let Some(ip) = ip {
+            Self::bump(db, config, KIND_IP, ip, config.login_max_failures_per_ip).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Record a successful login and reset the username's counter.
+    /// The IP counter is left to expire so one valid account can't be used to
+    /// reset it while guessing others.
+    pub async fn record_success(
+        db: &DatabaseConnection,
+        username: &str,
+        ip: Option<&str>,
+    ) -> AppResult<()> {
+        Self::record_attempt(db, username, ip, true, "success").await?;
+
+        login_lockout::Entity::delete_many()
+            .filter(login_lockout::Column::Kind.eq(KIND_USERNAME))
+            .filter(login_lockout::Column::Key.eq(normalize_username(username)))
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Counters with recent failures, currently locked ones first
+    pub async fn list_lockouts(db: &DatabaseConnection) -> AppResult<Vec<login_lockout::Model>> {
+        Ok(login_lockout::Entity::find()
+            .order_by_desc(login_lockout::Column::LockedUntil)
+            .order_by_desc(login_lockout::Column::LastFailureAt)
+            .all(db)
+            .await?)
+    }
+
+    /// Clear a counter, lifting its lockout
+    pub async fn clear(db: &DatabaseConnection, id: i32) -> AppResult<()> {
+        let result = login_lockout::Entity::delete_by_id(id).exec(db).await?;
+        if result.rows_affected == 0 {
+            return Err(AppError::NotFound(format!("Lockout {} not found", id)));
+        }
+        Ok(())
+    }
+
+    /// Most recent login attempts, optionally for one username
+    pub async fn recent_attempts(
+        db: &DatabaseConnection,
+        username: Option<&str>,
+        limit: u64,
+    ) -> AppResult<Vec<login_attempt::Model>> {
+        let mut query = login_attempt::Entity::find();
+        if let Some(username) = username {
+            query = query.filter(login_attempt::Column::Username.eq(username));
+        }
+
+        Ok(query
+            .order_by_desc(login_attempt::Column::Id)
+            .limit(limit)
+            .all(db)
+            .await?)
+    }
+
+    async fn find(
+        db: &DatabaseConnection,
+        kind: &str,
+        key: &str,
+    ) -> AppResult<Option<login_lockout::Model>> {
+        Ok(login_lockout::Entity::find()
+            .filter(login_lockout::Column::Kind.eq(kind))
+            .filter(login_lockout::Column::Key.eq(key))
+            .one(db)
+            .await?)
+    }
+
+    async fn bump(
+        db: &DatabaseConnection,
+        config: &Config,
+        kind: &str,
+        key: &str,
+        allowed: i32,
+    ) -> AppResult<()> {
+        let now = chrono::Utc::now();
+        let window = chrono::Duration::minutes(config.login_failure_window_minutes);
+
+        let existing = Self::find(db, kind, key).await?;
+        let failures = match &existing {
+            Some(existing) if now - existing.last_failure_at < window => existing.failures + 1,
+            _ => 1,
+        };
+
+        let delay = backoff_seconds(
+            failures - allowed,
+            config.login_backoff_base_seconds,
+            config.login_lockout_max_minutes * 60,
+        );
+        let locked_until = (delay > 0).then(|| now + chrono::Duration::seconds(delay));
+
+        if locked_until.is_some() {
+            tracing::warn!(
+                "Locking login {} {} for {}s after {} failures",
+                kind,
+                key,
+                delay,
+                failures
+            );
+        }
+
+        match existing {
+            Some(existing) => {
+                let mut active: login_lockout::ActiveModel = existing.into();
+                active.failures = Set(failures);
+                active.locked_until = Set(locked_until);
+                active.last_failure_at = Set(now);
+                active.update(db).await?;
+            }
+            None => {
+                login_lockout::ActiveModel {
+                    id: Default::default(),
+                    kind: Set(kind.to_string()),
+                    key: Set(key.to_string()),
+                    failures: Set(failures),
+                    locked_until: Set(locked_until),
+                    last_failure_at: Set(now),
+                }
+                .insert(db)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn record_attempt(
+        db: &DatabaseConnection,
+        username: &str,
+        ip: Option<&str>,
+        success: bool,
+        reason: &str,
+    ) -> AppResult<()> {
+        let now = chrono::Utc::now();
+
+        login_attempt::ActiveModel {
+            id: Default::default(),
+            username: Set(username.chars().take(128).collect()),
+            ip: Set(ip.map(|ip| ip.to_string())),
+            success: Set(success),
+            reason: Set(reason.to_string()),
+            created_at: Set(now),
+        }
+        .insert(db)
+        .await?;
+
+        login_attempt::Entity::delete_many()
+            .filter(
+                login_attempt::Column::CreatedAt
+                    .lt(now - chrono::Duration::days(ATTEMPT_RETENTION_DAYS)),
+            )
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+fn normalize_username(username: &str) -> String {
+    username.trim().to_lowercase().chars().take(128).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_seconds() {
+        assert_eq!(backoff_seconds(0, 2, 900), 0);
+        assert_eq!(backoff_seconds(-3, 2, 900), 0);
+        assert_eq!(backoff_seconds(1, 2, 900), 2);
+        assert_eq!(backoff_seconds(2, 2, 900), 4);
+        assert_eq!(backoff_seconds(5, 2, 900), 32);
+        assert_eq!(backoff_seconds(10, 2, 900), 900);
+        assert_eq!(backoff_seconds(500, 2, 900), 900);
+    }
+
+    #[tokio::test]
+    async fn test_lockout_after_allowed_failures() {
+        let state = crate::test_util::test_state().await;
+        let (db, config) = (&state.db, &state.config);
+        let ip = Some("203.0.113.7");
+
+        for _ in 0..config.login_max_failures_per_user {
+            LoginThrottle::check(db, "Admin", ip).await.unwrap();
+            LoginThrottle::record_failure(db, config, "Admin", ip, "invalid_credentials")
+                .await
+                .unwrap();
+        }
+        // Counted per normalized username, whatever the casing
+        LoginThrottle::check(db, "admin", None).await.unwrap();
+        LoginThrottle::record_failure(db, config, "admin", None, "invalid_credentials")
+            .await
+            .unwrap();
+
+        let err = LoginThrottle::check(db, "ADMIN", None).await.unwrap_err();
+        assert!(matches!(err, AppError::RateLimited(seconds) if seconds > 0));
+
+        let lockouts = LoginThrottle::list_lockouts(db).await.unwrap();
+        let user_lock = lockouts.iter().find(|l| l.kind == KIND_USERNAME).unwrap();
+        assert!(user_lock.locked_until.is_some());
+        let ip_lock = lockouts.iter().find(|l| l.kind == KIND_IP).unwrap();
+        assert!(ip_lock.locked_until.is_none());
+
+        LoginThrottle::clear(db, user_lock.id).await.unwrap();
+        LoginThrottle::check(db, "admin", ip).await.unwrap();
+
+        let attempts = LoginThrottle::recent_attempts(db, None, 100).await.unwrap();
+        assert!(attempts.iter().any(|a| a.reason == "locked_out"));
+    }
+}
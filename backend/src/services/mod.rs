@@ -1,4 +1,28 @@
+pub mod access;
+pub mod api_token;
+pub mod archive;
+pub mod disk_usage;
+pub mod attributes;
+pub mod audit;
 pub mod docker;
+pub mod files;
+pub mod jail;
+pub mod jobs;
+pub mod login_throttle;
 pub mod monitor;
+pub mod oidc;
 pub mod password;
+pub mod rbac;
+pub mod search;
+pub mod session;
+pub mod settings;
+pub mod signing_key;
+pub mod text;
+pub mod totp;
+pub mod transfer;
+pub mod trash;
+pub mod upload;
 pub mod user;
+pub mod versions;
+pub mod webauthn;
+pub mod ws_ticket;
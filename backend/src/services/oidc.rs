@@ -0,0 +1,590 @@
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use rand::RngCore;
+use sea_orm::{entity::prelude::*, ActiveValue::Set};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::config::OidcConfig;
+use crate::db::entities::{oidc_login, user};
+use crate::error::{AppError, AppResult};
+use crate::services::{rbac::RbacService, user::UserService};
+
+/// How long the user has to come back from the provider
+const LOGIN_TTL_MINUTES: i64 = 10;
+
+/// ID token signatures we accept; symmetric algorithms and `none` are refused
+const ALLOWED_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+];
+
+/// The parts of the provider's discovery document we use
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenEndpointResponse {
+    id_token: String,
+}
+
+/// Who the provider says signed in, taken from a validated ID token
+#[derive(Debug, Clone)]
+pub struct OidcIdentity {
+    pub subject: String,
+    pub username: Option<String>,
+    pub display_name: Option<String>,
+    pub groups: Vec<String>,
+}
+
+/// OpenID Connect login with the authorization code flow and PKCE.
+///
+/// [`OidcService::authorization_url`] remembers the state, nonce and PKCE
+/// verifier of the login in the database and returns where to send the
+/// browser. When the provider redirects back, [`OidcService::complete`]
+/// redeems the code, validates the ID token and
+/// [`OidcService::resolve_user`] maps the identity to a panel account.
+pub struct OidcService;
+
+impl OidcService {
+    /// Start a login and return the provider URL to send the browser to
+    pub async fn authorization_url(
+        db: &DatabaseConnection,
+        config: &OidcConfig,
+    ) -> AppResult<String> {
+        let metadata = discover(config).await?;
+
+        let state = random_token();
+        let nonce = random_token();
+        let code_verifier = random_token();
+        let code_challenge =
+            data_encoding::BASE64URL_NOPAD.encode(&Sha256::digest(code_verifier.as_bytes()));
+
+        purge_expired(db).await?;
+        oidc_login::ActiveModel {
+            state: Set(state.clone()),
+            nonce: Set(nonce.clone()),
+            code_verifier: Set(code_verifier),
+            created_at: Set(chrono::Utc::now()),
+        }
+        .insert(db)
+        .await?;
+
+        let mut url = url::Url::parse(&metadata.authorization_endpoint)
+            .map_err(|e| AppError::System(format!("Invalid authorization endpoint: {}", e)))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &config.client_id)
+            .append_pair("redirect_uri", &config.redirect_url)
+            .append_pair("scope", &config.scopes)
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(url.into())
+    }
+
+    /// Finish a login: redeem the authorization code and validate the ID token
+    pub async fn complete(
+        db: &DatabaseConnection,
+        config: &OidcConfig,
+        state: &str,
+        code: &str,
+    ) -> AppResult<OidcIdentity> {
+        let invalid = || AppError::Auth("Invalid or expired SSO login".to_string());
+
+        // Each login can only be completed once
+        let login = oidc_login::Entity::find_by_id(state.to_string())
+            .one(db)
+            .await?
+            .ok_or_else(invalid)?;
+        let deleted = oidc_login::Entity::delete_by_id(login.state.clone()).exec(db).await?;
+        if deleted.rows_affected != 1 {
+            return Err(invalid());
+        }
+        if login.created_at + chrono::Duration::minutes(LOGIN_TTL_MINUTES) < chrono::Utc::now() {
+            return Err(invalid());
+        }
+
+        let metadata = discover(config).await?;
+        let client = reqwest::Client::new();
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", config.redirect_url.as_str()),
+            ("client_id", config.client_id.as_str()),
+            ("code_verifier", login.code_verifier.as_str()),
+        ];
+        if let Some(secret) = &config.client_secret {
+            form.push(("client_secret", secret));
+        }
+
+        let response = client
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| AppError::System(format!("OIDC token request failed: {}", e)))?;
+        if !response.status().is_success() {
+            tracing::warn!("OIDC token endpoint returned {}", response.status());
+            return Err(AppError::Auth("SSO login was rejected by the provider".to_string()));
+        }
+        let tokens: TokenEndpointResponse = response
+            .json()
+            .await
+            .map_err(|e| AppError::System(format!("Invalid OIDC token response: {}", e)))?;
+
+        let jwks: JwkSet = client
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| AppError::System(format!("Failed to fetch OIDC keys: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| AppError::System(format!("Invalid OIDC key set: {}", e)))?;
+
+        let claims = validate_id_token(&tokens.id_token, &jwks, &metadata.issuer, config)?;
+        if claims.get("nonce").and_then(Value::as_str) != Some(login.nonce.as_str()) {
+            return Err(AppError::Auth("ID token nonce mismatch".to_string()));
+        }
+
+        identity_from_claims(&claims, config)
+    }
+
+    /// Find, link or provision the panel account for an identity, and bring
+    /// its role in line with the identity's groups
+    pub async fn resolve_user(
+        db: &DatabaseConnection,
+        config: &OidcConfig,
+        identity: &OidcIdentity,
+    ) -> AppResult<user::Model> {
+        let role = map_role(config, &identity.groups).ok_or_else(|| {
+            AppError::Forbidden("None of your groups grants access to the panel".to_string())
+        })?;
+        RbacService::ensure_role_exists(db, &role).await?;
+
+        let user = match UserService::find_by_oidc_subject(db, &identity.subject).await? {
+            Some(user) => user,
+            None => Self::link_or_provision(db, config, identity, &role).await?,
+        };
+
+        if user.disabled {
+            return Err(AppError::Auth("Account is disabled".to_string()));
+        }
+
+        if user.role == role {
+            return Ok(user);
+        }
+        match UserService::update_profile(db, user.id, None, Some(&role)).await {
+            Ok(user) => Ok(user),
+            Err(AppError::Validation(reason)) => {
+                // e.g. the provider would demote the last admin
+                tracing::warn!("Keeping role of {}: {}", user.username, reason);
+                Ok(user)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn link_or_provision(
+        db: &DatabaseConnection,
+        config: &OidcConfig,
+        identity: &OidcIdentity,
+        role: &str,
+    ) -> AppResult<user::Model> {
+        let username = identity.username.as_deref().ok_or_else(|| {
+            AppError::Auth(format!("ID token has no {} claim", config.username_claim))
+        })?;
+
+        if let Some(user) = UserService::find_by_username(db, username).await? {
+            if user.oidc_subject.is_some() {
+                return Err(AppError::Forbidden(format!(
+                    "Account {} is linked to a different SSO identity",
+                    username
+                )));
+            }
+            tracing::info!("Linking {} to OIDC subject {}", username, identity.subject);
+            return UserService::link_oidc_subject(db, user.id, &identity.subject).await;
+        }
+
+        if !config.auto_provision {
+            return Err(AppError::Forbidden(format!("No panel account for {}", username)));
+        }
+
+        tracing::info!("Provisioning {} from OIDC", username);
+        // SSO accounts don't get a usable local password
+        let user =
+            UserService::create_user(db, username, &random_token(), role, false).await?;
+        if identity.display_name.is_some() {
+            UserService::update_profile(db, user.id, Some(identity.display_name.clone()), None)
+                .await?;
+        }
+        UserService::link_oidc_subject(db, user.id, &identity.subject).await
+    }
+}
+
+async fn discover(config: &OidcConfig) -> AppResult<ProviderMetadata> {
+    let url = format!("{}/.well-known/openid-configuration", config.issuer);
+    let metadata: ProviderMetadata = reqwest::get(&url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| AppError::System(format!("OIDC discovery failed: {}", e)))?
+        .json()
+        .await
+        .map_err(|e| AppError::System(format!("Invalid OIDC discovery document: {}", e)))?;
+
+    if metadata.issuer.trim_end_matches('/') != config.issuer {
+        return Err(AppError::System(format!(
+            "OIDC issuer mismatch: configured {}, provider says {}",
+            config.issuer, metadata.issuer
+        )));
+    }
+
+    Ok(metadata)
+}
+
+/// Check the signature, issuer, audience and expiry of an ID token
+fn validate_id_token(
+    token: &str,
+    jwks: &JwkSet,
+    issuer: &str,
+    config: &OidcConfig,
+) -> AppResult<Map<String, Value>> {
+    let invalid = |reason: &str| AppError::Auth(format!("Invalid ID token: {}", reason));
+
+    let header = decode_header(token).map_err(|_| invalid("malformed"))?;
+    if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+        return Err(invalid("unsupported algorithm"));
+    }
+
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or_else(|| invalid("unknown signing key"))?;
+    let key = DecodingKey::from_jwk(jwk).map_err(|_| invalid("unusable signing key"))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[issuer]);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    decode::<Map<String, Value>>(token, &key, &validation)
+        .map(|data| data.claims)
+        .map_err(|e| invalid(&e.to_string()))
+}
+
+fn identity_from_claims(
+    claims: &Map<String, Value>,
+    config: &OidcConfig,
+) -> AppResult<OidcIdentity> {
+    let string_claim = |name: &str| {
+        claims
+            .get(name)
+            .and_then(Value::as_str)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+
+    let groups = match claims.get(&config.groups_claim) {
+        Some(Value::Array(groups)) => groups
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        Some(Value::String(group)) => vec![group.clone()],
+        _ => Vec::new(),
+    };
+
+    Ok(OidcIdentity {
+        subject: string_claim("sub")
+            .ok_or_else(|| AppError::Auth("ID token has no subject".to_string()))?,
+        username: string_claim(&config.username_claim),
+        display_name: string_claim("name"),
+        groups,
+    })
+}
+
+/// Role for a set of groups: the first matching mapping, else the default role
+fn map_role(config: &OidcConfig, groups: &[String]) -> Option<String> {
+    config
+        .role_mapping
+        .iter()
+        .find(|(group, _)| groups.contains(group))
+        .map(|(_, role)| role.clone())
+        .or_else(|| config.default_role.clone())
+}
+
+async fn purge_expired(db: &DatabaseConnection) -> AppResult<()> {
+    let cutoff = chrono::Utc::now() - chrono::Duration::minutes(LOGIN_TTL_MINUTES);
+    oidc_login::Entity::delete_many()
+        .filter(oidc_login::Column::CreatedAt.lt(cutoff))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    data_encoding::BASE64URL_NOPAD.encode(&bytes)
+}
+
+/// A minimal OpenID provider for tests: discovery, JWKS and a token endpoint
+/// that checks PKCE and hands out RS256 ID tokens.
+#[cfg(test)]
+pub mod mock_idp {
+    use axum::{
+        extract::State,
+        http::StatusCode,
+        routing::{get, post},
+        Form, Json, Router,
+    };
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::{json, Value};
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use crate::config::OidcConfig;
+
+    const PRIVATE_KEY: &str = include_str!("testdata/mock_idp_key.pem");
+    /// RSA modulus of the private key, for the JWKS
+    const MODULUS: &str = "\
+        tTSNRR6uEvtIvppCX8rTF7PBnR3lOjaZrMQkIt0cEg7-kjHUTa0bqc-iHuLxKCnKeenTyxANkXFFZxDl\
+        Pu41WgHFWZjAO5kgHZiCK8QxvoMfr4xPj2Y0C7oa3t37RNNpVokTGH5OipbVvIq9RLq-4Jk0-iAa3zbM\
+        91GfFXh5IA3N3mNgE9xPkgyLExIktrlzmVFK6V7wlvu8RyMbRrla2F4FV4nrYmp23UxgVff8Jk8CO5EU\
+        zp0SgKRQVu9rZ7KdYWRW2vbL4Oj2tqZQZzIsK-4ny8M81cYQKReRQD77wyi_vTfQVsL3G2uqaFgm-eAI\
+        xstjdsOo6Zd1Zj6nIlAxww";
+    const KID: &str = "mock-key";
+    pub const CLIENT_ID: &str = "mana-panel";
+
+    /// Claims to put into the ID token for a code, and the PKCE challenge it needs
+    struct PendingCode {
+        claims: Value,
+        code_challenge: String,
+    }
+
+    #[derive(Clone)]
+    struct IdpState {
+        issuer: String,
+        codes: Arc<Mutex<HashMap<String, PendingCode>>>,
+    }
+
+    pub struct MockIdp {
+        state: IdpState,
+    }
+
+    impl MockIdp {
+        pub async fn start() -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let state = IdpState {
+                issuer: format!("http://{}", listener.local_addr().unwrap()),
+                codes: Arc::default(),
+            };
+
+            let app = Router::new()
+                .route("/.well-known/openid-configuration", get(discovery))
+                .route("/jwks", get(jwks))
+                .route("/token", post(token))
+                .with_state(state.clone());
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+            Self { state }
+        }
+
+        pub fn config(&self) -> OidcConfig {
+            config_for(&self.state.issuer)
+        }
+
+        /// Play the user signing in at the provider: returns the `code` and
+        /// `state` the provider would redirect back with
+        pub fn sign_in(&self, authorization_url: &str, claims: Value) -> (String, String) {
+            let url = url::Url::parse(authorization_url).unwrap();
+            let param = |name: &str| {
+                url.query_pairs()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.into_owned())
+                    .unwrap()
+            };
+            assert_eq!(param("code_challenge_method"), "S256");
+
+            let mut claims = claims;
+            claims["iss"] = json!(self.state.issuer);
+            claims["aud"] = json!(CLIENT_ID);
+            claims["nonce"] = json!(param("nonce"));
+            claims["exp"] = json!(chrono::Utc::now().timestamp() + 300);
+
+            let code = uuid::Uuid::new_v4().to_string();
+            self.state.codes.lock().unwrap().insert(
+                code.clone(),
+                PendingCode {
+                    claims,
+                    code_challenge: param("code_challenge"),
+                },
+            );
+            (code, param("state"))
+        }
+    }
+
+    /// Client settings as the panel would be configured for a provider
+    pub fn config_for(issuer: &str) -> OidcConfig {
+        OidcConfig {
+            issuer: issuer.to_string(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+            redirect_url: "http://panel.test/login".to_string(),
+            scopes: "openid profile groups".to_string(),
+            provider_name: "Mock".to_string(),
+            username_claim: "preferred_username".to_string(),
+            groups_claim: "groups".to_string(),
+            role_mapping: vec![
+                ("admins".to_string(), "admin".to_string()),
+                ("ops".to_string(), "operator".to_string()),
+            ],
+            default_role: None,
+            auto_provision: true,
+        }
+    }
+
+    async fn discovery(State(state): State<IdpState>) -> Json<Value> {
+        Json(json!({
+            "issuer": state.issuer,
+            "authorization_endpoint": format!("{}/authorize", state.issuer),
+            "token_endpoint": format!("{}/token", state.issuer),
+            "jwks_uri": format!("{}/jwks", state.issuer),
+        }))
+    }
+
+    async fn jwks() -> Json<Value> {
+        Json(json!({
+            "keys": [{
+                "kty": "RSA",
+                "kid": KID,
+                "alg": "RS256",
+                "use": "sig",
+                "n": MODULUS,
+                "e": "AQAB",
+            }]
+        }))
+    }
+
+    async fn token(
+        State(state): State<IdpState>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let pending = form
+            .get("code")
+            .and_then(|code| state.codes.lock().unwrap().remove(code))
+            .ok_or(StatusCode::BAD_REQUEST)?;
+
+        let verifier = form.get("code_verifier").ok_or(StatusCode::BAD_REQUEST)?;
+        let challenge = data_encoding::BASE64URL_NOPAD.encode(&Sha256::digest(verifier.as_bytes()));
+        if challenge != pending.code_challenge {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        let header = Header {
+            kid: Some(KID.to_string()),
+            ..Header::new(jsonwebtoken::Algorithm::RS256)
+        };
+        let key = EncodingKey::from_rsa_pem(PRIVATE_KEY.as_bytes()).unwrap();
+        let id_token = encode(&header, &pending.claims, &key).unwrap();
+
+        Ok(Json(json!({
+            "access_token": "mock-access-token",
+            "token_type": "Bearer",
+            "id_token": id_token,
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock_idp::MockIdp;
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_login_provisions_user_with_mapped_role() {
+        let state = crate::test_util::test_state().await;
+        let db = &state.db;
+        let idp = MockIdp::start().await;
+        let config = idp.config();
+
+        let url = OidcService::authorization_url(db, &config).await.unwrap();
+        let claims = json!({ "sub": "u-1", "preferred_username": "kira", "groups": ["ops"] });
+        let (code, login_state) = idp.sign_in(&url, claims);
+
+        let identity = OidcService::complete(db, &config, &login_state, &code).await.unwrap();
+        assert_eq!(identity.subject, "u-1");
+        let user = OidcService::resolve_user(db, &config, &identity).await.unwrap();
+        assert_eq!(user.username, "kira");
+        assert_eq!(user.role, "operator");
+        assert_eq!(user.oidc_subject.as_deref(), Some("u-1"));
+
+        // The state can't be replayed
+        assert!(OidcService::complete(db, &config, &login_state, &code).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_completions_use_the_state_once() {
+        let state = crate::test_util::test_state().await;
+        let db = &state.db;
+        let idp = MockIdp::start().await;
+        let config = idp.config();
+
+        let url = OidcService::authorization_url(db, &config).await.unwrap();
+        let (code, login_state) = idp.sign_in(&url, json!({ "sub": "u-1" }));
+        let (first, second) = tokio::join!(
+            OidcService::complete(db, &config, &login_state, &code),
+            OidcService::complete(db, &config, &login_state, &code)
+        );
+        assert!(first.is_ok() != second.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_code_from_another_login_is_rejected() {
+        let state = crate::test_util::test_state().await;
+        let db = &state.db;
+        let idp = MockIdp::start().await;
+        let config = idp.config();
+
+        let first = OidcService::authorization_url(db, &config).await.unwrap();
+        let second = OidcService::authorization_url(db, &config).await.unwrap();
+        // A code issued for the first login presented with the second login's state
+        let (code, _) = idp.sign_in(&first, json!({ "sub": "u-1", "preferred_username": "kira" }));
+        let (_, second_state) = idp.sign_in(&second, json!({ "sub": "u-1" }));
+
+        assert!(OidcService::complete(db, &config, &second_state, &code).await.is_err());
+    }
+
+    #[test]
+    fn test_role_mapping() {
+        let mut config = super::mock_idp::config_for("http://idp.test");
+        let groups =
+            |names: &[&str]| names.iter().map(|name| String::from(*name)).collect::<Vec<_>>();
+
+        assert_eq!(map_role(&config, &groups(&["ops", "admins"])).as_deref(), Some("admin"));
+        assert_eq!(map_role(&config, &groups(&["ops"])).as_deref(), Some("operator"));
+        assert_eq!(map_role(&config, &groups(&["staff"])), None);
+
+        config.default_role = Some("viewer".to_string());
+        assert_eq!(map_role(&config, &groups(&["staff"])).as_deref(), Some("viewer"));
+    }
+}
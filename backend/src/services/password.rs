@@ -4,7 +4,12 @@ use argon2::{
 };
 use rand::rngs::OsRng;
 
-use crate::error::{AppError, AppResult};
+use crate::{
+    config::Config,
+    error::{AppError, AppResult},
+};
+
+const BUILTIN_BLOCKLIST: &str = include_str!("breached_passwords.txt");
 
 /// Hash a password using Argon2id algorithm
 pub fn hash_password(password: &str) -> AppResult<String> {
@@ -31,10 +36,120 @@ pub fn verify_password(password: &str, hash: &str) -> AppResult<bool> {
     Ok(result)
 }
 
+/// Rules every new password has to follow. Reuse of previous passwords is
+/// checked separately, against the user's password history.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub min_char_classes: usize,
+    pub history: u64,
+    pub blocklist_file: Option<String>,
+}
+
+impl PasswordPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            min_length: config.password_min_length,
+            min_char_classes: config.password_min_char_classes,
+            history: config.password_history,
+            blocklist_file: config.password_blocklist_file.clone(),
+        }
+    }
+
+    /// Check a new password for `username`
+    pub fn validate(&self, password: &str, username: &str) -> AppResult<()> {
+        if password.chars().count() < self.min_length {
+            return Err(AppError::Validation(format!(
+                "Password must be at least {} characters long",
+                self.min_length
+            )));
+        }
+
+        if char_classes(password) < self.min_char_classes {
+            return Err(AppError::Validation(format!(
+                "Password must mix at least {} of lowercase letters, uppercase letters, digits \
+                 and symbols",
+                self.min_char_classes
+            )));
+        }
+
+        let lowered = password.to_lowercase();
+        if !username.is_empty() && lowered.contains(&username.to_lowercase()) {
+            return Err(AppError::Validation(
+                "Password must not contain the username".to_string(),
+            ));
+        }
+
+        if self.is_breached(&lowered)? {
+            return Err(AppError::Validation(
+                "Password appears in a list of breached passwords".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn is_breached(&self, lowered: &str) -> AppResult<bool> {
+        if blocklist_contains(BUILTIN_BLOCKLIST, lowered) {
+            return Ok(true);
+        }
+
+        match &self.blocklist_file {
+            Some(path) => {
+                let list = std::fs::read_to_string(path)?;
+                Ok(blocklist_contains(&list, lowered))
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Number of character classes (lowercase, uppercase, digits, symbols) used
+fn char_classes(password: &str) -> usize {
+    let checks: [fn(char) -> bool; 4] = [
+        char::is_lowercase,
+        char::is_uppercase,
+        |c| c.is_ascii_digit(),
+        |c| !c.is_alphanumeric(),
+    ];
+    checks
+        .iter()
+        .filter(|&&check| password.chars().any(check))
+        .count()
+}
+
+fn blocklist_contains(list: &str, lowered: &str) -> bool {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .any(|line| line.to_lowercase() == lowered)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 10,
+            min_char_classes: 3,
+            history: 5,
+            blocklist_file: None,
+        }
+    }
+
+    #[test]
+    fn test_password_policy() {
+        let policy = policy();
+
+        assert!(policy.validate("c0rrect-horse", "alice").is_ok());
+        assert!(policy.validate("Sh0rt!", "alice").is_err());
+        assert!(policy.validate("onlylowercaseletters", "alice").is_err());
+        assert!(policy.validate("alice-2024-pw", "Alice").is_err());
+        assert!(policy.validate("Password123!", "alice").is_err());
+        assert!(policy.validate("PASSWORD123!", "alice").is_err());
+    }
+
     #[test]
     fn test_password_hash_and_verify() {
         let password = "test_password_123";
@@ -0,0 +1,176 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::db::entities::{role, role_permission};
+use crate::error::{AppError, AppResult};
+
+/// Built-in role names, seeded by the roles migration
+pub const ROLE_VIEWER: &str = "viewer";
+pub const ROLE_OPERATOR: &str = "operator";
+pub const ROLE_ADMIN: &str = "admin";
+
+/// A single capability that a route can require
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Permission {
+    SystemRead,
+    ProcessesRead,
+    ProcessesWrite,
+    FilesRead,
+    FilesWrite,
+    ServicesRead,
+    ServicesWrite,
+    DockerRead,
+    DockerWrite,
+    TerminalOpen,
+    UsersManage,
+    SecurityManage,
+    AuditRead,
+}
+
+impl Permission {
+    pub const ALL: &'static [Permission] = &[
+        Permission::SystemRead,
+        Permission::ProcessesRead,
+        Permission::ProcessesWrite,
+        Permission::FilesRead,
+        Permission::FilesWrite,
+        Permission::ServicesRead,
+        Permission::ServicesWrite,
+        Permission::DockerRead,
+        Permission::DockerWrite,
+        Permission::TerminalOpen,
+        Permission::UsersManage,
+        Permission::SecurityManage,
+        Permission::AuditRead,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::SystemRead => "system:read",
+            Permission::ProcessesRead => "processes:read",
+            Permission::ProcessesWrite => "processes:write",
+            Permission::FilesRead => "files:read",
+            Permission::FilesWrite => "files:write",
+            Permission::ServicesRead => "services:read",
+            Permission::ServicesWrite => "services:write",
+            Permission::DockerRead => "docker:read",
+            Permission::DockerWrite => "docker:write",
+            Permission::TerminalOpen => "terminal:open",
+            Permission::UsersManage => "users:manage",
+            Permission::SecurityManage => "security:manage",
+            Permission::AuditRead => "audit:read",
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Permission {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Permission::ALL
+            .iter()
+            .find(|p| p.as_str() == s)
+            .copied()
+            .ok_or_else(|| AppError::Validation(format!("Unknown permission: {}", s)))
+    }
+}
+
+impl TryFrom<String> for Permission {
+    type Error = AppError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Permission> for String {
+    fn from(value: Permission) -> Self {
+        value.as_str().to_string()
+    }
+}
+
+/// Role and permission lookups backed by the `roles` / `role_permissions` tables
+pub struct RbacService;
+
+impl RbacService {
+    /// Permissions granted to a role. Unknown roles get none.
+    pub async fn permissions_for_role(
+        db: &DatabaseConnection,
+        role: &str,
+    ) -> AppResult<HashSet<Permission>> {
+        let rows = role_permission::Entity::find()
+            .filter(role_permission::Column::Role.eq(role))
+            .all(db)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| match row.permission.parse() {
+                Ok(permission) => Some(permission),
+                Err(_) => {
+                    tracing::warn!("Ignoring unknown permission {:?} on role {}", row.permission, role);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// List all roles
+    pub async fn list_roles(db: &DatabaseConnection) -> AppResult<Vec<role::Model>> {
+        Ok(role::Entity::find().all(db).await?)
+    }
+
+    /// Ensure a role exists before assigning it to a user
+    pub async fn ensure_role_exists(db: &DatabaseConnection, role: &str) -> AppResult<()> {
+        role::Entity::find_by_id(role.to_string())
+            .one(db)
+            .await?
+            .map(|_| ())
+            .ok_or_else(|| AppError::Validation(format!("Unknown role: {}", role)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permission_round_trip() {
+        for permission in Permission::ALL {
+            assert_eq!(permission.as_str().parse::<Permission>().unwrap(), *permission);
+        }
+        assert!("files:delete".parse::<Permission>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_builtin_role_permissions() {
+        let state = crate::test_util::test_state().await;
+
+        let viewer = RbacService::permissions_for_role(&state.db, ROLE_VIEWER).await.unwrap();
+        assert!(viewer.contains(&Permission::SystemRead));
+        assert!(viewer.contains(&Permission::FilesRead));
+        assert!(!viewer.contains(&Permission::FilesWrite));
+        assert!(!viewer.contains(&Permission::TerminalOpen));
+
+        let operator = RbacService::permissions_for_role(&state.db, ROLE_OPERATOR).await.unwrap();
+        assert!(operator.contains(&Permission::ServicesWrite));
+        assert!(operator.contains(&Permission::DockerWrite));
+        assert!(!operator.contains(&Permission::TerminalOpen));
+        assert!(!operator.contains(&Permission::UsersManage));
+
+        let admin = RbacService::permissions_for_role(&state.db, ROLE_ADMIN).await.unwrap();
+        assert_eq!(admin.len(), Permission::ALL.len());
+
+        assert!(RbacService::permissions_for_role(&state.db, "nobody").await.unwrap().is_empty());
+    }
+}
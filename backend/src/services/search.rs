@@ -0,0 +1,483 @@
+use chrono::{DateTime, Utc};
+use glob::{MatchOptions, Pattern};
+use regex::{NoExpand, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+use walkdir::WalkDir;
+
+use crate::error::{AppError, AppResult};
+use crate::services::files;
+use crate::services::jail::{Jail, Resolved};
+
+pub const DEFAULT_MAX_DEPTH: usize = 32;
+pub const DEFAULT_MAX_RESULTS: usize = 1000;
+pub const MAX_RESULTS: usize = 10_000;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(300);
+const MAX_CONTEXT: usize = 5;
+/// Matching lines listed per file; the rest are only flagged
+const MAX_LINES_PER_FILE: usize = 100;
+/// Larger files aren't searched for content
+const MAX_CONTENT_BYTES: u64 = 16 * 1024 * 1024;
+/// A NUL byte in this many leading bytes marks a file as binary
+const BINARY_PROBE_BYTES: usize = 8192;
+
+/// A search from `root` down. Every filter is optional and they combine
+/// with AND.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SearchRequest {
+    pub root: String,
+    /// Glob on the file name, like `*.php`
+    pub name: Option<String>,
+    /// Regular expression on the file name, instead of `name`
+    pub name_regex: Option<String>,
+    /// Text to look for inside files; only text files are searched
+    pub content: Option<String>,
+    /// Take `content` as a regular expression instead of literal text
+    #[serde(default)]
+    pub regex: bool,
+    /// Match names and content regardless of case
+    #[serde(default)]
+    pub ignore_case: bool,
+    /// Replacement for `content`, to preview a find-and-replace
+    pub replace: Option<String>,
+    /// Lines shown before and after each content match, 1 by default
+    pub context: Option<usize>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub modified_after: Option<DateTime<Utc>>,
+    pub modified_before: Option<DateTime<Utc>>,
+    pub max_depth: Option<usize>,
+    pub max_results: Option<usize>,
+    /// Seconds before the search gives up
+    pub timeout: Option<u64>,
+}
+
+/// Finds a pattern in lines of text, as literal text or a regular expression
+pub struct Matcher {
+    regex: Regex,
+    literal: bool,
+}
+
+impl Matcher {
+    pub fn new(pattern: &str, regex: bool, ignore_case: bool) -> AppResult<Self> {
+        if pattern.is_empty() {
+            return Err(AppError::Validation("Nothing to search for".to_string()));
+        }
+        let source = if regex { Cow::Borrowed(pattern) } else { Cow::Owned(regex::escape(pattern)) };
+        Ok(Self { regex: compile(&source, ignore_case)?, literal: !regex })
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        self.regex.is_match(line)
+    }
+
+    /// Replace every match in `line`. With a regular expression the
+    /// replacement may refer to groups as `$1` or `${name}`.
+    pub fn replace<'a>(&self, line: &'a str, replacement: &str) -> Cow<'a, str> {
+        if self.literal {
+            self.regex.replace_all(line, NoExpand(replacement))
+        } else {
+            self.regex.replace_all(line, replacement)
+        }
+    }
+}
+
+fn compile(pattern: &str, ignore_case: bool) -> AppResult<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .size_limit(1 << 20)
+        .build()
+        .map_err(|e| AppError::Validation(format!("Invalid pattern: {}", e)))
+}
+
+enum NameFilter {
+    Glob(Pattern, MatchOptions),
+    Regex(Regex),
+}
+
+impl NameFilter {
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            NameFilter::Glob(pattern, options) => pattern.matches_with(name, *options),
+            NameFilter::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// A file or directory that matched
+#[derive(Debug, Serialize)]
+pub struct FileMatch {
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: i64,
+    /// Matching lines of a content search
+    pub lines: Vec<LineMatch>,
+    /// More lines matched than are listed
+    pub truncated: bool,
+    /// Hash of the content searched, passed back when applying a replace so
+    /// that files changed since the preview are left alone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LineMatch {
+    /// 1-based line number
+    pub line: usize,
+    pub text: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+    /// The line after replacing, in a replace preview
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replaced: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    MaxResults,
+    Timeout,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SearchSummary {
+    /// Entries looked at
+    pub scanned: u64,
+    pub matched: u64,
+    /// Files left out of a content search as binary, too large or not UTF-8
+    pub skipped: u64,
+    /// Why the search ended early, if it did
+    pub stopped: Option<StopReason>,
+}
+
+/// A validated [`SearchRequest`], ready to run
+pub struct Search {
+    name: Option<NameFilter>,
+    content: Option<Matcher>,
+    replace: Option<String>,
+    context: usize,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    modified_after: Option<SystemTime>,
+    modified_before: Option<SystemTime>,
+    max_depth: usize,
+    max_results: usize,
+    timeout: Duration,
+}
+
+impl Search {
+    pub fn new(request: &SearchRequest) -> AppResult<Self> {
+        let name = match (&request.name, &request.name_regex) {
+            (Some(_), Some(_)) => {
+                return Err(AppError::Validation(
+                    "Give either a name glob or a name regex".to_string(),
+                ));
+            }
+            (Some(glob), None) => {
+                let pattern = Pattern::new(glob)
+                    .map_err(|e| AppError::Validation(format!("Invalid glob: {}", e)))?;
+                let options = MatchOptions {
+                    case_sensitive: !request.ignore_case,
+                    ..MatchOptions::new()
+                };
+                Some(NameFilter::Glob(pattern, options))
+            }
+            (None, Some(regex)) => Some(NameFilter::Regex(compile(regex, request.ignore_case)?)),
+            (None, None) => None,
+        };
+        let content = request
+            .content
+            .as_deref()
+            .map(|content| Matcher::new(content, request.regex, request.ignore_case))
+            .transpose()?;
+        if request.replace.is_some() && content.is_none() {
+            return Err(AppError::Validation("A replace needs content to find".to_string()));
+        }
+
+        Ok(Self {
+            name,
+            content,
+            replace: request.replace.clone(),
+            context: request.context.unwrap_or(1).min(MAX_CONTEXT),
+            min_size: request.min_size,
+            max_size: request.max_size,
+            modified_after: request.modified_after.map(SystemTime::from),
+            modified_before: request.modified_before.map(SystemTime::from),
+            max_depth: request.max_depth.unwrap_or(DEFAULT_MAX_DEPTH),
+            max_results: request.max_results.unwrap_or(DEFAULT_MAX_RESULTS).clamp(1, MAX_RESULTS),
+            timeout: request
+                .timeout
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_TIMEOUT)
+                .min(MAX_TIMEOUT),
+        })
+    }
+
+    /// Walk `root` without following symlinks or entering denied paths, and
+    /// hand each match to `found`, which returns `false` to stop the search
+    pub fn run(
+        &self,
+        root: &Path,
+        jail: &Jail,
+        mut found: impl FnMut(FileMatch) -> bool,
+    ) -> SearchSummary {
+        let deadline = Instant::now() + self.timeout;
+        let mut summary = SearchSummary::default();
+        let walk = WalkDir::new(root)
+            .min_depth(1)
+            .max_depth(self.max_depth)
+            .follow_links(false)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| jail.check(entry.path()).is_ok());
+
+        // Unreadable directories are passed over
+        for entry in walk.flatten() {
+            if Instant::now() > deadline {
+                summary.stopped = Some(StopReason::Timeout);
+                break;
+            }
+            summary.scanned += 1;
+
+            let name = entry.file_name().to_string_lossy();
+            if self.name.as_ref().is_some_and(|filter| !filter.matches(&name)) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let is_dir = metadata.is_dir();
+            let size_filtered = self.min_size.is_some() || self.max_size.is_some();
+            if (is_dir || !metadata.is_file()) && (size_filtered || self.content.is_some()) {
+                continue;
+            }
+            if self.min_size.is_some_and(|min| metadata.len() < min)
+                || self.max_size.is_some_and(|max| metadata.len() > max)
+            {
+                continue;
+            }
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            if self.modified_after.is_some_and(|after| modified < after)
+                || self.modified_before.is_some_and(|before| modified > before)
+            {
+                continue;
+            }
+
+            let mut file = FileMatch {
+                path: entry.path().to_string_lossy().into_owned(),
+                is_dir,
+                size: metadata.len(),
+                modified: DateTime::<Utc>::from(modified).timestamp(),
+                lines: Vec::new(),
+                truncated: false,
+                sha256: None,
+            };
+            if let Some(matcher) = &self.content {
+                let opened = files::open_nofollow(entry.path()).ok();
+                let Some(text) = opened.and_then(read_text) else {
+                    summary.skipped += 1;
+                    continue;
+                };
+                if !self.grep(matcher, &text, &mut file) {
+                    continue;
+                }
+                if self.replace.is_some() {
+                    file.sha256 = Some(sha256(&text));
+                }
+            }
+
+            summary.matched += 1;
+            if !found(file) {
+                break;
+            }
+            if summary.matched as usize >= self.max_results {
+                summary.stopped = Some(StopReason::MaxResults);
+                break;
+            }
+        }
+        summary
+    }
+
+    /// Add the lines of `text` that match to `file`, returning whether any did
+    fn grep(&self, matcher: &Matcher, text: &str, file: &mut FileMatch) -> bool {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut any = false;
+        for (index, line) in lines.iter().enumerate() {
+            if !matcher.is_match(line) {
+                continue;
+            }
+            any = true;
+            if file.lines.len() == MAX_LINES_PER_FILE {
+                file.truncated = true;
+                break;
+            }
+            let context = |lines: &[&str]| lines.iter().map(|line| line.to_string()).collect();
+            file.lines.push(LineMatch {
+                line: index + 1,
+                text: line.to_string(),
+                before: context(&lines[index.saturating_sub(self.context)..index]),
+                after: context(&lines[index + 1..(index + 1 + self.context).min(lines.len())]),
+                replaced: self
+                    .replace
+                    .as_deref()
+                    .map(|replacement| matcher.replace(line, replacement).into_owned()),
+            });
+        }
+        any
+    }
+}
+
+/// The content of a text file, or `None` for binary, huge or non-UTF-8 ones
+fn read_text(file: File) -> Option<String> {
+    if file.metadata().ok()?.len() > MAX_CONTENT_BYTES {
+        return None;
+    }
+    let mut content = Vec::new();
+    file.take(MAX_CONTENT_BYTES).read_to_end(&mut content).ok()?;
+    if content[..content.len().min(BINARY_PROBE_BYTES)].contains(&0) {
+        return None;
+    }
+    String::from_utf8(content).ok()
+}
+
+fn sha256(text: &str) -> String {
+    data_encoding::HEXLOWER.encode(&Sha256::digest(text.as_bytes()))
+}
+
+/// A text file with every match replaced, not written yet
+#[derive(Debug)]
+pub struct Replaced {
+    pub content: String,
+    /// How many matches were replaced; with none there is nothing to write
+    pub count: usize,
+    /// The ETag of the file that was read, to write the content back with
+    pub etag: String,
+}
+
+/// Replace every match of `matcher` in a text file, line by line as the
+/// preview showed it. `expected_sha256` is the hash the preview reported; a
+/// file changed since is refused.
+///
+/// Nothing is written here: the caller saves the content through
+/// [`VersionService::write`](crate::services::versions::VersionService::write)
+/// with the returned ETag, like a save from the editor.
+pub fn replace_in_file(
+    path: &Resolved,
+    matcher: &Matcher,
+    replacement: &str,
+    expected_sha256: &str,
+) -> AppResult<Replaced> {
+    let file = path.open()?;
+    let etag = files::etag(&file.metadata()?);
+    let text = read_text(file).ok_or_else(|| {
+        AppError::Validation(format!("{} is not a text file", path.display()))
+    })?;
+    if !sha256(&text).eq_ignore_ascii_case(expected_sha256) {
+        return Err(AppError::Conflict(format!(
+            "{} changed since the preview",
+            path.display()
+        )));
+    }
+
+    let mut count = 0;
+    let mut content = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let body = line.trim_end_matches(['\n', '\r']);
+        count += matcher.regex.find_iter(body).count();
+        content.push_str(&matcher.replace(body, replacement));
+        content.push_str(&line[body.len()..]);
+    }
+    Ok(Replaced { content, count, etag })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn at(path: &Path) -> Resolved {
+        Jail::default().resolve(path).unwrap()
+    }
+
+    fn search(root: &Path, request: SearchRequest) -> (Vec<FileMatch>, SearchSummary) {
+        let mut found = Vec::new();
+        let search = Search::new(&request).unwrap();
+        let summary = search.run(root, &Jail::default(), |file| {
+            found.push(file);
+            true
+        });
+        (found, summary)
+    }
+
+    #[test]
+    fn test_search_and_replace() {
+        let dir = std::env::temp_dir().join(format!("mana-search-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("site/deep/er")).unwrap();
+        let dir = fs::canonicalize(dir).unwrap();
+        fs::write(dir.join("site/index.php"), "<?php\n$db = 'old_db';\necho $db;\n").unwrap();
+        fs::write(dir.join("site/config.PHP"), "define('DB', 'OLD_DB');\r\n").unwrap();
+        fs::write(dir.join("site/deep/er/old.php"), "old_db\n").unwrap();
+        fs::write(dir.join("site/logo.png"), b"old_db\0\x89PNG").unwrap();
+
+        let request = |name: Option<&str>, content: Option<&str>| SearchRequest {
+            name: name.map(String::from),
+            content: content.map(String::from),
+            ..Default::default()
+        };
+        let paths = |found: &[FileMatch]| -> Vec<String> {
+            found.iter().map(|file| file.path.replacen(&*dir.to_string_lossy(), "", 1)).collect()
+        };
+
+        let (found, _) = search(&dir, request(Some("*.php"), None));
+        assert_eq!(paths(&found), ["/site/deep/er/old.php", "/site/index.php"]);
+        let (found, _) = search(&dir, SearchRequest { max_depth: Some(2), ..request(None, None) });
+        assert_eq!(found.len(), 5);
+
+        let (found, summary) = search(&dir, request(None, Some("old_db")));
+        assert_eq!(paths(&found), ["/site/deep/er/old.php", "/site/index.php"]);
+        assert_eq!(summary.skipped, 1);
+        let line = &found[1].lines[0];
+        assert_eq!((line.line, line.text.as_str()), (2, "$db = 'old_db';"));
+        assert_eq!(line.before, ["<?php"]);
+        assert_eq!(line.after, ["echo $db;"]);
+
+        let preview = SearchRequest {
+            regex: true,
+            ignore_case: true,
+            replace: Some("new_$1".to_string()),
+            max_results: Some(2),
+            ..request(None, Some(r"old_(db)"))
+        };
+        let (found, summary) = search(&dir, preview);
+        assert_eq!(summary.stopped, Some(StopReason::MaxResults));
+        assert_eq!(found[0].lines[0].replaced.as_deref(), Some("define('DB', 'new_DB');"));
+
+        let matcher = Matcher::new(r"old_(db)", true, true).unwrap();
+        let config = dir.join("site/config.PHP");
+        let sha256 = found[0].sha256.as_deref().unwrap();
+        let replaced = replace_in_file(&at(&config), &matcher, "new_$1", sha256).unwrap();
+        assert_eq!(replaced.count, 1);
+        assert_eq!(replaced.content, "define('DB', 'new_DB');\r\n");
+        assert_eq!(replaced.etag, files::etag(&fs::metadata(&config).unwrap()));
+        fs::write(&config, &replaced.content).unwrap();
+        let again = replace_in_file(&at(&config), &matcher, "new_$1", sha256);
+        assert!(matches!(again, Err(AppError::Conflict(_))));
+
+        // Literal text stays literal, `$` and all
+        let matcher = Matcher::new("$db", false, false).unwrap();
+        let index = dir.join("site/index.php");
+        let sha256 = super::sha256(&fs::read_to_string(&index).unwrap());
+        let replaced = replace_in_file(&at(&index), &matcher, "$1", &sha256).unwrap();
+        assert_eq!(replaced.count, 2);
+        assert_eq!(replaced.content, "<?php\n$1 = 'old_db';\necho $1;\n");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+use rand::RngCore;
+use sea_orm::{entity::prelude::*, ActiveValue::Set, QueryOrder};
+use sha2::{Digest, Sha256};
+
+use crate::db::entities::session;
+use crate::error::{AppError, AppResult};
+
+/// Don't write `last_seen_at` on every request
+const TOUCH_INTERVAL_SECONDS: i64 = 60;
+
+/// Where a session was started from
+#[derive(Debug, Clone, Default)]
+pub struct SessionOrigin {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Server-side login sessions with rotating refresh tokens.
+///
+/// A refresh token has the form `<session id>.<secret>` and only the SHA-256
+/// of the secret is stored. Presenting a refresh token whose secret doesn't
+/// match the current one means an older, already rotated token is being
+/// replayed, so the whole session is revoked.
+pub struct SessionService;
+
+impl SessionService {
+    /// Start a session, returning it with its first refresh token
+    pub async fn create(
+        db: &DatabaseConnection,
+        user_id: i32,
+        origin: SessionOrigin,
+        ttl: chrono::Duration,
+    ) -> AppResult<(session::Model, String)> {
+        let now = chrono::Utc::now();
+        let id = uuid::Uuid::new_v4().to_string();
+        let secret = generate_secret();
+
+        let session = session::ActiveModel {
+            id: Set(id.clone()),
+            user_id: Set(user_id),
+            refresh_token_hash: Set(hash_secret(&secret)),
+            ip: Set(origin.ip),
+            user_agent: Set(origin.user_agent.map(|ua| ua.chars().take(512).collect())),
+            created_at: Set(now),
+            last_seen_at: Set(now),
+            expires_at: Set(now + ttl),
+            revoked_at: Set(None),
+        }
+        .insert(db)
+        .await?;
+
+        Ok((session, format!("{}.{}", id, secret)))
+    }
+
+    /// Exchange a refresh token for a new one, extending the session. The
+    /// token is swapped only if it is still the current one, so of two
+    /// requests presenting it, one loses and is treated as a replay.
+    pub async fn rotate(
+        db: &DatabaseConnection,
+        refresh_token: &str,
+        ttl: chrono::Duration,
+    ) -> AppResult<(session::Model, String)> {
+        let invalid = || AppError::Auth("Invalid or expired refresh token".to_string());
+
+        let (id, secret) = refresh_token.split_once('.').ok_or_else(invalid)?;
+        let session = Self::find_active(db, id).await?.ok_or_else(invalid)?;
+
+        let now = chrono::Utc::now();
+        let new_secret = generate_secret();
+        let rotated = session::Entity::update_many()
+            .col_expr(session::Column::RefreshTokenHash, Expr::value(hash_secret(&new_secret)))
+            .col_expr(session::Column::LastSeenAt, Expr::value(now))
+            .col_expr(session::Column::ExpiresAt, Expr::value(now + ttl))
+            .filter(session::Column::Id.eq(session.id.as_str()))
+            .filter(session::Column::RefreshTokenHash.eq(hash_secret(secret)))
+            .filter(session::Column::RevokedAt.is_null())
+            .exec(db)
+            .await?;
+        if rotated.rows_affected != 1 {
+            tracing::warn!("Refresh token reuse detected, revoking session {}", session.id);
+            Self::revoke(db, &session.id).await?;
+            return Err(invalid());
+        }
+
+        let session = session::Model {
+            refresh_token_hash: hash_secret(&new_secret),
+            last_seen_at: now,
+            expires_at: now + ttl,
+            ..session
+        };
+        Ok((session.clone(), format!("{}.{}", session.id, new_secret)))
+    }
+
+    /// A session that is neither revoked nor expired
+    pub async fn find_active(db: &DatabaseConnection, id: &str) -> AppResult<Option<session::Model>> {
+        let session = session::Entity::find_by_id(id.to_string())
+            .filter(session::Column::RevokedAt.is_null())
+            .filter(session::Column::ExpiresAt.gt(chrono::Utc::now()))
+            .one(db)
+            .await?;
+
+        Ok(session)
+    }
+
+    /// Record activity on a session, at most once per [`TOUCH_INTERVAL_SECONDS`]
+    pub async fn touch(db: &DatabaseConnection, session: &session::Model) -> AppResult<()> {
+        let now = chrono::Utc::now();
+        if (now - session.last_seen_at).num_seconds() < TOUCH_INTERVAL_SECONDS {
+            return Ok(());
+        }
+
+        session::Entity::update_many()
+            .col_expr(session::Column::LastSeenAt, Expr::value(now))
+            .filter(session::Column::Id.eq(session.id.as_str()))
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Active sessions of a user, most recently used first
+    pub async fn list_active(db: &DatabaseConnection, user_id: i32) -> AppResult<Vec<session::Model>> {
+        let sessions = session::Entity::find()
+            .filter(session::Column::UserId.eq(user_id))
+            .filter(session::Column::RevokedAt.is_null())
+            .filter(session::Column::ExpiresAt.gt(chrono::Utc::now()))
+            .order_by_desc(session::Column::LastSeenAt)
+            .all(db)
+            .await?;
+
+        Ok(sessions)
+    }
+
+    pub async fn revoke(db: &DatabaseConnection, id: &str) -> AppResult<()> {
+        session::Entity::update_many()
+            .col_expr(session::Column::RevokedAt, Expr::value(chrono::Utc::now()))
+            .filter(session::Column::Id.eq(id))
+            .filter(session::Column::RevokedAt.is_null())
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Revoke one session of a user, failing if it belongs to someone else
+    pub async fn revoke_for_user(db: &DatabaseConnection, user_id: i32, id: &str) -> AppResult<()> {
+        let owned = session::Entity::find_by_id(id.to_string())
+            .filter(session::Column::UserId.eq(user_id))
+            .one(db)
+            .await?
+            .is_some();
+
+        if !owned {
+            return Err(AppError::NotFound(format!("Session {} not found", id)));
+        }
+
+        Self::revoke(db, id).await
+    }
+
+    /// Revoke every session of a user, optionally keeping one (the caller's)
+    pub async fn revoke_all_for_user(
+        db: &DatabaseConnection,
+        user_id: i32,
+        except: Option<&str>,
+    ) -> AppResult<()> {
+        let mut query = session::Entity::update_many()
+            .col_expr(session::Column::RevokedAt, Expr::value(chrono::Utc::now()))
+            .filter(session::Column::UserId.eq(user_id))
+            .filter(session::Column::RevokedAt.is_null());
+
+        if let Some(except) = except {
+            query = query.filter(session::Column::Id.ne(except));
+        }
+
+        query.exec(db).await?;
+        Ok(())
+    }
+
+    /// Drop sessions that expired or were revoked more than a day ago
+    pub async fn purge_stale(db: &DatabaseConnection) -> AppResult<()> {
+        let cutoff = chrono::Utc::now() - chrono::Duration::days(1);
+
+        session::Entity::delete_many()
+            .filter(
+                session::Column::ExpiresAt
+                    .lt(cutoff)
+                    .or(session::Column::RevokedAt.lt(cutoff)),
+            )
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    data_encoding::BASE64URL_NOPAD.encode(&bytes)
+}
+
+fn hash_secret(secret: &str) -> String {
+    data_encoding::HEXLOWER.encode(&Sha256::digest(secret.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_refresh_rotation_and_reuse_detection() {
+        let state = crate::test_util::test_state().await;
+        let db = &state.db;
+        let ttl = chrono::Duration::hours(1);
+
+        let (session, first) = SessionService::create(db, 1, SessionOrigin::default(), ttl)
+            .await
+            .unwrap();
+        let (_, second) = SessionService::rotate(db, &first, ttl).await.unwrap();
+        assert_ne!(first, second);
+
+        // Replaying the rotated token kills the session, including the newest token
+        assert!(SessionService::rotate(db, &first, ttl).await.is_err());
+        assert!(SessionService::find_active(db, &session.id).await.unwrap().is_none());
+        assert!(SessionService::rotate(db, &second, ttl).await.is_err());
+
+        // Of two refreshes racing with the same token only one gets through,
+        // and the other one ends the session
+        let (session, token) = SessionService::create(db, 1, SessionOrigin::default(), ttl)
+            .await
+            .unwrap();
+        let (a, b) = tokio::join!(
+            SessionService::rotate(db, &token, ttl),
+            SessionService::rotate(db, &token, ttl)
+        );
+        assert!(a.is_ok() != b.is_ok());
+        assert!(SessionService::find_active(db, &session.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_all_except_current() {
+        let state = crate::test_util::test_state().await;
+        let db = &state.db;
+        let ttl = chrono::Duration::hours(1);
+
+        let (current, _) = SessionService::create(db, 1, SessionOrigin::default(), ttl).await.unwrap();
+        let (other, _) = SessionService::create(db, 1, SessionOrigin::default(), ttl).await.unwrap();
+
+        SessionService::revoke_all_for_user(db, 1, Some(&current.id)).await.unwrap();
+
+        let active = SessionService::list_active(db, 1).await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].id, current.id);
+        assert!(SessionService::find_active(db, &other.id).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,47 @@
+use sea_orm::{entity::prelude::*, ActiveValue::Set};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::db::entities::setting;
+use crate::error::{AppError, AppResult};
+
+/// Settings that admins change at runtime, as opposed to the environment
+/// configuration read at startup. Values are stored as JSON.
+pub struct SettingsService;
+
+impl SettingsService {
+    pub async fn get<T: DeserializeOwned>(
+        db: &DatabaseConnection,
+        key: &str,
+    ) -> AppResult<Option<T>> {
+        let Some(setting) = setting::Entity::find_by_id(key.to_string()).one(db).await? else {
+            return Ok(None);
+        };
+
+        serde_json::from_str(&setting.value)
+            .map(Some)
+            .map_err(|e| AppError::System(format!("Invalid value for setting {}: {}", key, e)))
+    }
+
+    pub async fn set<T: Serialize>(db: &DatabaseConnection, key: &str, value: &T) -> AppResult<()> {
+        let model = setting::ActiveModel {
+            key: Set(key.to_string()),
+            value: Set(serde_json::to_string(value).map_err(|e| AppError::Internal(e.into()))?),
+            updated_at: Set(chrono::Utc::now()),
+        };
+
+        setting::Entity::insert(model)
+            .on_conflict(
+                sea_orm::sea_query::OnConflict::column(setting::Column::Key)
+                    .update_columns([setting::Column::Value, setting::Column::UpdatedAt])
+                    .to_owned(),
+            )
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove(db: &DatabaseConnection, key: &str) -> AppResult<()> {
+        setting::Entity::delete_by_id(key.to_string()).exec(db).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+use jsonwebtoken::{decode, decode_header, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
+use sea_orm::{entity::prelude::*, ActiveValue::Set, QueryOrder};
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::{Arc, RwLock};
+
+use crate::db::entities::signing_key;
+use crate::error::{AppError, AppResult};
+
+/// Bytes of key material for HS256
+const KEY_BYTES: usize = 64;
+
+/// JWT signing keys, persisted in the database and cached in memory.
+///
+/// The newest key signs new tokens and its id goes into the `kid` header.
+/// After a rotation the previous keys are retired but still verify tokens for
+/// a grace period, so that tokens issued just before the rotation keep working
+/// until they expire.
+#[derive(Clone)]
+pub struct SigningKeys {
+    keys: Arc<RwLock<Vec<signing_key::Model>>>,
+    grace: chrono::Duration,
+}
+
+impl SigningKeys {
+    /// Load the stored keys, generating the first one on a fresh install.
+    /// `grace` should cover the lifetime of the tokens being signed.
+    pub async fn load(db: &DatabaseConnection, grace: chrono::Duration) -> AppResult<Self> {
+        let keys = Self {
+            keys: Arc::new(RwLock::new(Vec::new())),
+            grace,
+        };
+
+        keys.reload(db).await?;
+        if keys.active().is_none() {
+            tracing::info!("Generating JWT signing key");
+            keys.rotate(db).await?;
+        }
+
+        Ok(keys)
+    }
+
+    /// Keys in use, newest first
+    pub fn list(&self) -> Vec<signing_key::Model> {
+        self.keys.read().unwrap().clone()
+    }
+
+    /// Sign `claims` with the active key
+    pub fn sign<T: Serialize>(&self, claims: &T) -> AppResult<String> {
+        let key = self
+            .active()
+            .ok_or_else(|| AppError::System("No active signing key".to_string()))?;
+
+        let header = Header {
+            kid: Some(key.kid.clone()),
+            ..Default::default()
+        };
+
+        encode(&header, claims, &EncodingKey::from_secret(&key_bytes(&key)?))
+            .map_err(|e| AppError::Internal(e.into()))
+    }
+
+    /// Verify a token against the key named by its `kid` header
+    pub fn verify<T: DeserializeOwned>(&self, token: &str, validation: &Validation) -> Option<T> {
+        let kid = decode_header(token).ok()?.kid?;
+        let key = self.list().into_iter().find(|key| key.kid == kid)?;
+
+        if key
+            .retired_at
+            .is_some_and(|retired| retired + self.grace < chrono::Utc::now())
+        {
+            return None;
+        }
+
+        let secret = key_bytes(&key).ok()?;
+        decode::<T>(token, &DecodingKey::from_secret(&secret), validation)
+            .ok()
+            .map(|data| data.claims)
+    }
+
+    /// Generate a new active key, retire the current one and drop keys whose
+    /// grace period is over
+    pub async fn rotate(&self, db: &DatabaseConnection) -> AppResult<signing_key::Model> {
+        let now = chrono::Utc::now();
+
+        signing_key::Entity::update_many()
+            .col_expr(signing_key::Column::RetiredAt, Expr::value(now))
+            .filter(signing_key::Column::RetiredAt.is_null())
+            .exec(db)
+            .await?;
+        signing_key::Entity::delete_many()
+            .filter(signing_key::Column::RetiredAt.lt(now - self.grace))
+            .exec(db)
+            .await?;
+
+        let mut kid = [0u8; 8];
+        let mut secret = [0u8; KEY_BYTES];
+        rand::thread_rng().fill_bytes(&mut kid);
+        rand::thread_rng().fill_bytes(&mut secret);
+
+        let key = signing_key::ActiveModel {
+            kid: Set(data_encoding::HEXLOWER.encode(&kid)),
+            secret: Set(data_encoding::BASE64.encode(&secret)),
+            created_at: Set(now),
+            retired_at: Set(None),
+        }
+        .insert(db)
+        .await?;
+
+        self.reload(db).await?;
+        Ok(key)
+    }
+
+    fn active(&self) -> Option<signing_key::Model> {
+        self.keys
+            .read()
+            .unwrap()
+            .iter()
+            .find(|key| key.retired_at.is_none())
+            .cloned()
+    }
+
+    async fn reload(&self, db: &DatabaseConnection) -> AppResult<()> {
+        let keys = signing_key::Entity::find()
+            .order_by_desc(signing_key::Column::CreatedAt)
+            .all(db)
+            .await?;
+
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+}
+
+fn key_bytes(key: &signing_key::Model) -> AppResult<Vec<u8>> {
+    data_encoding::BASE64
+        .decode(key.secret.as_bytes())
+        .map_err(|e| AppError::Internal(e.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct TestClaims {
+        sub: String,
+        exp: usize,
+    }
+
+    fn claims() -> TestClaims {
+        TestClaims {
+            sub: "1".to_string(),
+            exp: (chrono::Utc::now() + chrono::Duration::minutes(5)).timestamp() as usize,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotation_keeps_recent_tokens_valid() {
+        let state = crate::test_util::test_state().await;
+        let db = &state.db;
+        let keys = SigningKeys::load(db, chrono::Duration::minutes(15)).await.unwrap();
+        let validation = Validation::default();
+
+        let old_token = keys.sign(&claims()).unwrap();
+        let first = keys.list()[0].clone();
+
+        let second = keys.rotate(db).await.unwrap();
+        assert_ne!(first.kid, second.kid);
+        let new_token = keys.sign(&claims()).unwrap();
+        assert_eq!(decode_header(&new_token).unwrap().kid, Some(second.kid));
+
+        assert_eq!(keys.verify::<TestClaims>(&old_token, &validation).unwrap().sub, "1");
+        assert!(keys.verify::<TestClaims>(&new_token, &validation).is_some());
+
+        // Reloading from the database gives the same keys
+        let reloaded = SigningKeys::load(db, chrono::Duration::minutes(15)).await.unwrap();
+        assert_eq!(reloaded.list().len(), 2);
+        assert!(reloaded.verify::<TestClaims>(&old_token, &validation).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_retired_key_expires_after_grace() {
+        let state = crate::test_util::test_state().await;
+        let db = &state.db;
+        let keys = SigningKeys::load(db, chrono::Duration::zero()).await.unwrap();
+        let validation = Validation::default();
+
+        let old_token = keys.sign(&claims()).unwrap();
+        keys.rotate(db).await.unwrap();
+        assert!(keys.verify::<TestClaims>(&old_token, &validation).is_none());
+
+        // Tokens without a known kid are rejected
+        let foreign =
+            encode(&Header::default(), &claims(), &EncodingKey::from_secret(b"x")).unwrap();
+        assert!(keys.verify::<TestClaims>(&foreign, &validation).is_none());
+    }
+}
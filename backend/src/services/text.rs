@@ -0,0 +1,468 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+
+use crate::error::{AppError, AppResult};
+
+/// Bytes looked at to tell the encoding and whether a file is text
+const SAMPLE_BYTES: usize = 64 * 1024;
+pub const DEFAULT_PAGE_BYTES: u64 = 1024 * 1024;
+pub const MAX_PAGE_BYTES: u64 = 8 * 1024 * 1024;
+pub const DEFAULT_PAGE_LINES: u64 = 1000;
+pub const DEFAULT_HEX_BYTES: u64 = 64 * 1024;
+pub const MAX_HEX_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Cr,
+    /// More than one kind; saving leaves them as they are
+    Mixed,
+}
+
+impl LineEnding {
+    /// The line ending `text` uses, `None` if it has a single line
+    pub fn detect(text: &str) -> Option<Self> {
+        let (mut lf, mut crlf, mut cr) = (false, false, false);
+        let mut bytes = text.bytes().peekable();
+        while let Some(byte) = bytes.next() {
+            match byte {
+                b'\r' if bytes.peek() == Some(&b'\n') => {
+                    bytes.next();
+                    crlf = true;
+                }
+                b'\r' => cr = true,
+                b'\n' => lf = true,
+                _ => {}
+            }
+        }
+        match (lf, crlf, cr) {
+            (false, false, false) => None,
+            (true, false, false) => Some(Self::Lf),
+            (false, true, false) => Some(Self::Crlf),
+            (false, false, true) => Some(Self::Cr),
+            _ => Some(Self::Mixed),
+        }
+    }
+
+    /// `text` with every line ending turned into this one
+    pub fn apply(self, text: &str) -> String {
+        let ending = match self {
+            Self::Lf => "\n",
+            Self::Crlf => "\r\n",
+            Self::Cr => "\r",
+            Self::Mixed => return text.to_string(),
+        };
+        let unified = text.replace("\r\n", "\n").replace('\r', "\n");
+        match self {
+            Self::Lf => unified,
+            _ => unified.replace('\n', ending),
+        }
+    }
+}
+
+/// Which part of a file to read, and how
+#[derive(Debug, Default)]
+pub struct PageRequest {
+    /// Byte offset to start at; continue from the `next_offset` of the last
+    /// page, which always falls on a line boundary
+    pub offset: Option<u64>,
+    pub length: Option<u64>,
+    /// First line to read, counting from 1, instead of an offset
+    pub start_line: Option<u64>,
+    pub lines: Option<u64>,
+    /// Encoding label to decode with instead of the detected one
+    pub encoding: Option<String>,
+    /// A hex dump even of a text file
+    pub hex: bool,
+}
+
+/// A part of a file, or all of it, ready to show
+#[derive(Debug, Serialize)]
+pub struct Page {
+    /// The decoded text, or a hex dump
+    pub content: String,
+    pub hex: bool,
+    /// Whether the file looks like anything but text
+    pub binary: bool,
+    /// Encoding the text was decoded from, e.g. `UTF-8`, `GBK` or `UTF-16LE`
+    pub encoding: Option<&'static str>,
+    /// Whether the file starts with a byte order mark
+    pub bom: bool,
+    pub line_ending: Option<LineEnding>,
+    /// Size of the whole file
+    pub size: u64,
+    /// The bytes this page covers
+    pub offset: u64,
+    pub length: u64,
+    /// Where the next page starts, `None` at the end of the file
+    pub next_offset: Option<u64>,
+    /// For pages read by line, the line after the last one read
+    pub next_line: Option<u64>,
+    /// Whether the page is the whole file
+    pub complete: bool,
+    /// Whether the page may be saved back from the editor
+    pub editable: bool,
+}
+
+/// Guess how a file is encoded from its first bytes. Returns the encoding
+/// and the length of its byte order mark, if any.
+fn detect(sample: &[u8], complete: bool) -> (&'static Encoding, usize) {
+    if let Some((encoding, bom)) = Encoding::for_bom(sample) {
+        return (encoding, bom);
+    }
+    match std::str::from_utf8(sample) {
+        Ok(_) => return (UTF_8, 0),
+        // Only cut off at the end of the sample
+        Err(e) if e.error_len().is_none() && !complete => return (UTF_8, 0),
+        Err(_) => {}
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(sample, complete);
+    (detector.guess(None, true), 0)
+}
+
+/// Whether a sample without a byte order mark looks like anything but text:
+/// text has no NUL bytes, and few control characters besides whitespace
+fn is_binary(sample: &[u8]) -> bool {
+    if sample.contains(&0) {
+        return true;
+    }
+    let controls = sample
+        .iter()
+        .filter(|&&byte| byte < 0x20 && !matches!(byte, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b))
+        .count();
+    controls * 10 > sample.len()
+}
+
+/// The bytes of a line feed in `encoding`
+fn newline(encoding: &'static Encoding) -> &'static [u8] {
+    if encoding == UTF_16LE {
+        b"\n\0"
+    } else if encoding == UTF_16BE {
+        b"\0\n"
+    } else {
+        // Also holds for GBK, Shift_JIS and the like, whose multibyte
+        // sequences never contain 0x0A
+        b"\n"
+    }
+}
+
+/// Read on from the reader's position at `position` until `lines` line
+/// feeds went by or the reader ran out. Returns where that left off.
+fn skip_lines(
+    reader: &mut impl BufRead,
+    newline: &[u8],
+    mut position: u64,
+    mut lines: u64,
+) -> io::Result<u64> {
+    // First byte of a UTF-16 unit when the read ended in its middle
+    let mut pending = None;
+    while lines > 0 {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        let mut used = 0;
+        for &byte in buf {
+            used += 1;
+            let is_newline = match newline {
+                [unit] => byte == *unit,
+                [first, second] => match pending.take() {
+                    Some(previous) => previous == *first && byte == *second,
+                    None => {
+                        pending = Some(byte);
+                        false
+                    }
+                },
+                _ => unreachable!("line feeds are one or two bytes"),
+            };
+            if is_newline {
+                lines -= 1;
+                if lines == 0 {
+                    break;
+                }
+            }
+        }
+        reader.consume(used);
+        position += used as u64;
+    }
+    Ok(position)
+}
+
+/// How much of `chunk` to keep so that it ends after a line feed, unless
+/// it has none
+fn end_of_last_line(chunk: &[u8], newline: &[u8]) -> usize {
+    chunk
+        .chunks_exact(newline.len())
+        .rposition(|unit| unit == newline)
+        .map_or(chunk.len(), |unit| (unit + 1) * newline.len())
+}
+
+fn read_at(file: &mut File, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = Vec::new();
+    file.take(length).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Read a page of a file: text decoded from its encoding, or a hex dump for
+/// binary files. Without an offset or line, a file up to `edit_max_bytes`
+/// is read whole and anything larger from its start.
+pub fn read_page(file: &mut File, request: &PageRequest, edit_max_bytes: u64) -> AppResult<Page> {
+    let size = file.metadata()?.len();
+    let sample = read_at(file, 0, SAMPLE_BYTES as u64)?;
+    let complete_sample = sample.len() as u64 == size;
+
+    let (encoding, bom) = match &request.encoding {
+        Some(label) => {
+            let encoding = encoding_for(label)?;
+            // A byte order mark is skipped all the same
+            let bom = Encoding::for_bom(&sample)
+                .filter(|(found, _)| *found == encoding)
+                .map_or(0, |(_, bom)| bom);
+            (encoding, bom)
+        }
+        None => detect(&sample, complete_sample),
+    };
+    let binary = request.encoding.is_none() && bom == 0 && is_binary(&sample);
+    let hex = request.hex || binary;
+    let (text_encoding, line_ending) = if binary {
+        (None, None)
+    } else {
+        let start = &sample[bom..end_of_last_line(&sample[bom..], newline(encoding)) + bom];
+        let (text, _) = encoding.decode_without_bom_handling(start);
+        (Some(encoding.name()), LineEnding::detect(&text))
+    };
+
+    let mut page = Page {
+        content: String::new(),
+        hex,
+        binary,
+        encoding: text_encoding,
+        bom: bom > 0,
+        line_ending,
+        size,
+        offset: 0,
+        length: 0,
+        next_offset: None,
+        next_line: None,
+        complete: false,
+        editable: false,
+    };
+
+    if hex {
+        let offset = request.offset.unwrap_or(0).min(size);
+        let length = request.length.unwrap_or(DEFAULT_HEX_BYTES).min(MAX_HEX_BYTES);
+        let bytes = read_at(file, offset, length)?;
+        page.content = hex_dump(&bytes, offset);
+        page.offset = offset;
+        page.length = bytes.len() as u64;
+    } else {
+        let newline = newline(encoding);
+        let (start, end) = match request.start_line {
+            Some(start_line) => {
+                let lines = request.lines.unwrap_or(DEFAULT_PAGE_LINES);
+                file.seek(SeekFrom::Start(bom as u64))?;
+                let mut reader = BufReader::new(&mut *file);
+                let skip = start_line.saturating_sub(1);
+                let start = skip_lines(&mut reader, newline, bom as u64, skip)?;
+                let mut reader = reader.take(MAX_PAGE_BYTES);
+                (start, skip_lines(&mut reader, newline, start, lines)?)
+            }
+            None => {
+                let whole = request.offset.is_none() && request.length.is_none();
+                let length = if whole && size <= edit_max_bytes {
+                    size
+                } else {
+                    request.length.unwrap_or(DEFAULT_PAGE_BYTES).min(MAX_PAGE_BYTES)
+                };
+                // Past the byte order mark, and on a whole UTF-16 unit
+                let offset = request.offset.unwrap_or(0).max(bom as u64).min(size);
+                let offset = offset - (offset - bom as u64) % newline.len() as u64;
+                (offset, offset.saturating_add(length).min(size))
+            }
+        };
+
+        let mut bytes = read_at(file, start, end - start)?;
+        if start + (bytes.len() as u64) < size {
+            bytes.truncate(end_of_last_line(&bytes, newline));
+        }
+        if let Some(start_line) = request.start_line {
+            let read = bytes.chunks_exact(newline.len()).filter(|unit| *unit == newline).count();
+            page.next_line = Some(start_line.max(1) + read as u64);
+        }
+        let (text, _) = encoding.decode_without_bom_handling(&bytes);
+        page.content = text.into_owned();
+        page.offset = start;
+        page.length = bytes.len() as u64;
+    }
+
+    let end = page.offset + page.length;
+    page.next_offset = (end < size).then_some(end);
+    page.next_line = page.next_line.filter(|_| end < size);
+    page.complete = page.offset <= bom as u64 && end == size;
+    page.editable = !hex && page.complete && size <= edit_max_bytes;
+    Ok(page)
+}
+
+/// A whole file's content as text in its detected encoding, `None` if it
+/// looks binary
+pub fn decode(bytes: &[u8]) -> Option<String> {
+    let (encoding, bom) = detect(bytes, true);
+    if bom == 0 && is_binary(&bytes[..bytes.len().min(SAMPLE_BYTES)]) {
+        return None;
+    }
+    Some(encoding.decode_without_bom_handling(&bytes[bom..]).0.into_owned())
+}
+
+fn encoding_for(label: &str) -> AppResult<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+        .filter(|encoding| *encoding != encoding_rs::REPLACEMENT)
+        .ok_or_else(|| AppError::Validation(format!("Unknown encoding: {}", label)))
+}
+
+/// Turn text from the editor back into the bytes of a file in `encoding`,
+/// UTF-8 by default. Characters the encoding has no bytes for are refused
+/// rather than lost.
+pub fn encode(
+    text: &str,
+    encoding: Option<&str>,
+    bom: bool,
+    line_ending: Option<LineEnding>,
+) -> AppResult<Vec<u8>> {
+    let encoding = encoding.map(encoding_for).transpose()?.unwrap_or(UTF_8);
+    let text = match line_ending {
+        Some(line_ending) => line_ending.apply(text),
+        None => text.to_string(),
+    };
+
+    // encoding_rs only decodes UTF-16
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let mut bytes = Vec::with_capacity(text.len() * 2 + 2);
+        for unit in std::iter::once(0xfeff).filter(|_| bom).chain(text.encode_utf16()) {
+            let unit = if encoding == UTF_16LE { unit.to_le_bytes() } else { unit.to_be_bytes() };
+            bytes.extend(unit);
+        }
+        return Ok(bytes);
+    }
+
+    let (bytes, _, unmappable) = encoding.encode(&text);
+    if unmappable {
+        return Err(AppError::Validation(format!(
+            "The text has characters {} can't represent",
+            encoding.name()
+        )));
+    }
+    let mut content = Vec::with_capacity(bytes.len() + 3);
+    if bom && encoding == UTF_8 {
+        content.extend_from_slice(b"\xef\xbb\xbf");
+    }
+    content.extend_from_slice(&bytes);
+    Ok(content)
+}
+
+/// 16 bytes a line, like `hexdump -C`:
+/// `00000010  48 65 6c 6c 6f 0a 00 00  00 00 00 00 00 00 00 00  |Hello...........|`
+pub fn hex_dump(bytes: &[u8], offset: u64) -> String {
+    let mut dump = String::with_capacity(bytes.len() * 5);
+    for (n, line) in bytes.chunks(16).enumerate() {
+        write!(dump, "{:08x}  ", offset + n as u64 * 16).unwrap();
+        for column in 0..16 {
+            match line.get(column) {
+                Some(byte) => write!(dump, "{:02x} ", byte).unwrap(),
+                None => dump.push_str("   "),
+            }
+            if column == 7 {
+                dump.push(' ');
+            }
+        }
+        dump.push_str(" |");
+        dump.extend(line.iter().map(|&byte| match byte {
+            0x20..=0x7e => byte as char,
+            _ => '.',
+        }));
+        dump.push_str("|\n");
+    }
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(path: &std::path::Path, request: PageRequest) -> Page {
+        read_page(&mut File::open(path).unwrap(), &request, 64).unwrap()
+    }
+
+    #[test]
+    fn test_encodings_and_pages() {
+        let dir = std::env::temp_dir().join(format!("mana-text-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Detected, decoded and encoded back the same
+        let (gbk, _, _) = encoding_rs::GBK.encode("服务器配置文件，请勿随意修改。\r\n端口 = 8080\r\n");
+        let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode("設定ファイルです。変更しないでください。\n");
+        let utf16 = encode("héllo\nwörld\n", Some("utf-16le"), true, None).unwrap();
+        let latin1 = b"caf\xe9 cr\xe8me br\xfbl\xe9e, na\xefve fa\xe7ade\n".to_vec();
+        let cases = [
+            ("gbk.conf", gbk.to_vec(), "GBK", false, Some(LineEnding::Crlf)),
+            ("sjis.txt", sjis.to_vec(), "Shift_JIS", false, Some(LineEnding::Lf)),
+            ("utf16.txt", utf16, "UTF-16LE", true, Some(LineEnding::Lf)),
+            ("latin1.txt", latin1, "windows-1252", false, Some(LineEnding::Lf)),
+        ];
+        for (name, bytes, encoding, bom, line_ending) in cases {
+            std::fs::write(dir.join(name), &bytes).unwrap();
+            let page = read(&dir.join(name), PageRequest::default());
+            assert_eq!(page.encoding, Some(encoding), "{}", name);
+            assert_eq!((page.bom, page.line_ending, page.binary), (bom, line_ending, false));
+            assert!(page.complete && page.editable, "{}", name);
+            // The editor hands back LF only
+            let text = page.content.replace("\r\n", "\n");
+            assert_eq!(encode(&text, page.encoding, bom, line_ending).unwrap(), bytes, "{}", name);
+        }
+        let unmappable = encode("€ and 中", Some("iso-8859-1"), false, None);
+        assert!(matches!(unmappable, Err(AppError::Validation(_))));
+
+        // Larger than the editable size of 64 bytes: pages end on a line
+        let log: String = (1..=20).map(|n| format!("line {}\n", n)).collect();
+        std::fs::write(dir.join("app.log"), &log).unwrap();
+        let page = read(&dir.join("app.log"), PageRequest::default());
+        assert!(!page.editable && page.content.starts_with("line 1\n"));
+        let page = read(&dir.join("app.log"), PageRequest {
+            offset: Some(10),
+            length: Some(20),
+            ..Default::default()
+        });
+        assert_eq!((page.content.as_str(), page.next_offset), ("e 2\nline 3\nline 4\n", Some(28)));
+        let page = read(&dir.join("app.log"), PageRequest {
+            start_line: Some(19),
+            lines: Some(5),
+            ..Default::default()
+        });
+        assert_eq!((page.content.as_str(), page.next_line), ("line 19\nline 20\n", None));
+        let page = read(&dir.join("app.log"), PageRequest {
+            start_line: Some(3),
+            lines: Some(2),
+            ..Default::default()
+        });
+        assert_eq!((page.content.as_str(), page.next_line), ("line 3\nline 4\n", Some(5)));
+
+        // Binary files come as a hex dump
+        std::fs::write(dir.join("app.bin"), b"\x7fELF\x02\x01\x01\0\0\0Hello, world\n").unwrap();
+        let page = read(&dir.join("app.bin"), PageRequest::default());
+        assert!(page.binary && page.hex && !page.editable);
+        assert_eq!(
+            page.content,
+            "00000000  7f 45 4c 46 02 01 01 00  00 00 48 65 6c 6c 6f 2c  |.ELF......Hello,|\n\
+             00000010  20 77 6f 72 6c 64 0a                              | world.|\n"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
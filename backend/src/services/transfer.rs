@@ -0,0 +1,393 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::error::{AppError, AppResult};
+use crate::services::files::{self, ConflictPolicy};
+use crate::services::jail::{Jail, Resolved};
+use crate::services::jobs::{copy_with_progress, JobHandle};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferKind {
+    Copy,
+    Move,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemStatus {
+    Done,
+    Skipped,
+    Failed,
+}
+
+/// The outcome of one path of a batch operation
+#[derive(Debug, Serialize)]
+pub struct ItemResult {
+    pub path: String,
+    pub status: ItemStatus,
+    /// Where the item ended up, which differs from the requested name after
+    /// a rename
+    pub destination: Option<PathBuf>,
+    pub error: Option<String>,
+}
+
+impl ItemResult {
+    pub fn new(path: &str, outcome: AppResult<Option<PathBuf>>) -> Self {
+        let (status, destination, error) = match outcome {
+            Ok(Some(destination)) => (ItemStatus::Done, Some(destination), None),
+            Ok(None) => (ItemStatus::Skipped, None, None),
+            Err(e) => (ItemStatus::Failed, None, Some(e.to_string())),
+        };
+        Self { path: path.to_string(), status, destination, error }
+    }
+}
+
+/// The result of a batch: every item and how many ended which way
+pub fn summary(items: &[ItemResult]) -> Value {
+    let count = |status| items.iter().filter(|item| item.status == status).count();
+    json!({
+        "items": items,
+        "done": count(ItemStatus::Done),
+        "skipped": count(ItemStatus::Skipped),
+        "failed": count(ItemStatus::Failed),
+    })
+}
+
+/// Copy or move each of `paths` into the directory `destination`, keeping
+/// its name. Directories go with everything below them and symlinks are
+/// transferred as links, never followed. A move within a filesystem is a
+/// rename; across filesystems it is a copy followed by removing the source.
+///
+/// A directory overwritten by another one is handed to `displace`, which
+/// moves it out of the way (the API puts it in the trash); it is never
+/// deleted here.
+///
+/// A path that fails doesn't stop the others; its error is reported in its
+/// result instead.
+pub fn transfer(
+    kind: TransferKind,
+    paths: &[String],
+    destination: &Path,
+    conflict: ConflictPolicy,
+    jail: &Jail,
+    displace: &dyn Fn(&Resolved) -> AppResult<()>,
+    handle: &JobHandle,
+) -> AppResult<Value> {
+    let sources: Vec<_> = paths.iter().map(|path| source(jail, path)).collect();
+    let displace = |target: &Resolved| {
+        jail.check_tree(target)?;
+        displace(target)
+    };
+
+    let mut total = 0;
+    for source in sources.iter().flatten() {
+        // A tree that can't be walked fails when its turn comes
+        total += tree_size(source, handle).unwrap_or_default();
+    }
+    handle.set_total(total);
+
+    let mut items = Vec::with_capacity(paths.len());
+    for (path, source) in paths.iter().zip(sources) {
+        handle.check_cancelled()?;
+        handle.set_current(path.as_str());
+        let outcome = source.and_then(|source| {
+            let name = source.file_name().unwrap_or_default();
+            let target = jail.resolve_link(destination.join(name))?;
+            match kind {
+                TransferKind::Copy => copy_item(&source, &target, conflict, &displace, handle),
+                TransferKind::Move => move_item(&source, &target, conflict, &displace, handle),
+            }
+        });
+        items.push(ItemResult::new(path, outcome));
+    }
+
+    Ok(summary(&items))
+}
+
+/// Give the file or directory at `path` the new `name` in the directory it
+/// is in
+pub fn rename(
+    jail: &Jail,
+    path: &str,
+    name: &str,
+    conflict: ConflictPolicy,
+) -> AppResult<Option<PathBuf>> {
+    let mut components = Path::new(name).components();
+    if !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+        || name.contains('/')
+    {
+        return Err(AppError::Validation(format!("Invalid name: {}", name)));
+    }
+
+    let source = source(jail, path)?;
+    let target = jail.resolve_link(source.with_file_name(name))?;
+    if target == source {
+        return Ok(Some(target.into_path()));
+    }
+    files::rename(&source, &target, conflict).map_err(files::conflict_error)
+}
+
+/// The path to copy, move or rename, itself if it is a symlink
+fn source(jail: &Jail, path: &str) -> AppResult<Resolved> {
+    let source = jail.resolve_link(path)?;
+    jail.check_tree(&source)?;
+    if source.file_name().is_none() {
+        return Err(AppError::Validation("Can't transfer /".to_string()));
+    }
+    source.symlink_metadata().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => AppError::NotFound(format!("Path not found: {}", path)),
+        _ => e.into(),
+    })?;
+    Ok(source)
+}
+
+/// Bytes in the files at and below `path`
+pub fn tree_size(path: &Path, handle: &JobHandle) -> AppResult<u64> {
+    let mut size = 0;
+    for entry in WalkDir::new(path).follow_links(false).follow_root_links(false) {
+        handle.check_cancelled()?;
+        let metadata = entry.and_then(|entry| entry.metadata()).map_err(io::Error::from)?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Refuse what no conflict policy can sort out, and settle the policies that
+/// don't need the data before it is copied
+fn check_target(
+    source: &Resolved,
+    target: &Resolved,
+    conflict: ConflictPolicy,
+) -> AppResult<Option<Option<PathBuf>>> {
+    let inside = target.starts_with(source) && target != source;
+    if source.symlink_metadata()?.is_dir() && inside {
+        return Err(AppError::Validation(format!(
+            "Can't put {} inside itself",
+            source.display()
+        )));
+    }
+    if !target.exists() {
+        return Ok(None);
+    }
+    match conflict {
+        ConflictPolicy::Skip => Ok(Some(None)),
+        ConflictPolicy::Fail => {
+            Err(AppError::Conflict(format!("{} already exists", target.display())))
+        }
+        ConflictPolicy::Overwrite if target == source => Err(AppError::Validation(format!(
+            "{} is already there",
+            source.display()
+        ))),
+        _ => Ok(None),
+    }
+}
+
+/// Under [`ConflictPolicy::Overwrite`], hand a directory at `target` to
+/// `displace` so that the directory `source` can take its place. Nothing
+/// else replaces a directory.
+fn make_way(
+    source: &Resolved,
+    target: &Resolved,
+    conflict: ConflictPolicy,
+    displace: &dyn Fn(&Resolved) -> AppResult<()>,
+) -> AppResult<()> {
+    if conflict != ConflictPolicy::Overwrite || !target.is_dir() {
+        return Ok(());
+    }
+    if !source.is_dir() {
+        return Err(AppError::Conflict(format!("{} is a directory", target.display())));
+    }
+    displace(target)
+}
+
+/// For callers that never replace a directory: one in the way is a conflict
+pub fn keep_directory(target: &Resolved) -> AppResult<()> {
+    Err(AppError::Conflict(format!("{} is a directory", target.display())))
+}
+
+fn copy_item(
+    source: &Resolved,
+    target: &Resolved,
+    conflict: ConflictPolicy,
+    displace: &dyn Fn(&Resolved) -> AppResult<()>,
+    handle: &JobHandle,
+) -> AppResult<Option<PathBuf>> {
+    if let Some(settled) = check_target(source, target, conflict)? {
+        handle.advance(tree_size(source, handle)?);
+        return Ok(settled);
+    }
+
+    // Copied next to the target, then moved into place
+    let temp = target.sibling(format!(".mana-copy-{}.part", uuid::Uuid::new_v4()))?;
+    let result = copy_tree(source, &temp, handle)
+        .and_then(|()| make_way(source, target, conflict, displace))
+        .and_then(|()| files::place(&temp, target, conflict).map_err(files::conflict_error));
+    if result.is_err() && temp.exists() {
+        temp.remove().ok();
+    }
+    result
+}
+
+/// Move `source` to `target`, copying it over when they are on different
+/// filesystems
+pub fn move_item(
+    source: &Resolved,
+    target: &Resolved,
+    conflict: ConflictPolicy,
+    displace: &dyn Fn(&Resolved) -> AppResult<()>,
+    handle: &JobHandle,
+) -> AppResult<Option<PathBuf>> {
+    if target == source {
+        return Ok(Some(target.to_path_buf()));
+    }
+    if let Some(settled) = check_target(source, target, conflict)? {
+        handle.advance(tree_size(source, handle)?);
+        return Ok(settled);
+    }
+
+    let size = tree_size(source, handle)?;
+    make_way(source, target, conflict, displace)?;
+    match files::rename(source, target, conflict) {
+        Ok(placed) => {
+            handle.advance(size);
+            Ok(placed)
+        }
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let placed = copy_item(source, target, conflict, displace, handle)?;
+            if placed.is_some() {
+                source.remove()?;
+            }
+            Ok(placed)
+        }
+        Err(e) => Err(files::conflict_error(e)),
+    }
+}
+
+/// Recreate the tree at `source` as `target`, which must not exist yet.
+/// Files keep their permissions and modification time.
+fn copy_tree(source: &Path, target: &Path, handle: &JobHandle) -> AppResult<()> {
+    // Directory permissions are set last, a read-only one couldn't be
+    // filled otherwise
+    let mut directories = Vec::new();
+
+    for entry in WalkDir::new(source).follow_links(false).follow_root_links(false) {
+        handle.check_cancelled()?;
+        let entry = entry.map_err(io::Error::from)?;
+        let relative = entry.path().strip_prefix(source).unwrap_or(entry.path());
+        let to = target.join(relative);
+        let metadata = entry.metadata().map_err(io::Error::from)?;
+
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            fs::create_dir(&to)?;
+            directories.push((to, metadata.permissions()));
+        } else if file_type.is_file() {
+            let from = files::open_nofollow(entry.path())?;
+            let mut file = File::create_new(&to)?;
+            copy_with_progress(from, &mut file, handle)?;
+            file.set_permissions(metadata.permissions())?;
+            if let Ok(modified) = metadata.modified() {
+                file.set_modified(modified)?;
+            }
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &to)?;
+        }
+        // Sockets, pipes and devices are left out
+    }
+
+    for (directory, permissions) in directories.into_iter().rev() {
+        fs::set_permissions(directory, permissions)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::jobs::{JobStatus, Jobs};
+
+    #[tokio::test]
+    async fn test_copy_and_move_batches() {
+        let dir = std::env::temp_dir().join(format!("mana-transfer-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("src/site/css")).unwrap();
+        fs::create_dir_all(dir.join("dst")).unwrap();
+        let dir = fs::canonicalize(dir).unwrap();
+        fs::write(dir.join("src/site/index.html"), "<h1>hi</h1>").unwrap();
+        fs::write(dir.join("src/site/css/main.css"), "body {}").unwrap();
+        fs::write(dir.join("src/notes.txt"), "new").unwrap();
+        fs::write(dir.join("dst/notes.txt"), "old").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("css/main.css", dir.join("src/site/style.css")).unwrap();
+
+        let jobs = Jobs::default();
+        let run = |kind, paths: &[&str], conflict| {
+            let paths: Vec<String> =
+                paths.iter().map(|path| dir.join(path).display().to_string()).collect();
+            let destination = dir.join("dst");
+            let displaced = dir.join("displaced");
+            let job = jobs.spawn_blocking(1, "transfer", String::new(), move |handle| {
+                let displace = |target: &Resolved| Ok(fs::rename(target, &displaced)?);
+                transfer(kind, &paths, &destination, conflict, &Jail::default(), &displace, handle)
+            });
+            let jobs = jobs.clone();
+            async move {
+                let info = jobs.wait(&job.id, 1).await;
+                assert_eq!(info.status, JobStatus::Completed, "{:?}", info.error);
+                info.result.unwrap()
+            }
+        };
+
+        let paths = ["src/site", "src/notes.txt", "src/missing", "src"];
+        let result = run(TransferKind::Copy, &paths, ConflictPolicy::Fail).await;
+        let items = result["items"].as_array().unwrap();
+        let status: Vec<_> = items.iter().map(|item| &item["status"]).collect();
+        assert_eq!(status, ["done", "failed", "failed", "done"]);
+        assert_eq!((result["done"].as_u64(), result["failed"].as_u64()), (Some(2), Some(2)));
+        assert_eq!(fs::read_to_string(dir.join("dst/site/css/main.css")).unwrap(), "body {}");
+        assert_eq!(fs::read_to_string(dir.join("dst/notes.txt")).unwrap(), "old");
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(dir.join("dst/site/style.css")).unwrap(),
+            Path::new("css/main.css")
+        );
+
+        // A directory can't go inside itself
+        let result = run(TransferKind::Move, &["dst"], ConflictPolicy::Rename).await;
+        assert_eq!(result["failed"], 1);
+
+        let result = run(TransferKind::Move, &["src/notes.txt"], ConflictPolicy::Rename).await;
+        let moved = dir.join("dst/notes (1).txt");
+        assert_eq!(result["items"][0]["destination"], moved.to_string_lossy().as_ref());
+        assert_eq!(fs::read_to_string(moved).unwrap(), "new");
+        assert!(!dir.join("src/notes.txt").exists());
+
+        // A file never overwrites a directory
+        fs::create_dir(dir.join("src/file")).unwrap();
+        fs::write(dir.join("src/file/site"), "").unwrap();
+        let result = run(TransferKind::Move, &["src/file/site"], ConflictPolicy::Overwrite).await;
+        assert_eq!(result["failed"], 1);
+        assert!(dir.join("dst/site/index.html").exists());
+
+        // Overwriting a directory moves the old one out of the way as a whole
+        fs::write(dir.join("dst/site/stale.txt"), "").unwrap();
+        let result = run(TransferKind::Move, &["src/site"], ConflictPolicy::Overwrite).await;
+        assert_eq!(result["done"], 1);
+        assert!(!dir.join("src/site").exists());
+        assert!(!dir.join("dst/site/stale.txt").exists());
+        assert!(dir.join("displaced/stale.txt").exists());
+        assert!(dir.join("dst/site/index.html").exists());
+
+        let result = run(TransferKind::Copy, &["dst/site"], ConflictPolicy::Skip).await;
+        assert_eq!(result["skipped"], 1);
+        assert_eq!(fs::read_dir(dir.join("dst")).unwrap().count(), 4);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,282 @@
+use chrono::Utc;
+use sea_orm::{entity::prelude::*, ActiveValue::Set, QueryOrder};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::db::entities::trash_item;
+use crate::error::{AppError, AppResult};
+use crate::services::files::{self, ConflictPolicy};
+use crate::services::jail::Resolved;
+use crate::services::jobs::JobHandle;
+use crate::services::transfer;
+
+/// Deleted files, kept in a directory of the panel's until they are restored
+/// or purged.
+///
+/// Each item is moved into the trash directory under a random name, and its
+/// row remembers where it came from, who deleted it and when. Items past the
+/// retention period, and the oldest ones once the trash outgrows its size
+/// limit, are purged whenever the trash is used.
+pub struct TrashService;
+
+impl TrashService {
+    /// The trash directory, created on first use
+    pub async fn dir(config: &Config) -> AppResult<PathBuf> {
+        Ok(files::private_dir(Path::new(&config.trash_dir))?)
+    }
+
+    /// Move `path` into the trash; a symlink goes itself, not what it points
+    /// to. Anything may be trashed but the trash and what is already in it,
+    /// as long as it is on the trash's filesystem.
+    pub async fn trash(
+        db: &DatabaseConnection,
+        config: &Config,
+        path: &Resolved,
+        user_id: Option<i32>,
+        username: Option<String>,
+    ) -> AppResult<trash_item::Model> {
+        let dir = Self::dir(config).await?;
+        if path.starts_with(&dir) {
+            return Err(AppError::Validation(format!(
+                "{} is already in the trash, purge it from there instead",
+                path.display()
+            )));
+        }
+        if dir.starts_with(path) {
+            return Err(AppError::Validation(format!(
+                "{} contains the trash and can only be deleted permanently",
+                path.display()
+            )));
+        }
+        let metadata = path.symlink_metadata()?;
+
+        let stored_name = uuid::Uuid::new_v4().to_string();
+        let (source, target) = (path.to_path_buf(), Resolved::new(&dir.join(&stored_name))?);
+        let size =
+            tokio::task::spawn_blocking(move || transfer::tree_size(&source, &JobHandle::detached()))
+                .await
+                .map_err(|e| AppError::Internal(e.into()))??;
+
+        // Recorded first, so that nothing sits in the trash without a row
+        let item = trash_item::ActiveModel {
+            original_path: Set(path.to_string_lossy().into_owned()),
+            stored_name: Set(stored_name),
+            is_dir: Set(metadata.is_dir()),
+            size: Set(size as i64),
+            deleted_by: Set(user_id),
+            deleted_by_username: Set(username),
+            deleted_at: Set(Utc::now()),
+            ..Default::default()
+        }
+        .insert(db)
+        .await?;
+
+        // Only ever a rename: copying a tree from another filesystem would
+        // hold up the request and could fill the trash's
+        let source = path.try_clone()?;
+        let moved = tokio::task::spawn_blocking(move || {
+            files::rename(&source, &target, ConflictPolicy::Fail).map_err(|e| match e.kind() {
+                std::io::ErrorKind::CrossesDevices => AppError::Validation(format!(
+                    "{} is on another filesystem than the trash, delete it permanently instead",
+                    source.display()
+                )),
+                _ => files::conflict_error(e),
+            })
+        })
+        .await;
+        if let Err(e) = moved.map_err(|e| AppError::Internal(e.into())).and_then(|moved| moved) {
+            trash_item::Entity::delete_by_id(item.id).exec(db).await?;
+            return Err(e);
+        }
+
+        Self::purge_expired(db, config, Some(item.id)).await?;
+        Ok(item)
+    }
+
+    /// Everything in the trash, most recently deleted first
+    pub async fn list(
+        db: &DatabaseConnection,
+        config: &Config,
+    ) -> AppResult<Vec<trash_item::Model>> {
+        Self::purge_expired(db, config, None).await?;
+        Ok(trash_item::Entity::find()
+            .order_by_desc(trash_item::Column::DeletedAt)
+            .order_by_desc(trash_item::Column::Id)
+            .all(db)
+            .await?)
+    }
+
+    pub async fn get(db: &DatabaseConnection, id: i32) -> AppResult<trash_item::Model> {
+        trash_item::Entity::find_by_id(id)
+            .one(db)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Trash item not found: {}", id)))
+    }
+
+    /// Move an item back to `target`, normally where it was deleted from,
+    /// recreating missing parent directories. Returns where it ended up, or
+    /// `None` if it was skipped and stays in the trash.
+    ///
+    /// A directory at `target` is never overwritten; trash it first to
+    /// replace it.
+    pub async fn restore(
+        db: &DatabaseConnection,
+        config: &Config,
+        item: &trash_item::Model,
+        target: &Resolved,
+        conflict: ConflictPolicy,
+    ) -> AppResult<Option<PathBuf>> {
+        let source = Resolved::new(&Self::dir(config).await?.join(&item.stored_name))?;
+        let target = target.try_clone()?.create_parents()?;
+        let placed = tokio::task::spawn_blocking(move || {
+            let displace = transfer::keep_directory;
+            transfer::move_item(&source, &target, conflict, &displace, &JobHandle::detached())
+        })
+        .await
+        .map_err(|e| AppError::Internal(e.into()))??;
+        if placed.is_some() {
+            trash_item::Entity::delete_by_id(item.id).exec(db).await?;
+        }
+        Ok(placed)
+    }
+
+    /// Delete an item for good
+    pub async fn purge(
+        db: &DatabaseConnection,
+        config: &Config,
+        item: &trash_item::Model,
+    ) -> AppResult<()> {
+        let path = Resolved::new(&Self::dir(config).await?.join(&item.stored_name))?;
+        tokio::task::spawn_blocking(move || match path.remove() {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        })
+        .await
+        .map_err(|e| AppError::Internal(e.into()))??;
+        trash_item::Entity::delete_by_id(item.id).exec(db).await?;
+        Ok(())
+    }
+
+    /// Purge items past the retention period, then the oldest ones until the
+    /// trash fits its size limit. `keep` is spared either way, so that a
+    /// delete never turns permanent right away.
+    pub async fn purge_expired(
+        db: &DatabaseConnection,
+        config: &Config,
+        keep: Option<i32>,
+    ) -> AppResult<u64> {
+        let cutoff = (config.trash_retention_days > 0)
+            .then(|| Utc::now() - chrono::Duration::days(config.trash_retention_days));
+        let items = trash_item::Entity::find()
+            .order_by_desc(trash_item::Column::DeletedAt)
+            .order_by_desc(trash_item::Column::Id)
+            .all(db)
+            .await?;
+
+        let mut total: u64 = 0;
+        let mut purged = 0;
+        for item in items {
+            total = total.saturating_add(item.size as u64);
+            let too_old = cutoff.is_some_and(|cutoff| item.deleted_at < cutoff);
+            let too_big = config.trash_max_bytes > 0 && total > config.trash_max_bytes;
+            if Some(item.id) == keep || (!too_old && !too_big) {
+                continue;
+            }
+            match Self::purge(db, config, &item).await {
+                Ok(()) => purged += 1,
+                Err(e) => tracing::warn!("Failed to purge trash item {}: {}", item.id, e),
+            }
+        }
+        Ok(purged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::jail::Jail;
+    use crate::test_util::test_state;
+
+    fn at(path: impl AsRef<Path>) -> Resolved {
+        Jail::default().resolve_link(path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_trash_restore_and_purge() {
+        let state = test_state().await;
+        let (db, config) = (&state.db, &state.config);
+        let dir = std::env::temp_dir().join(format!("mana-trashed-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("site/css")).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        std::fs::write(dir.join("site/index.html"), "<h1>hi</h1>").unwrap();
+        std::fs::write(dir.join("site/css/main.css"), "body {}").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("site"), dir.join("link")).unwrap();
+
+        let site = at(dir.join("site"));
+        let site = TrashService::trash(db, config, &site, Some(1), None).await.unwrap();
+        assert!(!dir.join("site").exists());
+        assert_eq!((site.is_dir, site.size), (true, 18));
+        let trash = TrashService::dir(config).await.unwrap();
+        assert!(trash.join(&site.stored_name).join("css/main.css").exists());
+        assert!(TrashService::trash(db, config, &at(&trash), None, None).await.is_err());
+
+        // A symlink goes into the trash itself
+        #[cfg(unix)]
+        {
+            let link = at(dir.join("link"));
+            let link = TrashService::trash(db, config, &link, None, None).await.unwrap();
+            assert_eq!((link.is_dir, link.size), (false, 0));
+            TrashService::purge(db, config, &link).await.unwrap();
+        }
+
+        std::fs::create_dir(dir.join("site")).unwrap();
+        let target = at(dir.join("site"));
+        let restored = TrashService::restore(db, config, &site, &target, ConflictPolicy::Fail).await;
+        assert!(matches!(restored, Err(AppError::Conflict(_))));
+        let restored = TrashService::restore(db, config, &site, &target, ConflictPolicy::Rename);
+        assert_eq!(restored.await.unwrap(), Some(dir.join("site (1)")));
+        assert!(dir.join("site (1)/css/main.css").exists());
+        assert!(TrashService::list(db, config).await.unwrap().is_empty());
+
+        // Past the size limit the oldest items go, but never the newest
+        let big = vec![0u8; 600 * 1024];
+        for name in ["a", "b", "c"] {
+            std::fs::write(dir.join(name), &big).unwrap();
+            TrashService::trash(db, config, &at(dir.join(name)), None, None).await.unwrap();
+        }
+        let items = TrashService::list(db, config).await.unwrap();
+        let paths: Vec<_> = items.iter().map(|item| item.original_path.as_str()).collect();
+        assert_eq!(paths, [dir.join("c").to_str().unwrap()]);
+        assert_eq!(std::fs::read_dir(&trash).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(trash).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_trash_refuses_other_filesystems() {
+        use std::os::unix::fs::MetadataExt;
+
+        let state = test_state().await;
+        let (db, config) = (&state.db, &state.config);
+        let trash = TrashService::dir(config).await.unwrap();
+        let shm = Path::new("/dev/shm");
+        let elsewhere = std::fs::metadata(shm).is_ok_and(|shm| {
+            shm.dev() != std::fs::metadata(&trash).unwrap().dev()
+        });
+        if !elsewhere {
+            return;
+        }
+        let path = shm.join(format!("mana-trashed-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "data").unwrap();
+
+        let trashed = TrashService::trash(db, config, &at(&path), None, None).await;
+        assert!(matches!(trashed, Err(AppError::Validation(_))));
+        assert!(path.exists());
+        assert!(TrashService::list(db, config).await.unwrap().is_empty());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
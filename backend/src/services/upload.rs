@@ -0,0 +1,302 @@
+use axum::body::Body;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use crate::services::files::{self, ConflictPolicy};
+use crate::services::jail::Resolved;
+
+/// Uploads without a chunk for this long are discarded
+pub const UPLOAD_IDLE_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A file being uploaded in chunks
+pub struct Upload {
+    pub id: String,
+    pub user_id: i32,
+    /// Destination as requested. It is resolved again when the upload
+    /// completes, in case the jail or the filesystem changed meanwhile.
+    pub path: String,
+    pub size: u64,
+    pub conflict: ConflictPolicy,
+    pub created_at: DateTime<Utc>,
+    /// Next to the destination, so that completing is a rename within the
+    /// same filesystem
+    temp: PathBuf,
+    /// Bytes received so far, all of them already in the temporary file
+    offset: AtomicU64,
+    /// When the last chunk arrived. Held while a chunk is written, which
+    /// keeps concurrent chunks out.
+    touched: tokio::sync::Mutex<Instant>,
+}
+
+impl Upload {
+    pub fn offset(&self) -> u64 {
+        self.offset.load(Ordering::SeqCst)
+    }
+
+    fn expired(&self) -> bool {
+        // An upload that is receiving a chunk right now is in use
+        self.touched.try_lock().is_ok_and(|touched| touched.elapsed() > UPLOAD_IDLE_TIMEOUT)
+    }
+}
+
+/// Resumable uploads in progress.
+///
+/// The protocol follows tus: an upload is created with its final size, then
+/// receives chunks at the offset it reports, so a client whose connection
+/// dropped asks for the offset and carries on from there. Uploads only live
+/// in memory, so a restart voids them. Each one leaves a record of where its
+/// data is in the uploads directory, which the next start goes through to
+/// remove that data.
+#[derive(Clone)]
+pub struct Uploads {
+    uploads: Arc<Mutex<HashMap<String, Arc<Upload>>>>,
+    /// One file per upload in progress, named after it and holding the path
+    /// of its data
+    records: Arc<PathBuf>,
+}
+
+impl Uploads {
+    /// Start without uploads, removing the data of those a restart
+    /// interrupted
+    pub async fn load(config: &Config) -> AppResult<Self> {
+        let records = files::private_dir(Path::new(&config.uploads_dir))?;
+        let mut entries = fs::read_dir(&records).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let temp = PathBuf::from(fs::read_to_string(entry.path()).await.unwrap_or_default());
+            // Whatever the record says, only ever an upload's data goes
+            let is_upload = temp
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(".mana-upload-") && name.ends_with(".part"));
+            if is_upload {
+                match fs::remove_file(&temp).await {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                        tracing::warn!("Failed to remove upload data {}: {}", temp.display(), e)
+                    }
+                    _ => tracing::info!("Removed data of interrupted upload {}", temp.display()),
+                }
+            }
+            fs::remove_file(entry.path()).await?;
+        }
+
+        Ok(Self {
+            uploads: Arc::default(),
+            records: Arc::new(records),
+        })
+    }
+
+    /// Start an upload of `size` bytes to `target`, an already resolved path
+    /// whose directory has to exist
+    pub async fn create(
+        &self,
+        user_id: i32,
+        path: &str,
+        target: &Resolved,
+        size: u64,
+        conflict: ConflictPolicy,
+    ) -> AppResult<Arc<Upload>> {
+        self.remove_expired().await;
+
+        let dir = target
+            .parent()
+            .ok_or_else(|| AppError::Validation("Can't upload to /".to_string()))?;
+        if !fs::metadata(dir).await.is_ok_and(|metadata| metadata.is_dir()) {
+            return Err(AppError::NotFound(format!("Directory not found: {}", dir.display())));
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let temp = target.sibling(format!(".mana-upload-{}.part", id))?;
+        temp.create_new()?;
+        let temp = temp.into_path();
+        let recorded = fs::write(self.records.join(&id), temp.to_string_lossy().as_bytes()).await;
+        if let Err(e) = recorded {
+            fs::remove_file(&temp).await.ok();
+            return Err(e.into());
+        }
+
+        let upload = Arc::new(Upload {
+            id: id.clone(),
+            user_id,
+            path: path.to_string(),
+            size,
+            conflict,
+            created_at: Utc::now(),
+            temp,
+            offset: AtomicU64::new(0),
+            touched: tokio::sync::Mutex::new(Instant::now()),
+        });
+        self.uploads.lock().unwrap().insert(id, upload.clone());
+        Ok(upload)
+    }
+
+    /// An upload of the given user; other users' uploads don't exist for them
+    pub fn get(&self, id: &str, user_id: i32) -> AppResult<Arc<Upload>> {
+        self.uploads
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|upload| upload.user_id == user_id)
+            .cloned()
+            .ok_or_else(|| AppError::NotFound(format!("Upload not found: {}", id)))
+    }
+
+    /// Append a chunk, which has to start at the upload's current offset.
+    /// Whatever arrives before the connection drops is kept, so the client
+    /// can resume from the returned offset.
+    pub async fn write_chunk(&self, upload: &Upload, offset: u64, body: Body) -> AppResult<u64> {
+        let mut touched = upload.touched.try_lock().map_err(|_| {
+            AppError::Conflict("Another chunk of this upload is being written".to_string())
+        })?;
+        let mut received = upload.offset();
+        if offset != received {
+            return Err(AppError::Conflict(format!(
+                "Chunk starts at {}, but the upload is at {}",
+                offset, received
+            )));
+        }
+        *touched = Instant::now();
+
+        let mut file = fs::OpenOptions::new().write(true).open(&upload.temp).await?;
+        file.seek(SeekFrom::Start(received)).await?;
+
+        let mut stream = body.into_data_stream();
+        let mut result = Ok(());
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    result = Err(AppError::Validation(format!("Chunk interrupted: {}", e)));
+                    break;
+                }
+            };
+            if received + chunk.len() as u64 > upload.size {
+                result = Err(AppError::Validation(format!(
+                    "Chunk goes past the declared size of {} bytes",
+                    upload.size
+                )));
+                break;
+            }
+            if let Err(e) = file.write_all(&chunk).await {
+                result = Err(e.into());
+                break;
+            }
+            received += chunk.len() as u64;
+        }
+
+        file.flush().await?;
+        // Drop a partially written piece, so the file and the offset agree
+        file.set_len(received).await?;
+        upload.offset.store(received, Ordering::SeqCst);
+        *touched = Instant::now();
+        result.map(|()| received)
+    }
+
+    /// Move a fully received upload to `target`, after checking its SHA-256
+    /// if the client sent one. Returns where the file ended up, `None` if it
+    /// was skipped because of a conflict. The data is taken from the
+    /// directory `target` resolves to now; if that isn't where the upload
+    /// started, there is nothing to complete.
+    pub async fn complete(
+        &self,
+        upload: &Upload,
+        target: &Resolved,
+        sha256: Option<&str>,
+    ) -> AppResult<Option<PathBuf>> {
+        let writing = upload.touched.try_lock().map_err(|_| {
+            AppError::Conflict("A chunk of this upload is still being written".to_string())
+        })?;
+        if upload.offset() != upload.size {
+            return Err(AppError::Validation(format!(
+                "Upload incomplete, {} of {} bytes received",
+                upload.offset(),
+                upload.size
+            )));
+        }
+
+        if let Some(expected) = sha256 {
+            let actual = file_sha256(&upload.temp).await?;
+            if !actual.eq_ignore_ascii_case(expected.trim()) {
+                drop(writing);
+                self.cancel(upload).await;
+                return Err(AppError::Validation(format!(
+                    "Checksum mismatch, the received file has SHA-256 {}",
+                    actual
+                )));
+            }
+        }
+
+        let temp = target.sibling(upload.temp.file_name().unwrap_or_default())?;
+        if *temp != *upload.temp {
+            return Err(AppError::Conflict(format!(
+                "{} moved since the upload started",
+                upload.path
+            )));
+        }
+        let (target, conflict) = (target.try_clone()?, upload.conflict);
+        let placed = tokio::task::spawn_blocking(move || files::place(&temp, &target, conflict))
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?
+            .map_err(files::conflict_error)?;
+        self.forget(upload).await;
+        Ok(placed)
+    }
+
+    /// Discard an upload and the data received so far
+    pub async fn cancel(&self, upload: &Upload) {
+        self.forget(upload).await;
+        if let Err(e) = fs::remove_file(&upload.temp).await {
+            tracing::warn!("Failed to remove upload data {}: {}", upload.temp.display(), e);
+        }
+    }
+
+    /// Drop an upload and its record, once its data is placed or about to go
+    async fn forget(&self, upload: &Upload) {
+        self.uploads.lock().unwrap().remove(&upload.id);
+        match fs::remove_file(self.records.join(&upload.id)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                tracing::warn!("Failed to remove the record of upload {}: {}", upload.id, e)
+            }
+            _ => {}
+        }
+    }
+
+    async fn remove_expired(&self) {
+        let mut expired = Vec::new();
+        self.uploads.lock().unwrap().retain(|_, upload| {
+            let keep = !upload.expired();
+            if !keep {
+                expired.push(upload.clone());
+            }
+            keep
+        });
+        for upload in expired {
+            tracing::info!("Discarding abandoned upload to {}", upload.path);
+            self.cancel(&upload).await;
+        }
+    }
+}
+
+async fn file_sha256(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(data_encoding::HEXLOWER.encode(&hasher.finalize()))
+}
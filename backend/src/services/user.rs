@@ -1,18 +1,24 @@
-use sea_orm::{entity::prelude::*, ActiveValue::Set};
-use crate::db::entities::user;
+use sea_orm::{entity::prelude::*, ActiveValue::Set, QueryOrder, QuerySelect};
+use crate::db::entities::{password_history, user};
 use crate::error::{AppError, AppResult};
-use crate::services::password;
+use crate::services::password::{self, PasswordPolicy};
+use crate::services::rbac::{RbacService, ROLE_ADMIN};
 
 /// User service for managing user accounts
 pub struct UserService;
 
 impl UserService {
-    /// Create a new user with hashed password
+    /// Create a new user with hashed password and the given role.
+    /// The password policy is up to the caller.
     pub async fn create_user(
         db: &DatabaseConnection,
         username: &str,
         password: &str,
+        role: &str,
+        must_change_password: bool,
     ) -> AppResult<user::Model> {
+        RbacService::ensure_role_exists(db, role).await?;
+
         // Check if username already exists
         let existing = user::Entity::find()
             .filter(user::Column::Username.eq(username))
@@ -33,6 +39,15 @@ impl UserService {
             id: Default::default(),
             username: Set(username.to_string()),
             password_hash: Set(password_hash),
+            role: Set(role.to_string()),
+            disabled: Set(false),
+            last_login_at: Set(None),
+            display_name: Set(None),
+            totp_secret: Set(None),
+            totp_enabled: Set(false),
+            totp_last_step: Set(None),
+            must_change_password: Set(must_change_password),
+            oidc_subject: Set(None),
             created_at: Set(now),
             updated_at: Set(now),
         };
@@ -58,6 +73,58 @@ impl UserService {
         
         Ok(user)
     }
+
+    /// Find a user by id
+    pub async fn find_by_id(db: &DatabaseConnection, user_id: i32) -> AppResult<Option<user::Model>> {
+        let user = user::Entity::find_by_id(user_id)
+            .one(db)
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?;
+
+        Ok(user)
+    }
+
+    /// Find the user linked to an account at the OIDC provider
+    pub async fn find_by_oidc_subject(
+        db: &DatabaseConnection,
+        subject: &str,
+    ) -> AppResult<Option<user::Model>> {
+        let user = user::Entity::find()
+            .filter(user::Column::OidcSubject.eq(subject))
+            .one(db)
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?;
+
+        Ok(user)
+    }
+
+    /// Link a user to an account at the OIDC provider
+    pub async fn link_oidc_subject(
+        db: &DatabaseConnection,
+        user_id: i32,
+        subject: &str,
+    ) -> AppResult<user::Model> {
+        user::Entity::update_many()
+            .col_expr(user::Column::OidcSubject, Expr::value(subject))
+            .col_expr(user::Column::UpdatedAt, Expr::value(chrono::Utc::now()))
+            .filter(user::Column::Id.eq(user_id))
+            .exec(db)
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?;
+
+        Self::get(db, user_id).await
+    }
+
+    /// List all users ordered by id
+    pub async fn list_users(db: &DatabaseConnection) -> AppResult<Vec<user::Model>> {
+        let users = user::Entity::find()
+            .order_by_asc(user::Column::Id)
+            .all(db)
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?;
+
+        Ok(users)
+    }
     
     /// Verify user credentials
     pub async fn verify_credentials(
@@ -79,23 +146,144 @@ impl UserService {
         }
     }
     
-    /// Update user password
+    /// Replace a user's password after checking it against the policy and
+    /// the user's recent passwords. `must_change_password` sets whether the
+    /// user has to pick yet another one on next use, e.g. after an admin reset.
     pub async fn update_password(
         db: &DatabaseConnection,
+        policy: &PasswordPolicy,
         user_id: i32,
         new_password: &str,
+        must_change_password: bool,
     ) -> AppResult<()> {
+        let user = Self::get(db, user_id).await?;
+        policy.validate(new_password, &user.username)?;
+
+        // The current password counts as the most recent of the `history` ones
+        if policy.history > 0 {
+            let older = password_history::Entity::find()
+                .filter(password_history::Column::UserId.eq(user_id))
+                .order_by_desc(password_history::Column::Id)
+                .limit(policy.history - 1)
+                .all(db)
+                .await
+                .map_err(|e| AppError::Internal(e.into()))?;
+            let recent = std::iter::once(&user.password_hash)
+                .chain(older.iter().map(|entry| &entry.password_hash));
+            for hash in recent {
+                if password::verify_password(new_password, hash)? {
+                    return Err(AppError::Validation(
+                        "Password was used recently, choose a different one".to_string(),
+                    ));
+                }
+            }
+        }
+
         let password_hash = password::hash_password(new_password)?;
         let now = chrono::Utc::now();
-        
+
+        password_history::ActiveModel {
+            id: Default::default(),
+            user_id: Set(user_id),
+            password_hash: Set(user.password_hash),
+            created_at: Set(now),
+        }
+        .insert(db)
+        .await
+        .map_err(|e| AppError::Internal(e.into()))?;
+        Self::prune_password_history(db, user_id, policy.history.saturating_sub(1)).await?;
+
         user::Entity::update_many()
             .col_expr(user::Column::PasswordHash, Expr::value(password_hash))
+            .col_expr(user::Column::MustChangePassword, Expr::value(must_change_password))
             .col_expr(user::Column::UpdatedAt, Expr::value(now))
             .filter(user::Column::Id.eq(user_id))
             .exec(db)
             .await
             .map_err(|e| AppError::Internal(e.into()))?;
-        
+
+        Ok(())
+    }
+
+    /// Record a successful login
+    pub async fn record_login(db: &DatabaseConnection, user_id: i32) -> AppResult<()> {
+        user::Entity::update_many()
+            .col_expr(user::Column::LastLoginAt, Expr::value(chrono::Utc::now()))
+            .filter(user::Column::Id.eq(user_id))
+            .exec(db)
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?;
+
+        Ok(())
+    }
+
+    /// Update display name and/or role. Refuses to demote the last active admin.
+    pub async fn update_profile(
+        db: &DatabaseConnection,
+        user_id: i32,
+        display_name: Option<Option<String>>,
+        role: Option<&str>,
+    ) -> AppResult<user::Model> {
+        let user = Self::get(db, user_id).await?;
+
+        if let Some(role) = role {
+            RbacService::ensure_role_exists(db, role).await?;
+            if role != ROLE_ADMIN {
+                Self::ensure_not_last_admin(db, &user).await?;
+            }
+        }
+
+        let mut active: user::ActiveModel = user.into();
+        if let Some(display_name) = display_name {
+            active.display_name = Set(display_name);
+        }
+        if let Some(role) = role {
+            active.role = Set(role.to_string());
+        }
+        active.updated_at = Set(chrono::Utc::now());
+
+        let user = active
+            .update(db)
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?;
+
+        Ok(user)
+    }
+
+    /// Disable or re-enable an account. Refuses to disable the last active admin.
+    pub async fn set_disabled(
+        db: &DatabaseConnection,
+        user_id: i32,
+        disabled: bool,
+    ) -> AppResult<user::Model> {
+        let user = Self::get(db, user_id).await?;
+
+        if disabled {
+            Self::ensure_not_last_admin(db, &user).await?;
+        }
+
+        let mut active: user::ActiveModel = user.into();
+        active.disabled = Set(disabled);
+        active.updated_at = Set(chrono::Utc::now());
+
+        let user = active
+            .update(db)
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?;
+
+        Ok(user)
+    }
+
+    /// Delete an account. Refuses to delete the last active admin.
+    pub async fn delete_user(db: &DatabaseConnection, user_id: i32) -> AppResult<()> {
+        let user = Self::get(db, user_id).await?;
+        Self::ensure_not_last_admin(db, &user).await?;
+
+        user::Entity::delete_by_id(user_id)
+            .exec(db)
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?;
+
         Ok(())
     }
     
@@ -115,9 +303,132 @@ impl UserService {
         
         if count == 0 {
             tracing::info!("Creating default admin user");
-            Self::create_user(db, "admin", "admin").await?;
+            Self::create_user(db, "admin", "admin", ROLE_ADMIN, true).await?;
+            return Ok(());
+        }
+
+        // Installs from before the password policy may still use the default
+        // credentials; make sure they get changed
+        if let Some(admin) = Self::find_by_username(db, "admin").await? {
+            let default_password = password::verify_password("admin", &admin.password_hash)?;
+            if default_password && !admin.must_change_password {
+                tracing::warn!("Default admin password still in use, forcing a change");
+                user::Entity::update_many()
+                    .col_expr(user::Column::MustChangePassword, Expr::value(true))
+                    .filter(user::Column::Id.eq(admin.id))
+                    .exec(db)
+                    .await
+                    .map_err(|e| AppError::Internal(e.into()))?;
+            }
         }
         
         Ok(())
     }
+
+    /// Keep only the `keep` newest password history entries of a user
+    async fn prune_password_history(
+        db: &DatabaseConnection,
+        user_id: i32,
+        keep: u64,
+    ) -> AppResult<()> {
+        let stale: Vec<i32> = password_history::Entity::find()
+            .filter(password_history::Column::UserId.eq(user_id))
+            .order_by_desc(password_history::Column::Id)
+            .all(db)
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?
+            .into_iter()
+            .skip(keep as usize)
+            .map(|entry| entry.id)
+            .collect();
+
+        if !stale.is_empty() {
+            password_history::Entity::delete_many()
+                .filter(password_history::Column::Id.is_in(stale))
+                .exec(db)
+                .await
+                .map_err(|e| AppError::Internal(e.into()))?;
+        }
+
+        Ok(())
+    }
+
+    async fn get(db: &DatabaseConnection, user_id: i32) -> AppResult<user::Model> {
+        Self::find_by_id(db, user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("User {} not found", user_id)))
+    }
+
+    /// Fail if removing admin rights from `user` would leave no enabled admin
+    async fn ensure_not_last_admin(db: &DatabaseConnection, user: &user::Model) -> AppResult<()> {
+        if user.role != ROLE_ADMIN || user.disabled {
+            return Ok(());
+        }
+
+        let other_admins = user::Entity::find()
+            .filter(user::Column::Role.eq(ROLE_ADMIN))
+            .filter(user::Column::Disabled.eq(false))
+            .filter(user::Column::Id.ne(user.id))
+            .count(db)
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?;
+
+        if other_admins == 0 {
+            return Err(AppError::Validation(
+                "Cannot remove the last active admin".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::rbac::ROLE_VIEWER;
+
+    #[tokio::test]
+    async fn test_last_admin_cannot_be_removed() {
+        let state = crate::test_util::test_state().await;
+        let db = &state.db;
+
+        assert!(UserService::delete_user(db, 1).await.is_err());
+        assert!(UserService::set_disabled(db, 1, true).await.is_err());
+        assert!(UserService::update_profile(db, 1, None, Some(ROLE_VIEWER)).await.is_err());
+
+        let other = UserService::create_user(db, "root2", "pw", ROLE_ADMIN, false).await.unwrap();
+        UserService::set_disabled(db, other.id, true).await.unwrap();
+        // A disabled admin doesn't count
+        assert!(UserService::delete_user(db, 1).await.is_err());
+
+        UserService::set_disabled(db, other.id, false).await.unwrap();
+        UserService::delete_user(db, 1).await.unwrap();
+        assert!(UserService::delete_user(db, other.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_password_history_prevents_reuse() {
+        let state = crate::test_util::test_state().await;
+        let db = &state.db;
+        let policy = PasswordPolicy {
+            history: 2,
+            ..PasswordPolicy::from_config(&state.config)
+        };
+
+        // The default admin password is flagged on the next start
+        UserService::init_default_admin(db).await.unwrap();
+        assert!(UserService::get(db, 1).await.unwrap().must_change_password);
+
+        UserService::update_password(db, &policy, 1, "first-pa55word", false).await.unwrap();
+        assert!(!UserService::get(db, 1).await.unwrap().must_change_password);
+        assert!(UserService::update_password(db, &policy, 1, "first-pa55word", false).await.is_err());
+
+        UserService::update_password(db, &policy, 1, "second-pa55word", false).await.unwrap();
+        assert!(UserService::update_password(db, &policy, 1, "first-pa55word", false).await.is_err());
+
+        // Only the last two passwords are remembered
+        UserService::update_password(db, &policy, 1, "third-pa55word", false).await.unwrap();
+        UserService::update_password(db, &policy, 1, "first-pa55word", false).await.unwrap();
+    }
 }
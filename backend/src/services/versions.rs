@@ -0,0 +1,300 @@
+use chrono::Utc;
+use sea_orm::{entity::prelude::*, ActiveValue::Set, QueryOrder};
+use similar::TextDiff;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::fs;
+
+use crate::config::Config;
+use crate::db::entities::file_version;
+use crate::error::{AppError, AppResult};
+use crate::middleware::auth::Claims;
+use crate::services::jail::Resolved;
+use crate::services::{files, text};
+
+/// Larger files are written without keeping a version of them
+pub const MAX_VERSIONED_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Earlier contents of files saved from the editor.
+///
+/// Before a save replaces a file, its content is copied into the versions
+/// directory under a random name, and a row remembers whose content it was,
+/// who replaced it and when. Only the newest versions of each file are kept.
+///
+/// Saves of the same file take turns, so that a second save sent with the
+/// same ETag sees the file the first one wrote and is refused.
+#[derive(Clone, Default)]
+pub struct VersionService {
+    locks: Arc<Mutex<HashMap<PathBuf, Weak<tokio::sync::Mutex<()>>>>>,
+}
+
+/// Who saved a file, remembered with the version the save replaced
+#[derive(Debug, Clone, Default)]
+pub struct Author {
+    pub user_id: Option<i32>,
+    pub username: Option<String>,
+}
+
+impl From<&Claims> for Author {
+    fn from(claims: &Claims) -> Self {
+        Self {
+            user_id: claims.user_id().ok(),
+            username: Some(claims.username.clone()),
+        }
+    }
+}
+
+impl VersionService {
+    /// The versions directory, created on first use
+    pub async fn dir(config: &Config) -> AppResult<PathBuf> {
+        Ok(files::private_dir(Path::new(&config.versions_dir))?)
+    }
+
+    /// Write `content` to `path` atomically, keeping what the file held
+    /// before as a version. `expected_etag` is the ETag of the file as the
+    /// client read it; a file that changed since is refused, so that two
+    /// people editing it don't silently undo each other. Without one only a
+    /// new file is written. Returns the new ETag.
+    pub async fn write(
+        &self,
+        db: &DatabaseConnection,
+        config: &Config,
+        path: &Resolved,
+        content: Vec<u8>,
+        expected_etag: Option<&str>,
+        author: Author,
+    ) -> AppResult<String> {
+        let lock = self.lock(path);
+        let _turn = lock.lock().await;
+
+        let current = path.symlink_metadata().ok();
+        if current.as_ref().is_some_and(|metadata| metadata.is_dir()) {
+            return Err(AppError::Validation(format!("{} is a directory", path.display())));
+        }
+        match expected_etag {
+            Some(expected) => {
+                let current = current.as_ref().map(files::etag);
+                if current.is_none_or(|current| !etag_matches(expected, &current)) {
+                    return Err(AppError::PreconditionFailed(format!(
+                        "{} was changed since it was read",
+                        path.display()
+                    )));
+                }
+            }
+            None if current.is_some() => {
+                return Err(AppError::PreconditionRequired(format!(
+                    "{} exists; send the ETag it had when it was read",
+                    path.display()
+                )));
+            }
+            None => {}
+        }
+
+        if let Some(metadata) = &current {
+            Self::save(db, config, path, metadata.len(), author).await?;
+        }
+        let target = path.try_clone()?;
+        tokio::task::spawn_blocking(move || files::write_atomic(&target, &content))
+            .await
+            .map_err(|e| AppError::Internal(e.into()))??;
+        Ok(files::etag(&path.symlink_metadata()?))
+    }
+
+    /// The lock saves of `path` take turns on. Locks no save holds any more
+    /// are forgotten.
+    fn lock(&self, path: &Path) -> Arc<tokio::sync::Mutex<()>> {
+        let mut locks = self.locks.lock().unwrap();
+        if let Some(lock) = locks.get(path).and_then(Weak::upgrade) {
+            return lock;
+        }
+        locks.retain(|_, lock| lock.strong_count() > 0);
+        let lock = Arc::new(tokio::sync::Mutex::new(()));
+        locks.insert(path.to_path_buf(), Arc::downgrade(&lock));
+        lock
+    }
+
+    /// Keep the current content of `path` as its newest version
+    async fn save(
+        db: &DatabaseConnection,
+        config: &Config,
+        path: &Resolved,
+        size: u64,
+        author: Author,
+    ) -> AppResult<()> {
+        if config.versions_keep == 0 || size > MAX_VERSIONED_BYTES {
+            return Ok(());
+        }
+
+        let stored_name = uuid::Uuid::new_v4().to_string();
+        let stored = Self::dir(config).await?.join(&stored_name);
+        let mut current = fs::File::from_std(path.open()?);
+        let mut copy = fs::File::create_new(&stored).await?;
+        if let Err(e) = tokio::io::copy(&mut current, &mut copy).await {
+            fs::remove_file(&stored).await.ok();
+            return Err(e.into());
+        }
+        let inserted = file_version::ActiveModel {
+            path: Set(path.to_string_lossy().into_owned()),
+            stored_name: Set(stored_name),
+            size: Set(size as i64),
+            created_by: Set(author.user_id),
+            created_by_username: Set(author.username),
+            created_at: Set(Utc::now()),
+            ..Default::default()
+        }
+        .insert(db)
+        .await;
+        if let Err(e) = inserted {
+            fs::remove_file(&stored).await.ok();
+            return Err(e.into());
+        }
+
+        Self::prune(db, config, path).await
+    }
+
+    /// Drop the oldest versions of `path` beyond the number kept
+    async fn prune(db: &DatabaseConnection, config: &Config, path: &Path) -> AppResult<()> {
+        let dir = Self::dir(config).await?;
+        let versions = Self::list(db, path).await?;
+        for version in versions.into_iter().skip(config.versions_keep as usize) {
+            match fs::remove_file(dir.join(&version.stored_name)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+            file_version::Entity::delete_by_id(version.id).exec(db).await?;
+        }
+        Ok(())
+    }
+
+    /// The versions of a file, newest first
+    pub async fn list(db: &DatabaseConnection, path: &Path) -> AppResult<Vec<file_version::Model>> {
+        Ok(file_version::Entity::find()
+            .filter(file_version::Column::Path.eq(path.to_string_lossy()))
+            .order_by_desc(file_version::Column::CreatedAt)
+            .order_by_desc(file_version::Column::Id)
+            .all(db)
+            .await?)
+    }
+
+    pub async fn get(db: &DatabaseConnection, id: i32) -> AppResult<file_version::Model> {
+        file_version::Entity::find_by_id(id)
+            .one(db)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("File version not found: {}", id)))
+    }
+
+    pub async fn content(config: &Config, version: &file_version::Model) -> AppResult<Vec<u8>> {
+        let path = Self::dir(config).await?.join(&version.stored_name);
+        Ok(fs::read(path).await?)
+    }
+
+    /// Put a version's content back into its file, which the caller has
+    /// resolved, keeping the content it replaces as a version in turn.
+    /// Returns the new ETag.
+    pub async fn restore(
+        &self,
+        db: &DatabaseConnection,
+        config: &Config,
+        version: &file_version::Model,
+        path: &Resolved,
+        expected_etag: Option<&str>,
+        author: Author,
+    ) -> AppResult<String> {
+        let content = Self::content(config, version).await?;
+        self.write(db, config, path, content, expected_etag, author).await
+    }
+}
+
+/// Compare ETags the way `If-Match` does, but weakly, since the editor
+/// doesn't care how the tag was sent
+pub fn etag_matches(expected: &str, current: &str) -> bool {
+    let bare = |tag: &str| tag.trim().trim_start_matches("W/").trim_matches('"').to_string();
+    expected.split(',').any(|tag| tag.trim() == "*" || bare(tag) == bare(current))
+}
+
+/// A unified diff from `old` to `new`, which both have to be text, in
+/// whatever encoding
+pub fn unified_diff(old: &[u8], new: &[u8], old_name: &str, new_name: &str) -> AppResult<String> {
+    let text = |content| {
+        text::decode(content)
+            .ok_or_else(|| AppError::Validation("Only text files can be compared".to_string()))
+    };
+    let (old, new) = (text(old)?, text(new)?);
+    let diff = TextDiff::from_lines(&old, &new);
+    Ok(diff.unified_diff().context_radius(3).header(old_name, new_name).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_state;
+
+    #[tokio::test]
+    async fn test_write_keeps_versions() {
+        let state = test_state().await;
+        let (db, config) = (&state.db, &state.config);
+        let dir = std::env::temp_dir().join(format!("mana-edited-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = std::fs::canonicalize(&dir).unwrap().join("nginx.conf");
+
+        let versions = VersionService::default();
+        let write = |content: &str, etag: Option<String>| {
+            let content = content.as_bytes().to_vec();
+            let (path, versions) = (Resolved::new(&path).unwrap(), versions.clone());
+            let author = Author { user_id: Some(1), username: None };
+            async move { versions.write(db, config, &path, content, etag.as_deref(), author).await }
+        };
+
+        // A new file has nothing to keep
+        let etag = write("worker_processes 1;\n", None).await.unwrap();
+        assert!(VersionService::list(db, &path).await.unwrap().is_empty());
+        let blind = write("worker_processes 4;\n", None).await;
+        assert!(matches!(blind, Err(AppError::PreconditionRequired(_))));
+
+        let stale = etag.clone();
+        let etag = write("worker_processes 2;\n", Some(etag)).await.unwrap();
+        let lost = write("lost\n", Some(stale)).await;
+        assert!(matches!(lost, Err(AppError::PreconditionFailed(_))));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "worker_processes 2;\n");
+
+        // The file is replaced, but keeps its mode
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        }
+        let etag = write("worker_processes 2;\n", Some(etag)).await.unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o7777, 0o640);
+        }
+
+        // Only the newest three are kept
+        let mut etag = etag;
+        for n in 3..=6 {
+            etag = write(&format!("worker_processes {};\n", n), Some(etag)).await.unwrap();
+        }
+        let kept = VersionService::list(db, &path).await.unwrap();
+        assert_eq!(kept.len(), 3);
+        let content = VersionService::content(config, &kept[0]).await.unwrap();
+        assert_eq!(content, b"worker_processes 5;\n");
+        let stored = std::fs::read_dir(VersionService::dir(config).await.unwrap()).unwrap();
+        assert_eq!(stored.count(), 3);
+
+        let diff = unified_diff(&content, &std::fs::read(&path).unwrap(), "a", "b").unwrap();
+        assert!(diff.contains("-worker_processes 5;\n+worker_processes 6;\n"), "{}", diff);
+
+        let author = Author::default();
+        let target = Resolved::new(&path).unwrap();
+        versions.restore(db, config, &kept[2], &target, Some(&etag), author).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "worker_processes 3;\n");
+        let kept = VersionService::list(db, &path).await.unwrap();
+        let content = VersionService::content(config, &kept[0]).await.unwrap();
+        assert_eq!(content, b"worker_processes 6;\n");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
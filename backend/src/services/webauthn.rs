@@ -0,0 +1,840 @@
+use ciborium::Value;
+use data_encoding::BASE64URL_NOPAD;
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use rand::RngCore;
+use sea_orm::{entity::prelude::*, ActiveValue::Set, QueryOrder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::WebauthnConfig;
+use crate::db::entities::{user, webauthn_challenge, webauthn_credential};
+use crate::error::{AppError, AppResult};
+
+/// How long a ceremony may take, also passed to the browser as its timeout
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+const PURPOSE_REGISTER: &str = "register";
+const PURPOSE_LOGIN: &str = "login";
+
+/// COSE algorithms we accept: ES256 for security keys and most passkeys,
+/// RS256 for Windows Hello
+const COSE_ES256: i32 = -7;
+const COSE_RS256: i32 = -257;
+const COSE_KTY_EC2: i128 = 2;
+const COSE_KTY_RSA: i128 = 3;
+const COSE_CRV_P256: i128 = 1;
+
+/// Authenticator data flags, see the WebAuthn spec section 6.1
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_DATA: u8 = 0x40;
+
+/// Options for `navigator.credentials.create()`, binary values base64url encoded
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    pub challenge: String,
+    pub rp: RelyingParty,
+    pub user: UserEntity,
+    pub pub_key_cred_params: Vec<CredentialParameter>,
+    pub timeout: u64,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: &'static str,
+}
+
+/// Options for `navigator.credentials.get()`, binary values base64url encoded
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub timeout: u64,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CredentialParameter {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub alg: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub id: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: &'static str,
+    pub user_verification: &'static str,
+}
+
+/// What `navigator.credentials.create()` returned, binary values base64url encoded
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// What `navigator.credentials.get()` returned, binary values base64url encoded
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssertionCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(default)]
+    pub user_handle: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+/// The parts of the authenticator data we check
+struct AuthenticatorData {
+    rp_id_hash: Vec<u8>,
+    flags: u8,
+    sign_count: u32,
+    /// Credential id and COSE key, only present on registration
+    attested: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+enum PublicKey {
+    Es256(VerifyingKey),
+    Rs256 { n: Vec<u8>, e: Vec<u8> },
+}
+
+/// Passkeys and security keys (WebAuthn), usable as a second factor after the
+/// password or, with user verification, as a passwordless login.
+///
+/// Attestation is not requested, so registration trusts whatever
+/// authenticator the user picked; only the ceremony itself is verified.
+pub struct WebauthnService;
+
+impl WebauthnService {
+    /// Start registering a new authenticator for `user`
+    pub async fn start_registration(
+        db: &DatabaseConnection,
+        config: &WebauthnConfig,
+        user: &user::Model,
+    ) -> AppResult<CreationOptions> {
+        let challenge = new_challenge(db, PURPOSE_REGISTER, Some(user.id)).await?;
+        let existing = Self::list(db, user.id).await?;
+
+        Ok(CreationOptions {
+            challenge,
+            rp: RelyingParty {
+                id: config.rp_id.clone(),
+                name: config.rp_name.clone(),
+            },
+            user: UserEntity {
+                id: user_handle(user.id),
+                name: user.username.clone(),
+                display_name: user.display_name.clone().unwrap_or_else(|| user.username.clone()),
+            },
+            pub_key_cred_params: [COSE_ES256, COSE_RS256]
+                .into_iter()
+                .map(|alg| CredentialParameter {
+                    kind: "public-key",
+                    alg,
+                })
+                .collect(),
+            timeout: timeout_ms(),
+            exclude_credentials: existing.iter().map(descriptor).collect(),
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "preferred",
+                user_verification: "preferred",
+            },
+            attestation: "none",
+        })
+    }
+
+    /// Verify the browser's response to [`Self::start_registration`] and store
+    /// the credential
+    pub async fn finish_registration(
+        db: &DatabaseConnection,
+        config: &WebauthnConfig,
+        user_id: i32,
+        name: &str,
+        credential: &RegistrationCredential,
+    ) -> AppResult<webauthn_credential::Model> {
+        let name = name.trim();
+        if name.is_empty() || name.len() > 64 {
+            return Err(AppError::Validation("Invalid authenticator name length".to_string()));
+        }
+
+        let client_data_json = decode(&credential.response.client_data_json)?;
+        let client_data = verify_client_data(config, &client_data_json, "webauthn.create")?;
+        let challenge = take_challenge(db, &client_data.challenge, PURPOSE_REGISTER).await?;
+        if challenge.user_id != Some(user_id) {
+            return Err(invalid_registration());
+        }
+
+        let attestation: Value =
+            ciborium::from_reader(decode(&credential.response.attestation_object)?.as_slice())
+                .map_err(|_| invalid_registration())?;
+        let auth_data = map_get(&attestation, |key| key.as_text() == Some("authData"))
+            .and_then(Value::as_bytes)
+            .ok_or_else(invalid_registration)?;
+        let auth_data = parse_authenticator_data(auth_data).ok_or_else(invalid_registration)?;
+        verify_flags(config, &auth_data, false).map_err(|_| invalid_registration())?;
+
+        let (credential_id, cose_key) = auth_data.attested.ok_or_else(invalid_registration)?;
+        if decode(&credential.id)? != credential_id {
+            return Err(invalid_registration());
+        }
+        let algorithm = cose_algorithm(&cose_key)?;
+        parse_public_key(&cose_key)?;
+
+        let credential_id = BASE64URL_NOPAD.encode(&credential_id);
+        let taken = webauthn_credential::Entity::find()
+            .filter(webauthn_credential::Column::CredentialId.eq(&credential_id))
+            .one(db)
+            .await?
+            .is_some();
+        if taken {
+            return Err(AppError::Validation("Authenticator is already registered".to_string()));
+        }
+
+        let model = webauthn_credential::ActiveModel {
+            id: Default::default(),
+            user_id: Set(user_id),
+            name: Set(name.to_string()),
+            credential_id: Set(credential_id),
+            public_key: Set(BASE64URL_NOPAD.encode(&cose_key)),
+            algorithm: Set(algorithm),
+            sign_count: Set(auth_data.sign_count as i64),
+            created_at: Set(chrono::Utc::now()),
+            last_used_at: Set(None),
+        }
+        .insert(db)
+        .await?;
+
+        Ok(model)
+    }
+
+    /// Start a login. With a user this is the second factor after the
+    /// password; without one the browser offers the discoverable credentials
+    /// stored on the authenticator and user verification is required.
+    pub async fn start_login(
+        db: &DatabaseConnection,
+        config: &WebauthnConfig,
+        user_id: Option<i32>,
+    ) -> AppResult<RequestOptions> {
+        let allow_credentials = match user_id {
+            Some(user_id) => {
+                let credentials = Self::list(db, user_id).await?;
+                if credentials.is_empty() {
+                    return Err(AppError::Validation(
+                        "No security keys are registered".to_string(),
+                    ));
+                }
+                credentials.iter().map(descriptor).collect()
+            }
+            None => Vec::new(),
+        };
+
+        Ok(RequestOptions {
+            challenge: new_challenge(db, PURPOSE_LOGIN, user_id).await?,
+            rp_id: config.rp_id.clone(),
+            timeout: timeout_ms(),
+            allow_credentials,
+            user_verification: if user_id.is_some() { "discouraged" } else { "required" },
+        })
+    }
+
+    /// Verify the browser's response to [`Self::start_login`], returning the
+    /// credential that signed it. `user_id` has to match the one the login
+    /// was started for.
+    pub async fn finish_login(
+        db: &DatabaseConnection,
+        config: &WebauthnConfig,
+        user_id: Option<i32>,
+        assertion: &AssertionCredential,
+    ) -> AppResult<webauthn_credential::Model> {
+        let client_data_json = decode(&assertion.response.client_data_json)?;
+        let client_data = verify_client_data(config, &client_data_json, "webauthn.get")?;
+        let challenge = take_challenge(db, &client_data.challenge, PURPOSE_LOGIN).await?;
+        if challenge.user_id != user_id {
+            return Err(invalid_assertion());
+        }
+
+        let credential_id = BASE64URL_NOPAD.encode(&decode(&assertion.id)?);
+        let credential = webauthn_credential::Entity::find()
+            .filter(webauthn_credential::Column::CredentialId.eq(credential_id))
+            .one(db)
+            .await?
+            .ok_or_else(invalid_assertion)?;
+        if user_id.is_some_and(|user_id| user_id != credential.user_id) {
+            return Err(invalid_assertion());
+        }
+        let handle = assertion.response.user_handle.as_deref().unwrap_or_default();
+        if !handle.is_empty() && handle != user_handle(credential.user_id) {
+            return Err(invalid_assertion());
+        }
+
+        let raw_auth_data = decode(&assertion.response.authenticator_data)?;
+        let auth_data = parse_authenticator_data(&raw_auth_data).ok_or_else(invalid_assertion)?;
+        verify_flags(config, &auth_data, user_id.is_none())?;
+
+        let mut signed = raw_auth_data;
+        signed.extend_from_slice(&Sha256::digest(&client_data_json));
+        let public_key = parse_public_key(&decode(&credential.public_key)?)?;
+        if !verify_signature(&public_key, &signed, &decode(&assertion.response.signature)?) {
+            return Err(invalid_assertion());
+        }
+
+        // Authenticators that count signatures must count up, otherwise the
+        // key may have been cloned. Passkeys synced between devices send 0.
+        let sign_count = auth_data.sign_count as i64;
+        if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+            tracing::warn!(
+                "Signature counter of WebAuthn credential {} went backwards",
+                credential.id
+            );
+            return Err(invalid_assertion());
+        }
+
+        let mut active: webauthn_credential::ActiveModel = credential.into();
+        active.sign_count = Set(sign_count);
+        active.last_used_at = Set(Some(chrono::Utc::now()));
+        Ok(active.update(db).await?)
+    }
+
+    /// Registered authenticators of a user, oldest first
+    pub async fn list(
+        db: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<Vec<webauthn_credential::Model>> {
+        Ok(webauthn_credential::Entity::find()
+            .filter(webauthn_credential::Column::UserId.eq(user_id))
+            .order_by_asc(webauthn_credential::Column::CreatedAt)
+            .all(db)
+            .await?)
+    }
+
+    pub async fn has_credentials(db: &DatabaseConnection, user_id: i32) -> AppResult<bool> {
+        let count = webauthn_credential::Entity::find()
+            .filter(webauthn_credential::Column::UserId.eq(user_id))
+            .count(db)
+            .await?;
+        Ok(count > 0)
+    }
+
+    /// Remove one of the user's authenticators
+    pub async fn remove(db: &DatabaseConnection, user_id: i32, id: i32) -> AppResult<()> {
+        let result = webauthn_credential::Entity::delete_many()
+            .filter(webauthn_credential::Column::Id.eq(id))
+            .filter(webauthn_credential::Column::UserId.eq(user_id))
+            .exec(db)
+            .await?;
+
+        if result.rows_affected == 0 {
+            return Err(AppError::NotFound("Authenticator not found".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn invalid_registration() -> AppError {
+    AppError::Validation("Invalid authenticator response".to_string())
+}
+
+fn invalid_assertion() -> AppError {
+    AppError::Auth("Security key verification failed".to_string())
+}
+
+fn timeout_ms() -> u64 {
+    (CHALLENGE_TTL_MINUTES * 60 * 1000) as u64
+}
+
+/// Opaque user handle stored on the authenticator; the user id, not the name
+fn user_handle(user_id: i32) -> String {
+    BASE64URL_NOPAD.encode(user_id.to_string().as_bytes())
+}
+
+fn descriptor(credential: &webauthn_credential::Model) -> CredentialDescriptor {
+    CredentialDescriptor {
+        kind: "public-key",
+        id: credential.credential_id.clone(),
+    }
+}
+
+/// Decode base64url, with or without padding
+fn decode(value: &str) -> AppResult<Vec<u8>> {
+    BASE64URL_NOPAD
+        .decode(value.trim_end_matches('=').as_bytes())
+        .map_err(|_| AppError::Validation("Invalid base64url value".to_string()))
+}
+
+async fn new_challenge(
+    db: &DatabaseConnection,
+    purpose: &str,
+    user_id: Option<i32>,
+) -> AppResult<String> {
+    let cutoff = chrono::Utc::now() - chrono::Duration::minutes(CHALLENGE_TTL_MINUTES);
+    webauthn_challenge::Entity::delete_many()
+        .filter(webauthn_challenge::Column::CreatedAt.lt(cutoff))
+        .exec(db)
+        .await?;
+
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let challenge = BASE64URL_NOPAD.encode(&bytes);
+
+    webauthn_challenge::ActiveModel {
+        challenge: Set(challenge.clone()),
+        user_id: Set(user_id),
+        purpose: Set(purpose.to_string()),
+        created_at: Set(chrono::Utc::now()),
+    }
+    .insert(db)
+    .await?;
+
+    Ok(challenge)
+}
+
+/// Consume a challenge; each one can only be answered once
+async fn take_challenge(
+    db: &DatabaseConnection,
+    challenge: &str,
+    purpose: &str,
+) -> AppResult<webauthn_challenge::Model> {
+    let invalid = || AppError::Auth("Invalid or expired WebAuthn challenge".to_string());
+
+    let stored = webauthn_challenge::Entity::find_by_id(challenge.to_string())
+        .one(db)
+        .await?
+        .ok_or_else(invalid)?;
+    // Whoever deletes the row consumed it; a request racing this one finds
+    // nothing left to delete
+    let deleted =
+        webauthn_challenge::Entity::delete_by_id(stored.challenge.clone()).exec(db).await?;
+    if deleted.rows_affected != 1 {
+        return Err(invalid());
+    }
+
+    let expired =
+        stored.created_at + chrono::Duration::minutes(CHALLENGE_TTL_MINUTES) < chrono::Utc::now();
+    if expired || stored.purpose != purpose {
+        return Err(invalid());
+    }
+    Ok(stored)
+}
+
+fn verify_client_data(
+    config: &WebauthnConfig,
+    client_data_json: &[u8],
+    expected_type: &str,
+) -> AppResult<ClientData> {
+    let client_data: ClientData = serde_json::from_slice(client_data_json)
+        .map_err(|_| AppError::Validation("Invalid client data".to_string()))?;
+
+    if client_data.kind != expected_type {
+        return Err(AppError::Validation("Unexpected WebAuthn ceremony type".to_string()));
+    }
+    if !config.origins.contains(&client_data.origin) {
+        return Err(AppError::Validation(format!(
+            "Origin {} is not allowed for WebAuthn",
+            client_data.origin
+        )));
+    }
+    Ok(client_data)
+}
+
+fn verify_flags(
+    config: &WebauthnConfig,
+    auth_data: &AuthenticatorData,
+    require_user_verification: bool,
+) -> AppResult<()> {
+    if auth_data.rp_id_hash != Sha256::digest(config.rp_id.as_bytes()).as_slice() {
+        return Err(invalid_assertion());
+    }
+    if auth_data.flags & FLAG_USER_PRESENT == 0 {
+        return Err(invalid_assertion());
+    }
+    if require_user_verification && auth_data.flags & FLAG_USER_VERIFIED == 0 {
+        return Err(AppError::Auth(
+            "Passwordless login requires user verification".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Parse authenticator data: rpIdHash (32), flags (1), signCount (4), then
+/// optionally aaguid (16), credential id length (2), credential id and COSE key
+fn parse_authenticator_data(data: &[u8]) -> Option<AuthenticatorData> {
+    if data.len() < 37 {
+        return None;
+    }
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes(data[33..37].try_into().ok()?);
+
+    let attested = if flags & FLAG_ATTESTED_DATA != 0 {
+        let rest = data.get(37 + 16..)?;
+        let id_len = u16::from_be_bytes(rest.get(..2)?.try_into().ok()?) as usize;
+        let credential_id = rest.get(2..2 + id_len)?.to_vec();
+
+        // The key is followed by optional extensions, so read exactly one item
+        let mut key_bytes = rest.get(2 + id_len..)?;
+        let before = key_bytes.len();
+        let _: Value = ciborium::from_reader(&mut key_bytes).ok()?;
+        let key_len = before - key_bytes.len();
+        Some((credential_id, rest[2 + id_len..2 + id_len + key_len].to_vec()))
+    } else {
+        None
+    };
+
+    Some(AuthenticatorData {
+        rp_id_hash: data[..32].to_vec(),
+        flags,
+        sign_count,
+        attested,
+    })
+}
+
+fn map_get(map: &Value, matches: impl Fn(&Value) -> bool) -> Option<&Value> {
+    map.as_map()?
+        .iter()
+        .find(|(key, _)| matches(key))
+        .map(|(_, value)| value)
+}
+
+/// Look up an integer label in a COSE key
+fn cose_get(key: &Value, label: i128) -> Option<&Value> {
+    map_get(key, |k| k.as_integer().map(i128::from) == Some(label))
+}
+
+fn cose_algorithm(cose_key: &[u8]) -> AppResult<i32> {
+    let key: Value = ciborium::from_reader(cose_key).map_err(|_| invalid_registration())?;
+    cose_get(&key, 3)
+        .and_then(Value::as_integer)
+        .and_then(|alg| i32::try_from(i128::from(alg)).ok())
+        .ok_or_else(invalid_registration)
+}
+
+fn parse_public_key(cose_key: &[u8]) -> AppResult<PublicKey> {
+    let unsupported = || AppError::Validation("Unsupported authenticator key type".to_string());
+
+    let key: Value = ciborium::from_reader(cose_key).map_err(|_| invalid_registration())?;
+    let int = |label| cose_get(&key, label).and_then(Value::as_integer).map(i128::from);
+    let bytes = |label| cose_get(&key, label).and_then(Value::as_bytes);
+
+    match (int(1), int(3)) {
+        (Some(COSE_KTY_EC2), Some(alg)) if alg == COSE_ES256 as i128 => {
+            if int(-1) != Some(COSE_CRV_P256) {
+                return Err(unsupported());
+            }
+            let (x, y) = bytes(-2).zip(bytes(-3)).ok_or_else(unsupported)?;
+            let mut point = vec![0x04];
+            point.extend_from_slice(x);
+            point.extend_from_slice(y);
+            VerifyingKey::from_sec1_bytes(&point)
+                .map(PublicKey::Es256)
+                .map_err(|_| unsupported())
+        }
+        (Some(COSE_KTY_RSA), Some(alg)) if alg == COSE_RS256 as i128 => {
+            let (n, e) = bytes(-1).zip(bytes(-2)).ok_or_else(unsupported)?;
+            Ok(PublicKey::Rs256 {
+                n: n.clone(),
+                e: e.clone(),
+            })
+        }
+        _ => Err(unsupported()),
+    }
+}
+
+fn verify_signature(key: &PublicKey, message: &[u8], signature: &[u8]) -> bool {
+    match key {
+        // WebAuthn ECDSA signatures are DER encoded
+        PublicKey::Es256(key) => Signature::from_der(signature)
+            .is_ok_and(|signature| key.verify(message, &signature).is_ok()),
+        PublicKey::Rs256 { n, e } => jsonwebtoken::crypto::verify(
+            &BASE64URL_NOPAD.encode(signature),
+            message,
+            &jsonwebtoken::DecodingKey::from_rsa_raw_components(n, e),
+            jsonwebtoken::Algorithm::RS256,
+        )
+        .unwrap_or(false),
+    }
+}
+
+/// A software authenticator for tests: one ES256 credential, no attestation
+#[cfg(test)]
+pub mod soft_authenticator {
+    use super::*;
+    use p256::ecdsa::{signature::Signer, SigningKey};
+    use serde_json::json;
+
+    pub struct SoftAuthenticator {
+        key: SigningKey,
+        pub credential_id: Vec<u8>,
+        pub sign_count: u32,
+        pub user_verified: bool,
+        user_handle: Option<String>,
+    }
+
+    impl Default for SoftAuthenticator {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl SoftAuthenticator {
+        pub fn new() -> Self {
+            let mut seed = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut seed);
+            let mut credential_id = vec![0u8; 16];
+            rand::thread_rng().fill_bytes(&mut credential_id);
+
+            Self {
+                key: SigningKey::from_slice(&seed).unwrap(),
+                credential_id,
+                sign_count: 0,
+                user_verified: true,
+                user_handle: None,
+            }
+        }
+
+        pub fn id(&self) -> String {
+            BASE64URL_NOPAD.encode(&self.credential_id)
+        }
+
+        /// Answer `navigator.credentials.create()` options
+        pub fn register(&mut self, options: &serde_json::Value, origin: &str) -> serde_json::Value {
+            let rp_id = options["rp"]["id"].as_str().unwrap();
+            self.user_handle = options["user"]["id"].as_str().map(str::to_string);
+
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let cose_key = Value::Map(vec![
+                (Value::from(1), Value::from(COSE_KTY_EC2 as i64)),
+                (Value::from(3), Value::from(COSE_ES256)),
+                (Value::from(-1), Value::from(COSE_CRV_P256 as i64)),
+                (Value::from(-2), Value::Bytes(point.x().unwrap().to_vec())),
+                (Value::from(-3), Value::Bytes(point.y().unwrap().to_vec())),
+            ]);
+
+            let mut auth_data = self.auth_data(rp_id, FLAG_ATTESTED_DATA);
+            auth_data.extend_from_slice(&[0u8; 16]);
+            auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            auth_data.extend_from_slice(&self.credential_id);
+            ciborium::into_writer(&cose_key, &mut auth_data).unwrap();
+
+            let attestation = Value::Map(vec![
+                (Value::from("fmt"), Value::from("none")),
+                (Value::from("attStmt"), Value::Map(vec![])),
+                (Value::from("authData"), Value::Bytes(auth_data)),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+
+            json!({
+                "id": self.id(),
+                "rawId": self.id(),
+                "type": "public-key",
+                "response": {
+                    "clientDataJSON": client_data("webauthn.create", options, origin),
+                    "attestationObject": BASE64URL_NOPAD.encode(&attestation_object),
+                },
+            })
+        }
+
+        /// Answer `navigator.credentials.get()` options
+        pub fn authenticate(
+            &mut self,
+            options: &serde_json::Value,
+            origin: &str,
+        ) -> serde_json::Value {
+            let client_data_json = client_data("webauthn.get", options, origin);
+            let auth_data = self.auth_data(options["rpId"].as_str().unwrap(), 0);
+
+            let mut signed = auth_data.clone();
+            signed.extend_from_slice(&Sha256::digest(decode(&client_data_json).unwrap()));
+            let signature: Signature = self.key.sign(&signed);
+
+            json!({
+                "id": self.id(),
+                "rawId": self.id(),
+                "type": "public-key",
+                "response": {
+                    "clientDataJSON": client_data_json,
+                    "authenticatorData": BASE64URL_NOPAD.encode(&auth_data),
+                    "signature": BASE64URL_NOPAD.encode(signature.to_der().as_bytes()),
+                    "userHandle": self.user_handle,
+                },
+            })
+        }
+
+        fn auth_data(&mut self, rp_id: &str, extra_flags: u8) -> Vec<u8> {
+            self.sign_count += 1;
+            let mut flags = FLAG_USER_PRESENT | extra_flags;
+            if self.user_verified {
+                flags |= FLAG_USER_VERIFIED;
+            }
+
+            let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+            data.push(flags);
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+            data
+        }
+    }
+
+    fn client_data(kind: &str, options: &serde_json::Value, origin: &str) -> String {
+        let client_data = json!({
+            "type": kind,
+            "challenge": options["challenge"],
+            "origin": origin,
+            "crossOrigin": false,
+        });
+        BASE64URL_NOPAD.encode(client_data.to_string().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::soft_authenticator::SoftAuthenticator;
+    use super::*;
+
+    const ORIGIN: &str = "http://localhost:5173";
+
+    fn options<T: Serialize>(options: &T) -> serde_json::Value {
+        serde_json::to_value(options).unwrap()
+    }
+
+    async fn setup() -> (std::sync::Arc<DatabaseConnection>, WebauthnConfig, user::Model) {
+        let state = crate::test_util::test_state().await;
+        let admin = crate::services::user::UserService::find_by_username(&state.db, "admin")
+            .await
+            .unwrap()
+            .unwrap();
+        (state.db, state.config.webauthn, admin)
+    }
+
+    async fn register(
+        db: &DatabaseConnection,
+        config: &WebauthnConfig,
+        user: &user::Model,
+        authenticator: &mut SoftAuthenticator,
+    ) -> webauthn_credential::Model {
+        let creation = WebauthnService::start_registration(db, config, user).await.unwrap();
+        let response = authenticator.register(&options(&creation), ORIGIN);
+        let response: RegistrationCredential = serde_json::from_value(response).unwrap();
+        WebauthnService::finish_registration(db, config, user.id, "Test key", &response)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_register_and_authenticate() {
+        let (db, config, admin) = setup().await;
+        let mut authenticator = SoftAuthenticator::new();
+
+        let credential = register(&db, &config, &admin, &mut authenticator).await;
+        assert_eq!(credential.credential_id, authenticator.id());
+        assert_eq!(credential.algorithm, COSE_ES256);
+        assert_eq!(credential.sign_count, 1);
+
+        let request = WebauthnService::start_login(&db, &config, Some(admin.id)).await.unwrap();
+        assert_eq!(request.allow_credentials.len(), 1);
+        let assertion: AssertionCredential =
+            serde_json::from_value(authenticator.authenticate(&options(&request), ORIGIN))
+                .unwrap();
+
+        let used = WebauthnService::finish_login(&db, &config, Some(admin.id), &assertion)
+            .await
+            .unwrap();
+        assert_eq!(used.sign_count, 2);
+        assert!(used.last_used_at.is_some());
+
+        // Challenges are single-use
+        let replay = WebauthnService::finish_login(&db, &config, Some(admin.id), &assertion).await;
+        assert!(matches!(replay, Err(AppError::Auth(_))));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_tampered_assertions() {
+        let (db, config, admin) = setup().await;
+        let mut authenticator = SoftAuthenticator::new();
+        register(&db, &config, &admin, &mut authenticator).await;
+
+        // Wrong origin
+        let request = WebauthnService::start_login(&db, &config, None).await.unwrap();
+        let response = authenticator.authenticate(&options(&request), "https://evil.example");
+        let assertion: AssertionCredential = serde_json::from_value(response).unwrap();
+        assert!(WebauthnService::finish_login(&db, &config, None, &assertion).await.is_err());
+
+        // Signature from another key
+        let request = WebauthnService::start_login(&db, &config, None).await.unwrap();
+        let mut impostor = SoftAuthenticator::new();
+        impostor.credential_id = authenticator.credential_id.clone();
+        let response = impostor.authenticate(&options(&request), ORIGIN);
+        let assertion: AssertionCredential = serde_json::from_value(response).unwrap();
+        assert!(WebauthnService::finish_login(&db, &config, None, &assertion).await.is_err());
+
+        // A counter that doesn't move forward points at a cloned key
+        let request = WebauthnService::start_login(&db, &config, None).await.unwrap();
+        authenticator.sign_count = 0;
+        let response = authenticator.authenticate(&options(&request), ORIGIN);
+        let assertion: AssertionCredential = serde_json::from_value(response).unwrap();
+        assert!(WebauthnService::finish_login(&db, &config, None, &assertion).await.is_err());
+
+        // Passwordless logins need user verification
+        let request = WebauthnService::start_login(&db, &config, None).await.unwrap();
+        authenticator.sign_count = 10;
+        authenticator.user_verified = false;
+        let response = authenticator.authenticate(&options(&request), ORIGIN);
+        let assertion: AssertionCredential = serde_json::from_value(response).unwrap();
+        assert!(WebauthnService::finish_login(&db, &config, None, &assertion).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_challenge_is_taken_once() {
+        let (db, _, _) = setup().await;
+        let challenge = new_challenge(&db, PURPOSE_LOGIN, None).await.unwrap();
+
+        let (first, second) = tokio::join!(
+            take_challenge(&db, &challenge, PURPOSE_LOGIN),
+            take_challenge(&db, &challenge, PURPOSE_LOGIN)
+        );
+        assert!(first.is_ok() != second.is_ok());
+    }
+}
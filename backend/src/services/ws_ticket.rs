@@ -0,0 +1,64 @@
+use data_encoding::BASE64URL_NOPAD;
+use rand::RngCore;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::middleware::auth::Claims;
+
+/// How long a ticket can wait for its WebSocket upgrade
+pub const TICKET_TTL: Duration = Duration::from_secs(30);
+
+/// Single-use tickets that authenticate a WebSocket upgrade.
+///
+/// Browsers can't set headers on a WebSocket handshake, and cookies don't
+/// carry a CSRF token there, so the page asks for a ticket with its normal
+/// credentials and passes it in the query string instead. Tickets only live in
+/// memory: they are redeemed within seconds and a restart simply voids them.
+#[derive(Clone, Default)]
+pub struct WsTickets {
+    tickets: Arc<Mutex<HashMap<String, (Claims, Instant)>>>,
+}
+
+impl WsTickets {
+    /// Issue a ticket standing in for the given verified claims
+    pub fn issue(&self, claims: Claims) -> String {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let ticket = BASE64URL_NOPAD.encode(&bytes);
+
+        let mut tickets = self.tickets.lock().unwrap();
+        tickets.retain(|_, (_, issued)| issued.elapsed() < TICKET_TTL);
+        tickets.insert(ticket.clone(), (claims, Instant::now()));
+        ticket
+    }
+
+    /// Consume a ticket, returning its claims if it was still valid
+    pub fn redeem(&self, ticket: &str) -> Option<Claims> {
+        let (claims, issued) = self.tickets.lock().unwrap().remove(ticket)?;
+        (issued.elapsed() < TICKET_TTL).then_some(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tickets_are_single_use() {
+        let tickets = WsTickets::default();
+        let claims = Claims {
+            sub: "1".to_string(),
+            username: "admin".to_string(),
+            role: "admin".to_string(),
+            sid: "session".to_string(),
+            exp: 0,
+            api_token_id: None,
+        };
+
+        let ticket = tickets.issue(claims);
+        assert_eq!(tickets.redeem(&ticket).unwrap().username, "admin");
+        assert!(tickets.redeem(&ticket).is_none());
+        assert!(tickets.redeem("made-up").is_none());
+    }
+}
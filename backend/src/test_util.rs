@@ -0,0 +1,163 @@
+//! Shared helpers for in-crate tests.
+
+use axum::Router;
+use sea_orm::{sea_query::Expr, ConnectOptions, Database, EntityTrait};
+use sea_orm_migration::MigratorTrait;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{
+    api,
+    config::{Config, WebauthnConfig},
+    db::{entities::user, migrator::Migrator},
+    middleware::auth::Claims,
+    services::{
+        access::AccessControl,
+        disk_usage::DiskUsage,
+        jail::JailPolicy,
+        jobs::Jobs,
+        monitor::SystemMonitor,
+        rbac::ROLE_ADMIN,
+        session::{SessionOrigin, SessionService},
+        signing_key::SigningKeys,
+        upload::Uploads,
+        user::UserService,
+        versions::VersionService,
+        ws_ticket::WsTickets,
+    },
+    AppState,
+};
+
+/// Config for tests, with the panel's own directories below `scratch`
+pub fn test_config(scratch: &Path) -> Config {
+    Config {
+        host: "127.0.0.1".to_string(),
+        port: 0,
+        database_url: "sqlite::memory:".to_string(),
+        access_token_minutes: 15,
+        refresh_token_hours: 1,
+        login_max_failures_per_user: 3,
+        login_max_failures_per_ip: 10,
+        login_backoff_base_seconds: 60,
+        login_lockout_max_minutes: 15,
+        login_failure_window_minutes: 30,
+        password_min_length: 10,
+        password_min_char_classes: 3,
+        password_history: 5,
+        password_blocklist_file: None,
+        oidc: None,
+        webauthn: WebauthnConfig {
+            rp_id: "localhost".to_string(),
+            rp_name: "Mana Panel".to_string(),
+            origins: vec!["http://localhost:5173".to_string()],
+        },
+        trusted_proxies: Vec::new(),
+        access_reset: false,
+        cookie_secure: true,
+        upload_max_bytes: 1024 * 1024,
+        uploads_dir: scratch.join("uploads").to_string_lossy().into_owned(),
+        trash_dir: scratch.join("trash").to_string_lossy().into_owned(),
+        trash_max_bytes: 1024 * 1024,
+        trash_retention_days: 30,
+        versions_dir: scratch.join("versions").to_string_lossy().into_owned(),
+        versions_keep: 3,
+        edit_max_bytes: 64 * 1024,
+    }
+}
+
+/// App state backed by a fresh in-memory database with the default admin.
+pub async fn test_state() -> AppState {
+    // A single connection, otherwise every pooled connection gets its own
+    // empty in-memory database
+    let mut options = ConnectOptions::new("sqlite::memory:");
+    options.max_connections(1).sqlx_logging(false);
+    let db = Database::connect(options).await.unwrap();
+    Migrator::up(&db, None).await.unwrap();
+    UserService::init_default_admin(&db).await.unwrap();
+    // Most tests act as the default admin; the forced password change is
+    // covered by its own tests
+    user::Entity::update_many()
+        .col_expr(user::Column::MustChangePassword, Expr::value(false))
+        .exec(&db)
+        .await
+        .unwrap();
+    let signing_keys = SigningKeys::load(&db, chrono::Duration::minutes(15)).await.unwrap();
+    let access = AccessControl::load(&db).await.unwrap();
+    let scratch = Arc::new(tempfile::tempdir().unwrap());
+    let config = test_config(scratch.path());
+    let jail = JailPolicy::load(&db, &config).await.unwrap();
+    let uploads = Uploads::load(&config).await.unwrap();
+
+    AppState {
+        config,
+        monitor: SystemMonitor::new(),
+        db: Arc::new(db),
+        docker: None,
+        signing_keys,
+        access,
+        ws_tickets: WsTickets::default(),
+        jail,
+        uploads,
+        jobs: Jobs::default(),
+        disk_usage: DiskUsage::default(),
+        versions: VersionService::default(),
+        scratch,
+    }
+}
+
+/// The full app as served by `main`.
+pub async fn test_app() -> Router {
+    test_app_with_state().await.0
+}
+
+pub async fn test_app_with_state() -> (Router, AppState) {
+    let state = test_state().await;
+    (app_for(&state), state)
+}
+
+/// The app for a state prepared by the test
+pub fn app_for(state: &AppState) -> Router {
+    api::app(state.clone())
+}
+
+/// Create an account with the given role (named after it) and return its bearer token
+pub async fn user_token(state: &AppState, role: &str) -> String {
+    let user = UserService::create_user(&state.db, role, "test-password", role, false)
+        .await
+        .unwrap();
+    bearer_token(state, user.id, &user.username, role).await
+}
+
+/// Bearer token for the default admin account
+pub async fn admin_token(state: &AppState) -> String {
+    bearer_token(state, 1, "admin", ROLE_ADMIN).await
+}
+
+/// Open a session for the account and sign an access token for it
+pub async fn bearer_token(state: &AppState, user_id: i32, username: &str, role: &str) -> String {
+    let (session, _) = SessionService::create(
+        &state.db,
+        user_id,
+        SessionOrigin::default(),
+        chrono::Duration::hours(1),
+    )
+    .await
+    .unwrap();
+
+    let claims = Claims {
+        sub: user_id.to_string(),
+        username: username.to_string(),
+        role: role.to_string(),
+        sid: session.id,
+        exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
+        api_token_id: None,
+    };
+    let token = state.signing_keys.sign(&claims).unwrap();
+
+    format!("Bearer {}", token)
+}
+
+pub async fn body_json(res: axum::response::Response) -> serde_json::Value {
+    let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null)
+}
@@ -14,18 +14,54 @@ api.interceptors.request.use((config) => {
   if (token) {
     config.headers.Authorization = `Bearer ${token}`
   }
+  // Double-submit token for requests that fall back to the session cookie
+  const csrf = document.cookie.match(/(?:^|; )mana_csrf=([^;]*)/)?.[1]
+  if (csrf) {
+    config.headers['X-CSRF-Token'] = csrf
+  }
   return config
 })
 
+// Shared so that concurrent 401s only trigger one refresh
+let refreshing: Promise<boolean> | null = null
+
 // Response interceptor for error handling
 api.interceptors.response.use(
   (response) => response,
-  (error) => {
-    if (error.response?.status === 401) {
+  async (error) => {
+    const original = error.config
+    const isAuthCall = [
+      '/auth/login',
+      '/auth/refresh',
+      '/auth/logout',
+      '/auth/oidc',
+    ].some((url) => original?.url?.startsWith(url))
+
+    if (error.response?.status === 401 && original && !isAuthCall) {
       const authStore = useAuthStore()
+
+      // Access tokens are short-lived: renew once with the refresh token and retry
+      if (!original._retried) {
+        original._retried = true
+        refreshing ??= authStore.refresh().finally(() => {
+          refreshing = null
+        })
+        if (await refreshing) {
+          return api(original)
+        }
+      }
+
       authStore.logout()
       window.location.href = '/login'
     }
+
+    // Everything but the password change is blocked until the password is changed
+    if (
+      error.response?.data?.error?.code === 'PASSWORD_CHANGE_REQUIRED' &&
+      window.location.pathname !== '/settings'
+    ) {
+      window.location.href = '/settings'
+    }
     return Promise.reject(error)
   },
 )
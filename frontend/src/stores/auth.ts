@@ -13,13 +13,45 @@ export const useAuthStore = defineStore('auth', () => {
 
   const isAuthenticated = computed(() => !!token.value)
 
+  const setTokens = (data: { token: string; refresh_token: string }) => {
+    token.value = data.token
+    localStorage.setItem('token', data.token)
+    localStorage.setItem('refresh_token', data.refresh_token)
+  }
+
   const login = async (username: string, password: string) => {
     const response = await api.post('/auth/login', { username, password })
-    token.value = response.data.token
-    localStorage.setItem('token', response.data.token)
+    setTokens(response.data)
+    await fetchUser()
+  }
+
+  // Single sign-on: send the browser to the identity provider...
+  const startOidcLogin = async () => {
+    const response = await api.post('/auth/oidc/authorize')
+    window.location.href = response.data.authorization_url
+  }
+
+  // ...and finish the login once it redirects back with a code
+  const completeOidcLogin = async (code: string, state: string) => {
+    const response = await api.post('/auth/oidc/callback', { code, state })
+    setTokens(response.data)
     await fetchUser()
   }
 
+  const refresh = async () => {
+    const refreshToken = localStorage.getItem('refresh_token')
+    if (!refreshToken) return false
+    try {
+      const response = await api.post('/auth/refresh', {
+        refresh_token: refreshToken,
+      })
+      setTokens(response.data)
+      return true
+    } catch {
+      return false
+    }
+  }
+
   const fetchUser = async () => {
     if (!token.value) return
     try {
@@ -31,9 +63,14 @@ export const useAuthStore = defineStore('auth', () => {
   }
 
   const logout = () => {
+    if (token.value) {
+      // Revoke the server-side session; the local state is cleared regardless
+      api.post('/auth/logout').catch(() => {})
+    }
     token.value = null
     user.value = null
     localStorage.removeItem('token')
+    localStorage.removeItem('refresh_token')
   }
 
   return {
@@ -41,6 +78,9 @@ export const useAuthStore = defineStore('auth', () => {
     user,
     isAuthenticated,
     login,
+    startOidcLogin,
+    completeOidcLogin,
+    refresh,
     fetchUser,
     logout,
   }